@@ -0,0 +1,15 @@
+//! Compiles the gRPC service definition in `proto/` when the `grpc` feature is enabled,
+//! with a vendored `protoc` so that building does not require one to be installed.
+
+#[cfg(feature = "grpc")]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/dashboard.proto");
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/dashboard.proto"], &["proto"])?;
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn main() {}
@@ -0,0 +1,251 @@
+//! Abuse report drafts per offending network.
+//!
+//! The `report-abuse` subcommand aggregates the logs like a normal run, looks up the top
+//! threat sources in the enrichment file (see the `enrich` module), and groups them by
+//! network and abuse contact. For every network with an abuse contact it writes an email
+//! draft, `abuse-<network>.eml`, listing the offending addresses with their event counts
+//! and a few sample events each. The drafts open in mail clients for review before
+//! sending; nothing is sent automatically.
+
+use crate::cli::Options;
+use crate::enrich::{Enrichment, Network};
+use crate::{aggregate, cutoff, guard_files, list_log_files, FileSinks};
+use chrono::{Local, NaiveDateTime};
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::metric::Addresses;
+use dashboard_aggregator::normalize::Field;
+use dashboard_aggregator::{
+    csv_reader, log, normalize, DATETIME_COLUMN, DATETIME_FORMAT, DESTINATION_COLUMN,
+    PRIORITY_COLUMN, SIGNATURE_COLUMN, SOURCE_COLUMN,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of top threat sources considered for abuse reports.
+const TOP_SOURCES: usize = 100;
+
+/// Number of sample events included per source.
+const SAMPLES_PER_SOURCE: usize = 5;
+
+/// The reported sources of one network, with their event counts.
+type Group<'a> = (&'a Network, Vec<(String, u32)>);
+
+/// Writes abuse report drafts for the top threat sources.
+///
+/// # Errors
+/// Returns an error if the enrichment file or the log directory cannot be read, or a
+/// draft cannot be written.
+pub fn run(options: &Options, config: &Config) -> io::Result<()> {
+    let enrichment_path = options
+        .enrichment
+        .as_deref()
+        .unwrap_or_else(|| Path::new(""));
+    let enrichment = Enrichment::load(enrichment_path)?;
+    let files = list_log_files(options)?;
+    let files = guard_files(files, options, true);
+    let (data, errors) = aggregate(&files, options, config, true, FileSinks::default());
+    for e in &errors {
+        log::error(e);
+    }
+    let top = data
+        .metric::<Addresses>("Threat Sources")
+        .map(|sources| sources.top(TOP_SOURCES))
+        .unwrap_or_default();
+
+    // Networks are listed in the order of their top source
+    let mut groups: Vec<Group> = Vec::new();
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    let mut unreported = Vec::new();
+    for (source, count) in top {
+        match enrichment.lookup(&source) {
+            Some(network) if !network.abuse_email.is_empty() => {
+                let position = *positions.entry(&network.cidr).or_insert_with(|| {
+                    groups.push((network, Vec::new()));
+                    groups.len() - 1
+                });
+                groups[position].1.push((source, count));
+            }
+            _ => unreported.push(source),
+        }
+    }
+
+    let reported: HashSet<&str> = groups
+        .iter()
+        .flat_map(|(_, sources)| sources.iter().map(|(source, _)| source.as_str()))
+        .collect();
+    let samples = samples(&files, options, config, &reported);
+    let window = format!(
+        "{} and {} (UTC{})",
+        cutoff(options.days_back).format("%Y-%m-%d %H:%M"),
+        Local::now().format("%Y-%m-%d %H:%M"),
+        Local::now().format("%:z")
+    );
+    for (network, sources) in &groups {
+        let path = options.output_dir_path(&format!("abuse-{}.eml", file_name(network)));
+        fs::write(&path, draft(network, sources, &samples, &window))?;
+        log::info(format_args!(
+            "Abuse report for {} ({} sources) saved to {}",
+            network.abuse_email,
+            sources.len(),
+            path.display()
+        ));
+    }
+    if !unreported.is_empty() {
+        log::info(format_args!(
+            "{} of the top sources have no known abuse contact: {}",
+            unreported.len(),
+            unreported.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the name of the draft file for `network`, from its ASN if known.
+fn file_name(network: &Network) -> String {
+    let name = if network.asn.is_empty() {
+        network.cidr.clone()
+    } else {
+        format!("AS{}", network.asn.trim_start_matches("AS"))
+    };
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Collects up to `SAMPLES_PER_SOURCE` counted events for each of `sources`, formatted
+/// as one line each and sorted by time. Files that cannot be read are skipped, as their
+/// errors were already reported while aggregating.
+fn samples(
+    files: &[PathBuf],
+    options: &Options,
+    config: &Config,
+    sources: &HashSet<&str>,
+) -> HashMap<String, Vec<String>> {
+    let cutoff = cutoff(options.days_back);
+    let mut samples: HashMap<String, Vec<String>> = HashMap::new();
+    for path in files {
+        let Ok(file) = File::open(path) else {
+            continue;
+        };
+        let mut reader = csv_reader(
+            options.format.reader(options.encoding.reader(file)),
+            config.delimiter_byte(),
+        );
+        let Ok(headers) = reader.headers().cloned() else {
+            continue;
+        };
+        let Ok(row_filters) = options
+            .filter
+            .rows
+            .iter()
+            .map(|row| row.resolve(&headers).map(|positions| (row, positions)))
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            continue;
+        };
+
+        for record in reader.records().flatten() {
+            let field = |index: usize| record.get(index).unwrap_or_default();
+            let (source, destination) = (field(SOURCE_COLUMN), field(DESTINATION_COLUMN));
+            if !sources.contains(source)
+                || samples.get(source).map_or(0, Vec::len) >= SAMPLES_PER_SOURCE
+            {
+                continue;
+            }
+            let Ok(datetime) =
+                NaiveDateTime::parse_from_str(field(DATETIME_COLUMN), DATETIME_FORMAT)
+            else {
+                continue;
+            };
+            if datetime > cutoff
+                && options.filter.in_period(datetime)
+                && options.filter.meets_priority(&normalize(
+                    config,
+                    Field::Priority,
+                    field(PRIORITY_COLUMN),
+                ))
+                && options.filter.matches(source, destination)
+                && row_filters
+                    .iter()
+                    .all(|(row, positions)| row.matches(&record, positions))
+            {
+                samples.entry(source.to_string()).or_default().push(format!(
+                    "{}  {source} -> {destination}  {}",
+                    datetime.format("%Y-%m-%d %H:%M:%S"),
+                    field(SIGNATURE_COLUMN)
+                ));
+            }
+        }
+    }
+    for lines in samples.values_mut() {
+        lines.sort();
+    }
+    samples
+}
+
+/// Renders the email draft reporting `sources` to the abuse contact of `network`.
+fn draft(
+    network: &Network,
+    sources: &[(String, u32)],
+    samples: &HashMap<String, Vec<String>>,
+    window: &str,
+) -> String {
+    let total: u64 = sources.iter().map(|(_, count)| u64::from(*count)).sum();
+    let mut name = network.cidr.clone();
+    if !network.asn.is_empty() {
+        name = format!("AS{} {name}", network.asn.trim_start_matches("AS"));
+    }
+    if !network.organization.is_empty() {
+        name = format!("{name}, {}", network.organization);
+    }
+
+    let mut draft = String::new();
+    let _ = writeln!(draft, "To: {}", network.abuse_email);
+    let _ = writeln!(
+        draft,
+        "Subject: Abuse report: {total} firewall events from {name}"
+    );
+    let _ = writeln!(draft, "Content-Type: text/plain; charset=utf-8");
+    // Makes Outlook open the file as an unsent draft
+    let _ = writeln!(draft, "X-Unsent: 1");
+    let _ = writeln!(draft);
+    let _ = writeln!(draft, "Hello,");
+    let _ = writeln!(draft);
+    let _ = writeln!(
+        draft,
+        "Between {window}, our firewalls recorded {total} events from the following \
+         addresses in your network ({name}):"
+    );
+    let _ = writeln!(draft);
+    for (source, count) in sources {
+        let _ = writeln!(draft, "  {source:<40} {count} events");
+    }
+    let _ = writeln!(draft);
+    let _ = writeln!(
+        draft,
+        "Sample events (time, source -> destination, signature):"
+    );
+    let _ = writeln!(draft);
+    for (source, _) in sources {
+        for line in samples.get(source).into_iter().flatten() {
+            let _ = writeln!(draft, "  {line}");
+        }
+    }
+    let _ = writeln!(draft);
+    let _ = writeln!(
+        draft,
+        "Please investigate these hosts and take appropriate action."
+    );
+    let _ = writeln!(draft);
+    let _ = writeln!(draft, "Regards,");
+    draft
+}
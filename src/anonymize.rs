@@ -0,0 +1,250 @@
+//! Anonymization of the source and destination addresses.
+//!
+//! With `--anonymize <mode>`, the addresses are rewritten in the aggregates before any
+//! output is written, so the outputs can be shared with third parties:
+//!
+//! - `truncate` zeroes the last octet of IPv4 addresses (a /24) and everything after the
+//!   first 48 bits of IPv6 addresses. Addresses in the same network are counted together.
+//! - `hmac` replaces each address with a pseudonym derived from HMAC-SHA256 with the key
+//!   from `--anonymize-key`. The same address always gets the same pseudonym for the same
+//!   key, so outputs from different runs can be correlated without revealing addresses.
+//! - `cryptopan` maps each address to another address of the same family such that two
+//!   addresses sharing an n-bit prefix map to addresses sharing an n-bit prefix, with the
+//!   Crypto-PAn scheme of Xu et al. The key is 32 bytes, the AES-128 key followed by the
+//!   pad, as in the reference implementation, whose results it reproduces.
+//!
+//! Values that are not IP addresses are replaced with `redacted` by `truncate`, and with
+//! an HMAC pseudonym by the keyed modes.
+
+use crate::cli::{Anonymization, Options};
+use crate::manifest::read_key;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use dashboard_aggregator::AggregatedData;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Replacement for values that are not IP addresses in `truncate` mode.
+const REDACTED: &str = "redacted";
+
+/// Number of leading IPv6 address bits kept in `truncate` mode.
+const IPV6_TRUNCATE_BITS: u32 = 48;
+
+/// Number of hex digits of the HMAC used in pseudonyms.
+const PSEUDONYM_DIGITS: usize = 16;
+
+/// Length of a Crypto-PAn key: the AES-128 key followed by the pad.
+const CRYPTOPAN_KEY_LENGTH: usize = 32;
+
+/// Rewrites addresses as configured by `--anonymize`.
+///
+/// # Fields
+/// - `key`: The keyed HMAC, for the modes that need one; `None` in `truncate` mode.
+/// - `cryptopan`: The Crypto-PAn mapping, in `cryptopan` mode.
+pub struct Anonymizer {
+    key: Option<Hmac<Sha256>>,
+    cryptopan: Option<CryptoPan>,
+}
+
+impl Anonymizer {
+    /// Creates the anonymizer configured in `options`, reading its key if it needs one.
+    ///
+    /// # Returns
+    /// The anonymizer, or `None` if addresses are not anonymized.
+    ///
+    /// # Errors
+    /// Returns an error if the key file cannot be read or is empty, or, in `cryptopan`
+    /// mode, does not hold 32 bytes.
+    pub fn load(options: &Options) -> io::Result<Option<Self>> {
+        let Some(mode) = options.anonymize else {
+            return Ok(None);
+        };
+        let (Some(path), true) = (&options.anonymize_key, mode.keyed()) else {
+            return Ok(Some(Self {
+                key: None,
+                cryptopan: None,
+            }));
+        };
+        let key = read_key(path)?;
+        let cryptopan = match mode {
+            Anonymization::CryptoPan => Some(CryptoPan::new(&key).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Key file {} must hold {CRYPTOPAN_KEY_LENGTH} bytes for cryptopan",
+                        path.display()
+                    ),
+                )
+            })?),
+            _ => None,
+        };
+        let key = <Hmac<Sha256> as Mac>::new_from_slice(&key).map_err(io::Error::other)?;
+        Ok(Some(Self {
+            key: Some(key),
+            cryptopan,
+        }))
+    }
+
+    /// Anonymizes the addresses in `data`.
+    pub fn apply(&self, data: &mut AggregatedData) {
+        data.anonymize(&|address| self.address(address));
+    }
+
+    /// Returns the anonymized form of `address`.
+    pub fn address(&self, address: &str) -> String {
+        // Only the keyed modes have a key
+        let Some(key) = &self.key else {
+            return truncate(address);
+        };
+        match (&self.cryptopan, address.parse::<IpAddr>()) {
+            (Some(cryptopan), Ok(ip)) => cryptopan.address(ip).to_string(),
+            _ => pseudonym(key, address),
+        }
+    }
+}
+
+/// Truncates `address` to its network, or redacts it if it is not an IP address.
+fn truncate(address: &str) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Ipv4Addr::from(u32::from(ip) & 0xFFFF_FF00).to_string(),
+        Ok(IpAddr::V6(ip)) => {
+            let mask = u128::MAX << (128 - IPV6_TRUNCATE_BITS);
+            Ipv6Addr::from(u128::from(ip) & mask).to_string()
+        }
+        Err(_) => REDACTED.to_string(),
+    }
+}
+
+/// Returns the keyed pseudonym of `value`, e.g. `anon-3f2a9c0e51d7b846`.
+fn pseudonym(key: &Hmac<Sha256>, value: &str) -> String {
+    let mac = key.clone().chain_update(b"address:").chain_update(value);
+    let mut digits = hex::encode(mac.finalize().into_bytes());
+    digits.truncate(PSEUDONYM_DIGITS);
+    format!("anon-{digits}")
+}
+
+/// The Crypto-PAn prefix-preserving mapping.
+///
+/// Bit i of the output is bit i of the input flipped by the first bit of the AES
+/// encryption of the first i bits of the input, padded to a block with the bits of the
+/// encrypted pad. Addresses that agree on their first n bits therefore also agree on the
+/// first n bits of the output, and the mapping is one-to-one.
+///
+/// # Fields
+/// - `cipher`: The AES-128 cipher keyed with the first half of the key.
+/// - `pad`: The second half of the key, encrypted with `cipher`.
+struct CryptoPan {
+    cipher: Aes128,
+    pad: u128,
+}
+
+impl CryptoPan {
+    /// Creates the mapping for `key`, or returns `None` if it is not 32 bytes long.
+    fn new(key: &[u8]) -> Option<Self> {
+        if key.len() != CRYPTOPAN_KEY_LENGTH {
+            return None;
+        }
+        let (aes_key, pad) = key.split_at(CRYPTOPAN_KEY_LENGTH / 2);
+        let cipher = Aes128::new_from_slice(aes_key).ok()?;
+        let mut cryptopan = Self { cipher, pad: 0 };
+        cryptopan.pad = cryptopan.encrypt(u128::from_be_bytes(pad.try_into().ok()?));
+        Some(cryptopan)
+    }
+
+    /// Encrypts the block `block`, read big-endian.
+    fn encrypt(&self, block: u128) -> u128 {
+        let mut block = block.to_be_bytes().into();
+        self.cipher.encrypt_block(&mut block);
+        u128::from_be_bytes(block.into())
+    }
+
+    /// Returns the anonymized form of `ip`, of the same family.
+    fn address(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let bits = self.map(u128::from(u32::from(ip)), u32::BITS);
+                IpAddr::V4(Ipv4Addr::from(u32::try_from(bits).unwrap_or(0)))
+            }
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(self.map(u128::from(ip), u128::BITS))),
+        }
+    }
+
+    /// Maps the `width` low bits of `bits`.
+    fn map(&self, bits: u128, width: u32) -> u128 {
+        // The address is aligned with the start of the block, as in the reference
+        let address = bits << (u128::BITS - width);
+        let mut flips = 0;
+        for i in 0..width {
+            let prefix = u128::MAX.checked_shl(u128::BITS - i).unwrap_or(0);
+            let block = (address & prefix) | (self.pad & !prefix);
+            flips |= (self.encrypt(block) >> (u128::BITS - 1)) << (width - 1 - i);
+        }
+        bits ^ flips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The key of the sample trace of the reference implementation.
+    const KEY: [u8; 32] = [
+        21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16, 216, 152, 143, 131,
+        121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2,
+    ];
+
+    fn anonymize(cryptopan: &CryptoPan, address: &str) -> String {
+        address
+            .parse()
+            .map_or_else(|_| String::new(), |ip| cryptopan.address(ip).to_string())
+    }
+
+    #[test]
+    fn matches_the_reference_implementation() {
+        let cryptopan = CryptoPan::new(&KEY).expect("32-byte key");
+        for (address, anonymized) in [
+            ("128.11.68.132", "135.242.180.132"),
+            ("129.118.74.4", "134.136.186.123"),
+            ("130.132.252.244", "133.68.164.234"),
+            ("141.223.7.43", "141.167.8.160"),
+            ("141.233.145.108", "141.129.237.235"),
+        ] {
+            assert_eq!(anonymize(&cryptopan, address), anonymized);
+        }
+        // The mapping only depends on the key
+        let again = CryptoPan::new(&KEY).expect("32-byte key");
+        assert_eq!(anonymize(&again, "128.11.68.132"), "135.242.180.132");
+        assert!(CryptoPan::new(&KEY[..16]).is_none());
+    }
+
+    #[test]
+    fn preserves_shared_prefixes() {
+        let cryptopan = CryptoPan::new(&KEY).expect("32-byte key");
+        let shared = |a: &str, b: &str| {
+            let a: IpAddr = anonymize(&cryptopan, a).parse().expect("address");
+            let b: IpAddr = anonymize(&cryptopan, b).parse().expect("address");
+            match (a, b) {
+                (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(a) ^ u32::from(b)).leading_zeros(),
+                (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a) ^ u128::from(b)).leading_zeros(),
+                _ => panic!("the family changed"),
+            }
+        };
+        assert_eq!(shared("10.1.2.3", "10.1.2.200"), 24);
+        assert_eq!(shared("10.1.2.3", "10.1.130.3"), 16);
+        assert_eq!(shared("10.1.2.3", "138.1.2.3"), 0);
+        assert_eq!(shared("2001:db8::1", "2001:db8::2"), 126);
+        assert_eq!(shared("2001:db8:1::1", "2001:db8:8000::1"), 32);
+        assert_eq!(shared("2001:db8::1", "a001:db8::1"), 0);
+    }
+
+    #[test]
+    fn keeps_the_address_family() {
+        let cryptopan = CryptoPan::new(&KEY).expect("32-byte key");
+        let v4 = cryptopan.address("192.0.2.1".parse().expect("address"));
+        let v6 = cryptopan.address("2001:db8::1".parse().expect("address"));
+        assert!(v4.is_ipv4() && v6.is_ipv6());
+        assert_ne!(v6, "2001:db8::1".parse::<IpAddr>().expect("address"));
+    }
+}
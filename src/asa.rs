@@ -0,0 +1,255 @@
+//! Parser for Cisco ASA syslog messages.
+//!
+//! ASA messages look like
+//!
+//! ```text
+//! Oct 16 09:30:01 asa %ASA-4-106023: Deny tcp src outside:203.0.113.5/51234 dst inside:192.168.1.10/22 by access-group "outside_access_in" [0x0, 0x0]
+//! ```
+//!
+//! The common denial and teardown messages are recognized by their ID and parsed with
+//! one pattern each; all other messages are skipped. The severity of a message becomes
+//! the priority of its event, and the signature is the message ID with its action, e.g.
+//! `ASA-4-106023 deny`.
+
+use crate::input::{syslog_header, Normalized};
+use chrono::NaiveDateTime;
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Marks the start of an ASA message, followed by `<severity>-<id>: <text>`.
+const MARKER: &str = "%ASA-";
+
+/// The parsed messages: their ID, and the pattern of their text with named groups for
+/// the fields of the event.
+const MESSAGES: [(&str, &str); 9] = [
+    (
+        "106001",
+        r"^Inbound (?P<proto>\w+) connection (?P<action>denied) from (?P<src>[^/\s]+)/(?P<sport>\d+) to (?P<dst>[^/\s]+)/(?P<dport>\d+) .*on interface (?P<interface>\S+)",
+    ),
+    (
+        "106006",
+        r"^(?P<action>Deny) (?P<direction>inbound|outbound) (?P<proto>\w+) from (?P<src>[^/\s]+)/(?P<sport>\d+) to (?P<dst>[^/\s]+)/(?P<dport>\d+) on interface (?P<interface>\S+)",
+    ),
+    (
+        "106007",
+        r"^(?P<action>Deny) (?P<direction>inbound|outbound) (?P<proto>\w+) from (?P<src>[^/\s]+)/(?P<sport>\d+) to (?P<dst>[^/\s]+)/(?P<dport>\d+) due to (?P<reason>[^.]+)",
+    ),
+    (
+        "106014",
+        r"^(?P<action>Deny) (?P<direction>inbound|outbound) (?P<proto>icmp) src (?P<interface>[^:\s]+):(?P<src>\S+) dst [^:\s]+:(?P<dst>\S+)(?: \(type (?P<itype>\d+), code (?P<icode>\d+)\))?",
+    ),
+    (
+        "106015",
+        r"^(?P<action>Deny) (?P<proto>\w+) \(no connection\) from (?P<src>[^/\s]+)/(?P<sport>\d+) to (?P<dst>[^/\s]+)/(?P<dport>\d+) flags (?P<reason>.*) on interface (?P<interface>\S+)",
+    ),
+    (
+        "106021",
+        r"^(?P<action>Deny) (?P<proto>\w+) (?P<reason>reverse path check) from (?P<src>\S+) to (?P<dst>\S+) on interface (?P<interface>\S+)",
+    ),
+    (
+        "106023",
+        r#"^(?P<action>Deny) (?P<proto>\w+) src (?P<interface>[^:\s]+):(?P<src>[^/\s]+)(?:/(?P<sport>\d+))? .*dst [^:\s]+:(?P<dst>[^/\s]+)(?:/(?P<dport>\d+))?(?: \(type (?P<itype>\d+), code (?P<icode>\d+)\))? .*by access-group "(?P<reason>[^"]*)""#,
+    ),
+    (
+        "106100",
+        r"^access-list (?P<reason>\S+) (?P<action>permitted|denied|est-allowed) (?P<proto>\w+) (?P<interface>[^/\s]+)/(?P<src>[^(\s]+)\((?P<sport>\d+)\) -> [^/\s]+/(?P<dst>[^(\s]+)\((?P<dport>\d+)\)",
+    ),
+    (
+        "302014|302016",
+        r"^(?P<action>Teardown) (?P<proto>TCP|UDP) connection \d+ for (?P<interface>[^:\s]+):(?P<src>[^/\s]+)/(?P<sport>\d+) .*to [^:\s]+:(?P<dst>[^/\s]+)/(?P<dport>\d+) duration \S+ bytes \d+ ?(?P<reason>[^(]*)",
+    ),
+];
+
+/// Returns the compiled patterns of `MESSAGES`, compiling them on first use.
+fn patterns() -> &'static [(&'static str, Regex)] {
+    static PATTERNS: OnceLock<Vec<(&str, Regex)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        MESSAGES
+            .iter()
+            .map(|&(ids, pattern)| (ids, Regex::new(pattern).expect("Valid ASA pattern")))
+            .collect()
+    })
+}
+
+/// Returns the normalized action of an ASA action word.
+fn action(word: &str) -> String {
+    match word.to_ascii_lowercase().as_str() {
+        "denied" => "deny".to_string(),
+        "permitted" => "permit".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Parses an ASA syslog line, returning `None` if it is not one of the recognized
+/// messages.
+pub fn parse(line: &str, now: NaiveDateTime) -> Option<Normalized> {
+    let (datetime, rest) = syslog_header(line, now)?;
+    let (_, message) = rest.split_once(MARKER)?;
+    let (severity, message) = message.split_once('-')?;
+    let (id, text) = message.split_once(": ")?;
+    let (_, regex) = patterns()
+        .iter()
+        .find(|(ids, _)| ids.split('|').any(|candidate| candidate == id))?;
+    let fields = regex.captures(text.trim_end())?;
+    let field = |name: &str| fields.name(name).map_or_else(String::new, |m| m.as_str().to_string());
+
+    let action = action(&field("action"));
+    let reason = field("reason").trim().to_string();
+    // Message 106015 logs the TCP flags of the denied packet as its reason
+    let tcp_flags = if id == "106015" {
+        reason.clone()
+    } else {
+        String::new()
+    };
+    Some(Normalized {
+        id: id.to_string(),
+        priority: severity.to_string(),
+        signature: format!("ASA-{severity}-{id} {action}"),
+        category: "asa",
+        datetime,
+        proto: field("proto").to_ascii_lowercase(),
+        source: field("src"),
+        src_port: field("sport"),
+        interface: field("interface"),
+        action,
+        direction: field("direction"),
+        reason,
+        destination: field("dst"),
+        dst_port: field("dport"),
+        bytes: String::new(),
+        icmp_type: field("itype"),
+        icmp_code: field("icode"),
+        tcp_flags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn parse_line(line: &str) -> Option<Normalized> {
+        let now = NaiveDate::from_ymd_opt(2026, 10, 16)
+            .and_then(|date| date.and_hms_opt(12, 0, 0))
+            .unwrap_or_default();
+        parse(&format!("Oct 16 09:30:01 asa {line}"), now)
+    }
+
+    #[test]
+    fn supported_messages_are_parsed() -> Result<(), String> {
+        // The message, and its ID, severity, action, protocol, addresses and ports,
+        // interface and reason
+        let cases = [
+            (
+                "%ASA-2-106001: Inbound TCP connection denied from 203.0.113.5/51234 to \
+                 192.168.1.10/22 flags SYN  on interface outside",
+                "106001 2 deny tcp 203.0.113.5/51234 -> 192.168.1.10/22 on outside []",
+            ),
+            (
+                "%ASA-2-106006: Deny inbound UDP from 203.0.113.5/5353 to 192.168.1.10/53 on \
+                 interface outside",
+                "106006 2 deny udp 203.0.113.5/5353 -> 192.168.1.10/53 on outside []",
+            ),
+            (
+                "%ASA-2-106007: Deny inbound UDP from 203.0.113.5/5353 to 192.168.1.10/53 due \
+                 to DNS Query.",
+                "106007 2 deny udp 203.0.113.5/5353 -> 192.168.1.10/53 on  [DNS Query]",
+            ),
+            (
+                "%ASA-3-106014: Deny inbound icmp src outside:203.0.113.5 dst \
+                 inside:192.168.1.10 (type 8, code 0)",
+                "106014 3 deny icmp 203.0.113.5/ -> 192.168.1.10/ on outside []",
+            ),
+            (
+                "%ASA-6-106015: Deny TCP (no connection) from 203.0.113.5/443 to \
+                 192.168.1.10/51234 flags RST  on interface outside",
+                "106015 6 deny tcp 203.0.113.5/443 -> 192.168.1.10/51234 on outside [RST]",
+            ),
+            (
+                "%ASA-1-106021: Deny TCP reverse path check from 10.1.1.1 to 192.168.1.10 on \
+                 interface outside",
+                "106021 1 deny tcp 10.1.1.1/ -> 192.168.1.10/ on outside [reverse path check]",
+            ),
+            (
+                "%ASA-4-106023: Deny tcp src outside:203.0.113.5/51234 dst inside:192.168.1.10/22 \
+                 by access-group \"acl_outside\" [0x0, 0x0]",
+                "106023 4 deny tcp 203.0.113.5/51234 -> 192.168.1.10/22 on outside [acl_outside]",
+            ),
+            (
+                "%ASA-6-106100: access-list acl_outside permitted tcp \
+                 outside/203.0.113.5(51234) -> inside/192.168.1.10(22) hit-cnt 1 first hit \
+                 [0x0, 0x0]",
+                "106100 6 permit tcp 203.0.113.5/51234 -> 192.168.1.10/22 on outside [acl_outside]",
+            ),
+            (
+                "%ASA-6-302014: Teardown TCP connection 12345 for outside:203.0.113.5/51234 to \
+                 inside:192.168.1.10/443 duration 0:00:30 bytes 1024 TCP FINs",
+                "302014 6 teardown tcp 203.0.113.5/51234 -> 192.168.1.10/443 on outside [TCP FINs]",
+            ),
+            (
+                "%ASA-6-302016: Teardown UDP connection 678 for outside:203.0.113.5/5353 to \
+                 inside:192.168.1.10/53 duration 0:02:01 bytes 120",
+                "302016 6 teardown udp 203.0.113.5/5353 -> 192.168.1.10/53 on outside []",
+            ),
+        ];
+        for (line, expected) in cases {
+            let event = parse_line(line).ok_or_else(|| format!("not parsed: {line}"))?;
+            let summary = format!(
+                "{} {} {} {} {}/{} -> {}/{} on {} [{}]",
+                event.id,
+                event.priority,
+                event.action,
+                event.proto,
+                event.source,
+                event.src_port,
+                event.destination,
+                event.dst_port,
+                event.interface,
+                event.reason
+            );
+            assert_eq!(summary, expected);
+            assert_eq!(
+                event.signature,
+                format!("ASA-{}-{} {}", event.priority, event.id, event.action)
+            );
+            assert_eq!(event.datetime.to_string(), "2026-10-16 09:30:01");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn icmp_types_and_tcp_flags_are_kept() -> Result<(), String> {
+        let event = parse_line(
+            "%ASA-4-106023: Deny icmp src outside:203.0.113.5 dst inside:192.168.1.10 (type 8, \
+             code 0) by access-group \"acl_outside\" [0x0, 0x0]",
+        )
+        .ok_or("106023 with ICMP not parsed")?;
+        assert_eq!([&event.icmp_type, &event.icmp_code], ["8", "0"]);
+        assert_eq!([&event.src_port, &event.dst_port], ["", ""]);
+        let event = parse_line(
+            "%ASA-6-106015: Deny TCP (no connection) from 203.0.113.5/443 to \
+             192.168.1.10/51234 flags FIN ACK  on interface outside",
+        )
+        .ok_or("106015 not parsed")?;
+        assert_eq!(event.tcp_flags, "FIN ACK");
+        Ok(())
+    }
+
+    #[test]
+    fn other_messages_are_skipped() {
+        for line in [
+            // Unknown IDs
+            "%ASA-6-302013: Built inbound TCP connection 1 for outside:203.0.113.5/51234 \
+             (203.0.113.5/51234) to inside:192.168.1.10/22 (192.168.1.10/22)",
+            "%ASA-4-999999: Deny tcp src outside:203.0.113.5/51234 dst inside:192.168.1.10/22 \
+             by access-group \"acl_outside\"",
+            // A known ID with text that does not match its pattern
+            "%ASA-4-106023: Something else entirely",
+            "%FTD-4-106023: Deny tcp src outside:203.0.113.5/51234 dst inside:192.168.1.10/22 \
+             by access-group \"acl_outside\"",
+            "sshd[123]: Accepted publickey for admin",
+        ] {
+            assert!(parse_line(line).is_none(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Reading the log files on tokio.
+//!
+//! With the `async-io` feature, each log file is read by a task on a shared tokio
+//! runtime, which reads the file in chunks ahead of the parser and sends them over a
+//! bounded channel to the aggregation worker parsing the file. The reading of a file
+//! then overlaps with its parsing and hashing, which pays off on network-backed volumes
+//! such as NFS, where every read waits on the server. The bound keeps at most a few
+//! chunks of each file in memory when the parser falls behind.
+//!
+//! Parsing is CPU-bound and stays on the worker threads of `aggregate`, so the default
+//! build, which reads the files on those threads directly, does not need a runtime.
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::mpsc::{self, Receiver};
+
+/// Size of the chunks a file is read in.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Number of chunks read ahead of the parser.
+const CHUNKS_AHEAD: usize = 4;
+
+/// Returns the runtime the files are read on, starting it on first use.
+///
+/// # Errors
+/// Returns an error if the runtime cannot be started.
+fn runtime() -> io::Result<&'static Runtime> {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = Builder::new_multi_thread()
+        .thread_name("dashboard_aggregator-reader")
+        .build()?;
+    // Another worker may have started one first, in which case this one is dropped
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+/// A file read by a task on the runtime.
+///
+/// # Fields
+/// - `chunks`: The chunks read by the task, which ends once the file does or fails.
+/// - `chunk`: The chunk being consumed, from `position` on.
+pub struct Reader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Reader {
+    /// Starts reading the file at `path`. Failing to open the file is reported by the
+    /// first read.
+    ///
+    /// # Errors
+    /// Returns an error if the runtime cannot be started.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (sender, chunks) = mpsc::channel(CHUNKS_AHEAD);
+        let path = path.to_path_buf();
+        runtime()?.spawn(async move {
+            let mut file = match File::open(&path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    return;
+                }
+            };
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let result = file.read(&mut chunk).await.map(|length| {
+                    chunk.truncate(length);
+                    chunk
+                });
+                let last = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+                // The reader is dropped once it has failed or the file is done
+                if sender.send(result).await.is_err() || last {
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                // The file has ended, and the task with it
+                None => return Ok(0),
+            }
+            self.position = 0;
+        }
+        let length = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reads_a_file_in_chunks() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("da-async-io-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("events.csv");
+        let contents: Vec<u8> = (0..=u8::MAX).cycle().take(CHUNK_SIZE * 3 + 17).collect();
+        fs::write(&path, &contents)?;
+
+        let mut read = Vec::new();
+        Reader::open(&path)?.read_to_end(&mut read)?;
+        assert_eq!(read, contents);
+        let missing = Reader::open(&dir.join("missing.csv"))?.read_to_end(&mut read);
+        assert_eq!(missing.map_err(|e| e.kind()), Err(io::ErrorKind::NotFound));
+        fs::remove_dir_all(&dir)
+    }
+}
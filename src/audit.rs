@@ -0,0 +1,90 @@
+//! Audit log of runs.
+//!
+//! With `--audit-log <file>`, every run appends one JSON object per line to `<file>`,
+//! recording who ran the tool when, with which arguments, which log files it read, and
+//! the checksums of the outputs it wrote, e.g.:
+//!
+//! ```json
+//! {"timestamp":"2026-10-16T09:30:00+02:00","user":"alice","arguments":["/var/log/fwd/db","15"],"files":["/var/log/fwd/db/fwddmp.log.tmp.1"],"failed_files":0,"outputs":[{"path":"events.json","sha256":"9f86d0..."}]}
+//! ```
+
+use crate::cli::Options;
+use crate::manifest::{self, Entry};
+use chrono::Local;
+use serde::Serialize;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// One line of the audit log.
+///
+/// # Fields
+/// - `timestamp`: When the run finished, in RFC 3339 format with the local offset.
+/// - `user`: The user that ran the tool, from `USER` or `USERNAME`, if known.
+/// - `arguments`: The command line arguments, excluding the program name.
+/// - `files`: The log files that matched and were read.
+/// - `failed_files`: How many of `files` could not be read.
+/// - `section_files`: The section files written by `--split-output`.
+/// - `outputs`: The output files and their SHA-256 checksums.
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    user: Option<String>,
+    arguments: &'a [String],
+    files: Vec<String>,
+    failed_files: usize,
+    outputs: Vec<Entry>,
+}
+
+/// Appends a record of the run to the audit log, if `--audit-log` is set.
+///
+/// # Arguments
+/// - `options`: The parsed command line.
+/// - `arguments`: The command line arguments, excluding the program name.
+/// - `files`: The log files that matched.
+/// - `failed_files`: How many of `files` could not be read.
+///
+/// # Errors
+/// Returns an error if an output file cannot be checksummed or the audit log cannot be
+/// written.
+pub fn append(
+    options: &Options,
+    arguments: &[String],
+    files: &[PathBuf],
+    failed_files: usize,
+    section_files: &[PathBuf],
+) -> io::Result<()> {
+    let Some(path) = &options.audit_log else {
+        return Ok(());
+    };
+    let record = Record {
+        timestamp: Local::now().to_rfc3339(),
+        user: env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .ok()
+            .filter(|user| !user.is_empty()),
+        arguments,
+        files: files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect(),
+        failed_files,
+        outputs: manifest::checksums(options, section_files, Path::new(""))?,
+    };
+
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    // A single write keeps lines from concurrent runs from interleaving
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to write audit log {}: {e}", path.display()),
+            )
+        })
+}
@@ -0,0 +1,367 @@
+//! Learned baselines of normal traffic.
+//!
+//! With `--baseline <file>`, every run learns from the events it counted, and keeps
+//! what it learned in `<file>`:
+//!
+//! - The value of each time series, such as `AWARE Threats` or `Priority Trend/High`, in
+//!   every AM/PM bucket the run covered completely. Buckets without events are learned
+//!   as zero, and a later run that covers the same buckets replaces their values, so
+//!   the overlapping periods of consecutive runs are not counted twice.
+//! - The frequent-source profile of the day: the events per day of the top threat
+//!   sources of the last run of that day.
+//!
+//! Only the last `RETENTION_DAYS` days are kept. Traffic follows the week, so the
+//! `Z-Score` variant of a series (see `series_variants`) compares a bucket with the
+//! learned buckets of the same weekday and period, e.g. a Monday morning with the
+//! previous Monday mornings, once there are `MIN_WEEKS` of them. Until then, it is
+//! compared with all learned buckets of the series once there are `MIN_BUCKETS` of
+//! them, and before that with the buckets of the run.
+//!
+//! `baseline export` writes the learned baseline to a portable file, together with the
+//! mean and standard deviation of each series and the profile of each source, and
+//! `baseline import` adds the buckets and days of such a file that the baseline has not
+//! learned itself, so a rebuilt machine does not have to learn normal traffic again.
+
+use crate::metric::{time_bucket, Addresses};
+use crate::AggregatedData;
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// Version of the baseline file format, which is raised when it changes incompatibly.
+const VERSION: u32 = 1;
+
+/// Number of days learned buckets and source profiles are kept for.
+const RETENTION_DAYS: u64 = 56;
+
+/// Number of learned buckets a series needs before z-scores are computed against them.
+const MIN_BUCKETS: usize = 4;
+
+/// Number of learned buckets of the same weekday and period a series needs before
+/// z-scores are computed against them rather than against all learned buckets.
+const MIN_WEEKS: usize = 3;
+
+/// Number of top threat sources in the profile of a day.
+const PROFILE_SOURCES: usize = 100;
+
+/// Number of hours in a time bucket.
+const BUCKET_HOURS: i64 = 12;
+
+/// The mean and standard deviation of a series.
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub mean: f64,
+    pub deviation: f64,
+}
+
+impl Stats {
+    /// Returns the mean and population standard deviation of `values`, padded with
+    /// zeros to `buckets` values.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(values: &[f64], buckets: usize) -> Self {
+        let buckets = buckets.max(values.len()).max(1) as f64;
+        let mean = values.iter().sum::<f64>() / buckets;
+        let squares = values.iter().map(|value| value * value).sum::<f64>() / buckets;
+        Self {
+            mean,
+            deviation: (squares - mean * mean).max(0.0).sqrt(),
+        }
+    }
+
+    /// Returns how many standard deviations `value` is from the mean. A standard
+    /// deviation of zero is taken as one, so a bucket that departs from a series that
+    /// never varied, such as the same quiet weekend morning every week, still stands
+    /// out.
+    #[must_use]
+    pub fn z_score(self, value: f64) -> f64 {
+        let deviation = if self.deviation > 0.0 {
+            self.deviation
+        } else {
+            1.0
+        };
+        (value - self.mean) / deviation
+    }
+}
+
+/// The layout of a baseline file; the other fields of an exported baseline are
+/// ignored, as they are computed from these.
+#[derive(Deserialize)]
+struct File {
+    version: u32,
+    #[serde(default)]
+    series: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(default)]
+    sources: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+/// What was learned about normal traffic.
+///
+/// # Fields
+/// - `series`: The learned values of each time series, keyed by series and then by
+///   time bucket.
+/// - `sources`: The events per day of the top threat sources, keyed by date and then by
+///   source.
+#[derive(Default)]
+pub struct Baseline {
+    series: BTreeMap<String, BTreeMap<String, u64>>,
+    sources: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl Baseline {
+    /// Parses a baseline file, or an exported baseline.
+    ///
+    /// # Errors
+    /// Returns a message if the file is not valid JSON of a baseline, or of another
+    /// version of the format.
+    pub fn from_json(contents: &str) -> Result<Self, String> {
+        let file: File = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+        if file.version != VERSION {
+            return Err(format!(
+                "version {} is not supported, expected {VERSION}",
+                file.version
+            ));
+        }
+        Ok(Self {
+            series: file.series,
+            sources: file.sources,
+        })
+    }
+
+    /// Returns the baseline file of the baseline.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        json!({ "version": VERSION, "series": self.series, "sources": self.sources })
+    }
+
+    /// Returns the portable export of the baseline: its baseline file, with the mean and
+    /// standard deviation of every series over its learned `buckets` under `baselines`,
+    /// and those of every weekday and period, such as `Mon AM`, under its `weekly`, and
+    /// the number of `days` every source was among the top sources and its mean
+    /// `events_per_day` on those days under `profiles`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn export_json(&self) -> Value {
+        let baselines: serde_json::Map<_, _> = self
+            .series
+            .iter()
+            .map(|(key, buckets)| {
+                let mut weekly: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+                for (bucket, &value) in buckets {
+                    if let Some(slot) = slot(bucket) {
+                        weekly.entry(slot).or_default().push(value as f64);
+                    }
+                }
+                let values: Vec<_> = buckets.values().map(|&value| value as f64).collect();
+                let mut baseline = summary(&values);
+                baseline["weekly"] = weekly
+                    .into_iter()
+                    .map(|(slot, values)| (slot, summary(&values)))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into();
+                (key.clone(), baseline)
+            })
+            .collect();
+        let mut days: HashMap<&str, Vec<f64>> = HashMap::new();
+        for profile in self.sources.values() {
+            for (source, &rate) in profile {
+                days.entry(source).or_default().push(rate);
+            }
+        }
+        let profiles: serde_json::Map<_, _> = days
+            .into_iter()
+            .map(|(source, rates)| {
+                let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+                let profile = json!({ "days": rates.len(), "events_per_day": mean });
+                (source.to_string(), profile)
+            })
+            .collect();
+        let mut export = self.to_json();
+        export["baselines"] = baselines.into();
+        export["profiles"] = profiles.into();
+        export
+    }
+
+    /// Reads the baseline file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not valid.
+    #[cfg(feature = "fs")]
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read baseline {}: {e}", path.display()),
+            )
+        })?;
+        Self::from_json(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid baseline {}: {e}", path.display()),
+            )
+        })
+    }
+
+    /// Reads the baseline file at `path` like `read`, or returns an empty baseline if
+    /// there is none yet.
+    ///
+    /// # Errors
+    /// Returns an error if the file exists but cannot be read or is not valid.
+    #[cfg(feature = "fs")]
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            Self::read(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Writes the baseline file to `path`. The file is replaced in one step, so a run
+    /// killed while saving leaves either the old or the new baseline.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(&self.to_json())?)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Learns the time series and top threat sources of `data`, which holds the events
+    /// from `start` to `end`, and forgets what is older than the retention.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn learn(&mut self, data: &AggregatedData, start: NaiveDateTime, end: NaiveDateTime) {
+        let buckets = complete_buckets(start, end);
+        for (key, entries) in data.series() {
+            let values: HashMap<_, _> = entries.into_iter().collect();
+            let learned = self.series.entry(key).or_default();
+            for bucket in &buckets {
+                learned.insert(bucket.clone(), values.get(bucket).copied().unwrap_or(0));
+            }
+        }
+        let days = (end - start).num_seconds() as f64 / TimeDelta::days(1).num_seconds() as f64;
+        if let Some(sources) = data.metric::<Addresses>("Threat Sources") {
+            if days > 0.0 {
+                let profile = sources
+                    .top(PROFILE_SOURCES)
+                    .into_iter()
+                    .map(|(source, count)| (source, f64::from(count) / days))
+                    .collect();
+                self.sources.insert(end.date().to_string(), profile);
+            }
+        }
+        self.forget(end);
+    }
+
+    /// Adds the buckets and days of `other` that have not been learned, and forgets
+    /// what is older than the retention before `now`.
+    ///
+    /// # Returns
+    /// The number of buckets and days added.
+    pub fn merge(&mut self, other: Self, now: NaiveDateTime) -> usize {
+        let mut added = 0;
+        for (key, buckets) in other.series {
+            let learned = self.series.entry(key).or_default();
+            for (bucket, value) in buckets {
+                if let Entry::Vacant(entry) = learned.entry(bucket) {
+                    entry.insert(value);
+                    added += 1;
+                }
+            }
+        }
+        for (date, profile) in other.sources {
+            if let Entry::Vacant(entry) = self.sources.entry(date) {
+                entry.insert(profile);
+                added += 1;
+            }
+        }
+        self.forget(now);
+        added
+    }
+
+    /// Returns the mean and standard deviation of the series `key` to compare the value
+    /// of `bucket` with: those of its learned buckets of the same weekday and period if
+    /// there are `MIN_WEEKS` of them, or else of all its learned buckets if there are
+    /// `MIN_BUCKETS` of them, or else `None`. The value of `bucket` itself is left out.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn stats(&self, key: &str, bucket: &str) -> Option<Stats> {
+        let learned: Vec<(&String, f64)> = self
+            .series
+            .get(key)?
+            .iter()
+            .filter(|(learned, _)| learned.as_str() != bucket)
+            .map(|(learned, &value)| (learned, value as f64))
+            .collect();
+        let wanted = slot(bucket);
+        let weekly: Vec<f64> = learned
+            .iter()
+            .filter(|(learned, _)| wanted.is_some() && slot(learned) == wanted)
+            .map(|&(_, value)| value)
+            .collect();
+        if weekly.len() >= MIN_WEEKS {
+            return Some(Stats::new(&weekly, weekly.len()));
+        }
+        let values: Vec<f64> = learned.iter().map(|&(_, value)| value).collect();
+        (values.len() >= MIN_BUCKETS).then(|| Stats::new(&values, values.len()))
+    }
+
+    /// Returns the number of learned buckets over all series.
+    #[must_use]
+    pub fn buckets(&self) -> usize {
+        self.series.values().map(BTreeMap::len).sum()
+    }
+
+    /// Forgets the buckets and days from before the retention, counting back from `now`.
+    fn forget(&mut self, now: NaiveDateTime) {
+        let Some(oldest) = now.date().checked_sub_days(Days::new(RETENTION_DAYS)) else {
+            return;
+        };
+        // Buckets start with their date, so they sort by it
+        let oldest = oldest.to_string();
+        for buckets in self.series.values_mut() {
+            buckets.retain(|bucket, _| bucket.as_str() >= oldest.as_str());
+        }
+        self.series.retain(|_, buckets| !buckets.is_empty());
+        self.sources.retain(|date, _| date.as_str() >= oldest.as_str());
+    }
+}
+
+/// Returns the weekday and period of a time bucket, such as `Mon AM` for
+/// `2024-04-01 AM`, or `None` if it is not a time bucket.
+fn slot(bucket: &str) -> Option<String> {
+    let date = NaiveDate::parse_from_str(bucket.get(..10)?, "%Y-%m-%d").ok()?;
+    let period = bucket.get(10..)?.trim();
+    Some(format!("{} {period}", date.weekday()))
+}
+
+/// Returns the number of `values`, and their mean and standard deviation, for an
+/// export.
+fn summary(values: &[f64]) -> Value {
+    let stats = Stats::new(values, values.len());
+    json!({ "buckets": values.len(), "mean": stats.mean, "deviation": stats.deviation })
+}
+
+/// Returns the time buckets that lie completely between `start` and `end`.
+fn complete_buckets(start: NaiveDateTime, end: NaiveDateTime) -> Vec<String> {
+    let length = TimeDelta::hours(BUCKET_HOURS);
+    let mut bucket = start.date().and_time(NaiveTime::MIN);
+    while bucket < start {
+        bucket += length;
+    }
+    let mut buckets = Vec::new();
+    while bucket + length <= end {
+        buckets.push(time_bucket(&bucket));
+        bucket += length;
+    }
+    buckets
+}
@@ -0,0 +1,196 @@
+//! Throughput benchmark.
+//!
+//! Runs the ingestion pipeline over a single file several times and reports how long
+//! each phase takes, so performance regressions between releases can be measured on
+//! real exports. The phases run one after another over the whole file rather than
+//! interleaved per record, which keeps timer overhead out of the measurements.
+//!
+//! With `--mmap`, the file is memory-mapped instead of read, like a run with `--mmap`.
+//!
+//! With `--compress-output <format>`, the file is also compressed with the compression of
+//! the outputs, whose throughput is reported separately, as it only affects the time
+//! taken to write the outputs.
+
+use crate::cli::{Compression, Options};
+use crate::mmap;
+use chrono::NaiveDateTime;
+use csv::StringRecord;
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::intern::Interner;
+use dashboard_aggregator::metric::Event;
+use dashboard_aggregator::{
+    csv_reader, AggregatedData, CATEGORY_COLUMN, DATETIME_COLUMN, DATETIME_FORMAT,
+    DESTINATION_COLUMN, PRIORITY_COLUMN, SIGNATURE_COLUMN, SOURCE_COLUMN,
+};
+use flate2::write::GzEncoder;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Accumulated time spent in each phase across all iterations.
+///
+/// # Fields
+/// - `io`: Reading the file into memory, or mapping it with `--mmap`.
+/// - `csv`: Splitting the contents into records, including normalizing syslog formats.
+/// - `datetime`: Parsing the event timestamps.
+/// - `hashing`: Passing the events to the metrics, e.g. counting priorities and addresses.
+/// - `compression`: Compressing the file with `--compress-output`, if given.
+#[derive(Default)]
+struct Timings {
+    io: Duration,
+    csv: Duration,
+    datetime: Duration,
+    hashing: Duration,
+    compression: Option<Duration>,
+}
+
+/// Benchmarks the file at `path` and prints the results.
+///
+/// # Arguments
+/// - `path`: The log file to parse.
+/// - `options`: The options of the run, giving the encoding and format of the log file.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `iterations`: How many times to parse the file.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or is not valid CSV.
+pub fn run(path: &Path, options: &Options, config: &Config, iterations: u32) -> io::Result<()> {
+    let mut timings = Timings::default();
+    let mut bytes = 0;
+    let mut records = 0;
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let (mapped, read);
+        let contents: &[u8] = if options.mmap {
+            mapped = mmap::map(path)?;
+            &mapped
+        } else {
+            read = fs::read(path)?;
+            &read
+        };
+        timings.io += start.elapsed();
+
+        let start = Instant::now();
+        let parsed = csv_reader(
+            options
+                .format
+                .reader(options.encoding.reader(contents)),
+            config.delimiter_byte(),
+        )
+        .records()
+        .collect::<Result<Vec<StringRecord>, _>>()?;
+        timings.csv += start.elapsed();
+
+        let start = Instant::now();
+        let datetimes: Vec<_> = parsed
+            .iter()
+            .map(|record| {
+                NaiveDateTime::parse_from_str(
+                    record.get(DATETIME_COLUMN).unwrap_or_default(),
+                    DATETIME_FORMAT,
+                )
+                .ok()
+            })
+            .collect();
+        timings.datetime += start.elapsed();
+
+        let start = Instant::now();
+        let mut data = AggregatedData::new(config);
+        let interner = RefCell::new(Interner::default());
+        for (record, datetime) in parsed.iter().zip(&datetimes) {
+            if let Some(datetime) = datetime {
+                data.observe(&Event::new(
+                    *datetime,
+                    record.get(PRIORITY_COLUMN).unwrap_or_default(),
+                    record.get(SIGNATURE_COLUMN).unwrap_or_default(),
+                    record.get(CATEGORY_COLUMN).unwrap_or_default(),
+                    record.get(SOURCE_COLUMN).unwrap_or_default(),
+                    record.get(DESTINATION_COLUMN).unwrap_or_default(),
+                    &interner,
+                ));
+            }
+        }
+        timings.hashing += start.elapsed();
+
+        if let Some(compression) = options.compress {
+            let start = Instant::now();
+            match compression {
+                Compression::Gzip => {
+                    let mut gzip = GzEncoder::new(io::sink(), flate2::Compression::default());
+                    gzip.write_all(contents)?;
+                    gzip.finish()?;
+                }
+                Compression::Zstd => zstd::stream::copy_encode(contents, io::sink(), 0)?,
+            }
+            *timings.compression.get_or_insert_with(Duration::default) += start.elapsed();
+        }
+
+        bytes += contents.len();
+        records += parsed.len();
+    }
+
+    print!("{}", report(path, iterations, bytes, records, &timings));
+    Ok(())
+}
+
+/// Formats the benchmark results as a table of phases followed by overall throughput.
+///
+/// # Arguments
+/// - `path`: The benchmarked file.
+/// - `iterations`: How many times the file was parsed.
+/// - `bytes`: The total number of bytes read across all iterations.
+/// - `records`: The total number of records parsed across all iterations.
+/// - `timings`: The accumulated phase timings.
+#[allow(clippy::cast_precision_loss)]
+fn report(path: &Path, iterations: u32, bytes: usize, records: usize, timings: &Timings) -> String {
+    let total = timings.io + timings.csv + timings.datetime + timings.hashing;
+    let seconds = total.as_secs_f64().max(f64::EPSILON);
+    let passes = iterations as usize;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Benchmarked {} ({iterations} iterations of {} records, {} bytes)\n",
+        path.display(),
+        records / passes,
+        bytes / passes,
+    );
+    let _ = writeln!(
+        out,
+        "{:<16}{:>14}{:>16}{:>8}",
+        "Phase", "Total", "Per iteration", "Share"
+    );
+    for (phase, elapsed) in [
+        ("IO", timings.io),
+        ("CSV parse", timings.csv),
+        ("Datetime parse", timings.datetime),
+        ("Hashing", timings.hashing),
+        ("Total", total),
+    ] {
+        let _ = writeln!(
+            out,
+            "{phase:<16}{:>14}{:>16}{:>7.1}%",
+            format!("{elapsed:.3?}"),
+            format!("{:.3?}", elapsed / iterations),
+            elapsed.as_secs_f64() / seconds * 100.0,
+        );
+    }
+    let _ = writeln!(
+        out,
+        "\nThroughput: {:.0} records/sec, {:.1} MB/sec",
+        records as f64 / seconds,
+        bytes as f64 / seconds / 1_000_000.0,
+    );
+    if let Some(compression) = timings.compression {
+        let _ = writeln!(
+            out,
+            "Output compression: {:.1} MB/sec",
+            bytes as f64 / compression.as_secs_f64().max(f64::EPSILON) / 1_000_000.0,
+        );
+    }
+    out
+}
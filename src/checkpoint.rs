@@ -0,0 +1,154 @@
+//! Checkpoints for resuming interrupted runs.
+//!
+//! With `--checkpoint`, the aggregates of every log file are saved to the `checkpoint`
+//! directory in the output directory as soon as the file has been read. If the process
+//! is killed before the outputs are written, for example by a nightly reboot, the next
+//! run with the same arguments and configuration merges the saved aggregates of the
+//! files that have not changed since and only reads the others. The directory is
+//! removed once a run has written its outputs.
+
+use crate::cli::Options;
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::{log, AggregatedData};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the checkpoint directory in the output directory.
+const DIRECTORY: &str = "checkpoint";
+
+/// Name of the file identifying the run that the checkpoint belongs to.
+const RUN_FILE: &str = "run";
+
+/// The checkpoint directory of a run.
+///
+/// # Fields
+/// - `directory`: Where the aggregates of the files are saved.
+pub struct Checkpoint {
+    directory: PathBuf,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint directory of the run described by `options`. A checkpoint
+    /// left by a run with other arguments or another configuration is discarded.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration file or the directory cannot be read, or
+    /// the directory cannot be created.
+    pub fn open(options: &Options) -> io::Result<Self> {
+        let directory = options.output_dir_path(DIRECTORY);
+        let key = run_key(options)?;
+        let run_file = directory.join(RUN_FILE);
+        if fs::read_to_string(&run_file).ok().as_deref() != Some(key.as_str()) {
+            remove_dir(&directory)?;
+            fs::create_dir_all(&directory)?;
+            fs::write(&run_file, key)?;
+        }
+        Ok(Self { directory })
+    }
+
+    /// Returns the saved aggregates of those of `files` that have not changed since
+    /// they were saved, built from `config`. Saved aggregates that cannot be restored
+    /// are reported and their files read again.
+    pub fn resume(&self, files: &[PathBuf], config: &Config) -> Vec<(PathBuf, AggregatedData)> {
+        files
+            .iter()
+            .filter_map(|path| {
+                let saved: Value =
+                    serde_json::from_slice(&fs::read(self.snapshot_path(path)).ok()?).ok()?;
+                if saved.get("identity") != Some(&identity(path)?) {
+                    return None;
+                }
+                let mut data = AggregatedData::new(config);
+                if let Err(e) = data.merge_state(saved.get("state").unwrap_or(&Value::Null)) {
+                    log::warn(format_args!(
+                        "reading {} again, its checkpoint is invalid: {e}",
+                        path.display()
+                    ));
+                    return None;
+                }
+                Some((path.clone(), data))
+            })
+            .collect()
+    }
+
+    /// Saves the aggregates `data` of the file at `path`. The file is replaced in one
+    /// step, so a run killed while saving leaves either the old or the new aggregates.
+    ///
+    /// # Errors
+    /// Returns an error if the aggregates cannot be written.
+    pub fn save(&self, path: &Path, data: &AggregatedData) -> io::Result<()> {
+        let Some(identity) = identity(path) else {
+            return Ok(());
+        };
+        let snapshot = self.snapshot_path(path);
+        let partial = snapshot.with_extension("partial");
+        let saved = json!({ "identity": identity, "state": data.state() });
+        fs::write(&partial, serde_json::to_vec(&saved)?)?;
+        fs::rename(&partial, &snapshot)
+    }
+
+    /// Returns the path the aggregates of the file at `path` are saved to.
+    fn snapshot_path(&self, path: &Path) -> PathBuf {
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        self.directory.join(format!("{name}.json"))
+    }
+}
+
+/// Removes the checkpoint directory of a run that has written its outputs.
+///
+/// # Errors
+/// Returns an error if the directory exists but cannot be removed.
+pub fn remove(options: &Options) -> io::Result<()> {
+    remove_dir(&options.output_dir_path(DIRECTORY))
+}
+
+/// Removes `directory` and its contents, if it exists.
+fn remove_dir(directory: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(directory) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Returns a key identifying the run: a hash of its arguments, including those taken
+/// from the environment, and of the contents of its configuration file.
+///
+/// # Errors
+/// Returns an error if the configuration file cannot be read.
+fn run_key(options: &Options) -> io::Result<String> {
+    let mut hash = Sha256::new();
+    for arg in env::args().skip(1) {
+        hash.update(arg.as_bytes());
+        hash.update([0]);
+    }
+    let resolved = format!(
+        "{}\0{}\0{}",
+        options.log_path.display(),
+        options.days_back,
+        options.format
+    );
+    hash.update(resolved.as_bytes());
+    if let Some(config) = &options.config {
+        hash.update(fs::read(config)?);
+    }
+    Ok(hex::encode(hash.finalize()))
+}
+
+/// Returns the size and modification time of the file at `path`, which tell whether it
+/// changed since its aggregates were saved, or `None` if they cannot be read.
+fn identity(path: &Path) -> Option<Value> {
+    let meta = fs::metadata(path).ok()?;
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(json!({
+        "size": meta.len(),
+        "modified": u64::try_from(modified.as_nanos()).unwrap_or(u64::MAX),
+    }))
+}
@@ -0,0 +1,86 @@
+//! `NetFlow`/IPFIX collector mode.
+//!
+//! `netflow-listen` receives `NetFlow` v5, v9 and IPFIX export packets on a UDP socket and
+//! turns each flow into an event (see `dashboard_aggregator::netflow`). Like `--follow`,
+//! the events are merged into in-memory aggregates, and the output files are rewritten
+//! every few seconds while flows arrive. Records are only ever added, so restart the
+//! process to drop flows that have fallen out of the `days_back` window.
+
+use crate::anonymize::Anonymizer;
+use crate::cli::Options;
+use crate::systemd;
+use crate::{cutoff, follow, manifest, write_json, write_reports};
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::input::NORMALIZED_HEADER;
+use dashboard_aggregator::netflow::Decoder;
+use dashboard_aggregator::{aggregate_reader, log, AggregatedData};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How often the received flows are aggregated and the outputs rewritten.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The largest possible UDP payload.
+const MAX_PACKET: usize = 65_535;
+
+/// Collects flows sent to `address` until the process is interrupted.
+///
+/// # Errors
+/// Returns an error if the socket cannot be bound or read, or the outputs cannot be
+/// written. Packets that cannot be decoded are reported as warnings instead.
+pub fn run(options: &Options, config: &Config, address: SocketAddr) -> io::Result<()> {
+    let anonymizer = Anonymizer::load(options)?;
+    let socket = UdpSocket::bind(address)?;
+    socket.set_read_timeout(Some(FLUSH_INTERVAL))?;
+    let listening = format!("Listening for NetFlow/IPFIX on {}", socket.local_addr()?);
+    log::info(&listening);
+    systemd::ready(&listening);
+
+    let mut header = NORMALIZED_HEADER.join(",").into_bytes();
+    header.push(b'\n');
+    let mut csv = header.clone();
+    let mut decoder = Decoder::default();
+    let mut data = AggregatedData::new(config);
+    let mut packet = vec![0; MAX_PACKET];
+    let mut last_flush = Instant::now();
+
+    loop {
+        systemd::watchdog();
+        match socket.recv_from(&mut packet) {
+            Ok((length, exporter)) => {
+                if let Err(e) = decoder.decode(exporter.ip(), &packet[..length], &mut csv) {
+                    log::warn(format_args!("ignoring packet from {exporter}: {e}"));
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e),
+        }
+
+        if last_flush.elapsed() < FLUSH_INTERVAL {
+            continue;
+        }
+        last_flush = Instant::now();
+        if csv.len() > header.len() {
+            let mut chunk = aggregate_reader(
+                io::Cursor::new(&csv),
+                cutoff(options.days_back),
+                &options.filter,
+                config,
+                options.max_memory,
+            )?;
+            csv.truncate(header.len());
+            if let Some(anonymizer) = &anonymizer {
+                anonymizer.apply(&mut chunk);
+            }
+            follow::merge(&mut data, &chunk, options);
+            let section_files = write_json(options, &data, Vec::new(), 0, 0)?;
+            write_reports(options, config, &data, 0, false)?;
+            manifest::write(options, &section_files)?;
+        }
+    }
+}
@@ -0,0 +1,962 @@
+//! Optional JSON configuration file.
+//!
+//! Every setting has a default matching the tool's original behavior, so the file
+//! only needs to contain what a deployment wants to change, e.g.:
+//!
+//! ```json
+//! {
+//!   "priorities": {
+//!     "values": ["1", "2", "3", "4"],
+//!     "labels": { "1": "Critical", "2": "High", "3": "Medium", "4": "Low" }
+//!   },
+//!   "metrics": ["priorities", "threat_sources", "aware_threats"],
+//!   "normalize": { "strip_port": true, "unknown": ["", "unknown"] },
+//!   "reputation": { "provider": "abuseipdb", "api_key": "...", "top": 20 }
+//! }
+//! ```
+
+use crate::baseline::Baseline;
+use crate::derived::DerivedConfig;
+use crate::domain::SuffixList;
+use crate::holiday::Holidays;
+use crate::metric;
+use crate::normalize::NormalizeConfig;
+use crate::oui::VendorTable;
+#[cfg(feature = "wasm")]
+use crate::plugin::Plugin;
+#[cfg(feature = "scripting")]
+use crate::script::Script;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+    Weekday,
+};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "fs")]
+use std::{fs, io};
+
+/// Top-level configuration.
+///
+/// # Fields
+/// - `priorities`: The expected priority values and their display labels.
+/// - `metrics`: The metrics to compute, by name (see `metric::BUILTIN`). Defaults to
+///   `metric::DEFAULT`.
+/// - `dashboard`: The sections of `events.json`, in the order they are written and with
+///   their titles, which sets `metrics` to the metrics of the sections; the sections of
+///   `metrics` under their default titles, in sorted order, if `None`.
+/// - `reputation`: Settings for looking up the reputation of the top threat sources;
+///   no lookups are made if `None`.
+/// - `whois`: Settings for looking up the owners of the top threat sources over RDAP;
+///   no lookups are made if `None`.
+/// - `normalize`: How the key fields of the records are cleaned before counting; they
+///   are counted as they are if `None`.
+/// - `empty_fields`: What happens to records with an empty priority, source or
+///   destination.
+/// - `delimiter`: The field delimiter of the CSV exports, an ASCII character. Defaults
+///   to `,`.
+/// - `filename_pattern`: A regex matched against the names of the log files, whose named
+///   groups, such as the device that wrote the file, become grouping dimensions; none
+///   if `None`.
+/// - `derived`: Counts and formulas computed into the `Derived Metrics` section; none
+///   if `None`.
+/// - `smoothing_window`: The number of time buckets averaged into the `Smoothed` series
+///   of the time series sections; they are not smoothed if `None`.
+/// - `series_variants`: The normalized variants of the time series sections that are
+///   added next to their counts; none if empty.
+/// - `session_gap_minutes`: The longest pause between the events of one source that the
+///   `sessions` metric counts as the same session. Defaults to 30.
+/// - `expected_countries`: The ISO 3166-1 alpha-2 codes of the countries traffic is
+///   expected from; with `--geoip`, events from other countries are broken down in a
+///   `Country Policy` section, which is left out if empty.
+/// - `columns`: The names of the header columns holding optional fields, such as the
+///   hostname, which are only read when mapped.
+/// - `public_suffix_list`: A copy of the public suffix list the `domains` metric rolls
+///   hostnames up with, read by `load`; a built-in list of common suffixes if `None`.
+/// - `suffixes`: The public suffix rules, read from `public_suffix_list`.
+/// - `oui_file`: A copy of the IEEE OUI registry or Wireshark's `manuf` file the
+///   `source_macs` metric looks up MAC vendors in, read by `load`; a built-in table of
+///   common vendors if `None`.
+/// - `vendors`: The MAC vendors, read from `oui_file`.
+/// - `web_targets`: The limits on the hosts and URL paths the `web_targets` metric
+///   counts.
+/// - `windows`: The windows of the most recent events that are also aggregated
+///   separately, set from `--windows`; none if empty.
+/// - `cap_per_source`: The most events the `threat_sources` metric counts for a single
+///   source, set from `--cap-per-source`; uncapped if `None`.
+/// - `baseline`: The learned baseline the z-scores of the time series are computed
+///   against, read from `--baseline` and updated by every run; none if `None`.
+/// - `business_hours`: The business hours the `business_hours` metric splits the events
+///   by.
+/// - `report_format`: How numbers and dates are written in the Excel report, the
+///   rendered template and the terminal dashboard.
+/// - `plugins`: The WebAssembly metric plugins, each computed into its own section;
+///   none if empty. They need the `wasm` feature.
+/// - `loaded_plugins`: The plugins, compiled from `plugins` by `load`.
+/// - `script`: A Rhai script that transforms or drops the records that pass the filters
+///   before they are counted (see the `script` module); none if `None`. It needs the
+///   `scripting` feature.
+/// - `loaded_script`: The script, compiled from `script` by `load`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub priorities: PriorityConfig,
+    pub metrics: Vec<String>,
+    pub dashboard: Option<Vec<SectionConfig>>,
+    pub reputation: Option<ReputationConfig>,
+    pub whois: Option<WhoisConfig>,
+    pub normalize: Option<NormalizeConfig>,
+    pub empty_fields: EmptyFieldPolicy,
+    pub delimiter: char,
+    pub filename_pattern: Option<FilenamePattern>,
+    pub derived: Option<DerivedConfig>,
+    pub smoothing_window: Option<usize>,
+    pub series_variants: Vec<SeriesVariant>,
+    pub session_gap_minutes: u32,
+    pub expected_countries: Vec<String>,
+    pub columns: ColumnConfig,
+    pub public_suffix_list: Option<PathBuf>,
+    #[serde(skip)]
+    pub suffixes: Arc<SuffixList>,
+    pub web_targets: WebTargetsConfig,
+    pub oui_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub vendors: Arc<VendorTable>,
+    #[serde(skip)]
+    pub windows: Vec<Window>,
+    #[serde(skip)]
+    pub cap_per_source: Option<u32>,
+    #[serde(skip)]
+    pub baseline: Option<Arc<RwLock<Baseline>>>,
+    pub business_hours: BusinessHoursConfig,
+    pub report_format: ReportFormatConfig,
+    pub plugins: Vec<PluginConfig>,
+    #[cfg(feature = "wasm")]
+    #[serde(skip)]
+    pub loaded_plugins: Vec<Arc<Plugin>>,
+    pub script: Option<PathBuf>,
+    #[cfg(feature = "scripting")]
+    #[serde(skip)]
+    pub loaded_script: Option<Arc<Script>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            priorities: PriorityConfig::default(),
+            metrics: metric::DEFAULT.iter().map(ToString::to_string).collect(),
+            dashboard: None,
+            reputation: None,
+            whois: None,
+            normalize: None,
+            empty_fields: EmptyFieldPolicy::default(),
+            delimiter: ',',
+            filename_pattern: None,
+            derived: None,
+            smoothing_window: None,
+            series_variants: Vec::new(),
+            session_gap_minutes: 30,
+            expected_countries: Vec::new(),
+            columns: ColumnConfig::default(),
+            public_suffix_list: None,
+            suffixes: Arc::new(SuffixList::builtin()),
+            web_targets: WebTargetsConfig::default(),
+            oui_file: None,
+            vendors: Arc::new(VendorTable::builtin()),
+            windows: Vec::new(),
+            cap_per_source: None,
+            baseline: None,
+            business_hours: BusinessHoursConfig::default(),
+            report_format: ReportFormatConfig::default(),
+            plugins: Vec::new(),
+            #[cfg(feature = "wasm")]
+            loaded_plugins: Vec::new(),
+            script: None,
+            #[cfg(feature = "scripting")]
+            loaded_script: None,
+        }
+    }
+}
+
+/// A section of `events.json`, as listed in the `dashboard`.
+///
+/// # Fields
+/// - `metric`: The metric computing the section, by name (see `metric::BUILTIN`).
+/// - `title`: The name of the section in `events.json`; the default name of the metric,
+///   such as `Threat Sources`, if `None`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SectionConfig {
+    pub metric: String,
+    pub title: Option<String>,
+}
+
+/// Names of the header columns holding optional fields, for exports with more than
+/// the standard columns. Files without a mapped column leave its field empty.
+///
+/// # Fields
+/// - `hostname`: The resolved hostname, URL or TLS SNI of the event, rolled up by the
+///   `domains` metric.
+/// - `url`: The requested URL or URI path, counted by the `web_targets` metric.
+/// - `sni`: The TLS server name, counted by the `web_targets` metric.
+/// - `user`: The authenticated username, counted by the `users` metric.
+/// - `zone`: The security zone of the event, broken down by the `zones` metric.
+/// - `interface`: The network interface, standing in for the zone if none is mapped.
+/// - `vlan`: The VLAN ID, standing in for the zone if no zone or interface is mapped.
+/// - `mac`: The source MAC address, counted by the `source_macs` metric.
+/// - `protocol`: The IP protocol, which tells the `icmp` metric ICMP from `ICMPv6` and
+///   other protocols, and the `scan_patterns` metric TCP from other protocols.
+/// - `icmp_type`: The ICMP type, counted by the `icmp` metric.
+/// - `icmp_code`: The ICMP code, counted by the `icmp` metric.
+/// - `tcp_flags`: The TCP flags, classified by the `scan_patterns` metric.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnConfig {
+    pub hostname: Option<String>,
+    pub url: Option<String>,
+    pub sni: Option<String>,
+    pub user: Option<String>,
+    pub zone: Option<String>,
+    pub interface: Option<String>,
+    pub vlan: Option<String>,
+    pub mac: Option<String>,
+    pub protocol: Option<String>,
+    pub icmp_type: Option<String>,
+    pub icmp_code: Option<String>,
+    pub tcp_flags: Option<String>,
+}
+
+impl ColumnConfig {
+    /// Returns the mapped fields with the names of their columns.
+    #[must_use]
+    pub fn mapped(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("hostname", &self.hostname),
+            ("url", &self.url),
+            ("sni", &self.sni),
+            ("user", &self.user),
+            ("zone", &self.zone),
+            ("interface", &self.interface),
+            ("vlan", &self.vlan),
+            ("mac", &self.mac),
+            ("protocol", &self.protocol),
+            ("icmp_type", &self.icmp_type),
+            ("icmp_code", &self.icmp_code),
+            ("tcp_flags", &self.tcp_flags),
+        ]
+        .into_iter()
+        .filter_map(|(field, header)| Some((field, header.as_deref()?)))
+        .collect()
+    }
+}
+
+/// Business hours, which the `business_hours` metric splits the events by.
+///
+/// # Fields
+/// - `days`: The working days, by English name or abbreviation such as `mon`. Defaults
+///   to Monday through Friday.
+/// - `start`: The time of day business hours start, as `HH:MM`. Defaults to `08:00`.
+/// - `end`: The time of day business hours end, as `HH:MM`, which must be after
+///   `start`. Defaults to `18:00`.
+/// - `timezone`: The UTC offset of the business hours, such as `+02:00` or `UTC`, which
+///   the date/time of the records is converted to from the local time zone; the
+///   date/time is used as it is if `None`.
+/// - `holiday_file`: An iCalendar file or list of dates whose days are outside business
+///   hours like weekends, read by `Config::load`; no holidays if `None`.
+/// - `holidays`: The holidays, read from `holiday_file`.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BusinessHoursConfig {
+    #[serde(deserialize_with = "weekdays")]
+    pub days: Vec<Weekday>,
+    #[serde(deserialize_with = "time_of_day")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "time_of_day")]
+    pub end: NaiveTime,
+    #[serde(deserialize_with = "utc_offset")]
+    pub timezone: Option<FixedOffset>,
+    pub holiday_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub holidays: Arc<Holidays>,
+}
+
+impl Default for BusinessHoursConfig {
+    fn default() -> Self {
+        Self {
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap_or_default(),
+            end: NaiveTime::from_hms_opt(18, 0, 0).unwrap_or_default(),
+            timezone: None,
+            holiday_file: None,
+            holidays: Arc::default(),
+        }
+    }
+}
+
+impl BusinessHoursConfig {
+    /// Returns whether an event with the local date/time `datetime` falls within
+    /// business hours.
+    #[must_use]
+    pub fn contains(&self, datetime: NaiveDateTime) -> bool {
+        let datetime = self.timezone.map_or(datetime, |offset| {
+            Local
+                .from_local_datetime(&datetime)
+                .earliest()
+                .map_or(datetime, |local| local.with_timezone(&offset).naive_local())
+        });
+        self.days.contains(&datetime.weekday())
+            && !self.holidays.contains(datetime.date())
+            && self.start <= datetime.time()
+            && datetime.time() < self.end
+    }
+}
+
+/// Deserializes the working days of `business_hours`.
+fn weekdays<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|day| {
+            day.parse()
+                .map_err(|_| de::Error::custom(format!("invalid day `{day}`, expected e.g. mon")))
+        })
+        .collect()
+}
+
+/// Deserializes a time of day of `business_hours`, written as `HH:MM`.
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|_| de::Error::custom(format!("invalid time `{time}`, expected e.g. 08:00")))
+}
+
+/// Deserializes the UTC offset of `business_hours`, written as `+HH:MM` or `UTC`.
+fn utc_offset<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<FixedOffset>, D::Error> {
+    let offset = String::deserialize(deserializer)?;
+    if offset.eq_ignore_ascii_case("utc") || offset == "Z" {
+        return Ok(FixedOffset::east_opt(0));
+    }
+    offset.parse().map(Some).map_err(|_| {
+        de::Error::custom(format!(
+            "invalid timezone `{offset}`, expected a UTC offset such as +02:00"
+        ))
+    })
+}
+
+/// A WebAssembly metric plugin, as listed in `plugins` (see the `plugin` module).
+///
+/// # Fields
+/// - `name`: The name of the plugin's section in `events.json`.
+/// - `path`: The plugin module, in the WebAssembly binary or text format.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// How numbers and dates are written in the reports meant for people.
+///
+/// These are the Excel report, the rendered template and the terminal dashboard;
+/// `events.json` and the other outputs read by programs keep plain numbers and dates.
+///
+/// # Fields
+/// - `thousands_separator`: The character that groups the digits of numbers in
+///   thousands, such as `,`, `.` or a space; numbers are not grouped if `None`.
+/// - `date_format`: The `strftime` format of dates, such as `%d.%m.%Y`. Defaults to
+///   `%Y-%m-%d`.
+/// - `clock`: Whether times are written on the 24-hour clock, such as `18:30:00`, or the
+///   12-hour clock, such as `06:30:00 PM`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportFormatConfig {
+    pub thousands_separator: Option<char>,
+    #[serde(deserialize_with = "date_format")]
+    pub date_format: String,
+    pub clock: Clock,
+}
+
+impl Default for ReportFormatConfig {
+    fn default() -> Self {
+        Self {
+            thousands_separator: None,
+            date_format: "%Y-%m-%d".to_string(),
+            clock: Clock::default(),
+        }
+    }
+}
+
+/// A normalized variant of the time series sections, as named in the configuration file.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesVariant {
+    /// `Rate`: The events per hour of each time bucket, or the score per hour of the
+    /// `Threat Score` section.
+    Rate,
+    /// `Z-Score`: How many standard deviations each time bucket is from the baseline of
+    /// the series, its mean over the time buckets of the run or the learned baseline.
+    ZScore,
+}
+
+/// The clock times are written on, `24h` or `12h` in the configuration file.
+#[derive(Clone, Copy, Default, Deserialize)]
+pub enum Clock {
+    #[default]
+    #[serde(rename = "24h")]
+    Hours24,
+    #[serde(rename = "12h")]
+    Hours12,
+}
+
+impl ReportFormatConfig {
+    /// Returns `number` with its digits grouped by the thousands separator.
+    #[must_use]
+    pub fn number(&self, number: u64) -> String {
+        let digits = number.to_string();
+        let Some(separator) = self.thousands_separator else {
+            return digits;
+        };
+        let mut grouped = String::with_capacity(digits.len() * 2);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+
+    /// Returns `date` in the date format.
+    #[must_use]
+    pub fn date(&self, date: NaiveDate) -> String {
+        date.format(&self.date_format).to_string()
+    }
+
+    /// Returns `datetime` in the date format, followed by the time on the clock.
+    #[must_use]
+    pub fn datetime(&self, datetime: NaiveDateTime) -> String {
+        let time = match self.clock {
+            Clock::Hours24 => "%H:%M:%S",
+            Clock::Hours12 => "%I:%M:%S %p",
+        };
+        format!("{} {}", self.date(datetime.date()), datetime.format(time))
+    }
+
+    /// Returns an AM/PM time bucket such as `2024-04-01 AM` with its date in the date
+    /// format. Other labels are returned as they are.
+    #[must_use]
+    pub fn bucket(&self, bucket: &str) -> String {
+        bucket
+            .split_once(' ')
+            .and_then(|(date, half)| {
+                let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+                Some(format!("{} {half}", self.date(date)))
+            })
+            .unwrap_or_else(|| bucket.to_string())
+    }
+}
+
+/// Deserializes the `strftime` date format of `report_format`, which must only use
+/// known specifiers.
+fn date_format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let format = String::deserialize(deserializer)?;
+    if StrftimeItems::new(&format).any(|item| matches!(item, Item::Error)) {
+        return Err(de::Error::custom(format!(
+            "invalid date format `{format}`, expected e.g. %d.%m.%Y"
+        )));
+    }
+    Ok(format)
+}
+
+/// A window of the most recent events, which are aggregated separately alongside the
+/// events of the whole run.
+///
+/// # Fields
+/// - `label`: The name of the window in the outputs, e.g. `7d`.
+/// - `start`: How long after the record cutoff of the run the window starts, which is
+///   the length of the run minus that of the window.
+pub struct Window {
+    pub label: String,
+    pub start: Duration,
+}
+
+/// Limits of the `web_targets` metric, which counts the hosts and URL paths targeted.
+///
+/// # Fields
+/// - `max_hosts`: The most hosts counted individually; events for further hosts are
+///   only counted in the total of the others. Defaults to 10000.
+/// - `max_paths`: The most URL paths counted individually, like `max_hosts`. Defaults to
+///   10000.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebTargetsConfig {
+    pub max_hosts: usize,
+    pub max_paths: usize,
+}
+
+impl Default for WebTargetsConfig {
+    fn default() -> Self {
+        Self {
+            max_hosts: 10_000,
+            max_paths: 10_000,
+        }
+    }
+}
+
+/// A regex with named groups that extracts dimensions such as the device from the
+/// names of the log files, e.g. `^fwddmp\.log\.tmp\.(?P<device>[^.]+)\.\d+$`.
+///
+/// # Fields
+/// - `regex`: The compiled regex.
+/// - `pattern`: The regex as configured.
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct FilenamePattern {
+    regex: Regex,
+    pattern: String,
+}
+
+impl TryFrom<String> for FilenamePattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, String> {
+        let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(format!(
+                "filename_pattern `{pattern}` has no named groups, such as (?P<device>...)"
+            ));
+        }
+        Ok(Self { regex, pattern })
+    }
+}
+
+impl FilenamePattern {
+    /// Returns the value of every named group in the file name of `path`, as `(name,
+    /// value)` pairs in the order of the groups. Groups that do not take part in the
+    /// match, for example because the name does not match at all, have the value
+    /// `(unknown)`.
+    #[must_use]
+    pub fn dimensions(&self, path: &Path) -> Vec<(String, String)> {
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        let captures = self.regex.captures(&name);
+        self.regex
+            .capture_names()
+            .flatten()
+            .map(|group| {
+                let value = captures
+                    .as_ref()
+                    .and_then(|captures| captures.name(group))
+                    .map_or_else(|| crate::UNKNOWN.to_string(), |m| m.as_str().to_string());
+                (group.to_string(), value)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for FilenamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// How records with an empty priority, source or destination are handled.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyFieldPolicy {
+    /// `keep`: Count them under the empty value, as a blank row.
+    #[default]
+    Keep,
+    /// `drop`: Leave them out of every metric.
+    Drop,
+    /// `unknown`: Count the empty values as `(unknown)`, or the `normalize` sentinel.
+    Unknown,
+    /// `strict`: Fail the file containing them.
+    Strict,
+}
+
+/// Priority settings.
+///
+/// # Fields
+/// - `values`: Priority values that are always present in the output, with a count of
+///   zero if no records have them. Defaults to `0` through `5`.
+/// - `labels`: Display labels keyed by raw priority value. Values without a label are
+///   shown as-is.
+/// - `weights`: Threat score weights keyed by raw priority value. Unlisted priorities
+///   weigh 0. When empty, no threat score is computed.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PriorityConfig {
+    pub values: Vec<String>,
+    pub labels: HashMap<String, String>,
+    pub weights: HashMap<String, u32>,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self {
+            values: (0..=5).map(|priority: u8| priority.to_string()).collect(),
+            labels: HashMap::new(),
+            weights: HashMap::new(),
+        }
+    }
+}
+
+/// A reputation lookup service.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReputationProvider {
+    /// `abuseipdb`: AbuseIPDB.com, scoring addresses by their abuse confidence from 0 to 100.
+    AbuseIpDb,
+    /// `virustotal`: VirusTotal.com, scoring addresses by the number of engines flagging them as malicious.
+    VirusTotal,
+}
+
+impl ReputationProvider {
+    /// Returns the name of the provider, as written in the configuration.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::AbuseIpDb => "abuseipdb",
+            Self::VirusTotal => "virustotal",
+        }
+    }
+
+    /// Returns the link to the report on `ip` on the service's website.
+    #[must_use]
+    pub fn report_url(self, ip: &str) -> String {
+        match self {
+            Self::AbuseIpDb => format!("https://www.abuseipdb.com/check/{ip}"),
+            Self::VirusTotal => format!("https://www.virustotal.com/gui/ip-address/{ip}"),
+        }
+    }
+}
+
+/// Reputation lookup settings.
+///
+/// # Fields
+/// - `provider`: The service to look addresses up with.
+/// - `api_key`: The API key of the service.
+/// - `top`: The number of top threat sources to look up. Defaults to 10.
+/// - `requests_per_minute`: The maximum rate of lookups. Defaults to 4, the limit of the
+///   free `virustotal` API.
+/// - `cache`: The file caching lookup results between runs. Defaults to
+///   `reputation_cache.json` in the output directory.
+/// - `cache_hours`: How long a cached result is used before the address is looked up
+///   again. Defaults to 24.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReputationConfig {
+    pub provider: ReputationProvider,
+    pub api_key: String,
+    #[serde(default = "ReputationConfig::default_top")]
+    pub top: usize,
+    #[serde(default = "ReputationConfig::default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    #[serde(default)]
+    pub cache: Option<PathBuf>,
+    #[serde(default = "ReputationConfig::default_cache_hours")]
+    pub cache_hours: u32,
+}
+
+impl ReputationConfig {
+    const fn default_top() -> usize {
+        10
+    }
+
+    const fn default_requests_per_minute() -> u32 {
+        4
+    }
+
+    const fn default_cache_hours() -> u32 {
+        24
+    }
+}
+
+/// WHOIS lookup settings, for looking up the owners of the top threat sources over RDAP.
+///
+/// # Fields
+/// - `server`: The base URL of the RDAP service, which is asked for `<server>/ip/<ip>`.
+///   Defaults to `https://rdap.org`, which redirects to the registry of the address.
+/// - `top`: The number of top threat sources to look up. Defaults to 10.
+/// - `requests_per_minute`: The maximum rate of lookups. Defaults to 10.
+/// - `cache`: The file caching lookup results between runs. Defaults to
+///   `whois_cache.json` in the output directory.
+/// - `cache_hours`: How long a cached result is used before the address is looked up
+///   again. Defaults to 168, a week, as network ownership rarely changes.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhoisConfig {
+    #[serde(default = "WhoisConfig::default_server")]
+    pub server: String,
+    #[serde(default = "WhoisConfig::default_top")]
+    pub top: usize,
+    #[serde(default = "WhoisConfig::default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    #[serde(default)]
+    pub cache: Option<PathBuf>,
+    #[serde(default = "WhoisConfig::default_cache_hours")]
+    pub cache_hours: u32,
+}
+
+impl WhoisConfig {
+    fn default_server() -> String {
+        "https://rdap.org".to_string()
+    }
+
+    const fn default_top() -> usize {
+        10
+    }
+
+    const fn default_requests_per_minute() -> u32 {
+        10
+    }
+
+    const fn default_cache_hours() -> u32 {
+        168
+    }
+}
+
+impl Config {
+    /// Parses the configuration from JSON text.
+    ///
+    /// # Errors
+    /// Returns a description of the problem if `contents` is not valid configuration.
+    pub fn from_json(contents: &str) -> Result<Self, String> {
+        let mut config: Self = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+        config.apply_dashboard()?;
+        if let Some(unknown) = config
+            .metrics
+            .iter()
+            .find(|name| !metric::BUILTIN.contains(&name.as_str()))
+        {
+            return Err(format!(
+                "unknown metric `{unknown}`, expected one of {}",
+                metric::BUILTIN.join(", ")
+            ));
+        }
+        if config
+            .reputation
+            .as_ref()
+            .is_some_and(|reputation| reputation.requests_per_minute == 0)
+        {
+            return Err("reputation.requests_per_minute must be at least 1".to_string());
+        }
+        if config
+            .whois
+            .as_ref()
+            .is_some_and(|whois| whois.requests_per_minute == 0)
+        {
+            return Err("whois.requests_per_minute must be at least 1".to_string());
+        }
+        if let Some(invalid) = config
+            .expected_countries
+            .iter()
+            .find(|code| code.len() != 2 || !code.bytes().all(|byte| byte.is_ascii_alphabetic()))
+        {
+            return Err(format!(
+                "expected_countries: `{invalid}` is not an ISO 3166-1 alpha-2 code"
+            ));
+        }
+        if config.metrics.iter().any(|name| name == "domains") && config.columns.hostname.is_none()
+        {
+            return Err("the domains metric needs a columns.hostname mapping".to_string());
+        }
+        if config.metrics.iter().any(|name| name == "web_targets")
+            && config.columns.url.is_none()
+            && config.columns.sni.is_none()
+        {
+            return Err("the web_targets metric needs a columns.url or columns.sni mapping".to_string());
+        }
+        if config.metrics.iter().any(|name| name == "users") && config.columns.user.is_none() {
+            return Err("the users metric needs a columns.user mapping".to_string());
+        }
+        if config.metrics.iter().any(|name| name == "zones")
+            && config.columns.zone.is_none()
+            && config.columns.interface.is_none()
+            && config.columns.vlan.is_none()
+        {
+            return Err(
+                "the zones metric needs a columns.zone, columns.interface or columns.vlan mapping"
+                    .to_string(),
+            );
+        }
+        if config.metrics.iter().any(|name| name == "source_macs") && config.columns.mac.is_none()
+        {
+            return Err("the source_macs metric needs a columns.mac mapping".to_string());
+        }
+        if config.metrics.iter().any(|name| name == "icmp") && config.columns.icmp_type.is_none() {
+            return Err("the icmp metric needs a columns.icmp_type mapping".to_string());
+        }
+        if config.metrics.iter().any(|name| name == "scan_patterns")
+            && config.columns.tcp_flags.is_none()
+        {
+            return Err("the scan_patterns metric needs a columns.tcp_flags mapping".to_string());
+        }
+        if config.web_targets.max_hosts == 0 || config.web_targets.max_paths == 0 {
+            return Err("web_targets.max_hosts and max_paths must be at least 1".to_string());
+        }
+        if config.business_hours.end <= config.business_hours.start {
+            return Err("business_hours.end must be after business_hours.start".to_string());
+        }
+        if config.session_gap_minutes == 0 {
+            return Err("session_gap_minutes must be at least 1".to_string());
+        }
+        if config.smoothing_window == Some(0) {
+            return Err("smoothing_window must be at least 1".to_string());
+        }
+        if !config.delimiter.is_ascii() || matches!(config.delimiter, '"' | '\n' | '\r') {
+            return Err(
+                "delimiter must be an ASCII character other than a quote or line break".to_string(),
+            );
+        }
+        Ok(config)
+    }
+
+    /// Sets `metrics` to the metrics of the sections of the `dashboard`, if there is one.
+    ///
+    /// # Errors
+    /// Returns a message if `metrics` is set as well, or the dashboard lists a metric or
+    /// title twice.
+    fn apply_dashboard(&mut self) -> Result<(), String> {
+        let Some(dashboard) = &self.dashboard else {
+            return Ok(());
+        };
+        if self.metrics != metric::DEFAULT {
+            return Err("set either metrics or dashboard, not both".to_string());
+        }
+        self.metrics = Vec::with_capacity(dashboard.len());
+        let mut titles = Vec::with_capacity(dashboard.len());
+        for section in dashboard {
+            if self.metrics.contains(&section.metric) {
+                return Err(format!("dashboard lists `{}` twice", section.metric));
+            }
+            if let Some(title) = &section.title {
+                if titles.contains(&title) {
+                    return Err(format!("dashboard: the title `{title}` is used twice"));
+                }
+                titles.push(title);
+            }
+            self.metrics.push(section.metric.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns the title the `dashboard` gives the section of the metric `name`, if any.
+    #[must_use]
+    pub fn title(&self, name: &str) -> Option<&str> {
+        self.dashboard
+            .iter()
+            .flatten()
+            .find(|section| section.metric == name)
+            .and_then(|section| section.title.as_deref())
+    }
+
+    /// Returns the field delimiter as a byte, for the CSV reader.
+    #[must_use]
+    pub fn delimiter_byte(&self) -> u8 {
+        u8::try_from(self.delimiter).unwrap_or(b',')
+    }
+
+    /// Loads the configuration from a JSON file, along with the public suffix list, OUI
+    /// file, holiday file, plugins and script it points to.
+    ///
+    /// # Errors
+    /// Returns an error if the file, the public suffix list, the OUI file, the holiday
+    /// file, a plugin or the script cannot be read, or one of them is not valid.
+    #[cfg(feature = "fs")]
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut config = Self::from_json(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid config file {}: {e}", path.display()),
+            )
+        })?;
+        if let Some(list) = &config.public_suffix_list {
+            let contents = read_reference(list, "public suffix list")?;
+            let suffixes = SuffixList::parse(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid public suffix list {}: {e}", list.display()),
+                )
+            })?;
+            config.suffixes = Arc::new(suffixes);
+        }
+        if let Some(file) = &config.oui_file {
+            let contents = read_reference(file, "OUI file")?;
+            config.vendors = Arc::new(VendorTable::parse(&contents));
+        }
+        if let Some(file) = &config.business_hours.holiday_file {
+            let contents = read_reference(file, "holiday file")?;
+            let holidays = Holidays::parse(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid holiday file {}: {e}", file.display()),
+                )
+            })?;
+            config.business_hours.holidays = Arc::new(holidays);
+        }
+        #[cfg(feature = "wasm")]
+        {
+            config.loaded_plugins = config
+                .plugins
+                .iter()
+                .map(|plugin| Plugin::load(&plugin.name, &plugin.path).map(Arc::new))
+                .collect::<io::Result<_>>()?;
+        }
+        #[cfg(not(feature = "wasm"))]
+        if !config.plugins.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid config file {}: plugins need a build with the `wasm` feature",
+                    path.display()
+                ),
+            ));
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &config.script {
+            config.loaded_script = Some(Arc::new(Script::load(script)?));
+        }
+        #[cfg(not(feature = "scripting"))]
+        if config.script.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid config file {}: script needs a build with the `scripting` feature",
+                    path.display()
+                ),
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// Reads a reference data file named in the configuration, described as `what` in
+/// errors.
+///
+/// # Errors
+/// Returns an error naming the file if it cannot be read.
+#[cfg(feature = "fs")]
+fn read_reference(path: &Path, what: &str) -> io::Result<String> {
+    fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to read {what} {}: {e}", path.display()),
+        )
+    })
+}
@@ -0,0 +1,140 @@
+//! Timeouts for filesystem calls that may never return.
+//!
+//! On a hard-mounted NFS share whose server has gone away, listing a directory or
+//! reading a file blocks in the kernel indefinitely. With `--io-timeout`, these calls are
+//! made on a thread of their own, and the caller stops waiting for it once the timeout
+//! passes without progress: a directory listing then fails, and a file is reported as
+//! unreadable like any other, while the run carries on with the remaining files. The
+//! blocked thread cannot be cancelled, and is left behind until the mount recovers or
+//! the process exits.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Size of the chunks a file is read in.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Number of chunks read ahead of the parser.
+const CHUNKS_AHEAD: usize = 4;
+
+/// Returns the error of a call that made no progress within `timeout`.
+fn timed_out(what: &str, timeout: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!(
+            "{what} timed out after {}s (--io-timeout)",
+            timeout.as_secs_f64()
+        ),
+    )
+}
+
+/// Runs `call` on a thread of its own and returns its result, or a `TimedOut` error
+/// naming `what` if it does not return within `timeout`.
+///
+/// # Errors
+/// Returns the error of `call`, or the timeout.
+pub fn call<T: Send + 'static>(
+    timeout: Duration,
+    what: &str,
+    call: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The caller may have stopped waiting
+        let _ = sender.send(call());
+    });
+    receiver
+        .recv_timeout(timeout)
+        .unwrap_or_else(|_| Err(timed_out(what, timeout)))
+}
+
+/// A file read on a thread of its own, which fails once no data arrived for the
+/// timeout.
+///
+/// # Fields
+/// - `chunks`: The chunks read by the thread, in order; an empty chunk ends the file.
+/// - `chunk`: The chunk being consumed, from `position` on.
+/// - `timeout`: How long to wait for the next chunk.
+/// - `failed`: Whether the read timed out; every later read then fails at once.
+pub struct Reader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    timeout: Duration,
+    failed: bool,
+}
+
+impl Reader {
+    /// Opens the file at `path` for reading with `timeout`. With `whole`, the file is read
+    /// in a single read, which `--mmap` falls back to, and the timeout applies to the whole
+    /// file.
+    pub fn open(path: &Path, whole: bool, timeout: Duration) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(CHUNKS_AHEAD);
+        let file_path = path.to_path_buf();
+        thread::spawn(move || {
+            if whole {
+                if sender.send(fs::read(&file_path)).is_ok() {
+                    let _ = sender.send(Ok(Vec::new()));
+                }
+                return;
+            }
+            let mut file = match File::open(&file_path) {
+                Ok(file) => file,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let result = match file.read(&mut chunk) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    result => result.map(|length| {
+                        chunk.truncate(length);
+                        chunk
+                    }),
+                };
+                let last = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+                // The reader is dropped once it has failed or the file is done
+                if sender.send(result).is_err() || last {
+                    return;
+                }
+            }
+        });
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            timeout,
+            failed: false,
+        }
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(timed_out("Reading", self.timeout));
+        }
+        if self.position == self.chunk.len() {
+            match self.chunks.recv_timeout(self.timeout) {
+                Ok(chunk) => self.chunk = chunk?,
+                // The file has ended, and the thread with it
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.failed = true;
+                    return Err(timed_out("Reading", self.timeout));
+                }
+            }
+            self.position = 0;
+        }
+        let length = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
@@ -0,0 +1,465 @@
+//! Derived metrics defined in the configuration.
+//!
+//! Dashboards often need ratios such as the share of denied events, and computing them
+//! client-side from the published sections is easy to get subtly different between
+//! panels. The `derived` configuration section defines them once, e.g.:
+//!
+//! ```json
+//! {
+//!   "derived": {
+//!     "counts": {
+//!       "deny_count": "col:category=~\"DENY|BLOCK\"",
+//!       "critical_ssh": "col:priority==\"1\" AND col:signature=~\"SSH\""
+//!     },
+//!     "metrics": {
+//!       "blocked_ratio": "deny_count / total",
+//!       "critical_ssh_pct": "100 * critical_ssh / total"
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! Each count is the number of events matching a condition in the syntax of `--filter`
+//! (see `filter::RowFilter`), whose columns are the event fields `priority`,
+//! `signature`, `category`, `source` and `destination`. Each metric is an arithmetic
+//! formula over the counts, `total` (all counted events) and numbers, with `+`, `-`,
+//! `*`, `/` and parentheses. Formulas are evaluated when `events.json` is written, and a
+//! division by zero gives `null`.
+
+use crate::filter::RowFilter;
+use csv::StringRecord;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Name of the variable holding the number of counted events.
+pub const TOTAL: &str = "total";
+
+/// The event fields that count conditions can test, in the order of the record they
+/// are tested against.
+const FIELDS: [&str; 5] = ["priority", "signature", "category", "source", "destination"];
+
+/// The `derived` section as written in the configuration file.
+///
+/// # Fields
+/// - `counts`: Conditions by count name.
+/// - `metrics`: Formulas by metric name.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawDerivedConfig {
+    counts: BTreeMap<String, String>,
+    metrics: BTreeMap<String, String>,
+}
+
+/// A named count of the events matching a condition.
+///
+/// # Fields
+/// - `name`: The name of the count, usable in formulas.
+/// - `filter`: The condition.
+/// - `positions`: The positions of the condition's columns in the event record.
+pub struct Count {
+    pub name: String,
+    filter: RowFilter,
+    positions: Vec<usize>,
+}
+
+impl Count {
+    /// Returns whether the event `record` (see `DerivedConfig::record`) matches.
+    #[must_use]
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        self.filter.matches(record, &self.positions)
+    }
+}
+
+/// The parsed `derived` configuration section, shared by the metrics of every file.
+///
+/// # Fields
+/// - `counts`: The counts, in name order.
+/// - `metrics`: The formulas and their names, in name order.
+#[derive(Deserialize)]
+#[serde(try_from = "RawDerivedConfig")]
+pub struct DerivedConfig {
+    pub counts: Arc<[Count]>,
+    pub metrics: Arc<[(String, Formula)]>,
+}
+
+impl TryFrom<RawDerivedConfig> for DerivedConfig {
+    type Error = String;
+
+    fn try_from(raw: RawDerivedConfig) -> Result<Self, String> {
+        let headers = StringRecord::from(FIELDS.to_vec());
+        let counts = raw
+            .counts
+            .into_iter()
+            .map(|(name, condition)| {
+                let invalid = |e: String| format!("derived count `{name}`: {e}");
+                check_name(&name).map_err(invalid)?;
+                let filter = RowFilter::parse(&condition).map_err(invalid)?;
+                let positions = filter.resolve(&headers).map_err(invalid)?;
+                Ok(Count {
+                    name,
+                    filter,
+                    positions,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let metrics = raw
+            .metrics
+            .into_iter()
+            .map(|(name, formula)| {
+                let invalid = |e: String| format!("derived metric `{name}`: {e}");
+                check_name(&name).map_err(invalid)?;
+                if counts.iter().any(|count| count.name == name) {
+                    return Err(invalid("is also the name of a count".to_string()));
+                }
+                let formula = Formula::parse(&formula).map_err(invalid)?;
+                if let Some(unknown) = formula.variables().find(|variable| {
+                    *variable != TOTAL && !counts.iter().any(|count| count.name == *variable)
+                }) {
+                    return Err(invalid(format!("unknown count `{unknown}`")));
+                }
+                Ok((name, formula))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            counts: counts.into(),
+            metrics: metrics.into(),
+        })
+    }
+}
+
+impl DerivedConfig {
+    /// Returns the record that count conditions are tested against for the event with
+    /// the given fields, in the order of `FIELDS`.
+    #[must_use]
+    pub fn record(fields: [&str; 5]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+}
+
+/// Checks that `name` is an identifier that formulas can refer to.
+fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err("names must be letters, digits and underscores".to_string());
+    }
+    if name == TOTAL {
+        return Err(format!("`{TOTAL}` is reserved for the number of events"));
+    }
+    Ok(())
+}
+
+/// An arithmetic formula over named counts.
+pub enum Formula {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Self>),
+    Add(Box<Self>, Box<Self>),
+    Subtract(Box<Self>, Box<Self>),
+    Multiply(Box<Self>, Box<Self>),
+    Divide(Box<Self>, Box<Self>),
+}
+
+impl Formula {
+    /// Parses a formula such as `100 * (deny_count + drop_count) / total`.
+    ///
+    /// # Errors
+    /// Returns a message describing the syntax error.
+    pub fn parse(formula: &str) -> Result<Self, String> {
+        let mut parser = FormulaParser {
+            chars: formula.chars().collect(),
+            pos: 0,
+        };
+        let parsed = parser.parse_sum()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(format!(
+                "unexpected `{}` in formula",
+                parser.chars[parser.pos..].iter().collect::<String>()
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the names of the variables the formula refers to.
+    fn variables(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self {
+            Self::Number(_) => Box::new(std::iter::empty()),
+            Self::Variable(name) => Box::new(std::iter::once(name.as_str())),
+            Self::Negate(inner) => inner.variables(),
+            Self::Add(a, b) | Self::Subtract(a, b) | Self::Multiply(a, b) | Self::Divide(a, b) => {
+                Box::new(a.variables().chain(b.variables()))
+            }
+        }
+    }
+
+    /// Evaluates the formula with the values of its variables looked up by `variable`.
+    ///
+    /// # Returns
+    /// The value, or `None` if it is not a finite number, e.g. after a division by zero.
+    pub fn evaluate(&self, variable: &dyn Fn(&str) -> f64) -> Option<f64> {
+        let value = match self {
+            Self::Number(number) => *number,
+            Self::Variable(name) => variable(name),
+            Self::Negate(inner) => -inner.evaluate(variable)?,
+            Self::Add(a, b) => a.evaluate(variable)? + b.evaluate(variable)?,
+            Self::Subtract(a, b) => a.evaluate(variable)? - b.evaluate(variable)?,
+            Self::Multiply(a, b) => a.evaluate(variable)? * b.evaluate(variable)?,
+            Self::Divide(a, b) => a.evaluate(variable)? / b.evaluate(variable)?,
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+struct FormulaParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl FormulaParser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `c` if it is next, after whitespace.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        let matched = self.chars.get(self.pos) == Some(&c);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn parse_sum(&mut self) -> Result<Formula, String> {
+        let mut formula = self.parse_product()?;
+        loop {
+            if self.eat('+') {
+                formula = Formula::Add(Box::new(formula), Box::new(self.parse_product()?));
+            } else if self.eat('-') {
+                formula = Formula::Subtract(Box::new(formula), Box::new(self.parse_product()?));
+            } else {
+                return Ok(formula);
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Formula, String> {
+        let mut formula = self.parse_unary()?;
+        loop {
+            if self.eat('*') {
+                formula = Formula::Multiply(Box::new(formula), Box::new(self.parse_unary()?));
+            } else if self.eat('/') {
+                formula = Formula::Divide(Box::new(formula), Box::new(self.parse_unary()?));
+            } else {
+                return Ok(formula);
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Formula, String> {
+        if self.eat('-') {
+            return Ok(Formula::Negate(Box::new(self.parse_unary()?)));
+        }
+        if self.eat('(') {
+            let formula = self.parse_sum()?;
+            if !self.eat(')') {
+                return Err("missing ) in formula".to_string());
+            }
+            return Ok(formula);
+        }
+        self.parse_operand()
+    }
+
+    fn parse_operand(&mut self) -> Result<Formula, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let Some(&first) = self.chars.get(start) else {
+            return Err("unexpected end of formula".to_string());
+        };
+        if first.is_ascii_digit() || first == '.' {
+            while self
+                .chars
+                .get(self.pos)
+                .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+            {
+                self.pos += 1;
+            }
+            let number: String = self.chars[start..self.pos].iter().collect();
+            return number
+                .parse()
+                .map(Formula::Number)
+                .map_err(|_| format!("invalid number `{number}` in formula"));
+        }
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(format!("unexpected `{first}` in formula"));
+        }
+        Ok(Formula::Variable(
+            self.chars[start..self.pos].iter().collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<DerivedConfig, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// Returns the message of the error parsing the configuration `json`.
+    fn config_error(json: &str) -> String {
+        parse(json).err().unwrap_or_default()
+    }
+
+    /// Evaluates `formula` with `deny` as `deny_count` and `total` as `total`.
+    fn evaluate(formula: &str, deny: f64, total: f64) -> Result<Option<f64>, String> {
+        let formula = Formula::parse(formula)?;
+        Ok(formula.evaluate(&|name| match name {
+            "deny_count" => deny,
+            TOTAL => total,
+            _ => f64::NAN,
+        }))
+    }
+
+    #[test]
+    fn formulas_follow_precedence_and_parentheses() -> Result<(), String> {
+        let cases = [
+            ("deny_count / total", Some(0.25)),
+            ("100 * deny_count / total", Some(25.0)),
+            ("1 + 2 * 3", Some(7.0)),
+            ("(1 + 2) * 3", Some(9.0)),
+            ("10 - 4 - 3", Some(3.0)),
+            ("8 / 4 / 2", Some(1.0)),
+            ("-deny_count + --2", Some(-3.0)),
+            ("- (total - deny_count)", Some(-15.0)),
+            (" .5*total ", Some(10.0)),
+            ("deny_count / 0", None),
+            ("0 / 0", None),
+            ("1 + (deny_count / 0)", None),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(evaluate(formula, 5.0, 20.0)?, expected, "{formula}");
+        }
+        let formula = Formula::parse("(a + b) * total - -a / 2")?;
+        assert_eq!(
+            formula.variables().collect::<Vec<_>>(),
+            ["a", "b", TOTAL, "a"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn formula_syntax_errors_are_described() {
+        let error = |formula| Formula::parse(formula).err().unwrap_or_default();
+        assert_eq!(error(""), "unexpected end of formula");
+        assert_eq!(error("1 +"), "unexpected end of formula");
+        assert_eq!(error("(1 + 2"), "missing ) in formula");
+        assert_eq!(error("1 + 2)"), "unexpected `)` in formula");
+        assert_eq!(error("total total"), "unexpected `total` in formula");
+        assert_eq!(error("1.2.3"), "invalid number `1.2.3` in formula");
+        assert_eq!(error("2 ^ 3"), "unexpected `^ 3` in formula");
+        assert_eq!(error("* 3"), "unexpected `*` in formula");
+    }
+
+    #[test]
+    fn counts_match_the_event_fields() -> Result<(), String> {
+        let config = parse(
+            r#"{
+                "counts": {
+                    "deny_count": "col:category=~\"DENY|BLOCK\"",
+                    "critical_ssh": "col:priority==\"1\" AND col:signature=~\"SSH\"",
+                    "internal": "col:4==\"10.1.0.1\""
+                },
+                "metrics": {"blocked_ratio": "deny_count / total"}
+            }"#,
+        )?;
+        let names: Vec<_> = config
+            .counts
+            .iter()
+            .map(|count| count.name.as_str())
+            .collect();
+        assert_eq!(names, ["critical_ssh", "deny_count", "internal"]);
+        let record = DerivedConfig::record(["1", "ET SSH scan", "DENY", "10.0.0.1", "10.1.0.1"]);
+        assert!(config.counts.iter().all(|count| count.matches(&record)));
+        let record = DerivedConfig::record(["2", "ET SSH scan", "ALLOW", "10.0.0.1", "10.1.0.2"]);
+        assert!(config.counts.iter().all(|count| !count.matches(&record)));
+        let names: Vec<_> = config
+            .metrics
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["blocked_ratio"]);
+        assert!(parse("{}")?.counts.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_columns_and_counts_are_rejected() -> Result<(), String> {
+        assert_eq!(
+            config_error(r#"{"counts": {"by_zone": "col:zone==\"dmz\""}}"#),
+            "derived count `by_zone`: Unknown filter column `zone`"
+        );
+        assert_eq!(
+            config_error(r#"{"metrics": {"ratio": "deny_count / total"}}"#),
+            "derived metric `ratio`: unknown count `deny_count`"
+        );
+        // Columns by position past the event fields compare as empty
+        let config = parse(r#"{"counts": {"past": "col:9==\"\""}}"#)?;
+        let record = DerivedConfig::record(["1", "sig", "cat", "10.0.0.1", "10.1.0.1"]);
+        assert!(config.counts.iter().all(|count| count.matches(&record)));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        let cases = [
+            (
+                r#"{"counts": {"deny count": "col:category==\"DENY\""}}"#,
+                "derived count `deny count`: names must be letters, digits and underscores",
+            ),
+            (
+                r#"{"counts": {"total": "col:category==\"DENY\""}}"#,
+                "derived count `total`: `total` is reserved for the number of events",
+            ),
+            (
+                r#"{"metrics": {"1st": "total"}}"#,
+                "derived metric `1st`: names must be letters, digits and underscores",
+            ),
+            (
+                r#"{"counts": {"deny": "col:category==\"DENY\""}, "metrics": {"deny": "total"}}"#,
+                "derived metric `deny`: is also the name of a count",
+            ),
+            (
+                r#"{"metrics": {"ratio": "total /"}}"#,
+                "derived metric `ratio`: unexpected end of formula",
+            ),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(config_error(json), expected, "{json}");
+        }
+        let condition = config_error(r#"{"counts": {"deny": "col:category=="}}"#);
+        assert!(
+            condition.starts_with("derived count `deny`: "),
+            "{condition}"
+        );
+        let unknown = config_error(r#"{"count": {}}"#);
+        assert!(unknown.starts_with("unknown field `count`"), "{unknown}");
+    }
+}
@@ -0,0 +1,260 @@
+//! Registrable domains of hostnames, for rolling many hostnames up into the sites they
+//! belong to.
+//!
+//! The registrable domain of a hostname is the public suffix under which anyone can
+//! register names, such as `com` or `co.uk`, plus one more label, so `a.b.example.co.uk`
+//! rolls up into `example.co.uk`. Public suffixes follow the rules of the public suffix
+//! list (<https://publicsuffix.org/list/>), including its wildcard and exception rules,
+//! as matched by the [`publicsuffix`] crate. A small built-in list covers common
+//! suffixes; deployments that need the complete list can point the `public_suffix_list`
+//! setting at a copy of `public_suffix_list.dat`.
+
+use publicsuffix::{List, Psl};
+use std::iter;
+use std::net::IpAddr;
+
+/// Public suffixes known without a list file: the common second-level suffixes of
+/// country domains, and hosting suffixes whose subdomains belong to different owners.
+/// Any other top-level domain is a public suffix on its own.
+const BUILTIN: &[&str] = &[
+    "ac.uk",
+    "co.uk",
+    "gov.uk",
+    "ltd.uk",
+    "me.uk",
+    "net.uk",
+    "org.uk",
+    "plc.uk",
+    "sch.uk",
+    "com.au",
+    "edu.au",
+    "gov.au",
+    "net.au",
+    "org.au",
+    "co.nz",
+    "net.nz",
+    "org.nz",
+    "ac.jp",
+    "co.jp",
+    "go.jp",
+    "ne.jp",
+    "or.jp",
+    "co.kr",
+    "or.kr",
+    "com.cn",
+    "edu.cn",
+    "gov.cn",
+    "net.cn",
+    "org.cn",
+    "com.hk",
+    "com.tw",
+    "com.sg",
+    "com.my",
+    "co.in",
+    "net.in",
+    "org.in",
+    "co.id",
+    "co.th",
+    "com.vn",
+    "com.br",
+    "net.br",
+    "org.br",
+    "com.ar",
+    "com.mx",
+    "co.za",
+    "com.tr",
+    "co.il",
+    "com.ua",
+    "com.ru",
+    "com.pl",
+    "appspot.com",
+    "azurewebsites.net",
+    "blogspot.com",
+    "cloudfront.net",
+    "duckdns.org",
+    "github.io",
+    "herokuapp.com",
+    "netlify.app",
+    "ngrok.io",
+    "pages.dev",
+    "vercel.app",
+    "workers.dev",
+];
+
+/// Marks the ICANN section of a list; rules outside a section are ignored.
+const ICANN_SECTION: &str = "// ===BEGIN ICANN DOMAINS===";
+
+/// A set of public suffix rules.
+///
+/// # Fields
+/// - `list`: The rules, matched by [`publicsuffix`].
+/// - `rules`: The number of rules.
+#[derive(Debug)]
+pub struct SuffixList {
+    list: List,
+    rules: usize,
+}
+
+impl SuffixList {
+    /// Returns the built-in list of common public suffixes.
+    #[must_use]
+    pub fn builtin() -> Self {
+        let contents = iter::once(ICANN_SECTION)
+            .chain(BUILTIN.iter().copied())
+            .collect::<Vec<_>>()
+            .join("\n");
+        // The built-in rules are valid, which the tests check
+        Self::parse(&contents).unwrap_or_else(|_| Self {
+            list: List::new(),
+            rules: 0,
+        })
+    }
+
+    /// Parses a list in the format of `public_suffix_list.dat`: one rule per line in the
+    /// ICANN and private domain sections, with `//` comments, `*.` wildcard rules and
+    /// `!` exception rules.
+    ///
+    /// # Errors
+    /// Returns an error if a rule is malformed or the list has no rules.
+    pub fn parse(contents: &str) -> Result<Self, publicsuffix::Error> {
+        let list = contents.parse()?;
+        let rules = contents
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|rule| !rule.starts_with("//"))
+            .count();
+        Ok(Self { list, rules })
+    }
+
+    /// Returns the number of rules in the list.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.rules
+    }
+
+    /// Returns whether the list has no rules, so only top-level domains are public.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the registrable domain of `hostname`, which must already be lowercase
+    /// without a trailing dot, or `None` if it is itself a public suffix.
+    #[must_use]
+    pub fn registrable<'a>(&self, hostname: &'a str) -> Option<&'a str> {
+        let domain = self.list.domain(hostname.as_bytes())?;
+        hostname.get(hostname.len() - domain.as_bytes().len()..)
+    }
+}
+
+/// Extracts the hostname from a hostname, URL or SNI field, lowercased and without a
+/// port, path or trailing dot.
+///
+/// # Returns
+/// The hostname, or `None` if the field is empty or an IP address.
+#[must_use]
+pub fn hostname(field: &str) -> Option<String> {
+    let field = field.trim();
+    let field = field.split_once("://").map_or(field, |(_, rest)| rest);
+    let authority = field.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    if host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    let host = host.trim_end_matches('.');
+    if host.is_empty() || host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    Some(host.to_lowercase())
+}
+
+/// Extracts the path of a URL or URI field, without the query string or fragment.
+///
+/// # Returns
+/// The path, `/` for a URL without one, or `None` if the field is empty.
+#[must_use]
+pub fn url_path(field: &str) -> Option<&str> {
+    let field = field.trim();
+    let field = field.split(['?', '#']).next().unwrap_or_default();
+    if field.is_empty() {
+        return None;
+    }
+    if field.starts_with('/') {
+        return Some(field);
+    }
+    // Skip the scheme and host of an absolute URL
+    let rest = field.split_once("://").map_or(field, |(_, rest)| rest);
+    Some(rest.find('/').map_or("/", |slash| &rest[slash..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_suffixes_roll_up_to_registrable_domains() {
+        let list = SuffixList::builtin();
+        assert_eq!(list.len(), BUILTIN.len());
+        let cases = [
+            ("a.b.example.co.uk", Some("example.co.uk")),
+            ("example.co.uk", Some("example.co.uk")),
+            ("co.uk", None),
+            ("uk", None),
+            ("www.example.com", Some("example.com")),
+            ("user.github.io", Some("user.github.io")),
+            ("github.io", None),
+            // Any other top-level domain is a public suffix of its own
+            ("x.y.example", Some("y.example")),
+        ];
+        for (hostname, expected) in cases {
+            assert_eq!(list.registrable(hostname), expected, "{hostname}");
+        }
+    }
+
+    #[test]
+    fn list_files_have_wildcard_and_exception_rules() -> Result<(), publicsuffix::Error> {
+        let list = SuffixList::parse(
+            "// A comment\n// ===BEGIN ICANN DOMAINS===\nuk\nco.uk\n*.ck\n!www.ck\n\n",
+        )?;
+        assert_eq!(list.len(), 4);
+        assert_eq!(list.registrable("a.b.example.co.uk"), Some("example.co.uk"));
+        assert_eq!(list.registrable("a.b.ck"), Some("a.b.ck"));
+        assert_eq!(list.registrable("b.ck"), None);
+        assert_eq!(list.registrable("a.www.ck"), Some("www.ck"));
+        assert!(SuffixList::parse("").is_err());
+        assert!(SuffixList::parse("// ===BEGIN ICANN DOMAINS===\n!ck").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hostnames_are_extracted_from_urls() {
+        let cases = [
+            (
+                "HTTPS://user@Example.CO.uk.:8443/path?q#f",
+                Some("example.co.uk"),
+            ),
+            ("www.example.com:443", Some("www.example.com")),
+            ("  mail.example.org  ", Some("mail.example.org")),
+            ("", None),
+            // IP literals have no hostname
+            ("192.0.2.1", None),
+            ("http://192.0.2.1:8080/admin", None),
+            ("https://[2001:db8::1]:443/", None),
+            ("2001:db8::1", None),
+        ];
+        for (field, expected) in cases {
+            assert_eq!(hostname(field).as_deref(), expected, "{field}");
+        }
+    }
+
+    #[test]
+    fn url_paths_drop_the_query_and_fragment() {
+        assert_eq!(url_path("/login?user=a"), Some("/login"));
+        assert_eq!(url_path("https://example.com/a/b#top"), Some("/a/b"));
+        assert_eq!(url_path("https://example.com"), Some("/"));
+        assert_eq!(url_path(" "), None);
+    }
+}
@@ -0,0 +1,292 @@
+//! Character encodings of the log files.
+//!
+//! Some appliances export their logs as UTF-16 with a byte order mark, or in Latin-1,
+//! which the CSV reader would mangle or reject. `Encoding::reader` wraps a log file in a
+//! reader that transcodes it to UTF-8 with [`encoding_rs`] before the CSV reader or the
+//! line parsers of the input formats see it.
+
+use std::fmt;
+use std::io::{self, Read};
+
+/// How many bytes are read at once.
+const CHUNK: usize = 64 * 1024;
+
+/// How many bytes at the start of a file are examined to detect its encoding.
+const SNIFF_LEN: usize = 4096;
+
+/// The character encoding of the log files.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Detected from the start of each file: UTF-16 or UTF-8 if it starts with a byte
+    /// order mark, otherwise UTF-8 if the first 4 KiB are valid UTF-8, and Latin-1 if not.
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO 8859-1, read as its superset windows-1252 like web browsers do, so bytes
+    /// 0x80 to 0x9F are characters such as `€` rather than control characters.
+    Latin1,
+}
+
+impl Encoding {
+    /// Parses the name of an encoding.
+    ///
+    /// # Errors
+    /// Returns a message listing the supported encodings if `name` is not one of them.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "utf-16le" | "utf16le" => Ok(Self::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Self::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Self::Latin1),
+            _ => Err(format!(
+                "Unsupported encoding `{name}`, expected auto, utf-8, utf-16le, utf-16be or latin-1"
+            )),
+        }
+    }
+
+    /// Returns the name of the encoding, as accepted by `parse`.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin-1",
+        }
+    }
+
+    /// Returns whether text in this encoding is split into lines at `\n` bytes, which
+    /// is not the case for UTF-16.
+    #[must_use]
+    pub const fn byte_lines(self) -> bool {
+        !matches!(self, Self::Utf16Le | Self::Utf16Be)
+    }
+
+    /// Wraps `reader` in a reader that yields its contents as UTF-8. A byte order mark
+    /// at the start is removed.
+    pub fn reader<'a, R: Read + 'a>(self, reader: R) -> Box<dyn Read + 'a> {
+        Box::new(Transcoder {
+            reader,
+            encoding: self,
+            decoder: None,
+            finished: false,
+            input: Vec::new(),
+            output: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Returns the `encoding_rs` encoding, which is UTF-8 for `Auto`.
+    const fn codec(self) -> &'static encoding_rs::Encoding {
+        match self {
+            Self::Auto | Self::Utf8 => encoding_rs::UTF_8,
+            Self::Utf16Le => encoding_rs::UTF_16LE,
+            Self::Utf16Be => encoding_rs::UTF_16BE,
+            Self::Latin1 => encoding_rs::WINDOWS_1252,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returns the encoding of a file starting with `start`.
+fn detect(start: &[u8]) -> Encoding {
+    match encoding_rs::Encoding::for_bom(start) {
+        Some((encoding, _)) if encoding == encoding_rs::UTF_16LE => Encoding::Utf16Le,
+        Some((encoding, _)) if encoding == encoding_rs::UTF_16BE => Encoding::Utf16Be,
+        Some(_) => Encoding::Utf8,
+        None => match std::str::from_utf8(start) {
+            // A character cut off at the end of `start` is not an error
+            Err(e) if e.error_len().is_some() => Encoding::Latin1,
+            _ => Encoding::Utf8,
+        },
+    }
+}
+
+/// Transcodes the bytes of `reader` from `encoding` to UTF-8.
+///
+/// # Fields
+/// - `reader`: The underlying reader.
+/// - `encoding`: The encoding of `reader`; `Auto` is resolved on the first read.
+/// - `decoder`: The decoder of `encoding`, created on the first read.
+/// - `finished`: Whether the end of `reader` has been decoded.
+/// - `input`: Bytes read from `reader` that have not been transcoded yet.
+/// - `output`: The transcoded bytes.
+/// - `position`: How much of `output` has been returned.
+struct Transcoder<R> {
+    reader: R,
+    encoding: Encoding,
+    decoder: Option<encoding_rs::Decoder>,
+    finished: bool,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Transcoder<R> {
+    /// Reads the next chunk of `reader` into `input`, returning whether the end was
+    /// reached.
+    fn fill(&mut self) -> io::Result<bool> {
+        let start = self.input.len();
+        self.input.resize(start + CHUNK, 0);
+        let result = self.reader.read(&mut self.input[start..]);
+        self.input
+            .truncate(start + result.as_ref().map_or(0, |count| *count));
+        result.map(|count| count == 0)
+    }
+
+    /// Reads the start of `reader`, detects the encoding if it is `Auto`, and creates
+    /// the decoder, which removes a byte order mark of the encoding. Returns whether
+    /// the end was reached.
+    fn start(&mut self) -> io::Result<bool> {
+        let mut eof = false;
+        while !eof && self.input.len() < SNIFF_LEN {
+            eof = self.fill()?;
+        }
+        if self.encoding == Encoding::Auto {
+            self.encoding = detect(&self.input);
+        }
+        self.decoder = Some(self.encoding.codec().new_decoder_with_bom_removal());
+        Ok(eof)
+    }
+
+    /// Transcodes `input` into `output`. The decoder keeps an incomplete character at
+    /// the end for the next chunk, unless `eof` is set.
+    fn decode(&mut self, eof: bool) {
+        let Some(decoder) = &mut self.decoder else {
+            return;
+        };
+        // Enough room for all of `input`, which is at most a few chunks
+        let room = decoder
+            .max_utf8_buffer_length(self.input.len())
+            .unwrap_or(CHUNK);
+        self.output.resize(room, 0);
+        let (_, read, written, _) = decoder.decode_to_utf8(&self.input, &mut self.output, eof);
+        self.input.drain(..read);
+        self.output.truncate(written);
+        self.finished = eof;
+    }
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.output.clear();
+            self.position = 0;
+            let eof = if self.decoder.is_some() {
+                self.fill()?
+            } else {
+                self.start()?
+            };
+            self.decode(eof);
+        }
+        let count = out.len().min(self.output.len() - self.position);
+        out[..count].copy_from_slice(&self.output[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns at most one byte per read, so characters are split across chunks.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            if out.is_empty() {
+                return Ok(0);
+            }
+            out[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    fn decode(encoding: Encoding, bytes: &[u8]) -> io::Result<String> {
+        let mut text = String::new();
+        encoding.reader(bytes).read_to_string(&mut text)?;
+        let mut trickled = String::new();
+        encoding
+            .reader(Trickle(bytes))
+            .read_to_string(&mut trickled)?;
+        assert_eq!(text, trickled);
+        Ok(text)
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| {
+                if big_endian {
+                    unit.to_be_bytes()
+                } else {
+                    unit.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn latin1_is_transcoded() -> io::Result<()> {
+        let bytes = b"caf\xe9,\x80 5\n";
+        assert_eq!(decode(Encoding::Latin1, bytes)?, "café,€ 5\n");
+        // Detected from bytes that are not valid UTF-8
+        assert_eq!(decode(Encoding::Auto, bytes)?, "café,€ 5\n");
+        assert_eq!(decode(Encoding::Auto, "café\n".as_bytes())?, "café\n");
+        Ok(())
+    }
+
+    #[test]
+    fn utf16_byte_order_marks_are_detected_and_removed() -> io::Result<()> {
+        let text = "\u{feff}src,dst\n10.0.0.1,\u{1F525}\n";
+        let expected = &text[3..];
+        assert_eq!(decode(Encoding::Auto, &utf16(text, false))?, expected);
+        assert_eq!(decode(Encoding::Auto, &utf16(text, true))?, expected);
+        assert_eq!(decode(Encoding::Utf16Le, &utf16(text, false))?, expected);
+        assert_eq!(decode(Encoding::Auto, text.as_bytes())?, expected);
+        // Without a byte order mark, UTF-16 must be given
+        assert_eq!(decode(Encoding::Utf16Be, &utf16(expected, true))?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_input_is_replaced() -> io::Result<()> {
+        // A lone surrogate, and an odd byte at the end
+        let mut bytes = utf16("a", false);
+        bytes.extend([0x00, 0xD8, b'b', 0x00, 0x41]);
+        assert_eq!(decode(Encoding::Utf16Le, &bytes)?, "a\u{fffd}b\u{fffd}");
+        assert_eq!(decode(Encoding::Utf8, b"a\xffb")?, "a\u{fffd}b");
+        Ok(())
+    }
+
+    #[test]
+    fn names_are_parsed() {
+        for encoding in [
+            Encoding::Auto,
+            Encoding::Utf8,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Latin1,
+        ] {
+            assert!(Encoding::parse(&encoding.name().to_uppercase()) == Ok(encoding));
+        }
+        assert!(Encoding::parse("iso-8859-1") == Ok(Encoding::Latin1));
+        assert!(Encoding::parse("ebcdic").is_err_and(|e| e.contains("ebcdic")));
+    }
+}
@@ -0,0 +1,121 @@
+//! Network enrichment data.
+//!
+//! With `--enrichment <file>`, addresses are looked up in a local CSV file describing
+//! networks, so reports can name the network an address belongs to without network
+//! access at run time. The file has a header row; only `network` is required:
+//!
+//! ```csv
+//! network,asn,organization,abuse_email
+//! 192.0.2.0/24,64500,Example Net,abuse@example.net
+//! 2001:db8::/32,64501,Example Six,abuse@six.example
+//! ```
+//!
+//! An address is matched to the most specific network containing it.
+
+use dashboard_aggregator::filter::Cidr;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A network described by the enrichment file.
+///
+/// # Fields
+/// - `cidr`: The network in CIDR notation, from the `network` column.
+/// - `asn`: The autonomous system number announcing the network, if known.
+/// - `organization`: The name of the organization owning the network, if known.
+/// - `abuse_email`: The address to report abuse from the network to, if known.
+#[derive(Deserialize)]
+pub struct Network {
+    #[serde(rename = "network")]
+    pub cidr: String,
+    #[serde(default)]
+    pub asn: String,
+    #[serde(default)]
+    pub organization: String,
+    #[serde(default)]
+    pub abuse_email: String,
+}
+
+/// Key of a network in the lookup table: whether it is IPv4, its prefix length, and its
+/// address with the host bits cleared.
+type Key = (bool, u8, u128);
+
+/// The networks of an enrichment file, indexed for longest-prefix lookups.
+///
+/// # Fields
+/// - `networks`: The networks, in file order.
+/// - `index`: The position in `networks` of each network, by key.
+/// - `prefixes`: The distinct prefix lengths of the IPv4 and IPv6 networks, longest
+///   first, which are tried in turn when looking up an address.
+pub struct Enrichment {
+    networks: Vec<Network>,
+    index: HashMap<Key, usize>,
+    prefixes: [Vec<u8>; 2],
+}
+
+/// Returns the lookup key of `ip` within a network of `prefix` bits.
+fn key(ip: IpAddr, prefix: u8) -> Key {
+    let (v4, bits, width) = match ip {
+        IpAddr::V4(ip) => (true, u128::from(u32::from(ip)), 32),
+        IpAddr::V6(ip) => (false, u128::from(ip), 128),
+    };
+    // Clear the host bits, which are the low `width - prefix` bits
+    let host_bits = width - u32::from(prefix);
+    let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+    (v4, prefix, bits & mask)
+}
+
+impl Enrichment {
+    /// Loads the enrichment file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, or a row is malformed or has an
+    /// invalid network.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid enrichment file {}: {message}", path.display()),
+            )
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let mut enrichment = Self {
+            networks: Vec::new(),
+            index: HashMap::new(),
+            prefixes: [Vec::new(), Vec::new()],
+        };
+        for row in reader.deserialize() {
+            let network: Network = row.map_err(|e| invalid(e.to_string()))?;
+            let cidr = Cidr::parse(&network.cidr).map_err(invalid)?;
+            let key = key(cidr.network, cidr.prefix);
+            let prefixes = &mut enrichment.prefixes[usize::from(!key.0)];
+            if !prefixes.contains(&cidr.prefix) {
+                prefixes.push(cidr.prefix);
+            }
+            // The first row wins if a network is listed twice
+            enrichment
+                .index
+                .entry(key)
+                .or_insert(enrichment.networks.len());
+            enrichment.networks.push(network);
+        }
+        for prefixes in &mut enrichment.prefixes {
+            prefixes.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        Ok(enrichment)
+    }
+
+    /// Returns the most specific network containing `address`, or `None` if `address`
+    /// is not an IP address or no network contains it.
+    pub fn lookup(&self, address: &str) -> Option<&Network> {
+        let ip: IpAddr = address.trim().parse().ok()?;
+        self.prefixes[usize::from(ip.is_ipv6())]
+            .iter()
+            .find_map(|&prefix| self.index.get(&key(ip, prefix)))
+            .map(|&position| &self.networks[position])
+    }
+}
@@ -0,0 +1,80 @@
+//! Per-file error report.
+//!
+//! With `--error-report <file>`, every run writes a JSON report of the log files it read,
+//! so format drift in an export can be traced to the files and lines it starts at
+//! without going through the warnings. For each file, the report lists:
+//!
+//! - `fully_processed`: Whether the file was read to its end. A file that could not be
+//!   opened, or failed part way, e.g. on `--io-timeout`, lists its `error` instead, and
+//!   its counts are `null`.
+//! - `records`: The records counted.
+//! - `skipped`: The records skipped, by cause: `csv` for records that are not valid CSV,
+//!   `bad_timestamp` for records whose date/time cannot be parsed, and `missing_column`
+//!   for records that end before the date/time column.
+//! - `first_error_line` and `last_error_line`: The lines of the first and last skipped
+//!   record, and `first_error` the reason the first was skipped.
+//!
+//! Files left out by the file guards, such as `--max-file-size`, are not read and not
+//! listed. The report is written as-is, without `--compress-output` or `--encrypt-to`,
+//! like the other report files.
+
+use chrono::{Local, SecondsFormat};
+use dashboard_aggregator::AggregatedData;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The files added to the report so far, in the order they were read.
+#[derive(Default)]
+pub struct ErrorReport {
+    files: Vec<Value>,
+}
+
+impl ErrorReport {
+    /// Adds the file at `path`, which was read to its end into `data`.
+    pub fn add(&mut self, path: &Path, data: &AggregatedData) {
+        let skipped = data.skipped();
+        self.files.push(json!({
+            "file": path.display().to_string(),
+            "fully_processed": true,
+            "error": null,
+            "records": data.records(),
+            "skipped": {
+                "total": data.parse_errors(),
+                "csv": skipped.csv,
+                "bad_timestamp": skipped.bad_timestamp,
+                "missing_column": skipped.missing_column,
+            },
+            "first_error_line": skipped.first_line,
+            "last_error_line": skipped.last_line,
+            "first_error": skipped.first_error,
+        }));
+    }
+
+    /// Adds the file at `path`, which could not be read to its end because of `error`.
+    pub fn add_error(&mut self, path: &Path, error: &io::Error) {
+        self.files.push(json!({
+            "file": path.display().to_string(),
+            "fully_processed": false,
+            "error": error.to_string(),
+            "records": null,
+            "skipped": null,
+            "first_error_line": null,
+            "last_error_line": null,
+            "first_error": null,
+        }));
+    }
+
+    /// Writes the report to `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn write(self, path: &Path) -> io::Result<()> {
+        let report = json!({
+            "generated": Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            "files": self.files,
+        });
+        fs::write(path, serde_json::to_string_pretty(&report)?)
+    }
+}
@@ -0,0 +1,206 @@
+//! Reading the firewall events from the Windows event log.
+//!
+//! With `--eventlog <channel>`, the Windows Filtering Platform events of the given
+//! channels, typically `Security`, are queried with `wevtutil` instead of reading log
+//! files. The query selects the events of `winevent::EVENT_IDS` within the `days_back`
+//! window, and the XML output is parsed by the `winevent` input format.
+
+use crate::cli::Options;
+use crate::cutoff;
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::winevent::EVENT_IDS;
+use dashboard_aggregator::{aggregate_reader, AggregatedData};
+use std::io;
+use std::process::Command;
+
+/// Program run to query the event log.
+const WEVTUTIL: &str = "wevtutil";
+
+/// Returns the `XPath` query selecting the parsed events of the last `days_back` days.
+fn query(days_back: i64) -> String {
+    let ids: Vec<String> = EVENT_IDS.iter().map(|id| format!("EventID={id}")).collect();
+    let millis = days_back.saturating_mul(24 * 60 * 60 * 1000);
+    format!(
+        "*[System[({}) and TimeCreated[timediff(@SystemTime) <= {millis}]]]",
+        ids.join(" or ")
+    )
+}
+
+/// Returns the `wevtutil` command querying the parsed events of `channel`.
+fn command(channel: &str, days_back: i64) -> Command {
+    let mut command = Command::new(WEVTUTIL);
+    command
+        .args(["qe", channel])
+        .arg(format!("/q:{}", query(days_back)))
+        .arg("/f:xml");
+    command
+}
+
+/// Runs the query `command` of `channel`, returning its XML output.
+fn run(mut command: Command, channel: &str) -> io::Result<Vec<u8>> {
+    let output = command
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to run {WEVTUTIL}: {e}")))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{WEVTUTIL} failed to query {channel} with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Aggregates the firewall events of `channels` within the `days_back` window.
+///
+/// # Errors
+/// Returns an error if `wevtutil` cannot be run or fails, e.g. without the rights to
+/// read the `Security` log.
+pub fn aggregate(
+    options: &Options,
+    config: &Config,
+    channels: &[String],
+) -> io::Result<AggregatedData> {
+    aggregate_events(options, config, channels, |channel| {
+        run(command(channel, options.days_back), channel)
+    })
+}
+
+/// Aggregates the events that `query` returns for each of `channels`.
+fn aggregate_events(
+    options: &Options,
+    config: &Config,
+    channels: &[String],
+    mut query: impl FnMut(&str) -> io::Result<Vec<u8>>,
+) -> io::Result<AggregatedData> {
+    let mut events = Vec::new();
+    for channel in channels {
+        events.extend(query(channel)?);
+        events.push(b'\n');
+    }
+
+    let mut data = aggregate_reader(
+        options.format.reader(io::Cursor::new(events)),
+        cutoff(options.days_back),
+        &options.filter,
+        config,
+        options.max_memory,
+    )?;
+    if let Some(sample) = &options.filter.sample {
+        data.scale(sample);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, SecondsFormat, Utc};
+    use dashboard_aggregator::input::InputFormat;
+    use serde_json::json;
+
+    /// Returns a blocked inbound connection from `source`, an hour old, as `wevtutil`
+    /// writes it.
+    fn event(source: &str) -> String {
+        let time = (Utc::now() - Duration::hours(1)).to_rfc3339_opts(SecondsFormat::Nanos, true);
+        format!(
+            "<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'><System>\
+             <EventID>5157</EventID><TimeCreated SystemTime='{time}'/>\
+             <EventRecordID>1</EventRecordID></System><EventData>\
+             <Data Name='Direction'>%%14592</Data><Data Name='SourceAddress'>10.0.0.1</Data>\
+             <Data Name='SourcePort'>445</Data><Data Name='DestAddress'>{source}</Data>\
+             <Data Name='DestPort'>50000</Data><Data Name='Protocol'>6</Data></EventData>\
+             </Event>"
+        )
+    }
+
+    fn options() -> Options {
+        Options {
+            format: InputFormat::Winevent,
+            days_back: 1,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn queries_select_the_parsed_events_within_the_window() {
+        assert_eq!(
+            query(2),
+            "*[System[(EventID=5152 or EventID=5153 or EventID=5156 or EventID=5157) \
+             and TimeCreated[timediff(@SystemTime) <= 172800000]]]"
+        );
+        let command = command("Security", 2);
+        assert_eq!(command.get_program(), WEVTUTIL);
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args.len(), 4);
+        assert_eq!(args.first().copied(), Some("qe".as_ref()));
+        assert_eq!(args.get(1).copied(), Some("Security".as_ref()));
+        assert_eq!(
+            args.get(2).copied(),
+            Some(format!("/q:{}", query(2)).as_ref())
+        );
+        assert_eq!(args.get(3).copied(), Some("/f:xml".as_ref()));
+    }
+
+    #[test]
+    fn the_events_of_every_channel_are_aggregated() -> io::Result<()> {
+        let channels = ["Security".to_string(), "ForwardedEvents".to_string()];
+        let mut queried = Vec::new();
+        let data = aggregate_events(&options(), &Config::default(), &channels, |channel| {
+            queried.push(channel.to_string());
+            // Without a trailing newline, as the channels are separated by one; the
+            // second event is named after the channel
+            Ok(format!("{}\n{}", event("203.0.113.5"), event(channel)).into_bytes())
+        })?;
+        assert_eq!(queried, channels);
+        assert_eq!(data.records(), 4);
+        assert_eq!(
+            data.events_json().get("Threat Sources"),
+            Some(&json!({
+                "Source": ["203.0.113.5", "ForwardedEvents", "Security"],
+                "Count": [2, 1, 1]
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_errors_are_returned() {
+        let channels = ["Security".to_string()];
+        let error = aggregate_events(&options(), &Config::default(), &channels, |channel| {
+            Err(io::Error::other(format!(
+                "{WEVTUTIL} failed to query {channel}"
+            )))
+        })
+        .err()
+        .map(|e| e.to_string());
+        assert_eq!(error.as_deref(), Some("wevtutil failed to query Security"));
+    }
+
+    #[test]
+    fn missing_programs_are_reported() {
+        let missing = run(Command::new("/nonexistent/wevtutil"), "Security")
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(missing.starts_with("Failed to run wevtutil: "), "{missing}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_failures_include_the_error_output() {
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "echo 'Access is denied.' >&2; exit 5"]);
+        let failed = run(failing, "Security").err().map(|e| e.to_string());
+        assert_eq!(
+            failed.as_deref(),
+            Some("wevtutil failed to query Security with exit status: 5: Access is denied.")
+        );
+        let mut succeeding = Command::new("echo");
+        succeeding.arg("<Event/>");
+        assert_eq!(
+            run(succeeding, "Security").ok(),
+            Some(b"<Event/>\n".to_vec())
+        );
+    }
+}
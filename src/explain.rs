@@ -0,0 +1,514 @@
+//! Dry-run explanation of how the logs would be processed.
+//!
+//! `--explain` prints the fully resolved configuration (after command line arguments,
+//! environment variables, and the configuration file are combined) and walks through
+//! the first few records of the first matching file, showing which column each field
+//! is read from and whether the record would be counted. No output files are written,
+//! which makes it the first thing to run when onboarding a new log source.
+
+use crate::cli::{Command, Options, SplitOutput};
+use crate::output::SECTIONS_DIR;
+use crate::{cutoff, guard_files, list_log_files};
+use chrono::NaiveDateTime;
+use csv::StringRecord;
+use dashboard_aggregator::config::{Clock, Config};
+use dashboard_aggregator::normalize::Field;
+use dashboard_aggregator::{
+    csv_reader, normalize, CATEGORY_COLUMN, DATETIME_COLUMN, DATETIME_FORMAT, DESTINATION_COLUMN,
+    PRIORITY_COLUMN, SIGNATURE_COLUMN, SOURCE_COLUMN,
+};
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Number of records of the first file that are explained.
+const EXPLAIN_RECORDS: usize = 5;
+
+/// The fields read from each record, with their column positions.
+const FIELDS: [(&str, usize); 6] = [
+    ("priority", PRIORITY_COLUMN),
+    ("signature", SIGNATURE_COLUMN),
+    ("category", CATEGORY_COLUMN),
+    ("date/time", DATETIME_COLUMN),
+    ("source", SOURCE_COLUMN),
+    ("destination", DESTINATION_COLUMN),
+];
+
+/// Prints the explanation for `options` and `config` to stdout.
+///
+/// # Errors
+/// Returns an error if the log directory or the first matching file cannot be read.
+pub fn run(options: &Options, config: &Config) -> io::Result<()> {
+    let mut out = configuration(options, config);
+
+    let files = guard_files(list_log_files(options)?, options, true);
+    let _ = writeln!(out, "\nFiles: {} matched", files.len());
+    if let Some(file) = files.first() {
+        let _ = writeln!(out, "\nFirst file: {}", file.display());
+        if let Some(pattern) = &config.filename_pattern {
+            let dimensions: Vec<_> = pattern
+                .dimensions(file)
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            let _ = writeln!(out, "Dimensions: {}", dimensions.join(", "));
+        }
+        records(&mut out, file, options, config)?;
+    }
+
+    print!("{out}");
+    Ok(())
+}
+
+/// Formats `value`, or `none` if it is not set.
+fn or_none<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "none".to_string(), |value| value.to_string())
+}
+
+/// Describes the subcommand of `options`.
+fn command(options: &Options) -> String {
+    match options.command {
+        Command::Run => options.loop_interval.map_or_else(
+            || "run (once)".to_string(),
+            |interval| format!("run (every {}s, with --loop)", interval.as_secs()),
+        ),
+        Command::Follow => "follow".to_string(),
+        Command::Tui { refresh_secs } => format!("tui (refresh every {refresh_secs}s)"),
+        Command::Bench { iterations } => format!("bench ({iterations} iterations)"),
+        Command::Verify => "verify".to_string(),
+        Command::ReportAbuse => "report-abuse".to_string(),
+        Command::NetflowListen { address } => format!("netflow-listen (on {address})"),
+        Command::Serve {
+            address,
+            refresh_secs,
+        } => format!("serve (on {address}, refresh every {refresh_secs}s)"),
+        Command::Query { ref sql } => format!("query ({sql})"),
+        Command::Schema { threat_sources } => format!(
+            "schema ({})",
+            if threat_sources { "threat_sources.json" } else { "events.json" }
+        ),
+        Command::Baseline { import: true } => "baseline import".to_string(),
+        Command::Baseline { import: false } => "baseline export".to_string(),
+        Command::Rollup => "rollup".to_string(),
+    }
+}
+
+/// Describes the resolved configuration.
+fn configuration(options: &Options, config: &Config) -> String {
+    let mut out = String::from("Configuration\n");
+    let mut line = |name: &str, value: &dyn Display| {
+        let _ = writeln!(out, "  {name:<20}{value}");
+    };
+
+    line("Command:", &command(options));
+    line("Log path:", &options.log_path.display());
+    line("Format:", &options.format);
+    line("Encoding:", &options.encoding);
+    line("Delimiter:", &format!("{:?}", config.delimiter));
+    let prefix = options.format.file_prefix();
+    line(
+        "Files:",
+        &options.file_days_back().map_or_else(
+            || format!("{prefix}* (any modification time)"),
+            |days| format!("{prefix}* modified in the last {days} days"),
+        ),
+    );
+    line(
+        "Record cutoff:",
+        &format!(
+            "after {} (local time)",
+            cutoff(options.days_back).format("%Y-%m-%d %H:%M:%S")
+        ),
+    );
+    let windows: Vec<_> = config
+        .windows
+        .iter()
+        .map(|window| {
+            let start = cutoff(options.days_back) + window.start;
+            format!("{} (after {})", window.label, start.format("%Y-%m-%d %H:%M:%S"))
+        })
+        .collect();
+    line("Windows:", &or_none((!windows.is_empty()).then(|| windows.join(", "))));
+    line("Date/time format:", &DATETIME_FORMAT);
+    let columns: Vec<_> = FIELDS
+        .iter()
+        .map(|(name, column)| format!("{name}={column}"))
+        .chain(config.columns.mapped().iter().map(|(name, header)| format!("{name}={header:?}")))
+        .collect();
+    line("Columns:", &columns.join(", "));
+
+    let filter = &options.filter;
+    line("Source filter:", &or_none(filter.source.as_ref()));
+    line("Destination filter:", &or_none(filter.destination.as_ref()));
+    if filter.rows.is_empty() {
+        line("Row filters:", &"none");
+    }
+    for row in &filter.rows {
+        line("Row filter:", row);
+    }
+    line("Sample:", &or_none(filter.sample.as_ref()));
+    line("Period:", &or_none(filter.period.as_ref()));
+    line("Min priority:", &or_none(filter.min_priority));
+    line(
+        "Filename pattern:",
+        &or_none(config.filename_pattern.as_ref()),
+    );
+
+    let metrics: Vec<_> = config
+        .metrics
+        .iter()
+        .map(|name| {
+            if name == "threat_score" && config.priorities.weights.is_empty() {
+                format!("{name} (inactive: no priority weights)")
+            } else if let Some(title) = config.title(name) {
+                format!("{name} as \"{title}\"")
+            } else {
+                name.clone()
+            }
+        })
+        .collect();
+    line("Metrics:", &metrics.join(", "));
+    line("Priority values:", &config.priorities.values.join(", "));
+    line("Priority labels:", &sorted(&config.priorities.labels));
+    line("Priority weights:", &sorted(&config.priorities.weights));
+    lookups(&mut out, config);
+    limits(&mut out, options);
+    outputs(&mut out, options, config);
+    out
+}
+
+/// Describes the lookups made for the top sources after aggregating, and the reference
+/// data the sections are computed with.
+fn lookups(out: &mut String, config: &Config) {
+    let mut line = |name: &str, value: &dyn Display| {
+        let _ = writeln!(out, "  {name:<20}{value}");
+    };
+
+    line(
+        "Reputation:",
+        &or_none(config.reputation.as_ref().map(|reputation| {
+            format!(
+                "top {} sources via {}, at most {} lookups per minute",
+                reputation.top,
+                reputation.provider.name(),
+                reputation.requests_per_minute
+            )
+        })),
+    );
+
+    line(
+        "WHOIS:",
+        &or_none(config.whois.as_ref().map(|whois| {
+            format!(
+                "top {} sources via {}, at most {} lookups per minute",
+                whois.top, whois.server, whois.requests_per_minute
+            )
+        })),
+    );
+    line(
+        "Public suffixes:",
+        &config.public_suffix_list.as_ref().map_or_else(
+            || format!("built-in ({} rules)", config.suffixes.len()),
+            |list| format!("{} ({} rules)", list.display(), config.suffixes.len()),
+        ),
+    );
+    line(
+        "MAC vendors:",
+        &config.oui_file.as_ref().map_or_else(
+            || format!("built-in ({} prefixes)", config.vendors.len()),
+            |file| format!("{} ({} prefixes)", file.display(), config.vendors.len()),
+        ),
+    );
+    let hours = &config.business_hours;
+    line(
+        "Holidays:",
+        &or_none(
+            hours
+                .holiday_file
+                .as_ref()
+                .map(|file| format!("{} ({} dates)", file.display(), hours.holidays.len())),
+        ),
+    );
+    line(
+        "Expected countries:",
+        &if config.expected_countries.is_empty() {
+            "none".to_string()
+        } else {
+            config.expected_countries.join(", ")
+        },
+    );
+}
+
+/// Describes the limits on the resources used and the files read.
+fn limits(out: &mut String, options: &Options) {
+    let mut line = |name: &str, value: &dyn Display| {
+        let _ = writeln!(out, "  {name:<20}{value}");
+    };
+
+    line(
+        "Memory cap:",
+        &or_none(options.max_memory.map(|bytes| format!("{bytes} bytes"))),
+    );
+    line(
+        "Jobs:",
+        &options
+            .jobs
+            .map_or_else(|| "one per CPU".to_string(), |jobs| jobs.to_string()),
+    );
+    line("Memory-mapped:", &options.mmap);
+    line(
+        "Max file size:",
+        &or_none(options.max_file_size.map(|bytes| format!("{bytes} bytes"))),
+    );
+    line(
+        "Min file age:",
+        &or_none(
+            options
+                .min_file_mtime_age
+                .map(|age| format!("{}s since modification", age.as_secs())),
+        ),
+    );
+    line(
+        "IO timeout:",
+        &or_none(options.io_timeout.map(|timeout| format!("{}s", timeout.as_secs()))),
+    );
+    line("Fail on empty:", &options.fail_on_empty);
+    line("Cap per source:", &or_none(options.cap_per_source));
+    line("Max skip ratio:", &or_none(options.max_skip_ratio));
+}
+
+/// Lists the JSON output files, including the section files of `--split-output`.
+fn output_files(options: &Options) -> String {
+    let events = options.output_path("events.json");
+    let sections = options.output_dir_path(SECTIONS_DIR);
+    let events = match options.split_output {
+        Some(SplitOutput::Also) => format!("{}, {}/*.json", events.display(), sections.display()),
+        Some(SplitOutput::Only) => format!("{}/*.json", sections.display()),
+        None => events.display().to_string(),
+    };
+    let events = match &options.jq {
+        Some(filter) => format!("{events} (jq filter `{}`)", filter.source()),
+        None => events,
+    };
+    format!(
+        "{events}, {}",
+        options.output_path("threat_sources.json").display()
+    )
+}
+
+/// Describes where the outputs are written, and how the reports for people format
+/// numbers and dates.
+fn outputs(out: &mut String, options: &Options, config: &Config) {
+    let mut line = |name: &str, value: &dyn Display| {
+        let _ = writeln!(out, "  {name:<20}{value}");
+    };
+
+    line("Outputs:", &output_files(options));
+    let format = &config.report_format;
+    line(
+        "Report format:",
+        &format!(
+            "dates {}, {} clock, {}",
+            format.date_format,
+            match format.clock {
+                Clock::Hours24 => "24-hour",
+                Clock::Hours12 => "12-hour",
+            },
+            format.thousands_separator.map_or_else(
+                || "no thousands separator".to_string(),
+                |separator| format!("thousands separator `{separator}`")
+            )
+        ),
+    );
+    line(
+        "Anonymize:",
+        &or_none(
+            options
+                .anonymize
+                .map(|mode| match (mode.keyed(), &options.anonymize_key) {
+                    (true, Some(key)) => format!("{} (key {})", mode.name(), key.display()),
+                    _ => mode.name().to_string(),
+                }),
+        ),
+    );
+    line(
+        "Encrypt to:",
+        &if options.encrypt_to.is_empty() {
+            "none".to_string()
+        } else {
+            options
+                .encrypt_to
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        },
+    );
+    line(
+        "Signing key:",
+        &or_none(options.sign_key.as_ref().map(|path| path.display())),
+    );
+    line(
+        "Enrichment:",
+        &or_none(options.enrichment.as_ref().map(|path| path.display())),
+    );
+    line(
+        "GeoIP database:",
+        &or_none(options.geoip.as_ref().map(|path| {
+            let format = if options.geoip_geojson {
+                " (GeoJSON locations)"
+            } else {
+                ""
+            };
+            format!("{}{format}", path.display())
+        })),
+    );
+    let files = [
+        ("GeoJSON file:", &options.geojson),
+        ("Audit log:", &options.audit_log),
+        ("Baseline:", &options.baseline),
+        ("Error report:", &options.error_report),
+    ];
+    for (name, path) in files {
+        line(name, &or_none(path.as_ref().map(|path| path.display())));
+    }
+    line(
+        "MISP export:",
+        &or_none(options.misp_url.as_ref().map(|url| {
+            format!(
+                "{url} (sources with at least {} events)",
+                options.misp_min_count
+            )
+        })),
+    );
+    line(
+        "Excel report:",
+        &or_none(options.xlsx.as_ref().map(|path| path.display())),
+    );
+    line(
+        "STIX bundle:",
+        &or_none(options.stix.as_ref().map(|path| path.display())),
+    );
+    line(
+        "Template:",
+        &or_none(options.template.as_ref().map(|path| {
+            let output = options.template_output.as_ref().map_or_else(
+                || "stdout".to_string(),
+                |output| output.display().to_string(),
+            );
+            format!("{} -> {output}", path.display())
+        })),
+    );
+}
+
+/// Formats a map as `key=value` pairs sorted by key, or `none` if it is empty.
+fn sorted<V: Display>(map: &HashMap<String, V>) -> String {
+    let mut entries: Vec<_> = map
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    if entries.is_empty() {
+        return "none".to_string();
+    }
+    entries.sort();
+    entries.join(", ")
+}
+
+/// Describes how the first records of the file at `path` map to fields, and whether
+/// each one would be counted.
+///
+/// # Errors
+/// Returns an error if the file cannot be read.
+fn records(out: &mut String, path: &Path, options: &Options, config: &Config) -> io::Result<()> {
+    let mut rdr = csv_reader(
+        options
+            .format
+            .reader(options.encoding.reader(File::open(path)?)),
+        config.delimiter_byte(),
+    );
+    let headers = rdr.headers()?.clone();
+    let _ = writeln!(
+        out,
+        "Header: {}",
+        headers.iter().collect::<Vec<_>>().join(", ")
+    );
+
+    let cutoff = cutoff(options.days_back);
+    for (index, result) in rdr.records().take(EXPLAIN_RECORDS).enumerate() {
+        let _ = writeln!(out, "\nRecord {}:", index + 1);
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let _ = writeln!(out, "  => skipped: failed to read record: {e}");
+                continue;
+            }
+        };
+        for (name, column) in FIELDS {
+            let header = headers.get(column).unwrap_or("missing");
+            let _ = writeln!(
+                out,
+                "  {name:<12} column {column:<3} {:<16} {:?}",
+                format!("({header})"),
+                record.get(column).unwrap_or_default()
+            );
+        }
+        for (name, header) in config.columns.mapped() {
+            let column = headers.iter().position(|h| h.trim() == header);
+            let _ = writeln!(
+                out,
+                "  {name:<12} column {:<3} {:<16} {:?}",
+                column.map_or_else(|| "-".to_string(), |column| column.to_string()),
+                format!("({header})"),
+                column.and_then(|column| record.get(column)).unwrap_or("missing")
+            );
+        }
+        let _ = writeln!(out, "  => {}", verdict(&record, &headers, cutoff, options, config));
+    }
+    Ok(())
+}
+
+/// Returns whether `record` would be counted, or the first reason it would be skipped.
+fn verdict(
+    record: &StringRecord,
+    headers: &StringRecord,
+    cutoff: NaiveDateTime,
+    options: &Options,
+    config: &Config,
+) -> String {
+    let field = |column: usize| record.get(column).unwrap_or_default();
+    let Ok(datetime) = NaiveDateTime::parse_from_str(field(DATETIME_COLUMN), DATETIME_FORMAT)
+    else {
+        return format!("skipped: date/time does not match {DATETIME_FORMAT}");
+    };
+    if datetime <= cutoff {
+        return "skipped: older than the record cutoff".to_string();
+    }
+
+    let filter = &options.filter;
+    if !filter.in_period(datetime) {
+        return "skipped: outside the --window period".to_string();
+    }
+    if !filter.meets_priority(&normalize(config, Field::Priority, field(PRIORITY_COLUMN))) {
+        return "skipped: less severe than --min-priority".to_string();
+    }
+    if !filter.matches(field(SOURCE_COLUMN), field(DESTINATION_COLUMN)) {
+        return "skipped: excluded by --src-filter/--dst-filter".to_string();
+    }
+    for row in &filter.rows {
+        match row.resolve(headers) {
+            Ok(positions) if row.matches(record, &positions) => {}
+            Ok(_) => return format!("skipped: does not match --filter {row}"),
+            Err(e) => return format!("error: {e}"),
+        }
+    }
+    if filter
+        .sample
+        .as_ref()
+        .is_some_and(|sample| !sample.keeps(record))
+    {
+        return "skipped: not part of the --sample".to_string();
+    }
+    "counted".to_string()
+}
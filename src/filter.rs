@@ -0,0 +1,735 @@
+//! Record filters applied before aggregation.
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use csv::StringRecord;
+use regex::Regex;
+use std::fmt;
+use std::net::IpAddr;
+
+/// A CIDR block such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// # Fields
+/// - `network`: The network address; host bits may be set.
+/// - `prefix`: The number of leading bits that make up the network.
+pub struct Cidr {
+    pub network: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    /// Parses a CIDR block, treating a bare address as a single-host block.
+    ///
+    /// # Errors
+    /// Returns a human-readable message if the address or prefix length is invalid.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (addr, prefix) = match spec.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (spec, None),
+        };
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid IP address in `{spec}`"))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max_prefix,
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&p| p <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in `{spec}`"))?,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    /// Returns whether `ip` is inside the block. IPv4-mapped IPv6 addresses such as
+    /// `::ffff:10.0.0.1` are compared as the IPv4 address they map, and the other way
+    /// round for IPv6 blocks.
+    #[must_use]
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match (self.network, *ip) {
+            (IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map_or(*ip, IpAddr::V4),
+            (IpAddr::V6(_), IpAddr::V4(v4)) => IpAddr::V6(v4.to_ipv6_mapped()),
+            _ => *ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// An include/exclude list of CIDR blocks, e.g. `10.0.0.0/8,!10.1.2.0/24`.
+///
+/// An address matches if it is inside at least one included block (or no blocks are
+/// included) and inside none of the excluded (`!`-prefixed) blocks. Values that are
+/// not IP addresses only match filters without included blocks.
+pub struct CidrFilter {
+    include: Vec<Cidr>,
+    exclude: Vec<Cidr>,
+}
+
+impl CidrFilter {
+    /// Parses a comma-separated filter expression.
+    ///
+    /// # Errors
+    /// Returns a message naming the first invalid block.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for spec in expression
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            match spec.strip_prefix('!') {
+                Some(excluded) => exclude.push(Cidr::parse(excluded)?),
+                None => include.push(Cidr::parse(spec)?),
+            }
+        }
+        Ok(Self { include, exclude })
+    }
+
+    /// Returns whether `value` passes the filter.
+    #[must_use]
+    pub fn matches(&self, value: &str) -> bool {
+        let Ok(ip) = value.trim().parse::<IpAddr>() else {
+            return self.include.is_empty();
+        };
+        (self.include.is_empty() || self.include.iter().any(|cidr| cidr.contains(&ip)))
+            && !self.exclude.iter().any(|cidr| cidr.contains(&ip))
+    }
+}
+
+impl fmt::Display for CidrFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let included = self.include.iter().map(ToString::to_string);
+        let excluded = self.exclude.iter().map(|cidr| format!("!{cidr}"));
+        write!(
+            f,
+            "{}",
+            included.chain(excluded).collect::<Vec<_>>().join(",")
+        )
+    }
+}
+
+/// A deterministic sampling rate such as `1/100`.
+///
+/// Records are selected by hashing their contents, so the same records are picked on
+/// every run over the same input.
+pub struct Sample {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl Sample {
+    /// Parses a `numerator/denominator` rate, e.g. `1/100`.
+    ///
+    /// # Errors
+    /// Returns a message if the rate is malformed or not in `(0, 1]`.
+    pub fn parse(rate: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid sample rate `{rate}`, expected e.g. 1/100");
+        let (numerator, denominator) = rate.split_once('/').ok_or_else(invalid)?;
+        let numerator: u64 = numerator.trim().parse().map_err(|_| invalid())?;
+        let denominator: u64 = denominator.trim().parse().map_err(|_| invalid())?;
+        if numerator == 0 || numerator > denominator {
+            return Err(invalid());
+        }
+        Ok(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Returns whether `record` is part of the sample.
+    #[must_use]
+    pub fn keeps(&self, record: &StringRecord) -> bool {
+        // FNV-1a over the fields, separated so that ("ab", "c") and ("a", "bc") differ
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for field in record.as_byte_record() {
+            for &byte in field.iter().chain(&[0x1f]) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash % self.denominator < self.numerator
+    }
+
+    /// Scales a count observed in the sample up to an estimate for the full input.
+    #[must_use]
+    pub const fn scale(&self, count: u64) -> u64 {
+        count
+            .saturating_mul(self.denominator)
+            .saturating_add(self.numerator / 2)
+            / self.numerator
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// A calendar period such as a week or a month, aligned to its calendar boundaries
+/// rather than rolling back from the current time.
+///
+/// # Fields
+/// - `start`: The first moment of the period.
+/// - `end`: The first moment after the period.
+/// - `spec`: The period as it was given, e.g. `month:2024-04`.
+pub struct Period {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    spec: String,
+}
+
+impl Period {
+    /// Parses a period relative to `now`: `today`, `yesterday`, `this-week`,
+    /// `last-week`, `this-month`, `last-month`, or a given month such as
+    /// `month:2024-04`. Weeks start on Monday.
+    ///
+    /// # Errors
+    /// Returns a message if the period is unknown or has not started yet.
+    pub fn parse(spec: &str, now: NaiveDateTime) -> Result<Self, String> {
+        let today = now.date();
+        let monday = today - Duration::days(i64::from(today.weekday().num_days_from_monday()));
+        let this_month = today.with_day(1).unwrap_or(today);
+        let (start, end) = match spec {
+            "today" => (today, today + Duration::days(1)),
+            "yesterday" => (today - Duration::days(1), today),
+            "this-week" => (monday, monday + Duration::weeks(1)),
+            "last-week" => (monday - Duration::weeks(1), monday),
+            "this-month" => (this_month, next_month(this_month)),
+            "last-month" => {
+                let last_month = (this_month - Duration::days(1))
+                    .with_day(1)
+                    .unwrap_or(today);
+                (last_month, this_month)
+            }
+            _ => {
+                let month = spec
+                    .strip_prefix("month:")
+                    .and_then(|month| {
+                        NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d").ok()
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid window `{spec}`, expected today, yesterday, this-week, \
+                             last-week, this-month, last-month or month:YYYY-MM"
+                        )
+                    })?;
+                (month, next_month(month))
+            }
+        };
+        if start > today {
+            return Err(format!("Window `{spec}` has not started yet"));
+        }
+        Ok(Self {
+            start: start.and_time(NaiveTime::MIN),
+            end: end.and_time(NaiveTime::MIN),
+            spec: spec.to_string(),
+        })
+    }
+
+    /// Returns whether `datetime` falls within the period.
+    #[must_use]
+    pub fn contains(&self, datetime: NaiveDateTime) -> bool {
+        self.start <= datetime && datetime < self.end
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} to {})",
+            self.spec,
+            self.start.format("%Y-%m-%d %H:%M:%S"),
+            self.end.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// Returns the first day of the month after the one starting on `first`.
+fn next_month(first: NaiveDate) -> NaiveDate {
+    let (year, month) = if first.month() == 12 {
+        (first.year() + 1, 1)
+    } else {
+        (first.year(), first.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(first)
+}
+
+/// Filters deciding which records are aggregated.
+///
+/// # Fields
+/// - `source`: Optional CIDR filter on the threat source address.
+/// - `destination`: Optional CIDR filter on the threat destination address.
+/// - `rows`: Row filter expressions, all of which must match.
+/// - `sample`: Optional sampling rate; counts are scaled up accordingly.
+/// - `period`: Optional calendar period the date/time of the records must fall in.
+/// - `min_priority`: Optional severity floor: the least severe priority that is counted,
+///   priority 1 being the most severe.
+#[derive(Default)]
+pub struct RecordFilter {
+    pub source: Option<CidrFilter>,
+    pub destination: Option<CidrFilter>,
+    pub rows: Vec<RowFilter>,
+    pub sample: Option<Sample>,
+    pub period: Option<Period>,
+    pub min_priority: Option<u32>,
+}
+
+impl RecordFilter {
+    /// Returns whether a record with the given source and destination should be counted.
+    #[must_use]
+    pub fn matches(&self, source: &str, destination: &str) -> bool {
+        self.source.as_ref().map_or(true, |f| f.matches(source))
+            && self
+                .destination
+                .as_ref()
+                .map_or(true, |f| f.matches(destination))
+    }
+
+    /// Returns whether a record with the given priority is at least as severe as the
+    /// severity floor, if one is set. A priority that is not a number is below any floor.
+    #[must_use]
+    pub fn meets_priority(&self, priority: &str) -> bool {
+        self.min_priority.map_or(true, |floor| {
+            priority
+                .trim()
+                .parse::<u32>()
+                .is_ok_and(|priority| priority <= floor)
+        })
+    }
+
+    /// Returns whether a record with the date/time `datetime` falls in the period, if
+    /// one is set.
+    #[must_use]
+    pub fn in_period(&self, datetime: NaiveDateTime) -> bool {
+        self.period
+            .as_ref()
+            .map_or(true, |period| period.contains(datetime))
+    }
+}
+
+/// The column a row filter predicate tests: a header name or a zero-based index.
+enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/// A boolean expression over column predicates.
+enum Expr {
+    /// `col:Name=~"regex"` (or `!~` when `negate` is set).
+    Matches {
+        slot: usize,
+        regex: Regex,
+        negate: bool,
+    },
+    /// `col:Name=="value"` (or `!=` when `negate` is set).
+    Equals {
+        slot: usize,
+        value: String,
+        negate: bool,
+    },
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+    Not(Box<Self>),
+}
+
+/// A row filter expression such as
+/// `col:Signature=~"SSH.*brute" AND (col:Priority=="1" OR col:Priority=="2")`.
+///
+/// Predicates compare a column (by header name or zero-based index) against a regex
+/// (`=~`, `!~`) or an exact value (`==`, `!=`), and can be combined with `AND`/`&&`,
+/// `OR`/`||`, `NOT`/`!`, and parentheses. `AND` binds tighter than `OR`.
+pub struct RowFilter {
+    expr: Expr,
+    columns: Vec<Column>,
+    source: String,
+}
+
+impl RowFilter {
+    /// Parses a row filter expression.
+    ///
+    /// # Errors
+    /// Returns a message describing the syntax error or invalid regex.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let mut parser = ExprParser {
+            chars: expression.chars().collect(),
+            pos: 0,
+            columns: Vec::new(),
+        };
+        let expr = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(format!(
+                "Unexpected `{}` in filter expression",
+                parser.chars[parser.pos..].iter().collect::<String>()
+            ));
+        }
+        Ok(Self {
+            expr,
+            columns: parser.columns,
+            source: expression.trim().to_string(),
+        })
+    }
+
+    /// Resolves the columns referenced by the expression against a file's header row,
+    /// returning their positions for use with `matches`.
+    ///
+    /// # Errors
+    /// Returns a message naming the first column that is not in `headers`.
+    pub fn resolve(&self, headers: &StringRecord) -> Result<Vec<usize>, String> {
+        self.columns
+            .iter()
+            .map(|column| match column {
+                Column::Index(index) => Ok(*index),
+                Column::Name(name) => headers
+                    .iter()
+                    .position(|header| header.trim().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| format!("Unknown filter column `{name}`")),
+            })
+            .collect()
+    }
+
+    /// Returns whether `record` satisfies the expression, given the column positions
+    /// returned by `resolve`. Missing fields compare as empty strings.
+    #[must_use]
+    pub fn matches(&self, record: &StringRecord, positions: &[usize]) -> bool {
+        evaluate(&self.expr, record, positions)
+    }
+}
+
+impl fmt::Display for RowFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn evaluate(expr: &Expr, record: &StringRecord, positions: &[usize]) -> bool {
+    let field = |slot: usize| record.get(positions[slot]).unwrap_or_default();
+    match expr {
+        Expr::Matches {
+            slot,
+            regex,
+            negate,
+        } => regex.is_match(field(*slot)) != *negate,
+        Expr::Equals {
+            slot,
+            value,
+            negate,
+        } => (field(*slot) == value) != *negate,
+        Expr::And(a, b) => evaluate(a, record, positions) && evaluate(b, record, positions),
+        Expr::Or(a, b) => evaluate(a, record, positions) || evaluate(b, record, positions),
+        Expr::Not(inner) => !evaluate(inner, record, positions),
+    }
+}
+
+struct ExprParser {
+    chars: Vec<char>,
+    pos: usize,
+    columns: Vec<Column>,
+}
+
+impl ExprParser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `token` (case-insensitively) if it is next, after whitespace.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let len = token.chars().count();
+        let Some(next) = self.chars.get(self.pos..self.pos + len) else {
+            return false;
+        };
+        let matched = next.iter().collect::<String>().eq_ignore_ascii_case(token);
+        // Keywords must not run into a following word, e.g. `ORDER`
+        let is_keyword = token.chars().all(char::is_alphabetic);
+        let boundary = !is_keyword
+            || !self
+                .chars
+                .get(self.pos + len)
+                .is_some_and(|c| c.is_alphanumeric());
+        if matched && boundary {
+            self.pos += len;
+        }
+        matched && boundary
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.eat("||") || self.eat("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.eat("&&") || self.eat("AND") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.eat("NOT") || self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        if self.eat("(") {
+            let expr = self.parse_or()?;
+            if !self.eat(")") {
+                return Err("Missing ) in filter expression".to_string());
+            }
+            return Ok(expr);
+        }
+        self.parse_predicate()
+    }
+
+    fn parse_predicate(&mut self) -> Result<Expr, String> {
+        if !self.eat("col:") {
+            return Err("Expected `col:<name>` in filter expression".to_string());
+        }
+
+        let name = if matches!(self.chars.get(self.pos), Some('"' | '\'')) {
+            let name = self.parse_quoted()?;
+            self.skip_whitespace();
+            name
+        } else {
+            let start = self.pos;
+            while self
+                .chars
+                .get(self.pos)
+                .is_some_and(|&c| c != '=' && c != '!' && c != '~')
+            {
+                self.pos += 1;
+            }
+            self.chars[start..self.pos].iter().collect()
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Missing column name in filter expression".to_string());
+        }
+
+        let op: String = self
+            .chars
+            .get(self.pos..self.pos + 2)
+            .map(|op| op.iter().collect())
+            .unwrap_or_default();
+        self.pos += 2;
+        self.skip_whitespace();
+        let value = self.parse_value()?;
+
+        let slot = self.columns.len();
+        self.columns.push(
+            name.parse()
+                .map_or_else(|_| Column::Name(name.to_string()), Column::Index),
+        );
+
+        match op.as_str() {
+            "=~" | "!~" => Ok(Expr::Matches {
+                slot,
+                regex: Regex::new(&value).map_err(|e| e.to_string())?,
+                negate: op == "!~",
+            }),
+            "==" | "!=" => Ok(Expr::Equals {
+                slot,
+                value,
+                negate: op == "!=",
+            }),
+            _ => Err(format!(
+                "Expected =~, !~, == or != after `col:{name}` in filter expression"
+            )),
+        }
+    }
+
+    /// Parses the value of a predicate: a quoted string, or else a bare word up to the
+    /// next whitespace or `)`, such as `1.2.3.4`.
+    fn parse_value(&mut self) -> Result<String, String> {
+        if matches!(self.chars.get(self.pos), Some('"' | '\'')) {
+            return self.parse_quoted();
+        }
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|&c| !c.is_whitespace() && c != ')')
+        {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err("Expected a value in filter expression".to_string());
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Parses a single- or double-quoted string, where `\` escapes the quote character.
+    /// Other backslashes are kept so regex escapes like `\d` pass through unchanged.
+    fn parse_quoted(&mut self) -> Result<String, String> {
+        let Some(&quote @ ('"' | '\'')) = self.chars.get(self.pos) else {
+            return Err("Expected a quoted value in filter expression".to_string());
+        };
+        self.pos += 1;
+
+        let mut value = String::new();
+        loop {
+            match self.chars.get(self.pos) {
+                None => return Err("Unterminated string in filter expression".to_string()),
+                Some('\\') if self.chars.get(self.pos + 1) == Some(&quote) => {
+                    value.push(quote);
+                    self.pos += 2;
+                }
+                Some(&c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                Some(&c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns whether the row filter `expression` matches `record` under `headers`.
+    fn row_matches(expression: &str, headers: &[&str], record: &[&str]) -> Result<bool, String> {
+        let filter = RowFilter::parse(expression)?;
+        let positions = filter.resolve(&StringRecord::from(headers.to_vec()))?;
+        Ok(filter.matches(&StringRecord::from(record.to_vec()), &positions))
+    }
+
+    #[test]
+    fn quoted_column_names_may_be_followed_by_whitespace() -> Result<(), String> {
+        let headers = ["Src IP", "Action"];
+        let record = ["1.2.3.4", "block"];
+        assert!(row_matches(r#"col:"Src IP" == 1.2.3.4"#, &headers, &record)?);
+        assert!(row_matches(r#"col:'Src IP'=="1.2.3.4""#, &headers, &record)?);
+        assert!(row_matches(r#"col:"Src IP" != "1.2.3.5""#, &headers, &record)?);
+        assert!(row_matches(r#"col:"src ip" =~ "^1\.2\.""#, &headers, &record)?);
+        Ok(())
+    }
+
+    #[test]
+    fn bare_values_end_at_whitespace_or_parentheses() -> Result<(), String> {
+        let headers = ["Priority", "Action"];
+        let record = ["1", "block"];
+        assert!(row_matches("(col:Priority==1) AND col:Action == block", &headers, &record)?);
+        assert!(!row_matches("col:Priority==2 OR NOT col:1==block", &headers, &record)?);
+        assert!(RowFilter::parse("col:Priority==").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn regexes_match_and_report_syntax_errors() -> Result<(), String> {
+        let headers = ["Signature"];
+        let record = ["ET SCAN Potential SSH Scan"];
+        assert!(row_matches(r#"col:0 =~ "(?i)\bssh\b""#, &headers, &record)?);
+        assert!(row_matches(r#"col:0 !~ "^GPL""#, &headers, &record)?);
+        assert!(!row_matches(r#"col:0 =~ "Scan$" AND col:0 =~ "^ET POLICY""#, &headers, &record)?);
+        assert!(RowFilter::parse(r#"col:0 =~ "(ssh""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_columns_are_reported() -> Result<(), String> {
+        let filter = RowFilter::parse(r#"col:Missing=="x""#)?;
+        assert!(filter.resolve(&StringRecord::from(vec!["Priority"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cidr_blocks_match_by_prefix() -> Result<(), String> {
+        let cases = [
+            ("0.0.0.0/0", "203.0.113.5", true),
+            ("0.0.0.0/0", "2001:db8::1", false),
+            ("::/0", "2001:db8::1", true),
+            ("10.0.0.0/8", "10.255.0.1", true),
+            ("10.0.0.0/8", "11.0.0.1", false),
+            ("10.0.0.1/32", "10.0.0.1", true),
+            ("10.0.0.1/32", "10.0.0.2", false),
+            ("10.0.0.1", "10.0.0.1", true),
+            ("2001:db8::1/128", "2001:db8::1", true),
+            ("2001:db8::1/128", "2001:db8::2", false),
+            ("2001:db8::/32", "2001:db8:ffff::1", true),
+            // IPv4-mapped IPv6 addresses match IPv4 blocks, and the other way round
+            ("10.0.0.0/8", "::ffff:10.1.2.3", true),
+            ("10.0.0.0/8", "::ffff:11.1.2.3", false),
+            ("::ffff:10.0.0.0/104", "10.1.2.3", true),
+            ("::ffff:0:0/96", "192.0.2.1", true),
+            ("10.0.0.0/8", "::a01:203", false),
+        ];
+        for (block, address, expected) in cases {
+            let ip: IpAddr = address.parse().map_err(|_| address.to_string())?;
+            let contains = Cidr::parse(block)?.contains(&ip);
+            assert_eq!(contains, expected, "{address} in {block}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_cidr_blocks_are_rejected() {
+        let error = |block| Cidr::parse(block).err().unwrap_or_default();
+        for block in [
+            "10.0.0.0/33",
+            "2001:db8::/129",
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+        ] {
+            assert_eq!(error(block), format!("Invalid prefix length in `{block}`"));
+        }
+        assert_eq!(error("10.0.0/8"), "Invalid IP address in `10.0.0/8`");
+        assert_eq!(error("/8"), "Invalid IP address in `/8`");
+        let filter = CidrFilter::parse("10.0.0.0/8, !10.1.0.0/16, 10.1.2.0/33")
+            .err()
+            .unwrap_or_default();
+        assert_eq!(filter, "Invalid prefix length in `10.1.2.0/33`");
+    }
+
+    #[test]
+    fn cidr_filters_include_and_exclude_blocks() -> Result<(), String> {
+        let filter = CidrFilter::parse("10.0.0.0/8, !10.1.0.0/16, 2001:db8::/32")?;
+        assert!(filter.matches(" 10.2.0.1 "));
+        assert!(filter.matches("::ffff:10.2.0.1"));
+        assert!(!filter.matches("10.1.0.1"));
+        assert!(!filter.matches("::ffff:10.1.0.1"));
+        assert!(filter.matches("2001:db8::1"));
+        assert!(!filter.matches("192.0.2.1"));
+        assert!(!filter.matches("host.example"));
+        assert_eq!(filter.to_string(), "10.0.0.0/8,2001:db8::/32,!10.1.0.0/16");
+        let excluding = CidrFilter::parse("!0.0.0.0/0")?;
+        assert!(!excluding.matches("192.0.2.1"));
+        assert!(excluding.matches("2001:db8::1"));
+        assert!(excluding.matches("host.example"));
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Follow mode for live log files.
+//!
+//! `--follow` aggregates the matching files once, then keeps the newest one open and reads
+//! lines as they are appended, like `tail -F`. New records are merged into the in-memory
+//! aggregates, and the output files are rewritten after every change. When the followed
+//! file is rotated (renamed away and recreated, or truncated) it is reopened from the
+//! start, and when a newer matching file appears, following switches to that file.
+//!
+//! Records are only ever added: the aggregates grow for as long as the process runs, and
+//! records are not subtracted again once they fall out of the `days_back` window.
+
+use crate::anonymize::Anonymizer;
+use crate::cli::Options;
+use crate::systemd;
+use crate::{
+    aggregate, cutoff, guard_files, list_log_files, manifest, write_json, write_reports,
+    FileSinks,
+};
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::input::InputFormat;
+use dashboard_aggregator::{aggregate_reader, log, AggregatedData};
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// How long to wait between checks for appended lines.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follows the newest matching log file until the process is interrupted.
+///
+/// # Errors
+/// Returns an error if the log directory or the followed file cannot be read, or the
+/// outputs cannot be written.
+pub fn run(options: &Options, config: &Config) -> io::Result<()> {
+    let anonymizer = Anonymizer::load(options)?;
+    let mut files = list_log_files(options)?;
+    // The followed file is read by the tail instead, so its lines are not counted twice
+    let followed = newest(&files);
+    files.retain(|path| Some(path) != followed.as_ref());
+    // The followed file is expected to be written to, so only the others are guarded
+    let files = guard_files(files, options, true);
+    let headers = options.format == InputFormat::Csv;
+    let mut tail = followed.map(|path| Tail::open(path, headers)).transpose()?;
+
+    let (mut data, errors) = aggregate(&files, options, config, true, FileSinks::default());
+    for e in &errors {
+        log::error(e);
+    }
+    if let Some(anonymizer) = &anonymizer {
+        anonymizer.apply(&mut data);
+    }
+    let mut file_count = files.len() - errors.len();
+    // Files that have already been counted are never switched to again
+    let mut seen: HashSet<PathBuf> = files.into_iter().collect();
+    seen.extend(tail.as_ref().map(|tail: &Tail| tail.path.clone()));
+    let mut changed = true;
+    systemd::ready(&format!(
+        "Following the newest log file in {}",
+        options.log_path.display()
+    ));
+
+    loop {
+        let newest = newest(&list_log_files(options)?);
+
+        if let Some(tail) = &mut tail {
+            for lines in tail.poll()? {
+                let mut chunk = aggregate_reader(
+                    options
+                        .format
+                        .reader(options.encoding.reader(io::Cursor::new(lines))),
+                    cutoff(options.days_back),
+                    &options.filter,
+                    config,
+                    options.max_memory,
+                )?;
+                if let Some(pattern) = &config.filename_pattern {
+                    chunk.tag(&pattern.dimensions(&tail.path));
+                }
+                if let Some(anonymizer) = &anonymizer {
+                    anonymizer.apply(&mut chunk);
+                }
+                merge(&mut data, &chunk, options);
+                changed = true;
+            }
+        }
+
+        // Switch to a newer file once the current one has been read to the end
+        if let Some(newest) = newest {
+            if seen.insert(newest.clone()) {
+                tail = Some(Tail::open(newest, headers)?);
+                file_count += 1;
+                continue;
+            }
+        }
+
+        if changed {
+            let files = file_count + errors.len();
+            let section_files = write_json(options, &data, Vec::new(), files, errors.len())?;
+            write_reports(options, config, &data, file_count, false)?;
+            manifest::write(options, &section_files)?;
+            changed = false;
+        }
+        systemd::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Merges `chunk` into `data`, keeping within the memory limit if there is one.
+pub fn merge(data: &mut AggregatedData, chunk: &AggregatedData, options: &Options) {
+    data.merge(chunk);
+    if let Some(limit) = options.max_memory {
+        data.enforce_memory_limit(limit);
+    }
+}
+
+/// Returns the most recently modified of `files`.
+fn newest(files: &[PathBuf]) -> Option<PathBuf> {
+    files
+        .iter()
+        .max_by_key(|path| {
+            fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        })
+        .cloned()
+}
+
+/// The identity of an open file: its device and inode numbers.
+#[cfg(unix)]
+type FileId = (u64, u64);
+
+/// The identity of an open file: its creation time, where available.
+#[cfg(not(unix))]
+type FileId = Option<SystemTime>;
+
+/// Identifies the file behind `metadata`, to notice when its path is replaced.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+/// Identifies the file behind `metadata`, to notice when its path is replaced.
+#[cfg(not(unix))]
+fn file_id(metadata: &Metadata) -> FileId {
+    metadata.created().ok()
+}
+
+/// A followed file and how far it has been read.
+///
+/// # Fields
+/// - `path`: The path of the file.
+/// - `file`: The open file, positioned after the last byte read.
+/// - `id`: The identity of the open file, to notice when `path` is replaced.
+/// - `header`: The CSV header line, once it has been read. Always empty for formats
+///   without a header line, which get one when normalized instead.
+/// - `has_header`: Whether the first line of the file is a header line.
+/// - `pending`: The bytes read after the last complete line.
+struct Tail {
+    path: PathBuf,
+    file: File,
+    id: FileId,
+    header: Vec<u8>,
+    has_header: bool,
+    pending: Vec<u8>,
+}
+
+impl Tail {
+    /// Opens the file at `path` for following from the start. `has_header` tells whether
+    /// its first line is a header line to repeat before every batch of new lines.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    fn open(path: PathBuf, has_header: bool) -> io::Result<Self> {
+        log::info(format_args!("Following file: {}", path.display()));
+        let file = File::open(&path)?;
+        let id = file_id(&file.metadata()?);
+        Ok(Self {
+            path,
+            file,
+            id,
+            header: Vec::new(),
+            has_header,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Reads the lines appended since the last poll, reopening the file if it was rotated.
+    ///
+    /// # Returns
+    /// The new complete lines as documents, each starting with the header line of the
+    /// file the lines were read from, if it has one. Empty if there are no new lines.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or reopened.
+    fn poll(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let position = self.file.stream_position()?;
+        // A missing path means the file was renamed away and not recreated yet, so the
+        // old file is read until it is
+        let (replaced, truncated) = fs::metadata(&self.path).map_or((false, false), |meta| {
+            let replaced = file_id(&meta) != self.id;
+            (replaced, !replaced && meta.len() < position)
+        });
+
+        let mut documents = Vec::new();
+        // The remaining lines of a renamed file are read before switching to the new one
+        if !truncated {
+            documents.extend(self.read_lines()?);
+        }
+        if replaced || truncated {
+            *self = Self::open(self.path.clone(), self.has_header)?;
+            documents.extend(self.read_lines()?);
+        }
+        Ok(documents)
+    }
+
+    /// Reads to the end of the file and returns the complete lines that were read,
+    /// preceded by the header line, or `None` if there are none.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read.
+    fn read_lines(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.file.read_to_end(&mut self.pending)?;
+        let Some(end) = self.pending.iter().rposition(|&byte| byte == b'\n') else {
+            return Ok(None);
+        };
+        let mut lines: Vec<u8> = self.pending.drain(..=end).collect();
+        if self.has_header && self.header.is_empty() {
+            let header_end = lines
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(lines.len(), |index| index + 1);
+            self.header = lines.drain(..header_end).collect();
+        }
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        let mut csv = self.header.clone();
+        csv.extend(lines);
+        Ok(Some(csv))
+    }
+}
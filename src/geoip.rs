@@ -0,0 +1,478 @@
+//! `GeoIP` lookups of the threat sources.
+//!
+//! With `--geoip <file>`, the sources are looked up in a local `MaxMind` DB file, such as
+//! `GeoLite2-City.mmdb` or `dbip-city-lite.mmdb`, and `events.json` gains two sections
+//! for the dashboard's map widget:
+//!
+//! - `Geo Sources`: the top sources with their country, city and coordinates.
+//! - `Geo Locations`: the events of all sources added up per country and city, sorted
+//!   by count; with `--geoip-geojson` a `GeoJSON` `FeatureCollection` of points instead.
+//!
+//! If the configuration lists `expected_countries`, a `Country Policy` section also
+//! compares the events from those countries with the rest, for geo-blocking reviews.
+//!
+//! Databases are read with the [`maxminddb`] crate. A country-level database gives no
+//! city or coordinates; those are then `null`.
+
+use crate::cli::Options;
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::metric::Addresses;
+use dashboard_aggregator::{log, AggregatedData};
+use maxminddb::{geoip2, Reader};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Number of entries in the `Geo Sources` section.
+const TOP_SOURCES: usize = 10;
+
+/// Number of sources looked up for the `--geojson` file.
+const GEOJSON_SOURCES: usize = 100;
+
+/// The location of an address.
+///
+/// # Fields
+/// - `country`: The ISO 3166-1 code of the country, if known.
+/// - `city`: The English name of the city, if known.
+/// - `latitude`: The latitude of the city or country, if known.
+/// - `longitude`: The longitude of the city or country, if known.
+#[derive(Default)]
+pub struct Location {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+impl Location {
+    /// Returns the coordinates as a `GeoJSON` position, `[longitude, latitude]`, if known.
+    pub fn position(&self) -> Option<[f64; 2]> {
+        Some([self.longitude?, self.latitude?])
+    }
+}
+
+/// A `MaxMind` DB file loaded into memory.
+pub struct Database {
+    reader: Reader<Vec<u8>>,
+}
+
+impl Database {
+    /// Loads the database at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a `MaxMind` DB file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read GeoIP database {}: {e}", path.display()),
+            )
+        })?;
+        let reader = Reader::from_source(bytes).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid GeoIP database {}: {e}", path.display()),
+            )
+        })?;
+        Ok(Self { reader })
+    }
+
+    /// Looks up the location of `ip`.
+    ///
+    /// # Returns
+    /// The location, or `None` if the database has no entry for the address or its
+    /// entry cannot be decoded.
+    pub fn lookup(&self, ip: IpAddr) -> Option<Location> {
+        let record: geoip2::City = self.reader.lookup(ip).ok()?;
+        let location = record.location.as_ref();
+        Some(Location {
+            country: record
+                .country
+                .and_then(|country| country.iso_code)
+                .map(String::from),
+            city: record
+                .city
+                .and_then(|city| city.names)
+                .and_then(|names| names.get("en").map(|name| (*name).to_string())),
+            latitude: location.and_then(|location| location.latitude),
+            longitude: location.and_then(|location| location.longitude),
+        })
+    }
+}
+
+/// Looks up the sources of `data` in the `--geoip` database, if one is given, and
+/// writes the `--geojson` file of their locations if requested.
+///
+/// Lookups use the addresses as logged, so this runs before anonymization. With
+/// `--anonymize`, only the `Geo Locations` section is returned, as the location of a
+/// source would tell the anonymized addresses apart.
+///
+/// # Returns
+/// The `Geo Sources`, `Geo Locations` and `Country Policy` sections, or none without
+/// `--geoip`.
+///
+/// # Errors
+/// Returns an error if the database cannot be loaded or the `GeoJSON` file cannot be
+/// written.
+pub fn sections(
+    options: &Options,
+    config: &Config,
+    data: &AggregatedData,
+) -> io::Result<Vec<(&'static str, Value)>> {
+    let Some(path) = &options.geoip else {
+        return Ok(Vec::new());
+    };
+    let database = Database::open(path)?;
+    let Some(sources) = data.metric::<Addresses>("Threat Sources") else {
+        return Ok(Vec::new());
+    };
+    if let Some(geojson_path) = &options.geojson {
+        let geojson = source_features(&database, sources);
+        fs::write(geojson_path, serde_json::to_string_pretty(&geojson)?)?;
+        log::info(format_args!("GeoJSON saved to {}", geojson_path.display()));
+    }
+    let mut sections = Vec::new();
+    if options.anonymize.is_some() {
+        log::warn("the Geo Sources section is left out for anonymized addresses");
+    } else {
+        sections.push(("Geo Sources", geo_sources(&database, sources)));
+    }
+    // Sources that cannot be located get an unknown location
+    let located: Vec<_> = sources
+        .counts
+        .iter()
+        .map(|(source, &count)| (locate(&database, source).unwrap_or_default(), count))
+        .collect();
+    sections.push((
+        "Geo Locations",
+        geo_locations(&located, options.geoip_geojson),
+    ));
+    if !config.expected_countries.is_empty() {
+        sections.push((
+            "Country Policy",
+            country_policy(&located, &config.expected_countries),
+        ));
+    }
+    Ok(sections)
+}
+
+/// Looks up the location of `source`, if it is an address the database has.
+fn locate(database: &Database, source: &str) -> Option<Location> {
+    source
+        .trim()
+        .parse()
+        .ok()
+        .and_then(|ip| database.lookup(ip))
+}
+
+/// Returns the `Geo Sources` section: the top sources with their locations.
+fn geo_sources(database: &Database, sources: &Addresses) -> Value {
+    let top = sources.top(TOP_SOURCES);
+    let located: Vec<_> = top
+        .iter()
+        .map(|(source, _)| locate(database, source).unwrap_or_default())
+        .collect();
+    json!({
+        "Source": top.iter().map(|(source, _)| source).collect::<Vec<_>>(),
+        "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>(),
+        "Country": located.iter().map(|location| &location.country).collect::<Vec<_>>(),
+        "City": located.iter().map(|location| &location.city).collect::<Vec<_>>(),
+        "Latitude": located.iter().map(|location| location.latitude).collect::<Vec<_>>(),
+        "Longitude": located.iter().map(|location| location.longitude).collect::<Vec<_>>(),
+    })
+}
+
+/// Returns a `GeoJSON` `FeatureCollection` with a point for each of the top
+/// `GEOJSON_SOURCES` sources that can be located, with the source, its event count,
+/// country and city as properties.
+fn source_features(database: &Database, sources: &Addresses) -> Value {
+    let top = sources.top(GEOJSON_SOURCES);
+    feature_collection(top.iter().filter_map(|(source, count)| {
+        let location = locate(database, source)?;
+        let properties = json!({
+            "source": source,
+            "count": count,
+            "country": location.country,
+            "city": location.city,
+        });
+        location.position().map(|position| (position, properties))
+    }))
+}
+
+/// Returns the `Geo Locations` section: the events of the `located` sources added up
+/// per location, sorted by count, as columns or as a `GeoJSON` `FeatureCollection`.
+fn geo_locations(located: &[(Location, u32)], geojson: bool) -> Value {
+    let mut counts: HashMap<_, (&Location, u64)> = HashMap::new();
+    for (location, count) in located {
+        let key = (
+            &location.country,
+            &location.city,
+            location.latitude.map(f64::to_bits),
+            location.longitude.map(f64::to_bits),
+        );
+        counts.entry(key).or_insert((location, 0)).1 += u64::from(*count);
+    }
+    let mut locations: Vec<_> = counts.into_values().collect();
+    locations.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| a.0.country.cmp(&b.0.country))
+            .then_with(|| a.0.city.cmp(&b.0.city))
+            .then_with(|| {
+                let position =
+                    |l: &Location| (l.latitude.map(f64::to_bits), l.longitude.map(f64::to_bits));
+                position(a.0).cmp(&position(b.0))
+            })
+    });
+
+    if geojson {
+        return feature_collection(locations.iter().filter_map(|(location, count)| {
+            let properties = json!({
+                "country": location.country,
+                "city": location.city,
+                "count": count,
+            });
+            location.position().map(|position| (position, properties))
+        }));
+    }
+    json!({
+        "Country": locations.iter().map(|(location, _)| &location.country).collect::<Vec<_>>(),
+        "City": locations.iter().map(|(location, _)| &location.city).collect::<Vec<_>>(),
+        "Latitude": locations.iter().map(|(location, _)| location.latitude).collect::<Vec<_>>(),
+        "Longitude": locations.iter().map(|(location, _)| location.longitude).collect::<Vec<_>>(),
+        "Count": locations.iter().map(|(_, count)| count).collect::<Vec<_>>(),
+    })
+}
+
+/// Returns the `Country Policy` section: the events and sources from the `expected`
+/// countries, from other countries and from unknown locations, and the events and
+/// sources of each unexpected country, sorted by events.
+fn country_policy(located: &[(Location, u32)], expected: &[String]) -> Value {
+    // Events and sources of each class: expected, unexpected and unknown
+    let mut classes = [(0_u64, 0_u64); 3];
+    let mut unexpected: HashMap<&str, (u64, u64)> = HashMap::new();
+    for (location, count) in located {
+        let count = u64::from(*count);
+        let class = match &location.country {
+            Some(country)
+                if expected
+                    .iter()
+                    .any(|code| code.eq_ignore_ascii_case(country)) =>
+            {
+                0
+            }
+            Some(country) => {
+                let entry = unexpected.entry(country).or_default();
+                entry.0 += count;
+                entry.1 += 1;
+                1
+            }
+            None => 2,
+        };
+        classes[class].0 += count;
+        classes[class].1 += 1;
+    }
+    let mut unexpected: Vec<_> = unexpected.into_iter().collect();
+    unexpected.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+    json!({
+        "Class": ["Expected", "Unexpected", "Unknown"],
+        "Count": classes.iter().map(|(events, _)| events).collect::<Vec<_>>(),
+        "Sources": classes.iter().map(|(_, sources)| sources).collect::<Vec<_>>(),
+        "Unexpected Countries": {
+            "Country": unexpected.iter().map(|(country, _)| country).collect::<Vec<_>>(),
+            "Count": unexpected.iter().map(|(_, (events, _))| events).collect::<Vec<_>>(),
+            "Sources": unexpected.iter().map(|(_, (_, sources))| sources).collect::<Vec<_>>(),
+        },
+    })
+}
+
+/// Returns a `GeoJSON` `FeatureCollection` of points at the given positions, with the
+/// given properties.
+pub fn feature_collection(points: impl Iterator<Item = ([f64; 2], Value)>) -> Value {
+    let features: Vec<_> = points
+        .map(|(position, properties)| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": position },
+                "properties": properties,
+            })
+        })
+        .collect();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// Marks the start of the metadata at the end of the file.
+    const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+
+    /// Number of zero bytes between the search tree and the data section.
+    const DATA_SEPARATOR: usize = 16;
+
+    /// Returns the control byte and extended type byte of a value of `kind` and `size`.
+    fn control(kind: u8, size: u8) -> Vec<u8> {
+        if kind > 7 {
+            vec![size, kind - 7]
+        } else {
+            vec![(kind << 5) | size]
+        }
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut bytes = control(2, u8::try_from(text.len()).unwrap_or_default());
+        bytes.extend_from_slice(text.as_bytes());
+        bytes
+    }
+
+    fn uint32(value: u32) -> Vec<u8> {
+        let mut bytes = control(6, 4);
+        bytes.extend_from_slice(&value.to_be_bytes());
+        bytes
+    }
+
+    fn double(value: f64) -> Vec<u8> {
+        let mut bytes = control(3, 8);
+        bytes.extend_from_slice(&value.to_bits().to_be_bytes());
+        bytes
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = control(7, u8::try_from(entries.len()).unwrap_or_default());
+        for (key, value) in entries {
+            bytes.extend(string(key));
+            bytes.extend_from_slice(value);
+        }
+        bytes
+    }
+
+    fn array(values: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = control(11, u8::try_from(values.len()).unwrap_or_default());
+        bytes.extend(values.concat());
+        bytes
+    }
+
+    /// Returns a `MaxMind` DB file with the search tree `nodes` of left and right records,
+    /// followed by `data`.
+    fn database(version: u32, record_size: u32, nodes: &[(usize, usize)], data: &[u8]) -> Vec<u8> {
+        let low = |record: usize| record.to_be_bytes()[usize::BITS as usize / 8 - 3..].to_vec();
+        let mut bytes = Vec::new();
+        for &(left, right) in nodes {
+            match record_size {
+                24 => bytes.extend(low(left).into_iter().chain(low(right))),
+                28 => {
+                    bytes.extend(low(left));
+                    bytes.push(u8::try_from(((left >> 24) << 4) | (right >> 24)).unwrap_or(0));
+                    bytes.extend(low(right));
+                }
+                _ => bytes.extend(
+                    [left, right]
+                        .iter()
+                        .flat_map(|&record| u32::try_from(record).unwrap_or(0).to_be_bytes()),
+                ),
+            }
+        }
+        bytes.extend([0; DATA_SEPARATOR]);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(METADATA_MARKER);
+        bytes.extend(map(&[
+            ("binary_format_major_version", uint32(2)),
+            ("binary_format_minor_version", uint32(0)),
+            ("build_epoch", uint32(0)),
+            ("database_type", string("Test-City")),
+            ("description", map(&[])),
+            ("languages", array(&[string("en")])),
+            ("node_count", uint32(u32::try_from(nodes.len()).unwrap_or(0))),
+            ("record_size", uint32(record_size)),
+            ("ip_version", uint32(version)),
+        ]));
+        bytes
+    }
+
+    /// The data of a city, Amsterdam.
+    fn amsterdam() -> Vec<u8> {
+        map(&[
+            ("country", map(&[("iso_code", string("NL"))])),
+            ("city", map(&[("names", map(&[("en", string("Amsterdam"))]))])),
+            (
+                "location",
+                map(&[("latitude", double(52.37)), ("longitude", double(4.89))]),
+            ),
+        ])
+    }
+
+    /// Writes `bytes` to a file of the test and opens it as a database.
+    fn open(name: &str, bytes: &[u8]) -> io::Result<Database> {
+        let dir = std::env::temp_dir().join(format!("da-geoip-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(name);
+        fs::write(&path, bytes)?;
+        let database = Database::open(&path);
+        fs::remove_file(&path)?;
+        database
+    }
+
+    #[test]
+    fn addresses_are_looked_up_in_the_search_tree() -> io::Result<()> {
+        // One node: addresses starting with a 0 bit have the data at offset 0, the
+        // others no entry
+        for record_size in [24, 28, 32] {
+            let bytes = database(4, record_size, &[(1 + DATA_SEPARATOR, 1)], &amsterdam());
+            let database = open(&format!("v4-{record_size}.mmdb"), &bytes)?;
+            let location = database.lookup(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+            let location = location.ok_or_else(|| io::Error::other("no location"))?;
+            assert_eq!(location.country.as_deref(), Some("NL"));
+            assert_eq!(location.city.as_deref(), Some("Amsterdam"));
+            assert_eq!(location.position(), Some([4.89, 52.37]));
+            assert!(database.lookup(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))).is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ipv4_addresses_are_looked_up_after_96_zero_bits_in_ipv6_databases() -> io::Result<()> {
+        // A chain of 96 nodes for the zero bits, then the node of the IPv4 subtree
+        let mut nodes: Vec<(usize, usize)> = (1..=96).map(|next| (next, 97)).collect();
+        nodes.push((97 + DATA_SEPARATOR, 97));
+        let database = open("v6.mmdb", &database(6, 24, &nodes, &amsterdam()))?;
+        let location = database.lookup(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(location.and_then(|location| location.country).as_deref(), Some("NL"));
+        assert!(database.lookup("2001:db8::1".parse().map_err(io::Error::other)?).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn country_databases_give_no_city_or_position() -> io::Result<()> {
+        let data = map(&[("country", map(&[("iso_code", string("NL"))]))]);
+        let database = open("country.mmdb", &database(4, 24, &[(1 + DATA_SEPARATOR, 1)], &data))?;
+        let location = database.lookup(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let location = location.ok_or_else(|| io::Error::other("no location"))?;
+        assert_eq!(location.country.as_deref(), Some("NL"));
+        assert_eq!(location.city, None);
+        assert_eq!(location.position(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn files_that_are_not_databases_are_rejected() {
+        let error = |name, bytes: &[u8]| match open(name, bytes) {
+            Ok(_) => String::new(),
+            Err(e) => e.to_string(),
+        };
+        let text = error("text.mmdb", b"not a database");
+        assert!(text.starts_with("Invalid GeoIP database "), "{text}");
+        assert!(text.contains("text.mmdb: "), "{text}");
+        // Metadata without the search tree size
+        let mut bytes = METADATA_MARKER.to_vec();
+        bytes.extend(map(&[("ip_version", uint32(4))]));
+        let metadata = error("metadata.mmdb", &bytes);
+        assert!(metadata.starts_with("Invalid GeoIP database "), "{metadata}");
+        let missing = Database::open(Path::new("/nonexistent/GeoLite2-City.mmdb"));
+        let missing = missing.err().map(|e| e.to_string()).unwrap_or_default();
+        assert!(missing.starts_with("Failed to read GeoIP database /nonexistent/"), "{missing}");
+    }
+}
@@ -0,0 +1,246 @@
+//! gRPC service of `serve --grpc`.
+//!
+//! Answers the `dashboard.v1.Aggregates` service of `proto/dashboard.proto` from the
+//! aggregates that `serve` keeps up to date, for internal services that prefer typed
+//! RPC over polling the JSON outputs:
+//!
+//! - `GetAggregates` returns the priorities, the top sources and destinations, and the
+//!   AWARE threat and threat score series of the latest aggregation.
+//! - `GetTopSources` returns up to `limit` of the top sources.
+//! - `StreamUpdates` sends the latest aggregates, and then those of every refresh. The
+//!   aggregates are checked for a refresh every `UPDATE_POLL`.
+//!
+//! The service runs on a Tokio runtime of its own thread, next to the HTTP server.
+
+use crate::rfc3339;
+use crate::serve::State;
+use chrono::SecondsFormat;
+use dashboard_aggregator::log;
+use proto::aggregates_server::{Aggregates, AggregatesServer};
+use proto::{
+    AggregatesReply, Count, GetAggregatesRequest, GetTopSourcesRequest, StreamUpdatesRequest,
+    TopSourcesReply,
+};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::runtime;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+/// The code generated from `proto/dashboard.proto`.
+#[allow(clippy::pedantic, clippy::nursery, clippy::unwrap_used)]
+mod proto {
+    tonic::include_proto!("dashboard.v1");
+}
+
+/// How often `StreamUpdates` checks whether the aggregates were refreshed.
+const UPDATE_POLL: Duration = Duration::from_secs(1);
+
+/// How many updates are buffered for a `StreamUpdates` client that reads them slowly.
+const UPDATES_BUFFERED: usize = 4;
+
+/// Serves the gRPC service on `address` from the aggregates in `state`, until the
+/// process is interrupted.
+///
+/// # Errors
+/// Returns an error if the runtime cannot be started or the address cannot be bound.
+pub fn serve(address: SocketAddr, state: Arc<Mutex<State>>) -> io::Result<()> {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    log::info(format_args!("Serving the gRPC service on {address}"));
+    runtime
+        .block_on(
+            Server::builder()
+                .add_service(AggregatesServer::new(Service { state }))
+                .serve(address),
+        )
+        .map_err(io::Error::other)
+}
+
+/// The `Aggregates` service, answering from the aggregates in `state`.
+struct Service {
+    state: Arc<Mutex<State>>,
+}
+
+/// Locks `state`, which stays usable if the refreshing thread panicked.
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the aggregates of `state`.
+fn reply(state: &State) -> AggregatesReply {
+    let summary = &state.summary;
+    AggregatesReply {
+        loaded: state.loaded.to_rfc3339_opts(SecondsFormat::Secs, false),
+        newest_record: state.newest.map(rfc3339),
+        priorities: counts(&summary.priorities),
+        top_sources: counts(&summary.top_sources),
+        top_destinations: counts(&summary.top_destinations),
+        aware_threats: counts(&summary.aware_threats),
+        threat_score: counts(&summary.threat_score),
+    }
+}
+
+/// Returns the labelled `counts` as messages.
+fn counts<T: Copy + Into<u64>>(counts: &[(String, T)]) -> Vec<Count> {
+    counts
+        .iter()
+        .map(|(label, count)| Count {
+            label: label.clone(),
+            count: (*count).into(),
+        })
+        .collect()
+}
+
+#[tonic::async_trait]
+impl Aggregates for Service {
+    async fn get_aggregates(
+        &self,
+        _request: Request<GetAggregatesRequest>,
+    ) -> Result<Response<AggregatesReply>, Status> {
+        Ok(Response::new(reply(&lock(&self.state))))
+    }
+
+    async fn get_top_sources(
+        &self,
+        request: Request<GetTopSourcesRequest>,
+    ) -> Result<Response<TopSourcesReply>, Status> {
+        let limit = match request.into_inner().limit {
+            0 => usize::MAX,
+            limit => usize::try_from(limit).unwrap_or(usize::MAX),
+        };
+        let sources = &lock(&self.state).summary.top_sources;
+        Ok(Response::new(TopSourcesReply {
+            sources: counts(&sources[..limit.min(sources.len())]),
+        }))
+    }
+
+    type StreamUpdatesStream = ReceiverStream<Result<AggregatesReply, Status>>;
+
+    async fn stream_updates(
+        &self,
+        _request: Request<StreamUpdatesRequest>,
+    ) -> Result<Response<Self::StreamUpdatesStream>, Status> {
+        let (sender, receiver) = mpsc::channel(UPDATES_BUFFERED);
+        let state = Arc::clone(&self.state);
+        tokio::spawn(async move {
+            let mut sent = None;
+            // Until the client goes away
+            while !sender.is_closed() {
+                let update = {
+                    let state = lock(&state);
+                    (sent != Some(state.loaded)).then(|| (state.loaded, reply(&state)))
+                };
+                if let Some((loaded, reply)) = update {
+                    if sender.send(Ok(reply)).await.is_err() {
+                        break;
+                    }
+                    sent = Some(loaded);
+                }
+                tokio::time::sleep(UPDATE_POLL).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Summary;
+    use chrono::Local;
+    use tokio_stream::StreamExt;
+
+    fn service() -> Service {
+        let pairs = |pairs: &[(&str, u32)]| {
+            pairs
+                .iter()
+                .map(|(label, count)| ((*label).to_string(), *count))
+                .collect::<Vec<_>>()
+        };
+        let summary = Summary {
+            priorities: pairs(&[("1", 7), ("2", 3)]),
+            top_sources: pairs(&[("10.0.0.1", 6), ("10.0.0.2", 3), ("10.0.0.3", 1)]),
+            top_destinations: pairs(&[("10.1.0.1", 10)]),
+            aware_threats: pairs(&[("2024-04-01 AM", 4)]),
+            threat_score: vec![("2024-04-01 AM".to_string(), 12)],
+        };
+        Service {
+            state: Arc::new(Mutex::new(State {
+                summary,
+                newest: None,
+                loaded: Local::now(),
+                last_error: None,
+            })),
+        }
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> io::Result<F::Output> {
+        Ok(runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(future))
+    }
+
+    fn labels(counts: &[Count]) -> Vec<(&str, u64)> {
+        counts
+            .iter()
+            .map(|count| (count.label.as_str(), count.count))
+            .collect()
+    }
+
+    #[test]
+    fn aggregates_are_those_of_the_summary() -> Result<(), Box<dyn std::error::Error>> {
+        let service = service();
+        let reply = block_on(service.get_aggregates(Request::new(GetAggregatesRequest {})))??
+            .into_inner();
+        assert_eq!(labels(&reply.priorities), [("1", 7), ("2", 3)]);
+        assert_eq!(labels(&reply.top_destinations), [("10.1.0.1", 10)]);
+        assert_eq!(labels(&reply.threat_score), [("2024-04-01 AM", 12)]);
+        assert_eq!(reply.newest_record, None);
+        Ok(())
+    }
+
+    #[test]
+    fn top_sources_are_limited() -> Result<(), Box<dyn std::error::Error>> {
+        let service = service();
+        let top = |limit| {
+            block_on(service.get_top_sources(Request::new(GetTopSourcesRequest { limit })))
+        };
+        let two = top(2)??.into_inner().sources;
+        assert_eq!(labels(&two), [("10.0.0.1", 6), ("10.0.0.2", 3)]);
+        assert_eq!(top(0)??.into_inner().sources.len(), 3);
+        assert_eq!(top(50)??.into_inner().sources.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn updates_are_streamed_after_a_refresh() -> Result<(), Box<dyn std::error::Error>> {
+        let service = service();
+        let updates = block_on(async {
+            let mut stream = service
+                .stream_updates(Request::new(StreamUpdatesRequest {}))
+                .await?
+                .into_inner();
+            let first = stream.next().await;
+            {
+                let mut state = lock(&service.state);
+                state.summary.top_sources.truncate(1);
+                state.loaded = Local::now() + chrono::Duration::seconds(1);
+            }
+            let second = stream.next().await;
+            Ok::<_, Status>((first, second))
+        })??;
+        let sources = |update: Option<Result<AggregatesReply, Status>>| {
+            update.and_then(Result::ok).map(|reply| reply.top_sources.len())
+        };
+        assert_eq!(sources(updates.0), Some(3));
+        assert_eq!(sources(updates.1), Some(1));
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! History of the aggregates of past runs.
+//!
+//! With `--history <file>`, every run appends one compact JSON object per line to
+//! `<file>` with the key aggregates of the run, giving a cheap time series of dashboard
+//! snapshots that can be charted or compared without a database, e.g.:
+//!
+//! ```json
+//! {"timestamp":"2026-10-16T09:30:00+02:00","days_back":1,"files":3,"failed_files":0,"events":6000,"parse_errors":0,"empty_records":0,"approximate":false,"priorities":[["1",1081]],"top_sources":[["10.0.3.13",95]],"top_destinations":[["192.168.1.1",694]],"aware_threats":2250,"threat_score":null}
+//! ```
+//!
+//! With `--retention <age>`, the runs older than that are pruned from the history
+//! before each run, and the file is rewritten without them, so that it does not grow
+//! without bound on appliances with small disks.
+
+use crate::cli::Options;
+use crate::{output, Summary};
+use chrono::{DateTime, Local, TimeDelta};
+use dashboard_aggregator::AggregatedData;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+
+/// One line of the history.
+///
+/// # Fields
+/// - `timestamp`: When the run finished, in RFC 3339 format with the local offset.
+/// - `days_back`: The number of days back the run aggregated.
+/// - `files`: How many log files matched.
+/// - `failed_files`: How many of the matched files could not be read.
+/// - `events`: The number of records aggregated.
+/// - `parse_errors`: The number of records that could not be parsed.
+/// - `empty_records`: The number of records with an empty key field.
+/// - `approximate`: Whether the counts are approximate because of `--max-memory`.
+/// - `priorities`: Priority labels and counts, sorted by priority.
+/// - `top_sources`: The 10 most frequent threat sources with their counts.
+/// - `top_destinations`: The 10 most frequent threat destinations with their counts.
+/// - `aware_threats`: The total AWARE threat count, if the metric is enabled.
+/// - `threat_score`: The total weighted threat score, if priority weights are configured.
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    days_back: i64,
+    files: usize,
+    failed_files: usize,
+    events: u64,
+    parse_errors: u64,
+    empty_records: u64,
+    approximate: bool,
+    priorities: &'a [(String, u32)],
+    top_sources: &'a [(String, u32)],
+    top_destinations: &'a [(String, u32)],
+    aware_threats: Option<u64>,
+    threat_score: Option<u64>,
+}
+
+/// Appends the key aggregates of the run to the history, if `--history` is set.
+///
+/// # Arguments
+/// - `options`: The parsed command line.
+/// - `data`: The aggregates of the run, as written to the outputs.
+/// - `files`: How many log files matched.
+/// - `failed_files`: How many of the matched files could not be read.
+///
+/// # Errors
+/// Returns an error if the history cannot be written.
+pub fn append(
+    options: &Options,
+    data: &AggregatedData,
+    files: usize,
+    failed_files: usize,
+) -> io::Result<()> {
+    let Some(path) = &options.history else {
+        return Ok(());
+    };
+    let summary = Summary::new(data);
+    let total = |counts: Vec<u64>| (!counts.is_empty()).then(|| counts.iter().sum());
+    let record = Record {
+        timestamp: Local::now().to_rfc3339(),
+        days_back: options.days_back,
+        files,
+        failed_files,
+        events: data.records(),
+        parse_errors: data.parse_errors(),
+        empty_records: data.empty_records(),
+        approximate: data.is_approximate(),
+        priorities: &summary.priorities,
+        top_sources: &summary.top_sources,
+        top_destinations: &summary.top_destinations,
+        aware_threats: total(
+            summary
+                .aware_threats
+                .iter()
+                .map(|(_, count)| u64::from(*count))
+                .collect(),
+        ),
+        threat_score: total(
+            summary
+                .threat_score
+                .iter()
+                .map(|(_, score)| *score)
+                .collect(),
+        ),
+    };
+
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    // A single write keeps lines from concurrent runs from interleaving
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to write history {}: {e}", path.display()),
+            )
+        })
+}
+
+/// Removes the runs older than `--retention` from the history, if both are set. The
+/// history is rewritten without them, replacing the file at once; lines that are not
+/// runs of the history are kept.
+///
+/// # Returns
+/// The number of runs removed.
+///
+/// # Errors
+/// Returns an error if the history cannot be read or written.
+pub fn prune(options: &Options) -> io::Result<usize> {
+    let (Some(path), Some(retention)) = (&options.history, options.history_retention) else {
+        return Ok(0);
+    };
+    let with_path = |e: io::Error| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to prune history {}: {e}", path.display()),
+        )
+    };
+    let history = match fs::read_to_string(path) {
+        Ok(history) => history,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(with_path(e)),
+    };
+    let Some(oldest) = TimeDelta::from_std(retention)
+        .ok()
+        .and_then(|retention| Local::now().checked_sub_signed(retention))
+    else {
+        return Ok(0);
+    };
+
+    let (kept, pruned): (Vec<&str>, Vec<&str>) = history
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .partition(|line| timestamp(line).map_or(true, |time| time >= oldest));
+    if !pruned.is_empty() {
+        let kept: String = kept.iter().flat_map(|line| [*line, "\n"]).collect();
+        output::write_file(path, kept.as_bytes()).map_err(with_path)?;
+    }
+    Ok(pruned.len())
+}
+
+/// Returns when the run of the history line `line` finished, if it is a run.
+fn timestamp(line: &str) -> Option<DateTime<Local>> {
+    let record: Value = serde_json::from_str(line).ok()?;
+    let timestamp = DateTime::parse_from_rfc3339(record.get("timestamp")?.as_str()?).ok()?;
+    Some(timestamp.with_timezone(&Local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_past_the_retention_are_pruned() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("da-history-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("history.ndjson");
+        let run = |days: i64| {
+            let time = Local::now() - TimeDelta::days(days);
+            format!("{{\"timestamp\":\"{}\",\"events\":{days}}}\n", time.to_rfc3339())
+        };
+        let recent = format!("{}not a run\n{}", run(1), run(89));
+        fs::write(&path, format!("{}{}{recent}", run(120), run(91)))?;
+        let options = Options {
+            history: Some(path.clone()),
+            history_retention: Some(Duration::from_secs(90 * 24 * 60 * 60)),
+            ..Options::default()
+        };
+
+        assert_eq!(prune(&options)?, 2);
+        assert_eq!(fs::read_to_string(&path)?, recent);
+        assert_eq!(prune(&options)?, 0);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! Holiday calendars, whose days the business-hours split treats like weekends.
+//!
+//! Holidays are read from an iCalendar (`.ics`) file, such as the public holiday
+//! calendars published for most countries, or from a plain list of dates. Only the
+//! dates of the events are used; recurrence rules are not expanded, so a calendar must
+//! list every year it covers.
+
+use chrono::{Duration, NaiveDate};
+use std::collections::HashSet;
+
+/// Longest all-day event whose days are all taken as holidays, which keeps a
+/// malformed end date from marking years as holidays.
+const MAX_EVENT_DAYS: i64 = 366;
+
+/// A set of holiday dates.
+///
+/// # Fields
+/// - `dates`: The dates that are holidays.
+#[derive(Debug, Default)]
+pub struct Holidays {
+    dates: HashSet<NaiveDate>,
+}
+
+impl Holidays {
+    /// Parses an iCalendar file, taking the days of every event as holidays, or a list
+    /// of dates with one `YYYY-MM-DD` date per line, optionally followed by the name of
+    /// the holiday, with `#` comments and empty lines ignored.
+    ///
+    /// # Errors
+    /// Returns a message naming the line if a line of a list of dates does not start
+    /// with a date.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        if contents.trim_start().starts_with("BEGIN:VCALENDAR") {
+            return Ok(Self::parse_ics(contents));
+        }
+        let mut dates = HashSet::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let date = line.split_whitespace().next().unwrap_or_default();
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+                format!(
+                    "line {}: expected a date such as 2024-12-25, found `{line}`",
+                    number + 1
+                )
+            })?;
+            dates.insert(date);
+        }
+        Ok(Self { dates })
+    }
+
+    /// Parses the `DTSTART` and `DTEND` dates of the events of an iCalendar file. The
+    /// end date of an all-day event is exclusive, as in the iCalendar format.
+    fn parse_ics(contents: &str) -> Self {
+        // Lines starting with a space or tab continue the previous line
+        let unfolded = contents
+            .replace("\r\n", "\n")
+            .replace("\n ", "")
+            .replace("\n\t", "");
+        let mut dates = HashSet::new();
+        let (mut start, mut end) = (None, None);
+        for line in unfolded.lines() {
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            // Properties may have parameters, as in DTSTART;VALUE=DATE:20241225
+            let name = name.split(';').next().unwrap_or_default();
+            match name.to_ascii_uppercase().as_str() {
+                "BEGIN" => (start, end) = (None, None),
+                "DTSTART" => start = ics_date(value),
+                "DTEND" => end = ics_date(value).filter(|_| value.len() == 8),
+                "END" if value.eq_ignore_ascii_case("VEVENT") => {
+                    let Some(first) = start else {
+                        continue;
+                    };
+                    let days = end.map_or(1, |end| (end - first).num_days());
+                    dates.extend(
+                        (0..days.clamp(1, MAX_EVENT_DAYS)).map(|day| first + Duration::days(day)),
+                    );
+                }
+                _ => {}
+            }
+        }
+        Self { dates }
+    }
+
+    /// Returns the number of holiday dates.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.dates.len()
+    }
+
+    /// Returns whether there are no holiday dates.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.dates.is_empty()
+    }
+
+    /// Returns whether `date` is a holiday.
+    #[must_use]
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.dates.contains(&date)
+    }
+}
+
+/// Parses the date of an iCalendar date or date-time value, such as `20241225` or
+/// `20241225T090000Z`.
+fn ics_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim().get(..8)?, "%Y%m%d").ok()
+}
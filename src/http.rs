@@ -0,0 +1,195 @@
+//! Minimal HTTP client for the REST integrations.
+//!
+//! Requests are made by the `curl` command line tool, so TLS and proxies work without a
+//! TLS library in this crate. The request, including headers and body, is passed to curl
+//! as a config file on its standard input rather than as arguments, so API keys do not
+//! show up in the process list.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Program run to make HTTP requests.
+const CURL: &str = "curl";
+
+/// Seconds after which a request is abandoned.
+const TIMEOUT_SECS: u32 = 60;
+
+/// Number of characters of an error response included in the error message.
+const ERROR_EXCERPT_CHARS: usize = 200;
+
+/// Marks the start of the status code that curl appends to the response body.
+const STATUS_MARKER: &str = "\n--http-status:";
+
+/// Quotes `value` as a string in a curl config file.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Makes an HTTP request and returns the response body.
+///
+/// # Arguments
+/// - `method`: The request method, e.g. `GET` or `POST`.
+/// - `url`: The URL to request.
+/// - `headers`: Extra request headers as `Name: value` lines.
+/// - `body`: The request body, if any.
+///
+/// # Errors
+/// Returns an error if curl cannot be run, the request fails, or the response status is
+/// not 2xx.
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[String],
+    body: Option<&str>,
+) -> io::Result<Vec<u8>> {
+    curl(method, url, headers, body.map(quote), false)
+}
+
+/// POSTs the contents of the file at `path`, which may be binary, such as a gzip
+/// stream, and returns the response body.
+///
+/// # Errors
+/// Returns an error like `request`.
+pub fn post_file(url: &str, headers: &[String], path: &Path) -> io::Result<Vec<u8>> {
+    // curl reads the body from the file named after `@`
+    let data = quote(&format!("@{}", path.display()));
+    curl("POST", url, headers, Some(data), false)
+}
+
+/// Runs curl to make an HTTP request and returns the response body.
+///
+/// # Arguments
+/// - `method`, `url`, `headers`: As for `request`.
+/// - `data`: The quoted `data-binary` value of the curl config, if any.
+/// - `follow`: Whether redirects are followed.
+///
+/// # Errors
+/// Returns an error like `request`.
+fn curl(
+    method: &str,
+    url: &str,
+    headers: &[String],
+    data: Option<String>,
+    follow: bool,
+) -> io::Result<Vec<u8>> {
+    let mut config = String::new();
+    let _ = writeln!(config, "url = {}", quote(url));
+    let _ = writeln!(config, "request = {}", quote(method));
+    if follow {
+        config.push_str("location\n");
+    }
+    for header in headers {
+        let _ = writeln!(config, "header = {}", quote(header));
+    }
+    if let Some(data) = data {
+        let _ = writeln!(config, "data-binary = {data}");
+    }
+    let _ = writeln!(
+        config,
+        "write-out = {}",
+        quote(&format!("{STATUS_MARKER}%{{http_code}}"))
+    );
+
+    let mut child = Command::new(CURL)
+        .args(["--silent", "--show-error", "--max-time"])
+        .arg(TIMEOUT_SECS.to_string())
+        .args(["--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to run {CURL}: {e}")))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(config.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{method} {url} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let mut response = output.stdout;
+    let marker = response
+        .windows(STATUS_MARKER.len())
+        .rposition(|window| window == STATUS_MARKER.as_bytes())
+        .ok_or_else(|| io::Error::other(format!("{method} {url}: no status from {CURL}")))?;
+    let status = String::from_utf8_lossy(&response[marker + STATUS_MARKER.len()..])
+        .trim()
+        .to_string();
+    response.truncate(marker);
+    if !status.starts_with('2') {
+        let excerpt: String = String::from_utf8_lossy(&response)
+            .chars()
+            .take(ERROR_EXCERPT_CHARS)
+            .collect();
+        return Err(io::Error::other(format!(
+            "{method} {url} returned HTTP {status}: {}",
+            excerpt.trim()
+        )));
+    }
+    Ok(response)
+}
+
+/// Parses the JSON response to a request to `url`.
+///
+/// # Errors
+/// Returns an error if the response is not JSON.
+fn parse_json(url: &str, response: &[u8]) -> io::Result<Value> {
+    serde_json::from_slice(response).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid JSON response from {url}: {e}"),
+        )
+    })
+}
+
+/// GETs `url` and parses the JSON response.
+///
+/// # Errors
+/// Returns an error if the request fails or the response is not JSON.
+pub fn get_json(url: &str, headers: &[String]) -> io::Result<Value> {
+    let mut headers = headers.to_vec();
+    headers.push("Accept: application/json".to_string());
+    parse_json(url, &request("GET", url, &headers, None)?)
+}
+
+/// GETs `url`, following redirects, and parses the JSON response. Redirects may lead
+/// to other hosts, so no headers such as API keys are sent.
+///
+/// # Errors
+/// Returns an error if the request fails or the response is not JSON.
+pub fn get_json_redirected(url: &str) -> io::Result<Value> {
+    let headers = ["Accept: application/json".to_string()];
+    parse_json(url, &curl("GET", url, &headers, None, true)?)
+}
+
+/// POSTs `value` as JSON and parses the JSON response.
+///
+/// # Errors
+/// Returns an error if the request fails or the response is not JSON.
+pub fn post_json<T: Serialize>(url: &str, headers: &[String], value: &T) -> io::Result<Value> {
+    let mut headers = headers.to_vec();
+    headers.push("Content-Type: application/json".to_string());
+    headers.push("Accept: application/json".to_string());
+    let body = serde_json::to_string(value)?;
+    parse_json(url, &request("POST", url, &headers, Some(&body))?)
+}
@@ -0,0 +1,327 @@
+//! Input formats other than the CSV exports.
+//!
+//! Syslog and other line-based firewall logs are normalized into the same CSV layout as
+//! the exports, so everything downstream (row filters, sampling, the metrics) works on
+//! them unchanged.
+//! `InputFormat::reader` wraps a log file in a reader that yields the normalized CSV,
+//! starting with `NORMALIZED_HEADER`. Lines that are not firewall events of the format,
+//! such as messages of other programs in the same syslog file, are skipped.
+
+use crate::{asa, pfirewall, pfsense, winevent, DATETIME_FORMAT};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Header row of the normalized CSV, matching the column constants of the exports.
+pub const NORMALIZED_HEADER: [&str; 18] = [
+    "Id",
+    "Priority",
+    "Signature",
+    "Category",
+    "Date/Time",
+    "Proto",
+    "Source",
+    "SrcPort",
+    "Interface",
+    "Action",
+    "Direction",
+    "Reason",
+    "Destination",
+    "DstPort",
+    "Bytes",
+    "IcmpType",
+    "IcmpCode",
+    "TcpFlags",
+];
+
+/// The format of the log files.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// The CSV exports, with a header row.
+    #[default]
+    Csv,
+    /// The comma-separated `filterlog` syslog messages of pfSense.
+    Pfsense,
+    /// The denial and teardown syslog messages of Cisco ASA firewalls.
+    Asa,
+    /// The `pfirewall.log` files of Windows Defender Firewall.
+    Pfirewall,
+    /// Windows Filtering Platform events of the Windows `Security` log, as XML.
+    Winevent,
+}
+
+impl InputFormat {
+    /// Parses the name of an input format.
+    ///
+    /// # Errors
+    /// Returns a message listing the supported formats if `name` is not one of them.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "csv" => Ok(Self::Csv),
+            "pfsense" => Ok(Self::Pfsense),
+            "asa" => Ok(Self::Asa),
+            "pfirewall" => Ok(Self::Pfirewall),
+            "winevent" => Ok(Self::Winevent),
+            _ => Err(format!(
+                "Unsupported input format `{name}`, expected csv, pfsense, asa, pfirewall or winevent"
+            )),
+        }
+    }
+
+    /// Returns the name of the format, as accepted by `parse`.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Pfsense => "pfsense",
+            Self::Asa => "asa",
+            Self::Pfirewall => "pfirewall",
+            Self::Winevent => "winevent",
+        }
+    }
+
+    /// Returns the prefix of the names of the log files in this format.
+    #[must_use]
+    pub const fn file_prefix(self) -> &'static str {
+        match self {
+            Self::Csv => "fwddmp.log.tmp",
+            Self::Pfsense => "filter.log",
+            Self::Asa => "asa.log",
+            Self::Pfirewall => "pfirewall.log",
+            Self::Winevent => "security",
+        }
+    }
+
+    /// Wraps `reader` so that it yields CSV with a header row, normalizing the records
+    /// if the format is not CSV already.
+    pub fn reader<'a, R: Read + 'a>(self, reader: R) -> Box<dyn Read + 'a> {
+        match self {
+            Self::Csv => Box::new(reader),
+            Self::Pfsense => Box::new(Normalizer::new(reader, Syslog::new(pfsense::parse))),
+            Self::Asa => Box::new(Normalizer::new(reader, Syslog::new(asa::parse))),
+            Self::Pfirewall => Box::new(Normalizer::new(reader, pfirewall::Parser::default())),
+            Self::Winevent => Box::new(Normalizer::new(reader, winevent::Parser::default())),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A firewall event parsed from a log line, with the fields of `NORMALIZED_HEADER`.
+/// `bytes` is empty for formats that do not log packet or flow sizes, and `icmp_type`
+/// and `icmp_code`, or `tcp_flags`, for events of other protocols or formats that do
+/// not log them.
+#[derive(Default)]
+pub(crate) struct Normalized {
+    pub id: String,
+    pub priority: String,
+    pub signature: String,
+    pub category: &'static str,
+    pub datetime: NaiveDateTime,
+    pub proto: String,
+    pub source: String,
+    pub src_port: String,
+    pub interface: String,
+    pub action: String,
+    pub direction: String,
+    pub reason: String,
+    pub destination: String,
+    pub dst_port: String,
+    pub bytes: String,
+    pub icmp_type: String,
+    pub icmp_code: String,
+    pub tcp_flags: String,
+}
+
+impl Normalized {
+    /// Appends the event to `out` as a CSV line.
+    pub(crate) fn write_csv(&self, out: &mut Vec<u8>) {
+        let datetime = self.datetime.format(DATETIME_FORMAT).to_string();
+        let fields = [
+            self.id.as_str(),
+            &self.priority,
+            &self.signature,
+            self.category,
+            &datetime,
+            &self.proto,
+            &self.source,
+            &self.src_port,
+            &self.interface,
+            &self.action,
+            &self.direction,
+            &self.reason,
+            &self.destination,
+            &self.dst_port,
+            &self.bytes,
+            &self.icmp_type,
+            &self.icmp_code,
+            &self.tcp_flags,
+        ];
+        write_csv_line(out, &fields);
+    }
+}
+
+/// Appends `fields` to `out` as a CSV line, quoting fields where needed.
+fn write_csv_line(out: &mut Vec<u8>, fields: &[&str]) {
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            out.push(b',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push(b'"');
+            out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    out.push(b'\n');
+}
+
+/// Returns the name of the IP protocol `number`, or the number itself for protocols
+/// without a name here.
+pub(crate) fn protocol_name(number: u8) -> String {
+    match number {
+        1 => "icmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        58 => "ipv6-icmp".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Parses the syslog header of `line`: an optional `<priority>`, and either an RFC 5424
+/// version and timestamp or an RFC 3164 (BSD) timestamp, optionally with a year as
+/// devices such as Cisco ASA add it.
+///
+/// BSD timestamps have no year or time zone. They are taken as local time in the year
+/// that puts them closest to `now`, i.e. in the previous year if they would otherwise
+/// lie more than a day in the future.
+///
+/// # Returns
+/// The local time of the message, and the rest of the line after the timestamp, which
+/// starts with the host name.
+#[must_use]
+pub(crate) fn syslog_header(line: &str, now: NaiveDateTime) -> Option<(NaiveDateTime, &str)> {
+    let mut line = line.trim_start();
+    if let Some(rest) = line.strip_prefix('<') {
+        let (priority, rest) = rest.split_once('>')?;
+        if !priority.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        line = rest;
+    }
+
+    // RFC 5424: `1 2026-10-16T09:30:01.123+02:00 host ...`
+    if let Some(rest) = line.strip_prefix("1 ") {
+        let (timestamp, rest) = rest.split_once(' ')?;
+        let datetime = DateTime::parse_from_rfc3339(timestamp).ok()?;
+        return Some((datetime.with_timezone(&Local).naive_local(), rest));
+    }
+
+    // `Oct 16 2026 09:30:01`
+    if let Some(datetime) = line
+        .get(..20)
+        .and_then(|timestamp| NaiveDateTime::parse_from_str(timestamp, "%b %e %Y %H:%M:%S").ok())
+    {
+        return Some((datetime, &line[20..]));
+    }
+
+    // RFC 3164: `Oct 16 09:30:01 host ...`, with the day padded by a space
+    let timestamp = line.get(..15)?;
+    let parse = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{year} {timestamp}"), "%Y %b %e %H:%M:%S").ok()
+    };
+    let mut datetime = parse(now.year())?;
+    if datetime > now + Duration::days(1) {
+        datetime = parse(now.year() - 1)?;
+    }
+    Some((datetime, &line[15..]))
+}
+
+/// Parses the lines of a format into events.
+pub(crate) trait LineParser {
+    /// Parses one line, returning `None` if it is not an event of the format.
+    fn parse(&mut self, line: &str) -> Option<Normalized>;
+}
+
+/// A parser of syslog messages, which need no state besides the current time.
+///
+/// # Fields
+/// - `parse`: Parses one line, given the current time.
+/// - `now`: The current local time, for the year of BSD timestamps.
+struct Syslog {
+    parse: fn(&str, NaiveDateTime) -> Option<Normalized>,
+    now: NaiveDateTime,
+}
+
+impl Syslog {
+    /// Creates a parser calling `parse` with the current time.
+    fn new(parse: fn(&str, NaiveDateTime) -> Option<Normalized>) -> Self {
+        Self {
+            parse,
+            now: Local::now().naive_local(),
+        }
+    }
+}
+
+impl LineParser for Syslog {
+    fn parse(&mut self, line: &str) -> Option<Normalized> {
+        (self.parse)(line, self.now)
+    }
+}
+
+/// Converts lines into normalized CSV as they are read.
+///
+/// # Fields
+/// - `lines`: The lines of the log file.
+/// - `parser`: Parses the lines of the format.
+/// - `buffer`: Normalized CSV, of which the bytes from `position` on have not been
+///   read yet.
+/// - `line`: The line being parsed, kept to reuse its allocation.
+struct Normalizer<R, P> {
+    lines: BufReader<R>,
+    parser: P,
+    buffer: Vec<u8>,
+    position: usize,
+    line: Vec<u8>,
+}
+
+impl<R: Read, P: LineParser> Normalizer<R, P> {
+    /// Wraps `reader`, starting with the header row.
+    fn new(reader: R, parser: P) -> Self {
+        let mut buffer = Vec::new();
+        write_csv_line(&mut buffer, &NORMALIZED_HEADER);
+        Self {
+            lines: BufReader::new(reader),
+            parser,
+            buffer,
+            position: 0,
+            line: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read, P: LineParser> Read for Normalizer<R, P> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            self.line.clear();
+            if self.lines.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+            if let Some(event) = self.parser.parse(&String::from_utf8_lossy(&self.line)) {
+                event.write_csv(&mut self.buffer);
+            }
+        }
+        let count = out.len().min(self.buffer.len() - self.position);
+        out[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
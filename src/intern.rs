@@ -0,0 +1,33 @@
+//! String interning for repeated field values.
+//!
+//! Priorities and a small set of hot addresses repeat across millions of records, and
+//! the same address often appears as both a source and a destination. Interning them
+//! as shared `Arc<str>` keys stores each distinct value once.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// A set of shared strings, handing out the existing copy of a value when there is one.
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    /// Returns the shared copy of `value`, allocating one the first time it is seen.
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(existing) = self.strings.get(value) {
+            return Arc::clone(existing);
+        }
+        let interned: Arc<str> = Arc::from(value);
+        self.strings.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Drops strings that are no longer referenced outside the interner, e.g. after
+    /// low-count entries were pruned to stay within the memory limit.
+    pub fn purge(&mut self) {
+        self.strings.retain(|value| Arc::strong_count(value) > 1);
+        self.strings.shrink_to_fit();
+    }
+}
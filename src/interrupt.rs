@@ -0,0 +1,41 @@
+//! Stopping a run early on SIGINT or SIGTERM.
+//!
+//! Once `install` is called, the first SIGINT (Ctrl-C) or SIGTERM only sets a flag. The
+//! run polls it between files: the files being read are finished, no further files are
+//! started, and the aggregates read so far are written to `events.partial.json` rather
+//! than the regular outputs, after which the process exits with `Outcome::Interrupted`.
+//! A second signal ends the process right away, e.g. when a file takes too long.
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Exit code of a process ended by a second signal, before any output was written.
+const FORCED_EXIT_CODE: i32 = 1;
+
+/// Set once SIGINT or SIGTERM is received after `install`.
+static REQUESTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Sets the flag of `requested` on SIGINT and SIGTERM instead of ending the process, and
+/// ends it with `FORCED_EXIT_CODE` on a second signal.
+///
+/// # Errors
+/// Returns an error if a signal handler cannot be registered.
+pub fn install() -> io::Result<()> {
+    let requested = REQUESTED.get_or_init(Arc::default);
+    for signal in [SIGINT, SIGTERM] {
+        // Registered first, so that it only sees the flag set by an earlier signal
+        flag::register_conditional_shutdown(signal, FORCED_EXIT_CODE, Arc::clone(requested))?;
+        flag::register(signal, Arc::clone(requested))?;
+    }
+    Ok(())
+}
+
+/// Returns whether SIGINT or SIGTERM was received since `install`.
+pub fn requested() -> bool {
+    REQUESTED
+        .get()
+        .is_some_and(|requested| requested.load(Ordering::Relaxed))
+}
@@ -0,0 +1,74 @@
+//! Reading the firewall logs from the systemd journal.
+//!
+//! With `--journal`, the log messages are read with `journalctl` instead of from log
+//! files, limited to the units given with `--unit`. They are requested in the classic
+//! syslog layout (`--output short`), so the syslog-based input formats parse them like
+//! lines of a syslog file.
+//!
+//! The position in the journal is kept in a cursor file in the output directory. The
+//! first run reads the `days_back` window, and every later run only the entries logged
+//! since the previous one, so scheduled runs report what is new.
+
+use crate::cli::Options;
+use crate::cutoff;
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::{aggregate_reader, log, AggregatedData};
+use std::io;
+use std::process::Command;
+
+/// Program run to read the journal.
+const JOURNALCTL: &str = "journalctl";
+
+/// Name of the cursor file in the output directory.
+const CURSOR_FILE: &str = "journal_cursor";
+
+/// Aggregates the journal entries of `units` logged since the previous run, or within
+/// the `days_back` window on the first run, and advances the cursor past them.
+///
+/// # Errors
+/// Returns an error if `journalctl` cannot be run or fails.
+pub fn aggregate(
+    options: &Options,
+    config: &Config,
+    units: &[String],
+) -> io::Result<AggregatedData> {
+    let cursor = options.output_dir_path(CURSOR_FILE);
+    let output = Command::new(JOURNALCTL)
+        .args(["--no-pager", "--quiet", "--output", "short", "--since"])
+        .arg(
+            cutoff(options.days_back)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        )
+        .arg("--cursor-file")
+        .arg(&cursor)
+        .args(units.iter().flat_map(|unit| ["--unit", unit]))
+        // The month names of the timestamps are parsed in English
+        .env("LC_ALL", "C")
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to run {JOURNALCTL}: {e}")))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{JOURNALCTL} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    log::info(format_args!(
+        "Read {} journal lines, cursor saved to {}",
+        String::from_utf8_lossy(&output.stdout).lines().count(),
+        cursor.display()
+    ));
+
+    let mut data = aggregate_reader(
+        options.format.reader(io::Cursor::new(output.stdout)),
+        cutoff(options.days_back),
+        &options.filter,
+        config,
+        options.max_memory,
+    )?;
+    if let Some(sample) = &options.filter.sample {
+        data.scale(sample);
+    }
+    Ok(data)
+}
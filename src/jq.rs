@@ -0,0 +1,235 @@
+//! jq filters for `--jq`.
+//!
+//! Filters are run by [jaq](https://github.com/01mf02/jaq), a jq clone, with its
+//! standard library, so quick extractions from `events.json` work on hosts without jq.
+//! jaq supports most of the jq language, including paths, pipes, `select`, `map`,
+//! `to_entries`, object construction and user-defined functions; the differences are
+//! listed in the jaq README.
+
+use jaq_core::load::lex::Expect as LexExpect;
+use jaq_core::load::parse::Expect as ParseExpect;
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{compile, load, Compiler, Ctx, Native, RcIter};
+use jaq_json::Val;
+use serde_json::Value;
+
+/// A compiled filter, kept together with its source for display.
+pub struct Filter {
+    source: String,
+    filter: jaq_core::Filter<Native<Val>>,
+}
+
+impl Filter {
+    /// Parses and compiles a filter.
+    ///
+    /// # Errors
+    /// Returns a human-readable message describing the syntax errors and undefined
+    /// functions or variables found.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let invalid =
+            |errors: Vec<String>| format!("Invalid --jq filter `{source}`: {}", errors.join(", "));
+        let arena = Arena::default();
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let modules = loader
+            .load(
+                &arena,
+                File {
+                    code: source,
+                    path: (),
+                },
+            )
+            .map_err(|errors| {
+                invalid(
+                    errors
+                        .into_iter()
+                        .flat_map(|(_, e)| load_errors(e))
+                        .collect(),
+                )
+            })?;
+        let filter = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errors| {
+                invalid(
+                    errors
+                        .into_iter()
+                        .flat_map(|(_, errors)| errors)
+                        .map(|(name, undefined)| undefined_error(name, &undefined))
+                        .collect(),
+                )
+            })?;
+        Ok(Self {
+            source: source.to_string(),
+            filter,
+        })
+    }
+
+    /// Returns the filter as it was given.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Applies the filter to `value`. A single output is returned as it is; no output or
+    /// several outputs are collected into an array.
+    ///
+    /// # Errors
+    /// Returns the message of the first error the filter raises, such as a lookup on a
+    /// number that is not marked with `?`.
+    pub fn apply(&self, value: &Value) -> Result<Value, String> {
+        let inputs = RcIter::new(core::iter::empty());
+        let mut outputs = self
+            .filter
+            .run((Ctx::new([], &inputs), Val::from(value.clone())))
+            .map(|output| output.map(Value::from).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(if outputs.len() == 1 {
+            outputs.remove(0)
+        } else {
+            Value::Array(outputs)
+        })
+    }
+}
+
+/// Returns the messages of the lexing and parsing errors in `error`.
+fn load_errors(error: load::Error<&str>) -> Vec<String> {
+    match error {
+        load::Error::Io(errors) => errors.into_iter().map(|(_, e)| e).collect(),
+        load::Error::Lex(errors) => errors
+            .into_iter()
+            .map(|(expect, at)| lex_error(&expect, at))
+            .collect(),
+        load::Error::Parse(errors) => errors
+            .into_iter()
+            .map(|(expect, at)| parse_error(&expect, at))
+            .collect(),
+    }
+}
+
+/// Returns the message of a lexing error, where `at` is the rest of the filter.
+fn lex_error(expect: &LexExpect<&str>, at: &str) -> String {
+    if at.is_empty() {
+        format!("expected {}", expect.as_str())
+    } else {
+        format!("expected {} at `{at}`", expect.as_str())
+    }
+}
+
+/// Returns the message of a parsing error, where `at` is the token found instead.
+fn parse_error(expect: &ParseExpect<&str>, at: &str) -> String {
+    if at.is_empty() {
+        format!("expected {}", expect.as_str())
+    } else {
+        format!("expected {}, found `{at}`", expect.as_str())
+    }
+}
+
+/// Returns the message of a reference to the undefined `name`.
+fn undefined_error(name: &str, undefined: &compile::Undefined) -> String {
+    match undefined {
+        compile::Undefined::Var => format!("undefined variable `{name}`"),
+        compile::Undefined::Filter(arity) => format!("undefined function `{name}/{arity}`"),
+        _ => format!("undefined `{name}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn jq(filter: &str, value: &Value) -> Result<Value, String> {
+        Filter::parse(filter)?.apply(value)
+    }
+
+    fn events() -> Value {
+        json!({
+            "Priorities": {"Priority": ["1", "2", "3"], "Count": [7, 3, 1]},
+            "Threat Sources": {"Source": ["10.0.0.1", "10.0.0.2"], "Count": [6, 3]},
+            "meta": {"version": "0.1.0"},
+        })
+    }
+
+    #[test]
+    fn paths_select_values() -> Result<(), String> {
+        let events = events();
+        assert_eq!(jq(" . ", &json!(1))?, json!(1));
+        assert_eq!(jq(".Priorities.Count", &events)?, json!([7, 3, 1]));
+        assert_eq!(
+            jq(".\"Threat Sources\".Source[0]", &events)?,
+            json!("10.0.0.1")
+        );
+        assert_eq!(jq(".[\"Threat Sources\"].Count", &events)?, json!([6, 3]));
+        assert_eq!(jq(".Priorities.Priority[-1]", &events)?, json!("3"));
+        assert_eq!(jq(".Priorities.Count[1:]", &events)?, json!([3, 1]));
+        assert_eq!(jq(".Priorities.Count[:-1]", &events)?, json!([7, 3]));
+        assert_eq!(jq(".Priorities.Count[9]", &events)?, Value::Null);
+        assert_eq!(jq(".missing", &events)?, Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn iteration_pipes_and_commas_collect_outputs() -> Result<(), String> {
+        let events = events();
+        assert_eq!(jq(".Priorities.Count[]", &events)?, json!([7, 3, 1]));
+        assert_eq!(jq(".meta[]", &events)?, json!("0.1.0"));
+        assert_eq!(
+            jq(".meta.version, .Priorities.Count[0]", &events)?,
+            json!(["0.1.0", 7])
+        );
+        assert_eq!(
+            jq(
+                "(.Priorities, .\"Threat Sources\") | .Count | length",
+                &events
+            )?,
+            json!([3, 2])
+        );
+        assert_eq!(
+            jq(".[] | keys", &json!({"a": {"y": 1, "x": 2}}))?,
+            json!(["x", "y"])
+        );
+        // No output is an empty array
+        assert_eq!(jq(".[]", &json!([]))?, json!([]));
+        Ok(())
+    }
+
+    #[test]
+    fn standard_library_filters_are_available() -> Result<(), String> {
+        let events = events();
+        let sources =
+            r#"."Threat Sources" | [.Source, .Count] | transpose | map({(.[0]): .[1]}) | add"#;
+        assert_eq!(jq(sources, &events)?, json!({"10.0.0.1": 6, "10.0.0.2": 3}));
+        assert_eq!(
+            jq(".Priorities.Count | map(select(. > 2)) | add", &events)?,
+            json!(10)
+        );
+        assert_eq!(jq(".meta | to_entries[0].key", &events)?, json!("version"));
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_reported_unless_tried() -> Result<(), String> {
+        let value = json!({"a": 1, "b": [1, {"c": 2}]});
+        assert!(jq(".a.b", &value).is_err());
+        assert!(jq(".a[]", &value).is_err());
+        assert_eq!(jq(".a[]?", &value)?, json!([]));
+        assert_eq!(jq(".b[].c?", &value)?, json!(2));
+        Ok(())
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        let error = |filter| Filter::parse(filter).err().unwrap_or_default();
+        assert!(error(".a |").starts_with("Invalid --jq filter `.a |`: expected "));
+        assert!(error("(.a").starts_with("Invalid --jq filter `(.a`: expected "));
+        assert_eq!(
+            error("frobnicate"),
+            "Invalid --jq filter `frobnicate`: undefined function `frobnicate/0`"
+        );
+        assert_eq!(
+            error("$x"),
+            "Invalid --jq filter `$x`: undefined variable `$x`"
+        );
+        let source = Filter::parse(" .a ").map(|filter| filter.source().to_string());
+        assert_eq!(source, Ok(" .a ".to_string()));
+    }
+}
@@ -0,0 +1,1103 @@
+//! Parsing and aggregation core of the dashboard aggregator.
+//!
+//! Everything needed to turn CSV log records into the `events.json` sections lives
+//! here, independent of where the records come from. The filesystem parts (loading
+//! configuration files, and the command-line binary itself) are behind the default
+//! `fs` feature, so the core also builds for `wasm32-unknown-unknown`, e.g. to
+//! aggregate a CSV dropped into the web dashboard client-side:
+//!
+//! ```sh
+//! cargo build --lib --no-default-features --target wasm32-unknown-unknown
+//! ```
+
+mod asa;
+pub mod baseline;
+pub mod config;
+pub mod derived;
+pub mod domain;
+pub mod encoding;
+pub mod filter;
+pub mod holiday;
+pub mod input;
+pub mod intern;
+pub mod log;
+pub mod metric;
+pub mod netflow;
+pub mod normalize;
+pub mod oui;
+mod pfirewall;
+mod pfsense;
+#[cfg(feature = "wasm")]
+pub mod plugin;
+pub mod schema;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod winevent;
+
+use chrono::{Duration, NaiveDateTime};
+use config::{ColumnConfig, Config, EmptyFieldPolicy};
+use csv::{ByteRecord, Reader, ReaderBuilder, StringRecord};
+use filter::{RecordFilter, RowFilter, Sample};
+use intern::Interner;
+use metric::{Event, MappedFields, Metric};
+use normalize::Field;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::str;
+
+/// Represents aggregated data from CSV file processing.
+///
+/// This struct holds the enabled metrics (see the `metric` module), such as counts of
+/// priorities, threat sources, threat destinations, and AWARE threats.
+///
+/// # Fields
+/// - `metrics`: The enabled metrics, in configuration order.
+/// - `titles`: The name of the section of each metric in `events.json`, which is its
+///   title in the configured dashboard or else the name of the metric.
+/// - `dashboard`: Whether a dashboard is configured, whose order the sections are
+///   written in.
+/// - `approximate`: Whether low-count entries were dropped to stay within the memory limit.
+/// - `records`: The number of records that were counted.
+/// - `records_read`: The number of records that were read, whether they were counted,
+///   skipped, or left out by the cutoff or a filter.
+/// - `newest`: The date and time of the newest record that was counted, if any.
+/// - `parse_errors`: The number of records that could not be read, or whose date/time
+///   could not be parsed.
+/// - `skipped`: The causes and lines of these records.
+/// - `empty_records`: The number of records with an empty priority, source or
+///   destination, whether they were dropped or counted.
+/// - `dimensions`: The number of records counted for each value of each dimension taken
+///   from the file names, by dimension name and value; see `tag`.
+/// - `windows`: The aggregates of the configured windows of the most recent events.
+pub struct AggregatedData {
+    metrics: Vec<Box<dyn Metric>>,
+    titles: Vec<String>,
+    dashboard: bool,
+    windows: Vec<WindowData>,
+    approximate: bool,
+    records: u64,
+    records_read: u64,
+    newest: Option<NaiveDateTime>,
+    parse_errors: u64,
+    skipped: Skipped,
+    empty_records: u64,
+    dimensions: BTreeMap<String, HashMap<String, u64>>,
+}
+
+/// The records that were skipped as they could not be read or parsed, by cause.
+///
+/// # Fields
+/// - `csv`: The number of records that are not valid CSV, such as a record with a stray
+///   quote.
+/// - `bad_timestamp`: The number of records whose date/time could not be parsed.
+/// - `missing_column`: The number of records that end before the date/time column.
+/// - `first_line`: The line of the first skipped record, if known.
+/// - `last_line`: The line of the last skipped record, if known.
+/// - `first_error`: Where and why the first record was skipped, such as
+///   `line 12: invalid date/time`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Skipped {
+    pub csv: u64,
+    pub bad_timestamp: u64,
+    pub missing_column: u64,
+    pub first_line: Option<u64>,
+    pub last_line: Option<u64>,
+    pub first_error: Option<String>,
+}
+
+/// Why a record was skipped.
+enum Cause {
+    Csv,
+    BadTimestamp,
+    MissingColumn,
+}
+
+impl Skipped {
+    /// Counts a record skipped for `cause` at `line` of the file if known, keeping the
+    /// `reason` of the first.
+    fn add(&mut self, cause: &Cause, line: Option<u64>, reason: impl FnOnce() -> String) {
+        *match cause {
+            Cause::Csv => &mut self.csv,
+            Cause::BadTimestamp => &mut self.bad_timestamp,
+            Cause::MissingColumn => &mut self.missing_column,
+        } += 1;
+        if self.first_error.is_none() {
+            self.first_line = line;
+            self.first_error = Some(match line {
+                Some(line) => format!("line {line}: {}", reason()),
+                None => reason(),
+            });
+        }
+        self.last_line = line.or(self.last_line);
+    }
+
+    /// Adds the skipped records of `other`, which were read after those of `self`.
+    fn merge(&mut self, other: &Self) {
+        self.csv += other.csv;
+        self.bad_timestamp += other.bad_timestamp;
+        self.missing_column += other.missing_column;
+        if self.first_error.is_none() {
+            self.first_line = other.first_line;
+            self.first_error.clone_from(&other.first_error);
+        }
+        self.last_line = other.last_line.or(self.last_line);
+    }
+}
+
+/// The aggregates of the events in one window of the most recent events.
+///
+/// # Fields
+/// - `label`: The name of the window, e.g. `7d`.
+/// - `start`: How long after the record cutoff the window starts.
+/// - `metrics`: The enabled metrics, observing only the events of the window.
+/// - `records`: The number of records counted in the window.
+struct WindowData {
+    label: String,
+    start: Duration,
+    metrics: Vec<Box<dyn Metric>>,
+    records: u64,
+}
+
+/// Zero-based column of the priority in the CSV logs.
+pub const PRIORITY_COLUMN: usize = 1;
+/// Zero-based column of the IDS signature name.
+pub const SIGNATURE_COLUMN: usize = 2;
+/// Zero-based column of the category, which contains `AWARE` for AWARE events.
+pub const CATEGORY_COLUMN: usize = 3;
+/// Zero-based column of the event date and time.
+pub const DATETIME_COLUMN: usize = 4;
+/// Zero-based column of the threat source address.
+pub const SOURCE_COLUMN: usize = 6;
+/// Zero-based column of the threat destination address.
+pub const DESTINATION_COLUMN: usize = 12;
+/// Format of the event date and time column.
+pub const DATETIME_FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+
+/// Value that empty keys are counted as under `EmptyFieldPolicy::Unknown`, unless the
+/// `normalize` configuration sets another sentinel.
+const UNKNOWN: &str = "(unknown)";
+
+/// How many records `aggregate_reader` reads between memory limit checks.
+const MEMORY_CHECK_INTERVAL: usize = 100_000;
+
+/// Returns the index of the first field of `record` that is not valid UTF-8, if any.
+fn invalid_utf8_field(record: &ByteRecord) -> Option<usize> {
+    if record.as_slice().is_ascii() {
+        return None;
+    }
+    record.iter().position(|field| str::from_utf8(field).is_err())
+}
+
+/// Returns `value` of `field` normalized as configured, or as it is without a
+/// `normalize` section.
+#[must_use]
+pub fn normalize<'a>(config: &'a Config, field: Field, value: &'a str) -> Cow<'a, str> {
+    config
+        .normalize
+        .as_ref()
+        .map_or(Cow::Borrowed(value), |normalize| {
+            normalize.apply(field, value)
+        })
+}
+
+/// Returns a CSV reader over `reader`, with the dialect of the log exports spelled out
+/// rather than left to the defaults of the `csv` crate:
+///
+/// - The first record is the header row.
+/// - Fields are separated by `delimiter`.
+/// - Fields containing the delimiter, quotes or line breaks are enclosed in double
+///   quotes, and quotes inside them are escaped by doubling them, as in `"say ""hi"""`.
+///   Backslashes have no special meaning.
+/// - Records may have more or fewer fields than the header row. Missing fields read as
+///   empty, so a short record does not fail the whole row.
+pub fn csv_reader<R: io::Read>(reader: R, delimiter: u8) -> Reader<R> {
+    ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(delimiter)
+        .quoting(true)
+        .quote(b'"')
+        .double_quote(true)
+        .escape(None)
+        .flexible(true)
+        .from_reader(reader)
+}
+
+/// Applies the `empty_fields` policy to the priority, source and destination of a record
+/// on `line` of which at least one is empty.
+///
+/// # Returns
+/// Whether the record is counted.
+///
+/// # Errors
+/// Returns an error under the `strict` policy.
+fn fill_empty<'a>(config: &'a Config, keys: &mut [Cow<'a, str>; 3], line: u64) -> io::Result<bool> {
+    match config.empty_fields {
+        EmptyFieldPolicy::Keep => Ok(true),
+        EmptyFieldPolicy::Drop => Ok(false),
+        EmptyFieldPolicy::Unknown => {
+            let unknown = config
+                .normalize
+                .as_ref()
+                .map_or(UNKNOWN, |normalize| &normalize.sentinel);
+            for key in keys.iter_mut().filter(|key| key.is_empty()) {
+                *key = Cow::Borrowed(unknown);
+            }
+            Ok(true)
+        }
+        EmptyFieldPolicy::Strict => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Record on line {line} has an empty priority, source or destination"),
+        )),
+    }
+}
+
+/// Completes the normalized priority, source and destination `keys` of a record on
+/// `line` with its normalized signature and category `labels`, and runs the configured
+/// `script` on them.
+///
+/// # Returns
+/// The priority, signature, category, source and destination, as the script left them,
+/// or `None` if it dropped the record.
+///
+/// # Errors
+/// Returns an error if the script fails.
+fn transform<'a>(
+    config: &'a Config,
+    [priority, source, destination]: [Cow<'a, str>; 3],
+    [signature, category]: [&'a str; 2],
+    line: u64,
+) -> io::Result<Option<[Cow<'a, str>; 5]>> {
+    let signature = normalize(config, Field::Signature, signature);
+    let category = normalize(config, Field::Category, category);
+    run_script(config, [priority, signature, category, source, destination], line)
+}
+
+/// Runs the configured `script` on the `fields` of a record on `line`, like `transform`.
+///
+/// # Errors
+/// Returns an error if the script fails.
+#[cfg(feature = "scripting")]
+fn run_script<'a>(
+    config: &Config,
+    fields: [Cow<'a, str>; 5],
+    line: u64,
+) -> io::Result<Option<[Cow<'a, str>; 5]>> {
+    let Some(script) = &config.loaded_script else {
+        return Ok(Some(fields));
+    };
+    script.run(fields).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Script failed on the record on line {line}: {e}"),
+        )
+    })
+}
+
+/// Returns the fields of a record unchanged, as scripts need the `scripting` feature.
+#[cfg(not(feature = "scripting"))]
+#[allow(clippy::unnecessary_wraps)]
+const fn run_script<'a>(
+    _config: &Config,
+    fields: [Cow<'a, str>; 5],
+    _line: u64,
+) -> io::Result<Option<[Cow<'a, str>; 5]>> {
+    Ok(Some(fields))
+}
+
+/// Resolves the columns of the row filters of `filter` against `headers`.
+///
+/// # Errors
+/// Returns an error if a row filter refers to a column that does not exist.
+fn resolve_rows<'a>(
+    filter: &'a RecordFilter,
+    headers: &StringRecord,
+) -> io::Result<Vec<(&'a RowFilter, Vec<usize>)>> {
+    filter
+        .rows
+        .iter()
+        .map(|row| {
+            let positions = row
+                .resolve(headers)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            Ok((row, positions))
+        })
+        .collect()
+}
+
+/// Positions of the optional columns mapped in the `columns` configuration in the
+/// header row of one file; `None` if a field is not mapped or the file has no such
+/// column.
+struct MappedColumns {
+    hostname: Option<usize>,
+    url: Option<usize>,
+    sni: Option<usize>,
+    user: Option<usize>,
+    zone: Option<usize>,
+    interface: Option<usize>,
+    vlan: Option<usize>,
+    mac: Option<usize>,
+    protocol: Option<usize>,
+    icmp_type: Option<usize>,
+    icmp_code: Option<usize>,
+    tcp_flags: Option<usize>,
+}
+
+impl MappedColumns {
+    /// Resolves the columns mapped in `columns` against `headers`.
+    fn resolve(columns: &ColumnConfig, headers: &StringRecord) -> Self {
+        let position = |name: &Option<String>| {
+            headers
+                .iter()
+                .position(|header| Some(header.trim()) == name.as_deref())
+        };
+        Self {
+            hostname: position(&columns.hostname),
+            url: position(&columns.url),
+            sni: position(&columns.sni),
+            user: position(&columns.user),
+            zone: position(&columns.zone),
+            interface: position(&columns.interface),
+            vlan: position(&columns.vlan),
+            mac: position(&columns.mac),
+            protocol: position(&columns.protocol),
+            icmp_type: position(&columns.icmp_type),
+            icmp_code: position(&columns.icmp_code),
+            tcp_flags: position(&columns.tcp_flags),
+        }
+    }
+
+    /// Reads the non-empty mapped fields of a record with `field`, which returns the
+    /// field at a position.
+    fn read<'a>(&self, field: impl Fn(usize) -> &'a str) -> MappedFields<'a> {
+        let read = |column: Option<usize>| column.map(&field).filter(|value| !value.is_empty());
+        MappedFields {
+            hostname: read(self.hostname),
+            url: read(self.url),
+            sni: read(self.sni),
+            user: read(self.user),
+            zone: read(self.zone),
+            interface: read(self.interface),
+            vlan: read(self.vlan),
+            mac: read(self.mac),
+            protocol: read(self.protocol),
+            icmp_type: read(self.icmp_type),
+            icmp_code: read(self.icmp_code),
+            tcp_flags: read(self.tcp_flags),
+        }
+    }
+}
+
+/// Aggregates the CSV log records read from `reader`, focusing on recent entries and
+/// filtering based on specific threat awareness.
+///
+/// Parses the records to observe every enabled metric, such as counts of priorities,
+/// threat sources, threat destinations, and occurrences of "AWARE" events. Only records
+/// newer than `cutoff` that match `filter` are counted. Malformed lines are skipped.
+/// The key fields are normalized first if the configuration has a `normalize` section.
+/// Records with an empty priority, source or destination are handled according to the
+/// `empty_fields` policy of the configuration. Optional fields are read from the
+/// columns mapped in the `columns` configuration setting.
+///
+/// # Arguments
+/// - `reader`: The CSV contents, starting with the header row.
+/// - `cutoff`: Records with a 'Date/Time' on or before this are skipped.
+/// - `filter`: Filters deciding which records are counted; records that do not match are skipped.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `memory_limit`: An optional budget in bytes for the metrics; see
+///   `AggregatedData::enforce_memory_limit`.
+///
+/// # Errors
+/// Returns an error if reading the header row fails, a row filter refers to a column
+/// that does not exist, or a record has an empty key under the `strict` policy.
+pub fn aggregate_reader<R: io::Read>(
+    reader: R,
+    cutoff: NaiveDateTime,
+    filter: &RecordFilter,
+    config: &Config,
+    memory_limit: Option<usize>,
+) -> io::Result<AggregatedData> {
+    aggregate_reader_with(reader, cutoff, filter, config, memory_limit, |_| {})
+}
+
+/// Aggregates the CSV log records read from `reader` like `aggregate_reader`, and also
+/// passes every counted event to `on_event`, e.g. to export the individual records.
+///
+/// # Errors
+/// Returns an error like `aggregate_reader`.
+pub fn aggregate_reader_with<R: io::Read>(
+    reader: R,
+    cutoff: NaiveDateTime,
+    filter: &RecordFilter,
+    config: &Config,
+    memory_limit: Option<usize>,
+    mut on_event: impl FnMut(&Event),
+) -> io::Result<AggregatedData> {
+    let mut rdr = csv_reader(reader, config.delimiter_byte());
+    let mut data = AggregatedData::new(config);
+    let interner = RefCell::new(Interner::default());
+
+    // Resolve row filter and mapped columns against this file's header row
+    let headers = rdr.headers()?.clone();
+    let row_filters = resolve_rows(filter, &headers)?;
+    let mapped = MappedColumns::resolve(&config.columns, &headers);
+
+    // Reuse a single record so parsing does not allocate per row. Unless a row filter or
+    // sampling needs the whole record, only the columns used below are decoded as UTF-8
+    let projected = row_filters.is_empty() && filter.sample.is_none();
+    let mut record = StringRecord::new();
+    let mut raw = ByteRecord::new();
+    for index in 0.. {
+        // Periodically make sure the maps stay within the memory budget
+        let check_memory = index % MEMORY_CHECK_INTERVAL == 0;
+        if check_memory && memory_limit.is_some_and(|limit| data.enforce_memory_limit(limit)) {
+            interner.borrow_mut().purge();
+        }
+
+        // Skip malformed lines
+        let result = if projected {
+            rdr.read_byte_record(&mut raw)
+        } else {
+            rdr.read_record(&mut record)
+        };
+        match result {
+            Ok(true) => data.records_read += 1,
+            Ok(false) => break,
+            // The file itself cannot be read any further, e.g. it timed out
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                let line = e.position().map(csv::Position::line);
+                data.records_read += 1;
+                data.skip(&Cause::Csv, line, || e.to_string());
+                continue;
+            }
+        }
+        let line = if projected {
+            raw.position()
+        } else {
+            record.position()
+        }
+        .map(csv::Position::line);
+        // Skip invalid UTF-8 as `read_record` does, instead of reading the field as empty
+        if let Some(index) = projected.then(|| invalid_utf8_field(&raw)).flatten() {
+            data.skip(&Cause::Csv, line, || format!("invalid UTF-8 in field {index}"));
+            continue;
+        }
+        let field = |index: usize| {
+            if projected {
+                raw.get(index)
+                    .and_then(|field| str::from_utf8(field).ok())
+                    .unwrap_or_default()
+            } else {
+                record.get(index).unwrap_or_default()
+            }
+        };
+
+        let event_datetime_str = field(DATETIME_COLUMN);
+        if let Ok(event_datetime) =
+            NaiveDateTime::parse_from_str(event_datetime_str, DATETIME_FORMAT)
+        {
+            // --min-priority applies to the priority as counted, after normalization
+            let priority = normalize(config, Field::Priority, field(PRIORITY_COLUMN));
+            let source_ip = normalize(config, Field::Source, field(SOURCE_COLUMN));
+            let destination_ip = normalize(config, Field::Destination, field(DESTINATION_COLUMN));
+            if event_datetime > cutoff
+                && filter.in_period(event_datetime)
+                && filter.meets_priority(&priority)
+                && filter.matches(&source_ip, &destination_ip)
+                && row_filters
+                    .iter()
+                    .all(|(row, positions)| row.matches(&record, positions))
+                && filter.sample.as_ref().map_or(true, |s| s.keeps(&record))
+            {
+                let mut keys = [priority, source_ip, destination_ip];
+                if keys.iter().any(|key| key.is_empty()) {
+                    data.empty_records += 1;
+                    if !fill_empty(config, &mut keys, line.unwrap_or(0))? {
+                        continue;
+                    }
+                }
+                let labels = [field(SIGNATURE_COLUMN), field(CATEGORY_COLUMN)];
+                let Some([priority, signature, category, source_ip, destination_ip]) =
+                    transform(config, keys, labels, line.unwrap_or(0))?
+                else {
+                    continue;
+                };
+                data.records += 1;
+                data.newest = data.newest.max(Some(event_datetime));
+                let event = Event::new(
+                    event_datetime,
+                    &priority,
+                    &signature,
+                    &category,
+                    &source_ip,
+                    &destination_ip,
+                    &interner,
+                )
+                .with_mapped(mapped.read(field));
+                data.observe(&event);
+                data.observe_windows(&event, cutoff);
+                on_event(&event);
+            }
+        } else {
+            let length = if projected { raw.len() } else { record.len() };
+            data.skip_timestamp(length, line, event_datetime_str);
+        }
+    }
+
+    Ok(data)
+}
+
+impl AggregatedData {
+    /// Creates an `AggregatedData` holding fresh instances of the metrics enabled in
+    /// `config`.
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        let (titles, metrics) = metric::build_named(config)
+            .into_iter()
+            .map(|(name, metric)| {
+                let title = config.title(name).unwrap_or_else(|| metric.name());
+                (title.to_string(), metric)
+            })
+            .unzip();
+        Self {
+            metrics,
+            titles,
+            dashboard: config.dashboard.is_some(),
+            windows: config
+                .windows
+                .iter()
+                .map(|window| WindowData {
+                    label: window.label.clone(),
+                    start: window.start,
+                    metrics: metric::build(config),
+                    records: 0,
+                })
+                .collect(),
+            approximate: false,
+            records: 0,
+            records_read: 0,
+            newest: None,
+            parse_errors: 0,
+            skipped: Skipped::default(),
+            empty_records: 0,
+            dimensions: BTreeMap::new(),
+        }
+    }
+
+    /// Counts a record that could not be read or parsed for `cause`, at `line` of the
+    /// file if known, keeping the `reason` of the first.
+    fn skip(&mut self, cause: &Cause, line: Option<u64>, reason: impl FnOnce() -> String) {
+        self.parse_errors += 1;
+        self.skipped.add(cause, line, reason);
+    }
+
+    /// Counts a record of `length` fields at `line` of the file if known, whose date/time
+    /// `datetime` could not be parsed, as missing the column if it is too short to have it.
+    fn skip_timestamp(&mut self, length: usize, line: Option<u64>, datetime: &str) {
+        if length <= DATETIME_COLUMN {
+            self.skip(&Cause::MissingColumn, line, || {
+                format!("no date/time column in {length} columns")
+            });
+        } else {
+            self.skip(&Cause::BadTimestamp, line, || {
+                format!("invalid date/time `{datetime}`")
+            });
+        }
+    }
+
+    /// Passes `event` to every metric.
+    pub fn observe(&mut self, event: &Event) {
+        for metric in &mut self.metrics {
+            metric.observe(event);
+        }
+    }
+
+    /// Passes `event` to the metrics of every window it falls into, given the record
+    /// `cutoff` the windows start after.
+    fn observe_windows(&mut self, event: &Event, cutoff: NaiveDateTime) {
+        for window in &mut self.windows {
+            if event.datetime > cutoff + window.start {
+                window.records += 1;
+                for metric in &mut window.metrics {
+                    metric.observe(event);
+                }
+            }
+        }
+    }
+
+    /// Returns every metric, including those of the windows.
+    fn all_metrics_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Metric>> {
+        self.metrics.iter_mut().chain(
+            self.windows
+                .iter_mut()
+                .flat_map(|window| window.metrics.iter_mut()),
+        )
+    }
+
+    /// Adds the metrics of `other`, which was built from the same configuration, into `self`.
+    pub fn merge(&mut self, other: &Self) {
+        merge_metrics(&mut self.metrics, &other.metrics);
+        for (window, other_window) in self.windows.iter_mut().zip(&other.windows) {
+            merge_metrics(&mut window.metrics, &other_window.metrics);
+            window.records += other_window.records;
+        }
+
+        self.approximate |= other.approximate;
+        self.records += other.records;
+        self.records_read += other.records_read;
+        self.newest = self.newest.max(other.newest);
+        self.parse_errors += other.parse_errors;
+        self.skipped.merge(&other.skipped);
+        self.empty_records += other.empty_records;
+        self.add_dimensions(other.dimensions.clone());
+    }
+
+    /// Returns the complete state of the aggregates, which `merge_state` can restore,
+    /// for checkpoints.
+    #[must_use]
+    pub fn state(&self) -> Value {
+        let windows: serde_json::Map<String, Value> = self
+            .windows
+            .iter()
+            .map(|window| {
+                let state = json!({
+                    "metrics": metrics_state(&window.metrics),
+                    "records": window.records,
+                });
+                (window.label.clone(), state)
+            })
+            .collect();
+        json!({
+            "metrics": metrics_state(&self.metrics),
+            "windows": windows,
+            "approximate": self.approximate,
+            "records": self.records,
+            "records_read": self.records_read,
+            "newest": self.newest.map(|newest| newest.format(DATETIME_FORMAT).to_string()),
+            "parse_errors": self.parse_errors,
+            "skipped": self.skipped,
+            "empty_records": self.empty_records,
+            "dimensions": self.dimensions,
+        })
+    }
+
+    /// Adds a `state` returned by `state` for aggregates built from the same
+    /// configuration into `self`, like `merge`.
+    ///
+    /// # Errors
+    /// Returns a message if `state` is malformed or lacks the state of an enabled metric.
+    pub fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_metrics_state(&mut self.metrics, state)?;
+        for window in &mut self.windows {
+            let window_state = state
+                .get("windows")
+                .and_then(|windows| windows.get(&window.label))
+                .ok_or_else(|| format!("missing the state of window {}", window.label))?;
+            merge_metrics_state(&mut window.metrics, window_state)
+                .map_err(|e| format!("{e} in window {}", window.label))?;
+            window.records += window_state
+                .get("records")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| format!("missing records of window {}", window.label))?;
+        }
+        let count = |name: &str| {
+            state
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| format!("missing {name}"))
+        };
+        self.records += count("records")?;
+        // Checkpoints written before the records read were tracked have none
+        self.records_read += state.get("records_read").and_then(Value::as_u64).unwrap_or(0);
+        // Checkpoints written before the newest record was tracked have none
+        let newest = state.get("newest").and_then(Value::as_str);
+        let newest = newest
+            .map(|newest| NaiveDateTime::parse_from_str(newest, DATETIME_FORMAT))
+            .transpose()
+            .map_err(|e| format!("invalid newest record time: {e}"))?;
+        self.newest = self.newest.max(newest);
+        self.parse_errors += count("parse_errors")?;
+        // Checkpoints written before the causes were tracked have none
+        if let Some(skipped) = state.get("skipped") {
+            let skipped: Skipped = serde_json::from_value(skipped.clone())
+                .map_err(|e| format!("invalid skipped records: {e}"))?;
+            self.skipped.merge(&skipped);
+        }
+        self.empty_records += count("empty_records")?;
+        self.approximate |= state.get("approximate").and_then(Value::as_bool) == Some(true);
+        let dimensions: BTreeMap<String, HashMap<String, u64>> = serde_json::from_value(
+            state
+                .get("dimensions")
+                .cloned()
+                .unwrap_or_else(|| json!({})),
+        )
+        .map_err(|e| format!("invalid dimensions: {e}"))?;
+        self.add_dimensions(dimensions);
+        Ok(())
+    }
+
+    /// Adds the counts of `dimensions`, by dimension name and value, into `self`.
+    fn add_dimensions(&mut self, dimensions: BTreeMap<String, HashMap<String, u64>>) {
+        for (name, values) in dimensions {
+            let counts = self.dimensions.entry(name).or_default();
+            for (value, count) in values {
+                *counts.entry(value).or_default() += count;
+            }
+        }
+    }
+
+    /// Counts the records of a single file under its `dimensions`, the `(name, value)`
+    /// pairs taken from its name by `FilenamePattern::dimensions`. Call this on the
+    /// aggregates of one file, before they are merged with others.
+    pub fn tag(&mut self, dimensions: &[(String, String)]) {
+        for (name, value) in dimensions {
+            *self
+                .dimensions
+                .entry(name.clone())
+                .or_default()
+                .entry(value.clone())
+                .or_default() += self.records;
+        }
+    }
+
+    /// Returns the number of records that were counted.
+    #[must_use]
+    pub const fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Returns the number of records that were read, whether they were counted or not.
+    #[must_use]
+    pub const fn records_read(&self) -> u64 {
+        self.records_read
+    }
+
+    /// Returns the date and time of the newest record that was counted, if any.
+    #[must_use]
+    pub const fn newest(&self) -> Option<NaiveDateTime> {
+        self.newest
+    }
+
+    /// Returns the number of records that could not be read, or whose date/time could
+    /// not be parsed.
+    #[must_use]
+    pub const fn parse_errors(&self) -> u64 {
+        self.parse_errors
+    }
+
+    /// Returns the causes and lines of the records that could not be read or parsed.
+    #[must_use]
+    pub const fn skipped(&self) -> &Skipped {
+        &self.skipped
+    }
+
+    /// Returns the number of records with an empty priority, source or destination.
+    #[must_use]
+    pub const fn empty_records(&self) -> u64 {
+        self.empty_records
+    }
+
+    /// Returns whether low-count entries were dropped to stay within the memory limit,
+    /// making the counts approximate.
+    #[must_use]
+    pub const fn is_approximate(&self) -> bool {
+        self.approximate
+    }
+
+    /// Returns the metric named `name` if it is enabled and of type `M`.
+    #[must_use]
+    pub fn metric<M: 'static>(&self, name: &str) -> Option<&M> {
+        self.metrics
+            .iter()
+            .find(|metric| metric.name() == name)
+            .and_then(|metric| metric.as_any().downcast_ref())
+    }
+
+    /// Returns the time series of the metrics, keyed like `Metric::series`.
+    #[must_use]
+    pub fn series(&self) -> Vec<(String, Vec<(String, u64)>)> {
+        self.metrics.iter().flat_map(|metric| metric.series()).collect()
+    }
+
+    /// Returns the approximate heap usage of all metrics, in bytes.
+    #[must_use]
+    pub fn approx_memory(&self) -> usize {
+        self.metrics
+            .iter()
+            .chain(self.windows.iter().flat_map(|window| &window.metrics))
+            .map(|metric| metric.memory())
+            .sum()
+    }
+
+    /// Keeps the metrics within `limit` bytes by switching to approximate counting.
+    ///
+    /// When the estimate exceeds the limit, the entries with the lowest counts (e.g. rare
+    /// sources and destinations) are dropped, doubling the count threshold until usage is
+    /// at most half the limit to leave headroom. This preserves the heavy hitters that
+    /// make up the top lists. A warning is logged the first time this happens.
+    ///
+    /// # Returns
+    /// Whether any pruning happened.
+    pub fn enforce_memory_limit(&mut self, limit: usize) -> bool {
+        let before = self.approx_memory();
+        if before <= limit {
+            return false;
+        }
+
+        let mut threshold: u32 = 1;
+        let mut dropped = 0;
+        while self.approx_memory() > limit / 2 {
+            dropped += self
+                .all_metrics_mut()
+                .map(|metric| metric.prune(threshold))
+                .sum::<usize>();
+            if threshold == u32::MAX {
+                break;
+            }
+            threshold = threshold.saturating_mul(2);
+        }
+
+        if !self.approximate {
+            log::warn(format_args!(
+                "estimated memory use of {before} bytes exceeds --max-memory {limit}; \
+                 dropped {dropped} low-count sources/destinations, counts are now approximate"
+            ));
+        }
+        self.approximate = true;
+        true
+    }
+
+    /// Scales every metric from a sample up to an estimate for the full input.
+    pub fn scale(&mut self, sample: &Sample) {
+        for metric in self.all_metrics_mut() {
+            metric.scale(sample);
+        }
+        for count in self.dimensions.values_mut().flat_map(HashMap::values_mut) {
+            *count = sample.scale(*count);
+        }
+    }
+
+    /// Replaces the source and destination addresses in every metric with
+    /// `anonymize(address)`, adding up the counts of addresses that become the same.
+    pub fn anonymize(&mut self, anonymize: &dyn Fn(&str) -> String) {
+        for metric in self.all_metrics_mut() {
+            metric.anonymize(anonymize);
+        }
+    }
+
+    /// Returns the titles of the sections of the metrics in the order of the configured
+    /// dashboard, or `None` without a dashboard, when they are sorted by title.
+    #[must_use]
+    pub fn section_order(&self) -> Option<&[String]> {
+        self.dashboard.then_some(self.titles.as_slice())
+    }
+
+    /// Builds the `events.json` document from the metrics.
+    ///
+    /// Each metric contributes one section, under its title in the configured dashboard
+    /// or else its name, typically an object of parallel arrays (labels
+    /// and counts), which is the shape the dashboard charts consume. The threat score
+    /// section is only included when priority weights are configured. With a
+    /// `filename_pattern`, a `Dimensions` section counts the records of every value of
+    /// each dimension, such as `{"device": {"device": [...], "Count": [...]}}`, sorted by
+    /// count. With windows, a `Windows` section holds the sections of the metrics of
+    /// each window and its number of `Records`, keyed by its label, such as
+    /// `{"7d": {"Priorities": ..., "Records": 42}}`.
+    #[must_use]
+    pub fn events_json(&self) -> Value {
+        let mut sections = finish_metrics(&self.metrics, &self.titles);
+        if !self.windows.is_empty() {
+            let windows = self
+                .windows
+                .iter()
+                .map(|window| {
+                    let mut window_sections = finish_metrics(&window.metrics, &self.titles);
+                    window_sections.insert("Records".to_string(), json!(window.records));
+                    (window.label.clone(), Value::Object(window_sections))
+                })
+                .collect();
+            sections.insert("Windows".to_string(), Value::Object(windows));
+        }
+        if !self.dimensions.is_empty() {
+            let dimensions = self
+                .dimensions
+                .iter()
+                .map(|(name, counts)| {
+                    let mut sorted: Vec<_> = counts.iter().collect();
+                    sorted.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                    let section = json!({
+                        name: sorted.iter().map(|(value, _)| value).collect::<Vec<_>>(),
+                        "Count": sorted.iter().map(|(_, count)| count).collect::<Vec<_>>()
+                    });
+                    (name.clone(), section)
+                })
+                .collect();
+            sections.insert("Dimensions".to_string(), Value::Object(dimensions));
+        }
+        Value::Object(sections)
+    }
+}
+
+/// Adds the metrics `other`, built from the same configuration, into `metrics`.
+fn merge_metrics(metrics: &mut [Box<dyn Metric>], other: &[Box<dyn Metric>]) {
+    for (metric, other_metric) in metrics.iter_mut().zip(other) {
+        metric.merge(other_metric.as_ref());
+    }
+}
+
+/// Returns the states of `metrics`, keyed by metric name.
+fn metrics_state(metrics: &[Box<dyn Metric>]) -> serde_json::Map<String, Value> {
+    metrics
+        .iter()
+        .map(|metric| (metric.name().to_string(), metric.state()))
+        .collect()
+}
+
+/// Adds the states in the `metrics` object of `state` into `metrics`.
+///
+/// # Errors
+/// Returns a message if the state of a metric is missing or malformed.
+fn merge_metrics_state(metrics: &mut [Box<dyn Metric>], state: &Value) -> Result<(), String> {
+    for metric in metrics {
+        let metric_state = state
+            .get("metrics")
+            .and_then(|metrics| metrics.get(metric.name()))
+            .ok_or_else(|| format!("missing the state of {}", metric.name()))?;
+        metric
+            .merge_state(metric_state)
+            .map_err(|e| format!("invalid state of {}: {e}", metric.name()))?;
+    }
+    Ok(())
+}
+
+/// Returns the sections of `metrics`, keyed by their `titles`.
+fn finish_metrics(
+    metrics: &[Box<dyn Metric>],
+    titles: &[String],
+) -> serde_json::Map<String, Value> {
+    metrics
+        .iter()
+        .zip(titles)
+        .map(|(metric, title)| (title.clone(), metric.finish()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use normalize::NormalizeConfig;
+
+    const HEADER: &str = "id,prio,sig,cat,dt,x,src,a,b,c,d,e,dst\n";
+
+    fn aggregate(log: &str, filter: &RecordFilter, config: &Config) -> io::Result<AggregatedData> {
+        let cutoff = NaiveDateTime::parse_from_str("2024/01/01 00:00:00", DATETIME_FORMAT)
+            .map_err(io::Error::other)?;
+        aggregate_reader(log.as_bytes(), cutoff, filter, config, None)
+    }
+
+    #[test]
+    fn min_priority_applies_to_the_normalized_priority() -> io::Result<()> {
+        let log = format!(
+            "{HEADER}\
+             1,0,sig,cat,2024/06/01 10:00:00,x,10.0.0.1,a,b,c,d,e,10.1.0.1\n\
+             2, 1 ,sig,cat,2024/06/01 10:00:00,x,10.0.0.2,a,b,c,d,e,10.1.0.1\n\
+             3,3,sig,cat,2024/06/01 10:00:00,x,10.0.0.3,a,b,c,d,e,10.1.0.1\n"
+        );
+        let filter = RecordFilter {
+            min_priority: Some(2),
+            ..RecordFilter::default()
+        };
+        let config = Config {
+            normalize: Some(NormalizeConfig {
+                unknown: vec!["0".to_string()],
+                ..NormalizeConfig::default()
+            }),
+            ..Config::default()
+        };
+
+        // `0` is counted as `(unknown)`, which is not a number and below any floor
+        assert_eq!(aggregate(&log, &filter, &config)?.records(), 1);
+        assert_eq!(aggregate(&log, &filter, &Config::default())?.records(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn blank_keys_are_empty_after_normalization() -> io::Result<()> {
+        let log = format!(
+            "{HEADER}\
+             1,1,sig,cat,2024/06/01 10:00:00,x,10.0.0.1,a,b,c,d,e,10.1.0.1\n\
+             2,1,sig,cat,2024/06/01 10:00:00,x,  ,a,b,c,d,e,10.1.0.1\n\
+             3, ,sig,cat,2024/06/01 10:00:00,x,10.0.0.3,a,b,c,d,e,\n"
+        );
+        let config = |empty_fields, sentinel: &str| Config {
+            empty_fields,
+            normalize: Some(NormalizeConfig {
+                sentinel: sentinel.to_string(),
+                ..NormalizeConfig::default()
+            }),
+            ..Config::default()
+        };
+        let filter = RecordFilter::default();
+
+        let data = aggregate(&log, &filter, &config(EmptyFieldPolicy::Unknown, "-"))?;
+        assert_eq!(data.empty_records(), 2);
+        assert_eq!(
+            data.events_json().get("Threat Sources"),
+            Some(&serde_json::json!({"Source": ["-", "10.0.0.1", "10.0.0.3"], "Count": [1, 1, 1]}))
+        );
+        let data = aggregate(&log, &filter, &config(EmptyFieldPolicy::Drop, "-"))?;
+        assert_eq!([data.records(), data.empty_records()], [1, 2]);
+        // Without a `normalize` section, blank keys are kept as they are
+        let unnormalized = Config {
+            empty_fields: EmptyFieldPolicy::Drop,
+            ..Config::default()
+        };
+        let data = aggregate(&log, &filter, &unnormalized)?;
+        assert_eq!([data.records(), data.empty_records()], [2, 1]);
+        let strict = aggregate(&log, &filter, &config(EmptyFieldPolicy::Strict, "-"));
+        assert_eq!(
+            strict.err().map(|e| e.to_string()).as_deref(),
+            Some("Record on line 3 has an empty priority, source or destination")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_utf8_is_skipped_whether_or_not_records_are_projected() -> io::Result<()> {
+        let mut log = format!(
+            "{HEADER}\
+             1,1,sig,cat,2024/06/01 10:00:00,x,10.0.0.1,a,b,c,d,e,10.1.0.1\n\
+             2,1,sig,cat,2024/06/01 10:00:00,x,10.0.0.2,a,b,c,d,e,10.1.0.1\n"
+        )
+        .into_bytes();
+        // An invalid byte in the source, and one in a column that is not counted
+        log.extend_from_slice(b"3,1,sig,cat,2024/06/01 10:00:00,x,10.0.\xff.3,a,b,c,d,e,dst\n");
+        log.extend_from_slice(b"4,1,sig,cat,2024/06/01 10:00:00,\xfe,10.0.0.4,a,b,c,d,e,dst\n");
+        let cutoff = NaiveDateTime::parse_from_str("2024/01/01 00:00:00", DATETIME_FORMAT)
+            .map_err(io::Error::other)?;
+        let config = Config::default();
+
+        let unfiltered = RecordFilter::default();
+        let projected = aggregate_reader(&log[..], cutoff, &unfiltered, &config, None)?;
+        // A row filter that keeps every record needs whole records
+        let filter = RecordFilter {
+            rows: vec![RowFilter::parse("col:id != none").map_err(io::Error::other)?],
+            ..RecordFilter::default()
+        };
+        let whole = aggregate_reader(&log[..], cutoff, &filter, &config, None)?;
+
+        for data in [&projected, &whole] {
+            assert_eq!(data.records(), 2);
+            assert_eq!(data.records_read(), 4);
+            assert_eq!(data.skipped().csv, 2);
+            assert_eq!(data.skipped().first_line, Some(4));
+            assert_eq!(data.skipped().last_line, Some(5));
+        }
+        Ok(())
+    }
+}
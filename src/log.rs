@@ -0,0 +1,98 @@
+//! Progress messages, warnings and errors.
+//!
+//! By default messages are plain text: progress goes to stdout, and warnings and errors
+//! go to stderr prefixed with `Warning: ` or `Error: `. With `--log-format json`, every
+//! message goes to stdout as a JSON object on its own line with its time, level and
+//! text, so a container runtime can collect and index them.
+//!
+//! A warning is only logged the first time in a run, and with `--max-warnings` only as
+//! many different warnings are logged as it allows. How many were left out is logged
+//! once the run is over, by `finish_run`.
+
+use chrono::{Local, SecondsFormat};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Whether messages are logged as JSON lines.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// The most different warnings logged in a run.
+static MAX_WARNINGS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The warnings logged so far in this run.
+static WARNINGS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// The number of warnings left out so far in this run.
+static SUPPRESSED: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets whether messages are logged as JSON lines rather than as plain text.
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+/// Sets the most different warnings logged in a run, or no limit with `None`.
+pub fn set_max_warnings(max: Option<usize>) {
+    MAX_WARNINGS.store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Logs a progress message.
+pub fn info(message: impl Display) {
+    log("info", message);
+}
+
+/// Logs a warning about something that was skipped or degraded, unless the same warning
+/// was already logged in this run or the run has reached `--max-warnings`.
+pub fn warn(message: impl Display) {
+    let message = message.to_string();
+    let new = {
+        let mut warnings = WARNINGS.lock().unwrap_or_else(PoisonError::into_inner);
+        warnings.len() < MAX_WARNINGS.load(Ordering::Relaxed) && warnings.insert(message.clone())
+    };
+    if new {
+        log("warning", message);
+    } else {
+        SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Logs how many warnings were left out in the run that just ended, and starts a new
+/// one, in which every warning is logged again.
+pub fn finish_run() {
+    WARNINGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    let suppressed = SUPPRESSED.swap(0, Ordering::Relaxed);
+    if suppressed > 0 {
+        log(
+            "warning",
+            format_args!("{suppressed} repeated or further warnings were left out (--max-warnings)"),
+        );
+    }
+}
+
+/// Logs an error.
+pub fn error(message: impl Display) {
+    log("error", message);
+}
+
+/// Logs `message` at `level`, as a JSON line or as plain text.
+fn log(level: &str, message: impl Display) {
+    if JSON.load(Ordering::Relaxed) {
+        let line = json!({
+            "time": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            "level": level,
+            "message": message.to_string(),
+        });
+        println!("{line}");
+    } else {
+        match level {
+            "warning" => eprintln!("Warning: {message}"),
+            "error" => eprintln!("Error: {message}"),
+            _ => println!("{message}"),
+        }
+    }
+}
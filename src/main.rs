@@ -1,61 +1,111 @@
-use chrono::{Duration, Local, NaiveDateTime, Timelike};
-use csv::ReaderBuilder;
-use serde_json::{json, to_string_pretty};
-use std::collections::HashMap;
+mod abuse;
+mod anonymize;
+#[cfg(feature = "async-io")]
+mod async_io;
+mod audit;
+mod bench;
+mod checkpoint;
+mod cli;
+mod clickhouse;
+mod collector;
+mod deadline;
+mod enrich;
+mod error_report;
+mod eventlog;
+mod explain;
+mod follow;
+mod geoip;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod history;
+mod http;
+mod interrupt;
+mod jq;
+mod journal;
+mod manifest;
+mod misp;
+mod mmap;
+mod mqtt;
+mod output;
+mod per_file;
+mod query;
+mod redis;
+mod reputation;
+mod retention;
+mod rollup;
+mod serve;
+mod stix;
+mod systemd;
+mod telemetry;
+mod template;
+mod tui;
+mod whois;
+mod xlsx;
+
+use anonymize::Anonymizer;
+use checkpoint::Checkpoint;
+use chrono::{Duration, Local, NaiveDateTime, SecondsFormat, TimeZone};
+use cli::{Command, Options, SplitOutput};
+use dashboard_aggregator::baseline::Baseline;
+use dashboard_aggregator::config::{Config, EmptyFieldPolicy, ReportFormatConfig, Window};
+use dashboard_aggregator::input::InputFormat;
+use dashboard_aggregator::metric::{Addresses, AwareThreats, Event, Priorities, ThreatScore};
+use dashboard_aggregator::{aggregate_reader_with, log, schema, AggregatedData};
+use error_report::ErrorReport;
+use output::Ordered;
+use per_file::Breakdown;
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::convert::TryInto;
 use std::env;
-use std::fs::{self, DirEntry, File};
+use std::fs;
 use std::io::{self, Write};
-use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, PoisonError, RwLock};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use telemetry::Telemetry;
+use template::Template;
 
-/// Represents aggregated data from CSV file processing.
-///
-/// This struct holds aggregated counts of various threat indicators and priorities,
-/// including counts of priorities, threat sources, threat destinations, and AWARE threats.
-///
-/// # Fields
-/// - `priorities_count`: A map of priority labels to their respective counts.
-/// - `threat_sources`: A map of threat source IP addresses to their occurrence counts.
-/// - `threat_destinations`: A map of threat destination IP addresses to their occurrence counts.
-/// - `aware_threats`: A map of dates (and possibly times of day) to counts of AWARE threats.
-struct AggregatedData {
-    priorities_count: HashMap<String, u32>,
-    threat_sources: HashMap<String, u32>,
-    threat_destinations: HashMap<String, u32>,
-    aware_threats: HashMap<String, u32>,
+/// Returns the record cutoff for `days_back`: records must be newer than this.
+fn cutoff(days_back: i64) -> NaiveDateTime {
+    (Local::now() - Duration::days(days_back)).naive_local()
 }
 
 /// Filters files in a specified directory that match a naming pattern and were modified
 /// within a specified number of days back from the current date.
 ///
-/// This function looks for files starting with "fwddmp.log.tmp" and filters them based on their
-/// last modified time, keeping only those modified within the last `days_back` days.
+/// This function looks for files starting with `prefix`, e.g. "fwddmp.log.tmp", and filters them
+/// based on their last modified time, keeping only those modified within the last `days_back` days.
 ///
 /// # Arguments
 /// - `path`: A reference to the path of the directory to search in.
+/// - `prefix`: The prefix of the log file names, which depends on the input format.
 /// - `days_back`: The number of days back from the current date to consider when filtering files.
-///                Files modified more recently than this will be included in the results.
+///   Files modified more recently than this will be included in the results. If `None`, every
+///   file matching the naming pattern is included regardless of its modification time.
 ///
 /// # Returns
-/// A vector of `DirEntry` representing the filtered files that match the criteria.
+/// A vector of the paths of the filtered files that match the criteria.
+///
+/// # Errors
+/// Returns an error if the directory cannot be read.
 ///
 /// # Panics
-/// Panics if reading the directory fails, if there is an error calculating time durations,
-/// or if converting system times to a comparable format fails.
-fn filter_files(path: &Path, days_back: i64) -> Vec<DirEntry> {
+/// Panics if there is an error calculating time durations, or if converting system times
+/// to a comparable format fails.
+fn filter_files(path: &Path, prefix: &str, days_back: Option<i64>) -> io::Result<Vec<PathBuf>> {
     let now = Local::now();
-    fs::read_dir(path)
-        .expect("Error reading directory")
+    let entries = fs::read_dir(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{e}: {}", path.display())))?;
+    Ok(entries
         .filter_map(Result::ok)
         .filter(|entry| {
-            entry
-                .file_name()
-                .to_string_lossy()
-                .starts_with("fwddmp.log.tmp")
-                && entry
-                    .metadata()
-                    .map(|meta| {
+            entry.file_name().to_string_lossy().starts_with(prefix)
+                && days_back.map_or(true, |days_back| {
+                    entry.metadata().is_ok_and(|meta| {
                         let file_time = meta
                             .modified()
                             .unwrap_or_else(|_| SystemTime::now())
@@ -72,235 +122,1286 @@ fn filter_files(path: &Path, days_back: i64) -> Vec<DirEntry> {
 
                         file_time > comparison_time
                     })
-                    .unwrap_or(false)
+                })
+        })
+        .map(|entry| entry.path())
+        .collect())
+}
+
+/// Lists the log files selected by `options` with `filter_files`. With `--io-timeout`,
+/// the listing fails if the log directory does not answer in time, e.g. on a dead NFS
+/// mount.
+///
+/// # Errors
+/// Returns an error if the log directory cannot be read, or not in time.
+fn list_log_files(options: &Options) -> io::Result<Vec<PathBuf>> {
+    let path = options.log_path.clone();
+    let (prefix, days_back) = (options.format.file_prefix(), options.file_days_back());
+    match options.io_timeout {
+        Some(timeout) => deadline::call(
+            timeout,
+            &format!("Listing {}", path.display()),
+            move || filter_files(&path, prefix, days_back),
+        ),
+        None => filter_files(&path, prefix, days_back),
+    }
+}
+
+/// Leaves out the files that `--max-file-size` and `--min-file-mtime-age` guard against:
+/// files larger than the maximum size, which are likely corrupt, and files modified less
+/// than the minimum age ago, which the exporter is likely still writing.
+///
+/// # Arguments
+/// - `files`: The files to check, typically the output of `filter_files`.
+/// - `options`: The parsed command line, providing the guards.
+/// - `verbose`: Whether to print a warning naming every skipped file and why.
+///
+/// # Returns
+/// The files that pass the guards, in their original order.
+fn guard_files(files: Vec<PathBuf>, options: &Options, verbose: bool) -> Vec<PathBuf> {
+    if options.max_file_size.is_none() && options.min_file_mtime_age.is_none() {
+        return files;
+    }
+    files
+        .into_iter()
+        .filter(|path| {
+            let Some(reason) = skip_reason(path, options) else {
+                return true;
+            };
+            if verbose {
+                log::warn(format_args!("skipping {}: {reason}", path.display()));
+            }
+            false
         })
         .collect()
 }
 
+/// Returns why the file at `path` is skipped by the file guards, or `None` if it is
+/// read. Files whose metadata cannot be read are left to fail when they are opened.
+fn skip_reason(path: &Path, options: &Options) -> Option<String> {
+    let meta = fs::metadata(path).ok()?;
+    if let Some(max) = options.max_file_size.filter(|&max| meta.len() > max) {
+        return Some(format!(
+            "{} bytes is larger than --max-file-size of {max} bytes",
+            meta.len()
+        ));
+    }
+    let min_age = options.min_file_mtime_age?;
+    // A modification time in the future counts as just modified
+    let age = meta
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .unwrap_or_default();
+    (age < min_age).then(|| {
+        format!(
+            "modified {}s ago, less than --min-file-mtime-age of {}s",
+            age.as_secs(),
+            min_age.as_secs()
+        )
+    })
+}
+
 /// Processes a given CSV file to aggregate data related to threat indicators,
 /// focusing on recent entries and filtering based on specific threat awareness.
 ///
-/// Parses the CSV file to count occurrences of various metrics such as priorities, threat sources,
-/// threat destinations, and occurrences of "AWARE" events within a specified date range. The function filters entries
-/// to include only those newer than a given number of days back from the current date. Malformed lines are skipped.
+/// Opens the file and aggregates its records with `read_csv_file`, which counts
+/// occurrences of various metrics such as priorities, threat sources, threat destinations,
+/// and occurrences of "AWARE" events. Only entries newer than a given number of days back
+/// from the current date are included. Malformed lines are skipped. The counted events
+/// are also inserted into `ClickHouse` if `clickhouse_url` is set.
 ///
 /// # Arguments
 /// - `file_path`: A reference to the path of the CSV file to be processed.
-/// - `days_back`: The number of days back from the current date to consider when filtering records. Only records
-///   with a 'Date/Time' on or after this threshold are processed.
+/// - `options`: The options of the run. Only records with a 'Date/Time' within its
+///   `days_back` that match its `filter` are counted, and the file is read in its
+///   `encoding` and `format`, memory-mapped if `mmap` is set.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `memory_limit`: An optional budget in bytes for the file's maps; see
+///   `AggregatedData::enforce_memory_limit`.
 ///
 /// # Returns
-/// An `io::Result` wrapping an `AggregatedData` struct containing aggregated counts from the file. This structure includes:
-/// - `priorities_count`: A hash map of priorities and their occurrence counts.
-/// - `threat_sources`: A hash map of source IP addresses and their occurrence counts.
-/// - `threat_destinations`: A hash map of destination IP addresses and their occurrence counts.
-/// - `aware_threats`: A hash map of dates with counts of AWARE flagged events, segmented by AM/PM.
+/// An `io::Result` wrapping an `AggregatedData` struct containing the file's metrics, such as:
+/// - Priorities and their occurrence counts.
+/// - Source and destination IP addresses and their occurrence counts.
+/// - Dates with counts of AWARE flagged events, segmented by AM/PM.
+/// - Dates with the summed priority weights of all events, segmented by AM/PM.
 ///
 /// # Errors
 /// Returns an error if reading the CSV file or parsing its contents fails. This includes errors due to
 /// file access issues, data format issues, or other IO-related failures.
-fn process_csv_file(file_path: &Path, days_back: i64) -> io::Result<AggregatedData> {
-    let now = Local::now();
-    let cutoff = now - Duration::days(days_back);
-
-    let mut rdr = ReaderBuilder::new().from_path(file_path)?;
-    let mut priorities_count: HashMap<String, u32> = HashMap::new();
-    let mut threat_sources: HashMap<String, u32> = HashMap::new();
-    let mut threat_destinations: HashMap<String, u32> = HashMap::new();
-    let mut aware_threats: HashMap<String, u32> = HashMap::new();
-
-    for result in rdr.records() {
-        // Skip malformed lines
-        let record = match result {
-            Ok(record) => record,
-            Err(e) => {
-                println!("Failed to read record: {e}");
-                continue;
-            }
+fn process_csv_file(
+    file_path: &Path,
+    options: &Options,
+    config: &Config,
+    memory_limit: Option<usize>,
+) -> io::Result<AggregatedData> {
+    let Some(url) = &options.clickhouse_url else {
+        return read_csv_file(file_path, options, config, memory_limit, |_| {});
+    };
+    let device = device_name(config, file_path);
+    let mut inserter = clickhouse::Inserter::new(url, &options.clickhouse_table, device);
+    let data = read_csv_file(file_path, options, config, memory_limit, |event| {
+        inserter.add(event);
+    })?;
+    inserter
+        .finish()
+        .map_err(|e| io::Error::new(e.kind(), format!("{e} in {}", file_path.display())))?;
+    Ok(data)
+}
+
+/// Aggregates the records of the file at `file_path` like `process_csv_file`, passing
+/// every counted event to `on_event`.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or its contents cannot be parsed.
+fn read_csv_file(
+    file_path: &Path,
+    options: &Options,
+    config: &Config,
+    memory_limit: Option<usize>,
+    on_event: impl FnMut(&Event),
+) -> io::Result<AggregatedData> {
+    let cutoff = cutoff(options.days_back);
+
+    // A mapped file is parsed straight from the page cache, without the copies and many
+    // small reads of a buffered reader. A mapped read cannot time out, so with a timeout
+    // the whole file is read in one read instead
+    let reader: Box<dyn io::Read> = if let Some(timeout) = options.io_timeout {
+        Box::new(deadline::Reader::open(file_path, options.mmap, timeout))
+    } else if options.mmap {
+        Box::new(io::Cursor::new(mmap::map(file_path)?))
+    } else {
+        open(file_path)?
+    };
+    let reader = options.format.reader(options.encoding.reader(reader));
+    let mut data = aggregate_reader_with(
+        reader,
+        cutoff,
+        &options.filter,
+        config,
+        memory_limit,
+        on_event,
+    )
+    .map_err(|e| io::Error::new(e.kind(), format!("{e} in {}", file_path.display())))?;
+    if let Some(pattern) = &config.filename_pattern {
+        data.tag(&pattern.dimensions(file_path));
+    }
+    Ok(data)
+}
+
+/// Opens the log file at `path` for reading, on tokio with the `async-io` feature (see
+/// the `async_io` module).
+///
+/// # Errors
+/// Returns an error if the file cannot be opened.
+#[cfg(feature = "async-io")]
+fn open(path: &Path) -> io::Result<Box<dyn io::Read>> {
+    Ok(Box::new(async_io::Reader::open(path)?))
+}
+
+/// Opens the log file at `path` for reading.
+///
+/// # Errors
+/// Returns an error if the file cannot be opened.
+#[cfg(not(feature = "async-io"))]
+fn open(path: &Path) -> io::Result<Box<dyn io::Read>> {
+    Ok(Box::new(fs::File::open(path)?))
+}
+
+/// Returns the device that wrote the log file at `path`: the `device` group of the
+/// configured `filename_pattern`, or otherwise the file name.
+fn device_name(config: &Config, path: &Path) -> String {
+    config
+        .filename_pattern
+        .as_ref()
+        .and_then(|pattern| {
+            pattern
+                .dimensions(path)
+                .into_iter()
+                .find(|(name, _)| name == "device")
+                .map(|(_, device)| device)
+        })
+        .unwrap_or_else(|| {
+            path.file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+        })
+}
+
+/// The sorted, truncated view of `AggregatedData` that is presented to users.
+///
+/// # Fields
+/// - `priorities`: Priority labels and counts, sorted by raw priority value in descending order.
+/// - `top_sources`: The 10 most frequent threat sources, sorted by count in descending order.
+/// - `top_destinations`: The 10 most frequent threat destinations, sorted by count in descending order.
+/// - `aware_threats`: AWARE threat counts per date period, sorted by date.
+/// - `threat_score`: Weighted threat scores per date period, sorted by date.
+struct Summary {
+    priorities: Vec<(String, u32)>,
+    top_sources: Vec<(String, u32)>,
+    top_destinations: Vec<(String, u32)>,
+    aware_threats: Vec<(String, u32)>,
+    threat_score: Vec<(String, u64)>,
+}
+
+impl Summary {
+    /// Builds a `Summary` from aggregated data. Sections whose metric is disabled are empty.
+    fn new(data: &AggregatedData) -> Self {
+        let addresses = |name| {
+            data.metric::<Addresses>(name)
+                .map_or_else(Vec::new, |metric| metric.top(10))
         };
+        Self {
+            priorities: data
+                .metric::<Priorities>("Priorities")
+                .map_or_else(Vec::new, Priorities::sorted),
+            top_sources: addresses("Threat Sources"),
+            top_destinations: addresses("Threat Destinations"),
+            aware_threats: data
+                .metric::<AwareThreats>("AWARE Threats")
+                .map_or_else(Vec::new, AwareThreats::sorted),
+            threat_score: data
+                .metric::<ThreatScore>("Threat Score")
+                .map_or_else(Vec::new, ThreatScore::sorted),
+        }
+    }
+}
+
+/// Where the outcome of every log file read is recorded, besides the merged aggregates.
+///
+/// # Fields
+/// - `breakdown`: Where the aggregates of each file are added, if given.
+/// - `error_report`: Where the skipped records or the error of each file are added, if
+///   given.
+/// - `checkpoint`: Where the aggregates of each file are saved, and the saved aggregates
+///   of files read by an interrupted run are taken from instead of reading them again.
+/// - `telemetry`: Where a span is recorded for each file read, if given.
+/// - `read`: Where the path of each file read, or that failed, is added, if given.
+/// - `failed`: Where the path and error of each file that failed are added, if given.
+#[derive(Default)]
+struct FileSinks<'a, 'b> {
+    breakdown: Option<&'a mut Breakdown<'b>>,
+    error_report: Option<&'a mut ErrorReport>,
+    checkpoint: Option<&'a Checkpoint>,
+    telemetry: Option<&'a mut Telemetry>,
+    read: Option<&'a mut Vec<PathBuf>>,
+    failed: Option<&'a mut Vec<(PathBuf, String)>>,
+}
+
+/// Processes every file in `files` and merges the results into a single `AggregatedData`.
+/// When sampling, the merged counts are scaled up to estimates for the full input.
+///
+/// Files are read concurrently by a pool of worker threads, which send each file's
+/// aggregates over a bounded channel to the calling thread for merging. The bound keeps
+/// at most a few finished files in memory while the merge catches up, and lets the
+/// reading, parsing, and hashing of different files overlap. Once the run is interrupted
+/// (see the `interrupt` module), the workers finish their files but start no others.
+/// With the `async-io` feature, the files are read ahead of the workers on tokio (see the
+/// `async_io` module).
+///
+/// # Arguments
+/// - `files`: The files to process, typically the output of `filter_files`.
+/// - `options`: The parsed command line, providing the record-level cutoff, filters, and
+///   number of workers.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `verbose`: Whether to print the name of each file as it is processed.
+/// - `sinks`: Where the outcome of each file is recorded.
+///
+/// # Returns
+/// The merged aggregates of the files that could be read, and the errors of those that
+/// could not. A file that fails does not stop the others from being processed.
+fn aggregate(
+    files: &[PathBuf],
+    options: &Options,
+    config: &Config,
+    verbose: bool,
+    mut sinks: FileSinks,
+) -> (AggregatedData, Vec<io::Error>) {
+    let mut data = AggregatedData::new(config);
+    let mut errors = Vec::new();
 
-        let event_datetime_str = record.get(4).unwrap_or_default();
-        if let Ok(event_datetime) =
-            NaiveDateTime::parse_from_str(event_datetime_str, "%Y/%m/%d %H:%M:%S")
-        {
-            if event_datetime > cutoff.naive_local() {
-                let priority = record.get(1).unwrap_or_default().to_string();
-                *priorities_count.entry(priority).or_insert(0) += 1;
-
-                let source_ip = record.get(6).unwrap_or_default().to_string();
-                *threat_sources.entry(source_ip).or_insert(0) += 1;
-
-                let destination_ip = record.get(12).unwrap_or_default().to_string();
-                *threat_destinations.entry(destination_ip).or_insert(0) += 1;
-
-                if record.get(3).unwrap_or_default().contains("AWARE") {
-                    if let Ok(date_time) = NaiveDateTime::parse_from_str(
-                        record.get(4).unwrap_or_default(),
-                        "%Y/%m/%d %H:%M:%S",
-                    ) {
-                        // Determine whether the event is in the morning or afternoon period
-                        let period = if date_time.hour() < 12 {
-                            "AM" //"00-11"
-                        } else {
-                            "PM" //"12-23"
-                        };
-                        let date_period = format!("{} {}", date_time.date(), period);
-
-                        *aware_threats.entry(date_period).or_insert(0) += 1;
+    let checkpoint = sinks.checkpoint;
+    let resumed = checkpoint.map_or_else(Vec::new, |checkpoint| checkpoint.resume(files, config));
+    if verbose && !resumed.is_empty() {
+        log::info(format_args!("Resuming {} files from the checkpoint", resumed.len()));
+    }
+    let files: Vec<&PathBuf> = files
+        .iter()
+        .filter(|path| !resumed.iter().any(|(done, _)| done == *path))
+        .collect();
+    for (path, file_data) in resumed {
+        add_file(&mut data, &mut sinks, options, &path, file_data);
+        if let Some(read) = sinks.read.as_deref_mut() {
+            read.push(path);
+        }
+    }
+
+    let workers = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from))
+        .clamp(1, files.len().max(1));
+    // Split the budget so that every worker's in-progress file fits alongside the others
+    let worker_limit = options.max_memory.map(|limit| limit / workers);
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(workers);
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (next, files) = (&next, &files);
+            scope.spawn(move || {
+                while !interrupt::requested() {
+                    let Some(&path) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    if verbose {
+                        log::info(format_args!("Processing file: {}", path.display()));
+                    }
+                    let (started, timer) = (SystemTime::now(), Instant::now());
+                    let result = process_csv_file(path, options, config, worker_limit);
+                    if sender
+                        .send((path, result, started, timer.elapsed()))
+                        .is_err()
+                    {
+                        break;
                     }
                 }
-            } else {
-                continue;
+            });
+        }
+        drop(sender);
+
+        for (path, result, started, elapsed) in receiver {
+            systemd::watchdog();
+            if let Some(telemetry) = sinks.telemetry.as_deref_mut() {
+                telemetry.file(path, started, elapsed, &result);
+            }
+            if let Some(read) = sinks.read.as_deref_mut() {
+                read.push(path.clone());
+            }
+            match result {
+                Ok(file_data) => {
+                    let saved = checkpoint.map(|checkpoint| checkpoint.save(path, &file_data));
+                    if let Some(Err(e)) = saved {
+                        log::warn(format_args!("failed to checkpoint {}: {e}", path.display()));
+                    }
+                    if verbose {
+                        warn_parse_errors(path, &file_data);
+                    }
+                    add_file(&mut data, &mut sinks, options, path, file_data);
+                }
+                Err(e) => {
+                    if let Some(breakdown) = sinks.breakdown.as_deref_mut() {
+                        breakdown.add_error(path, &e);
+                    }
+                    if let Some(error_report) = sinks.error_report.as_deref_mut() {
+                        error_report.add_error(path, &e);
+                    }
+                    if let Some(failed) = sinks.failed.as_deref_mut() {
+                        failed.push((path.clone(), e.to_string()));
+                    }
+                    errors.push(e);
+                }
+            }
+            if let Some(limit) = options.max_memory {
+                data.enforce_memory_limit(limit);
             }
         }
+    });
+
+    if let Some(sample) = &options.filter.sample {
+        data.scale(sample);
+    }
+    (data, errors)
+}
+
+/// Logs a single warning for the records of the file at `path` that could not be read or
+/// parsed, with their number and the first of them, rather than one for each record.
+fn warn_parse_errors(path: &Path, file_data: &AggregatedData) {
+    let count = file_data.parse_errors();
+    if count == 0 {
+        return;
+    }
+    let noun = if count == 1 { "record" } else { "records" };
+    log::warn(format_args!(
+        "skipped {} malformed {noun} in {} (first at {})",
+        template::group_digits(&count.to_string(), ","),
+        path.display(),
+        file_data
+            .skipped()
+            .first_error
+            .as_deref()
+            .unwrap_or("an unknown line"),
+    ));
+}
+
+/// Merges the aggregates `file_data` of the file at `path` into `data`, adds its skipped
+/// records to the error report of `sinks` if given, and adds the aggregates to the
+/// breakdown of `sinks` if given, scaled like the merged aggregates will be.
+fn add_file(
+    data: &mut AggregatedData,
+    sinks: &mut FileSinks,
+    options: &Options,
+    path: &Path,
+    mut file_data: AggregatedData,
+) {
+    data.merge(&file_data);
+    if let Some(error_report) = sinks.error_report.as_deref_mut() {
+        error_report.add(path, &file_data);
+    }
+    if let Some(breakdown) = sinks.breakdown.as_deref_mut() {
+        if let Some(sample) = &options.filter.sample {
+            file_data.scale(sample);
+        }
+        breakdown.add(path, file_data);
     }
+}
+
+/// Converts `(key, value)` pairs into template rows like `{"ip": key, "count": value}`.
+fn template_rows<T: Serialize>(entries: &[(String, T)], label: &str, value: &str) -> Vec<Value> {
+    entries
+        .iter()
+        .map(|(key, count)| json!({ label: key, value: count }))
+        .collect()
+}
 
-    Ok(AggregatedData {
-        priorities_count,
-        threat_sources,
-        threat_destinations,
-        aware_threats,
+/// Builds the context that user templates are rendered against.
+///
+/// Each section is exposed as an array of objects (e.g. `top_sources` as
+/// `[{"ip": ..., "count": ...}]`) so templates can loop over rows directly, and the
+/// exact `events.json` document is available as `events`. The run time and the dates of
+/// the rows are written as set by `format`, whose thousands separator is passed on for
+/// the `number` filter.
+fn template_context(
+    options: &Options,
+    file_count: usize,
+    summary: &Summary,
+    events: &Value,
+    format: &ReportFormatConfig,
+) -> Value {
+    json!({
+        "generated": format.datetime(Local::now().naive_local()),
+        "thousands_separator": format.thousands_separator.map(String::from),
+        "log_path": options.log_path.display().to_string(),
+        "days_back": options.days_back,
+        "files_processed": file_count,
+        "priorities": template_rows(&summary.priorities, "priority", "count"),
+        "top_sources": template_rows(&summary.top_sources, "ip", "count"),
+        "top_destinations": template_rows(&summary.top_destinations, "ip", "count"),
+        "aware_threats": template_rows(&dated(&summary.aware_threats, format), "date", "count"),
+        "threat_score": template_rows(&dated(&summary.threat_score, format), "date", "score"),
+        "events": events,
     })
 }
 
-/// Main function that orchestrates the reading, processing, and output generation for threat data.
+/// Renders `template_path` against `context`, writing to `output` or stdout.
 ///
-/// This function now accepts two command line arguments specifying the directory path
-/// where the log files are located and the number of days back to filter files based on
-/// their modification date. It reads files from this directory, processes each
-/// for threat data, aggregates this data, and finally writes the aggregated data to
-/// JSON files. It ensures default counts for missing data and generates separate files
-/// for top threat sources and a comprehensive list of all threat sources.
+/// # Errors
+/// Returns an error if the template cannot be read, parsed, or rendered, or the
+/// output cannot be written.
+fn render_template(template_path: &Path, output: Option<&Path>, context: &Value) -> io::Result<()> {
+    let template = Template::parse(&fs::read_to_string(template_path)?)?;
+    let rendered = template.render(context)?;
+    match output {
+        Some(path) => fs::write(path, rendered),
+        None => io::stdout().write_all(rendered.as_bytes()),
+    }
+}
+
+/// Returns the `(time bucket, value)` pairs of `entries` with the dates of the buckets
+/// in the date format of `format`.
+fn dated<T: Copy>(entries: &[(String, T)], format: &ReportFormatConfig) -> Vec<(String, T)> {
+    entries
+        .iter()
+        .map(|(bucket, value)| (format.bucket(bucket), *value))
+        .collect()
+}
+
+/// Builds the workbook sheets for the Excel report, one per `events.json` section, with
+/// dates and thousands separators as set by `format`.
+fn xlsx_sheets(summary: &Summary, format: &ReportFormatConfig) -> Vec<xlsx::Sheet> {
+    let grouped = format.thousands_separator.is_some();
+    let sheet = |name: &str, label: &str, entries: &[(String, u32)]| xlsx::Sheet {
+        name: name.to_string(),
+        headers: vec![label.to_string(), "Count".to_string()],
+        rows: entries
+            .iter()
+            .map(|(key, count)| {
+                vec![
+                    xlsx::Cell::Text(key.clone()),
+                    xlsx::Cell::Number(u64::from(*count)),
+                ]
+            })
+            .collect(),
+        grouped,
+    };
+    let aware_threats = dated(&summary.aware_threats, format);
+
+    let mut sheets = vec![
+        sheet("Priorities", "Priority", &summary.priorities),
+        sheet("Threat Sources", "Source", &summary.top_sources),
+        sheet(
+            "Threat Destinations",
+            "Destination",
+            &summary.top_destinations,
+        ),
+        sheet("AWARE Threats", "Date", &aware_threats),
+    ];
+
+    if !summary.threat_score.is_empty() {
+        sheets.push(xlsx::Sheet {
+            name: "Threat Score".to_string(),
+            headers: vec!["Date".to_string(), "Score".to_string()],
+            rows: summary
+                .threat_score
+                .iter()
+                .map(|(date, score)| {
+                    vec![
+                        xlsx::Cell::Text(format.bucket(date)),
+                        xlsx::Cell::Number(*score),
+                    ]
+                })
+                .collect(),
+            grouped,
+        });
+    }
+
+    sheets
+}
+
+/// Name of the output file the aggregates of an interrupted run are written to.
+const PARTIAL_OUTPUT: &str = "events.partial.json";
+
+/// How a run ended, reported as the process exit code so that wrapping scripts and
+/// schedulers can branch on the outcome. Any other error exits with 1.
+#[derive(Clone, Copy)]
+enum Outcome {
+    /// Every matching file was processed and the outputs were written.
+    Success = 0,
+    /// The command line was invalid.
+    Usage = 2,
+    /// No log files matched the path and days back with `--fail-on-empty`; empty outputs
+    /// were written.
+    NoFiles = 3,
+    /// Some files could not be read; the outputs cover the others.
+    PartialFailure = 4,
+    /// More of the records read were skipped as malformed than `--max-skip-ratio`
+    /// allows; the outputs were written.
+    TooManySkipped = 5,
+    /// The run was interrupted by SIGINT or SIGTERM; the aggregates of the files read
+    /// until then were written to `events.partial.json`.
+    Interrupted = 130,
+}
+
+impl From<Outcome> for ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        Self::from(outcome as u8)
+    }
+}
+
+/// Runs the program and converts its outcome into the process exit code.
+fn main() -> ExitCode {
+    match run() {
+        Ok(outcome) => outcome.into(),
+        Err(e) => {
+            log::error(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The `threat_sources.json` document, listing threat sources with their counts.
+#[derive(Serialize)]
+#[serde(bound = "SourceColumns<S, C>: Serialize")]
+struct ThreatSources<S, C> {
+    #[serde(rename = "Threat Sources")]
+    threat_sources: SourceColumns<S, C>,
+}
+
+/// The sources and their counts, as two parallel columns.
+#[derive(Serialize)]
+#[serde(bound = "Column<S>: Serialize, Column<C>: Serialize")]
+struct SourceColumns<S, C> {
+    #[serde(rename = "Source")]
+    source: Column<S>,
+    #[serde(rename = "Count")]
+    count: Column<C>,
+}
+
+/// Serializes the items of an iterator as a JSON array, without collecting them first.
+struct Column<I>(I);
+
+impl<I> Serialize for Column<I>
+where
+    I: Iterator + Clone,
+    I::Item: Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.clone())
+    }
+}
+
+/// Builds the `meta` block of `events.json`, which lets consumers detect stale or partial
+/// data: when and by which version it was generated, the period the records were
+/// counted in, how many of the `files` log files could not be read (`failed`), how
+/// many records were counted, skipped as unparsable or empty, and when the newest was
+/// logged.
+fn meta(options: &Options, data: &AggregatedData, files: usize, failed: usize) -> Value {
+    let now = Local::now().naive_local();
+    let (start, end) = counted_period(options, now);
+    json!({
+        "generated": rfc3339(now),
+        "version": env!("CARGO_PKG_VERSION"),
+        "window": { "start": rfc3339(start), "end": rfc3339(end) },
+        "files_processed": files - failed,
+        "files_failed": failed,
+        "records": data.records(),
+        "newest_record": data.newest().map(rfc3339),
+        "parse_errors": data.parse_errors(),
+        "empty_records": data.empty_records(),
+        "approximate": data.is_approximate(),
+    })
+}
+
+/// Returns the start and end of the period the records of a run at `now` are counted
+/// in: the last `days_back` days, within `--period` if given.
+fn counted_period(options: &Options, now: NaiveDateTime) -> (NaiveDateTime, NaiveDateTime) {
+    let (mut start, mut end) = (cutoff(options.days_back), now);
+    if let Some(period) = &options.filter.period {
+        start = start.max(period.start);
+        end = end.min(period.end);
+    }
+    (start, end)
+}
+
+/// Formats a local time as RFC 3339 with the local UTC offset, such as
+/// `2024-05-01T06:00:00+02:00`.
+fn rfc3339(time: NaiveDateTime) -> String {
+    Local.from_local_datetime(&time).earliest().map_or_else(
+        || time.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        |time| time.to_rfc3339_opts(SecondsFormat::Secs, false),
+    )
+}
+
+/// Writes `events.json` and `threat_sources.json` for `data` to the output directory,
+/// adding the `meta` block for `files` log files read, of which `failed` could not be,
+/// and the `extra` sections, such as `Reputation`, to `events.json`. With
+/// `--split-output`, each section is also, or only, written to its own file, and with
+/// `--jq`, `events.json` holds the output of the filter instead.
 ///
-/// The program requires the path to the log files directory to be passed as the first
-/// command line argument and the number of days back to filter files as the second.
-/// If not provided, it will exit with an error message instructing
-/// the user on proper usage.
+/// # Returns
+/// The paths of the section files written.
 ///
-/// # Usage
-/// `dashboard_aggregator <path_to_log_files> <days_back>`
+/// # Errors
+/// Returns an error if a file cannot be written or JSON serialization fails.
+fn write_json(
+    options: &Options,
+    data: &AggregatedData,
+    extra: Vec<(&str, Value)>,
+    files: usize,
+    failed: usize,
+) -> io::Result<Vec<PathBuf>> {
+    let mut events = data.events_json();
+    let mut section_files = Vec::new();
+    if let Value::Object(sections) = &mut events {
+        sections.insert("meta".to_string(), meta(options, data, files, failed));
+        for (name, section) in extra {
+            sections.insert(name.to_string(), section);
+        }
+        if let Some(filter) = &options.jq {
+            let filtered = filter
+                .apply(&Value::Object(sections.clone()))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("--jq: {e}")))?;
+            output::write_json(options, "events.json", &filtered)?;
+        } else if options.split_output != Some(SplitOutput::Only) {
+            let first = data.section_order().unwrap_or_default();
+            let object = &*sections;
+            output::write_json(options, "events.json", &Ordered { object, first })?;
+        }
+        if options.split_output.is_some() {
+            section_files = output::write_sections(options, sections)?;
+        }
+    }
+
+    // Serialize and write to all threat sources to JSON
+    let counts = data
+        .metric::<Addresses>("Threat Sources")
+        .map(|metric| &metric.counts);
+    let mut sources: Vec<(&str, u32)> = counts
+        .into_iter()
+        .flatten()
+        .map(|(source, &count)| (source.as_ref(), count))
+        .collect();
+    // Sorted, as the map iterates in a different order in every run
+    sources.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    if let Some(max) = options.max_output_entries {
+        sources.truncate(max);
+    }
+    write_threat_sources(options, sources.into_iter())?;
+    Ok(section_files)
+}
+
+/// Writes `threat_sources.json` with the `(source, count)` pairs of `entries`, which is
+/// iterated once per column.
 ///
-/// # Returns
-/// An `io::Result<()>` indicating the success or failure of the operation.
+/// # Errors
+/// Returns an error if the file cannot be written.
+fn write_threat_sources<'a>(
+    options: &Options,
+    entries: impl Iterator<Item = (&'a str, u32)> + Clone,
+) -> io::Result<()> {
+    let threat_sources = ThreatSources {
+        threat_sources: SourceColumns {
+            source: Column(entries.clone().map(|(source, _)| source)),
+            count: Column(entries.map(|(_, count)| count)),
+        },
+    };
+    output::write_json(options, "threat_sources.json", &threat_sources)
+}
+
+/// Writes the aggregates `data` of an interrupted run to `events.partial.json`, with
+/// `partial` set in its `meta` block, instead of the outputs of a complete run. The
+/// checkpoint is kept, so that the next run resumes from the files read.
+///
+/// # Arguments
+/// - `options`: The parsed command line, providing the output directory.
+/// - `data`: The aggregates of the files read before the run was interrupted.
+/// - `files`: The number of files read, including the failed ones.
+/// - `failed`: The number of files that could not be read.
 ///
 /// # Errors
-/// Returns an error if any file operations or JSON serialization fails. It also returns
-/// an error if the program is invoked without specifying the required arguments.
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+/// Returns an error if the file cannot be written.
+fn write_partial(
+    options: &Options,
+    data: &AggregatedData,
+    files: usize,
+    failed: usize,
+) -> io::Result<Outcome> {
+    let mut events = data.events_json();
+    if let Value::Object(sections) = &mut events {
+        let mut meta = meta(options, data, files, failed);
+        meta["partial"] = json!(true);
+        sections.insert("meta".to_string(), meta);
+        let first = data.section_order().unwrap_or_default();
+        let object = &*sections;
+        output::write_json(options, PARTIAL_OUTPUT, &Ordered { object, first })?;
+    }
+    log::warn(format_args!(
+        "Interrupted after reading {files} files. Partial output saved to {}",
+        options.output_path(PARTIAL_OUTPUT).display()
+    ));
+    Ok(Outcome::Interrupted)
+}
+
+/// Writes the optional Excel report, STIX bundle and rendered template for `data`.
+///
+/// # Arguments
+/// - `options`: The parsed command line, providing the report paths.
+/// - `config`: The configuration, providing the number and date formats of the reports.
+/// - `data`: The aggregates to report.
+/// - `file_count`: The number of files the aggregates cover, for the template context.
+/// - `verbose`: Whether to print where the Excel report was saved.
+///
+/// # Errors
+/// Returns an error if a report cannot be written, or the template cannot be rendered.
+fn write_reports(
+    options: &Options,
+    config: &Config,
+    data: &AggregatedData,
+    file_count: usize,
+    verbose: bool,
+) -> io::Result<()> {
+    let summary = Summary::new(data);
 
-    if args.len() < 3 {
-        eprintln!("Usage: {} <path_to_log_files> <days_back>", args[0]);
-        std::process::exit(1);
+    if let Some(xlsx_path) = &options.xlsx {
+        xlsx::write(xlsx_path, &xlsx_sheets(&summary, &config.report_format))?;
+        if verbose {
+            log::info(format_args!("Excel report saved to {}", xlsx_path.display()));
+        }
     }
 
-    let log_file_path = &args[1];
-    let days_back: i64 = args[2]
-        .parse()
-        .expect("Please provide a valid number for days");
+    redis::write(options, data)?;
+    mqtt::publish(options, data)?;
 
-    if days_back < 0 {
-        eprintln!("Error: <days_back> must be a non-negative number.");
-        std::process::exit(1);
+    if let Some(stix_path) = &options.stix {
+        stix::write(stix_path, options, data)?;
+        if verbose {
+            log::info(format_args!("STIX bundle saved to {}", stix_path.display()));
+        }
     }
 
-    let files = filter_files(Path::new(log_file_path), days_back);
-    let mut global_priorities_count: HashMap<String, u32> = HashMap::new();
-    let mut global_threat_sources: HashMap<String, u32> = HashMap::new();
-    let mut global_threat_destinations: HashMap<String, u32> = HashMap::new();
-    let mut global_aware_threats: HashMap<String, u32> = HashMap::new();
+    if let Some(template_path) = &options.template {
+        let context = template_context(
+            options,
+            file_count,
+            &summary,
+            &data.events_json(),
+            &config.report_format,
+        );
+        render_template(template_path, options.template_output.as_deref(), &context)?;
+    }
+    Ok(())
+}
 
-    // Prepopulate global_priorities_count with priorities 0 through 5 and default count of 0
-    for priority in 0..=5 {
-        global_priorities_count.insert(priority.to_string(), 0);
+/// Reads the records of a run: from the systemd journal with `--journal` (see the
+/// `journal` module), from the Windows event log with `--eventlog` (see the `eventlog`
+/// module), and from the matching log files otherwise.
+///
+/// # Arguments
+/// - `options`: The parsed command line.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `breakdown`: Where the aggregates of each file are added with `--per-file`.
+/// - `error_report`: Where the skipped records of each file are added with
+///   `--error-report`.
+/// - `telemetry`: Where a span is recorded for each file read with `--otel-endpoint`.
+///
+/// # Returns
+/// The files that matched, or only those read if the run was interrupted, which are none
+/// for the journal and the event log, the merged aggregates, and the errors of the files
+/// that could not be read.
+///
+/// # Errors
+/// Returns an error if the log directory, the journal or the event log cannot be read.
+fn read_logs(
+    options: &Options,
+    config: &Config,
+    breakdown: Option<&mut Breakdown>,
+    error_report: Option<&mut ErrorReport>,
+    telemetry: Option<&mut Telemetry>,
+) -> io::Result<(Vec<PathBuf>, AggregatedData, Vec<io::Error>)> {
+    if let Some(units) = &options.journal_units {
+        let data = journal::aggregate(options, config, units)?;
+        return Ok((Vec::new(), data, Vec::new()));
     }
+    if !options.eventlog_channels.is_empty() {
+        let data = eventlog::aggregate(options, config, &options.eventlog_channels)?;
+        return Ok((Vec::new(), data, Vec::new()));
+    }
+    let files = list_log_files(options)?;
+    if files.is_empty() {
+        let modified = options.file_days_back().map_or_else(String::new, |days| {
+            format!(" modified in the last {days} days")
+        });
+        log::warn(format_args!(
+            "0 files matched {}*{modified} in {}",
+            options.format.file_prefix(),
+            options.log_path.display()
+        ));
+    }
+    let files = guard_files(files, options, true);
+    let checkpoint = options
+        .checkpoint
+        .then(|| Checkpoint::open(options))
+        .transpose()?;
+    let mut read = Vec::new();
+    let sinks = FileSinks {
+        breakdown,
+        error_report,
+        checkpoint: checkpoint.as_ref(),
+        telemetry,
+        read: Some(&mut read),
+        failed: None,
+    };
+    let (data, errors) = aggregate(&files, options, config, true, sinks);
+    if interrupt::requested() {
+        return Ok((read, data, errors));
+    }
+    Ok((files, data, errors))
+}
 
-    for file in files {
-        println!("Processing file: {}", file.path().display());
+/// Prints how many records had an empty priority, source or destination, and what the
+/// `empty_fields` policy did with them.
+fn print_empty_records(config: &Config, data: &AggregatedData) {
+    if data.empty_records() > 0 {
+        let handled = match config.empty_fields {
+            EmptyFieldPolicy::Drop => "dropped",
+            EmptyFieldPolicy::Unknown => "counted as unknown",
+            EmptyFieldPolicy::Keep | EmptyFieldPolicy::Strict => "counted as empty",
+        };
+        log::info(format_args!(
+            "{} records with an empty priority, source or destination were {handled}",
+            data.empty_records()
+        ));
+    }
+}
 
-        // Aggregating counts
-        let AggregatedData {
-            priorities_count,
-            threat_sources,
-            threat_destinations,
-            aware_threats,
-        } = process_csv_file(&file.path(), days_back)?;
+/// Loads the configuration file, if any, and applies the options that override it.
+///
+/// # Errors
+/// Returns an error if the configuration file cannot be read or is invalid.
+fn load_config(options: &Options) -> io::Result<Config> {
+    let mut config = match &options.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    config.windows = options
+        .windows
+        .iter()
+        .map(|(label, length)| Window {
+            label: label.clone(),
+            start: Duration::days(options.days_back)
+                - Duration::from_std(*length).unwrap_or_default(),
+        })
+        .collect();
+    // The STIX bundle lists the top signatures, which are also added to events.json then
+    if options.stix.is_some() && !config.metrics.iter().any(|name| name == "signatures") {
+        config.metrics.push("signatures".to_string());
+    }
+    config.cap_per_source = options.cap_per_source;
+    if let (Some(path), Command::Run) = (&options.baseline, &options.command) {
+        config.baseline = Some(Arc::new(RwLock::new(Baseline::load(path)?)));
+    }
+    // Priorities less severe than --min-priority are never counted, so not listed either
+    config
+        .priorities
+        .values
+        .retain(|priority| options.filter.meets_priority(priority));
 
-        for (priority, count) in priorities_count {
-            *global_priorities_count.entry(priority).or_insert(0) += count;
-        }
+    // Only csv log files have a delimiter; the other formats are normalized to commas
+    if let Some(delimiter) = options.delimiter {
+        config.delimiter = delimiter;
+    } else if !matches!(options.format, InputFormat::Csv)
+        || matches!(options.command, Command::NetflowListen { .. })
+    {
+        config.delimiter = ',';
+    }
+    Ok(config)
+}
 
-        for (source_ip, count) in threat_sources {
-            *global_threat_sources.entry(source_ip).or_insert(0) += count;
+/// Runs the subcommand selected by `options`, which is not `Command::Run`.
+///
+/// # Errors
+/// Returns the error of the subcommand.
+fn run_subcommand(options: &Options, config: &Config) -> io::Result<()> {
+    match options.command {
+        Command::Run => Ok(()),
+        Command::Tui { refresh_secs } => tui::run(options, config, refresh_secs),
+        Command::Bench { iterations } => bench::run(&options.log_path, options, config, iterations),
+        Command::Follow => follow::run(options, config),
+        Command::Verify => {
+            let key = options.sign_key.as_deref().unwrap_or_else(|| Path::new(""));
+            manifest::verify(&options.log_path, key)
         }
-
-        for (destination_ip, count) in threat_destinations {
-            *global_threat_destinations
-                .entry(destination_ip)
-                .or_insert(0) += count;
+        Command::ReportAbuse => abuse::run(options, config),
+        Command::NetflowListen { address } => collector::run(options, config, address),
+        Command::Serve {
+            address,
+            refresh_secs,
+        } => serve::run(options, config, address, refresh_secs),
+        Command::Query { ref sql } => query::run(options, config, sql),
+        Command::Schema { threat_sources } => {
+            let schema = if threat_sources {
+                schema::threat_sources()
+            } else {
+                schema::events(config)
+            };
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            Ok(())
         }
+        Command::Baseline { import } => exchange_baseline(options, import),
+        Command::Rollup => rollup::run(options),
+    }
+}
 
-        for (date, count) in aware_threats {
-            *global_aware_threats.entry(date).or_insert(0) += count;
-        }
+/// Imports the baseline exported to the file given to `baseline import` into the
+/// baseline of `--baseline` if `import` is set, or else exports that baseline to the
+/// file given to `baseline export`.
+///
+/// # Errors
+/// Returns an error if a baseline cannot be read or written.
+fn exchange_baseline(options: &Options, import: bool) -> io::Result<()> {
+    let path = options.baseline.as_deref().unwrap_or_else(|| Path::new(""));
+    let file = &options.log_path;
+    let now = Local::now().naive_local();
+    if import {
+        let mut baseline = Baseline::load(path)?;
+        let added = baseline.merge(Baseline::read(file)?, now);
+        baseline.save(path)?;
+        log::info(format_args!(
+            "Imported {added} buckets and days from {} into {}",
+            file.display(),
+            path.display()
+        ));
+    } else {
+        let mut export = Baseline::read(path)?.export_json();
+        export["exported"] = json!(rfc3339(now));
+        fs::write(file, serde_json::to_string_pretty(&export)?)?;
+        log::info(format_args!("Baseline exported to {}", file.display()));
     }
+    Ok(())
+}
 
-    // Clone global_threat_sources for write to separate file
-    let all_threat_sources = global_threat_sources.clone();
+/// Learns the time series and top sources of `data` into the baseline of `--baseline`,
+/// if given. Approximate counts are not learned, as they would skew the baseline.
+///
+/// # Errors
+/// Returns an error if the baseline cannot be written.
+fn learn_baseline(options: &Options, config: &Config, data: &AggregatedData) -> io::Result<()> {
+    let (Some(path), Some(baseline)) = (&options.baseline, &config.baseline) else {
+        return Ok(());
+    };
+    if data.is_approximate() {
+        log::warn("the baseline was not updated, as the counts are approximate (--max-memory)");
+        return Ok(());
+    }
+    let (start, end) = counted_period(options, Local::now().naive_local());
+    let mut baseline = baseline.write().unwrap_or_else(PoisonError::into_inner);
+    baseline.learn(data, start, end);
+    baseline.save(path)
+}
 
-    // Sort global_priorities_count by keys in descending order
-    let mut priorities_vec: Vec<_> = global_priorities_count.iter().collect();
-    priorities_vec.sort_by(|a, b| b.0.cmp(a.0));
+/// Orchestrates the reading, processing, and output generation for threat data.
+///
+/// Parses the command line, falling back to the `DA_*` environment variables (see the
+/// `cli` module), and loads the configuration given with `--config` (see the `config`
+/// module). A subcommand such as `tui`, `serve` or `bench` is then run instead of the
+/// default run (see `run_subcommand`). The default run reads the log files of the last
+/// `<days_back>` days from `<path_to_log_files>`, aggregates them and writes
+/// `events.json` and `threat_sources.json`, along with the other outputs selected by
+/// the options, such as `--template` or `--xlsx`. With `--loop`, the run is repeated at
+/// an interval until the process is stopped (see `run_loop`).
+///
+/// # Usage
+/// `dashboard_aggregator <path_to_log_files> <days_back> [--template <file> [-o <file>]] [--xlsx <file>]`
+/// `dashboard_aggregator tui <path_to_log_files> <days_back> [refresh_secs]`
+/// `dashboard_aggregator serve <address:port> <path_to_log_files> <days_back> [refresh_secs]`
+///
+/// # Returns
+/// The `Outcome` of the run, which becomes the exit code.
+///
+/// # Errors
+/// Returns an error if the configuration cannot be loaded, or writing the outputs or
+/// JSON serialization fails.
+fn run() -> io::Result<Outcome> {
+    let args: Vec<String> = env::args().collect();
+    let program = args.first().map_or("dashboard_aggregator", String::as_str);
 
-    // Sort and take the top 10 threat sources
-    let mut threat_sources_vec: Vec<_> = global_threat_sources.into_iter().collect();
-    threat_sources_vec.sort_by(|a, b| b.1.cmp(&a.1));
-    let top_threat_sources: Vec<_> = threat_sources_vec.into_iter().take(10).collect();
+    let args = args.get(1..).unwrap_or_default();
+    let mut options = match cli::parse(args, |name| env::var(name).ok()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("Error: {message}\n\n{}", cli::usage(program));
+            return Ok(Outcome::Usage);
+        }
+    };
+    log::set_json(options.log_json);
+    log::set_max_warnings(options.max_warnings);
 
-    // Sort and take the top 10 threat destinations
-    let mut threat_destinations_vec: Vec<_> = global_threat_destinations.into_iter().collect();
-    threat_destinations_vec.sort_by(|a, b| b.1.cmp(&a.1));
-    let top_threat_destinations: Vec<_> = threat_destinations_vec.into_iter().take(10).collect();
+    let config = load_config(&options)?;
 
-    // Sort and prepare AWARE threats for JSON output
-    let mut aware_threats_vec: Vec<_> = global_aware_threats.into_iter().collect();
-    aware_threats_vec.sort_by(|a, b| a.0.cmp(&b.0)); // Sort by date
+    if options.explain {
+        return explain::run(&options, &config).map(|()| Outcome::Success);
+    }
 
-    // Serialize and write to JSON, including only the top 5 for sources and destinations
-    let json_data = json!({
-        "Priorities": {
-            "Priority": priorities_vec.iter().map(|(priority, _)| *priority).collect::<Vec<&String>>(),
-            "Count": priorities_vec.iter().map(|(_, &count)| count).collect::<Vec<u32>>()
-        },
-        "Threat Sources": {
-            "Source": top_threat_sources.iter().map(|(ip, _)| ip).collect::<Vec<&String>>(),
-            "Count": top_threat_sources.iter().map(|(_, count)| count).collect::<Vec<&u32>>()
-        },
-        "Threat Destinations": {
-            "Destination": top_threat_destinations.iter().map(|(ip, _)| ip).collect::<Vec<&String>>(),
-            "Count": top_threat_destinations.iter().map(|(_, count)| count).collect::<Vec<&u32>>()
-        },
-        "AWARE Threats": {
-            "Date": aware_threats_vec.iter().map(|(date, _)| date).collect::<Vec<&String>>(),
-            "Count": aware_threats_vec.iter().map(|(_, count)| count).collect::<Vec<&u32>>()
-        }
-    });
+    // A missing or read-only volume fails now rather than after the logs were read
+    if matches!(
+        options.command,
+        Command::Run | Command::Follow | Command::ReportAbuse | Command::NetflowListen { .. }
+    ) {
+        output::check_writable(&options)?;
+    }
 
-    let mut file = File::create("events.json")?;
-    file.write_all(to_string_pretty(&json_data)?.as_bytes())?;
+    if !matches!(options.command, Command::Run) {
+        return run_subcommand(&options, &config).map(|()| Outcome::Success);
+    }
 
-    // Serialize and write to all threat sources to JSON
-    let json_threat_sources = json!({
-        "Threat Sources": {
-            "Source": all_threat_sources.keys().collect::<Vec<&String>>(),
-            "Count": all_threat_sources.values().collect::<Vec<&u32>>()
-        },
-    });
+    interrupt::install()?;
+    if let Some(interval) = options.loop_interval {
+        return Ok(run_loop(&mut options, &config, args, interval));
+    }
+    let outcome = run_once(&options, &config, args);
+    log::finish_run();
+    outcome
+}
+
+/// Runs the aggregation again every `interval` with `--loop`, until the process is
+/// interrupted. A run that fails or ends with another outcome than success is logged,
+/// and the next run is still started at its time.
+///
+/// # Arguments
+/// - `options`: The parsed command line. The time stamp of `--timestamp-outputs` is
+///   renewed for every run.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `args`: The program arguments, for the audit log.
+/// - `interval`: The time between the starts of consecutive runs.
+///
+/// # Returns
+/// `Outcome::Interrupted`, once SIGINT or SIGTERM is received.
+fn run_loop(
+    options: &mut Options,
+    config: &Config,
+    args: &[String],
+    interval: std::time::Duration,
+) -> Outcome {
+    systemd::ready(&format!("Running every {}s", interval.as_secs()));
+    loop {
+        let started = Instant::now();
+        if options.output_stamp.is_some() {
+            options.output_stamp = Some(retention::stamp());
+        }
+        let outcome = run_once(options, config, args);
+        log::finish_run();
+        let status = match outcome {
+            Ok(Outcome::Success) => "succeeded".to_string(),
+            Ok(outcome) => {
+                log::warn(format_args!("run ended with exit code {}", outcome as u8));
+                format!("ended with exit code {}", outcome as u8)
+            }
+            Err(e) => {
+                log::error(&e);
+                format!("failed: {e}")
+            }
+        };
+        systemd::status(&format!(
+            "Last run at {} {status}",
+            rfc3339(Local::now().naive_local())
+        ));
+        if interrupt::requested() {
+            return Outcome::Interrupted;
+        }
+        let wait = interval.saturating_sub(started.elapsed());
+        log::info(format_args!("Next run in {:.1}s", wait.as_secs_f64()));
+        systemd::sleep(wait);
+        if interrupt::requested() {
+            return Outcome::Interrupted;
+        }
+    }
+}
 
-    let mut file = File::create("threat_sources.json")?;
-    file.write_all(to_string_pretty(&json_threat_sources)?.as_bytes())?;
+/// Reads the logs once and writes the outputs and reports of the run.
+///
+/// # Arguments
+/// - `options`: The parsed command line.
+/// - `config`: The configuration, which determines the metrics to compute.
+/// - `args`: The program arguments, for the audit log.
+///
+/// # Returns
+/// The `Outcome` of the run, which becomes the exit code.
+///
+/// # Errors
+/// Returns an error if the logs cannot be read, or writing the outputs or JSON
+/// serialization fails.
+fn run_once(options: &Options, config: &Config, args: &[String]) -> io::Result<Outcome> {
+    let anonymizer = Anonymizer::load(options)?;
+    let pruned = history::prune(options)?;
+    if pruned > 0 {
+        log::info(format_args!("Pruned {pruned} runs older than --retention from the history"));
+    }
+    let mut breakdown = options
+        .per_file
+        .then(|| Breakdown::new(anonymizer.as_ref()));
+    let mut telemetry = Telemetry::start(options);
+    let mut error_report = options.error_report.as_ref().map(|_| ErrorReport::default());
+    let (files, mut data, errors) = read_logs(
+        options,
+        config,
+        breakdown.as_mut(),
+        error_report.as_mut(),
+        telemetry.as_mut(),
+    )?;
+    for e in &errors {
+        log::error(e);
+    }
+    if let (Some(error_report), Some(path)) = (error_report, &options.error_report) {
+        error_report.write(path)?;
+        log::info(format_args!("Error report saved to {}", path.display()));
+    }
+    // Addresses are located as logged, before they are anonymized
+    let geo = geoip::sections(options, config, &data)?;
+    if let Some(anonymizer) = &anonymizer {
+        anonymizer.apply(&mut data);
+    }
+    if interrupt::requested() {
+        return write_partial(options, &data, files.len(), errors.len());
+    }
+
+    let reputation = reputation::lookup(options, config.reputation.as_ref(), &data)?;
+    let whois = whois::lookup(options, config.whois.as_ref(), &data)?;
+    let extra = reputation
+        .map(|reputation| ("Reputation", reputation))
+        .into_iter()
+        .chain(whois.map(|whois| ("Whois", whois)))
+        .chain(geo)
+        .chain(breakdown.map(|breakdown| ("Per File", breakdown.into_json())))
+        .collect();
+    let section_files = write_json(options, &data, extra, files.len(), errors.len())?;
+    // The partial output of an earlier, interrupted run is superseded, if there is one
+    let _ = fs::remove_file(options.output_path(PARTIAL_OUTPUT));
+    let processed = if options.journal_units.is_some() {
+        "the journal".to_string()
+    } else if !options.eventlog_channels.is_empty() {
+        "the event log".to_string()
+    } else {
+        format!(
+            "{} of {} matched files",
+            files.len() - errors.len(),
+            files.len()
+        )
+    };
+    log::info(format_args!(
+        "Finished processing {processed}. Output saved to {} and {}",
+        match options.split_output {
+            Some(SplitOutput::Only) => options.output_dir_path(output::SECTIONS_DIR),
+            _ => options.output_path("events.json"),
+        }
+        .display(),
+        options.output_path("threat_sources.json").display()
+    ));
+    print_empty_records(config, &data);
+    write_reports(options, config, &data, files.len(), true)?;
+    if let Some(manifest_path) = manifest::write(options, &section_files)? {
+        log::info(format_args!("Signed manifest saved to {}", manifest_path.display()));
+    }
+    let pruned = retention::prune(options)?;
+    if pruned > 0 {
+        log::info(format_args!("Removed {pruned} output files of earlier runs"));
+    }
+    // The outputs are complete, so an interrupted later run starts over
+    if options.checkpoint {
+        checkpoint::remove(options)?;
+    }
+    if let Some((id, count)) = misp::push(options, &data)? {
+        log::info(format_args!("Pushed {count} threat sources to MISP event {id}"));
+    }
+    audit::append(options, args, &files, errors.len(), &section_files)?;
+    history::append(options, &data, files.len(), errors.len())?;
+    learn_baseline(options, config, &data)?;
+    if let Some(telemetry) = telemetry {
+        if let Err(e) = telemetry.export(options, &data, files.len(), errors.len()) {
+            log::warn(format_args!("failed to export telemetry: {e}"));
+        }
+    }
 
-    println!("Finished processing files. Output saved to events.json and threat_sources.json");
+    Ok(
+        if files.is_empty() && options.reads_files() && options.fail_on_empty {
+            Outcome::NoFiles
+        } else if !errors.is_empty() {
+            Outcome::PartialFailure
+        } else if too_many_skipped(options, &data) {
+            Outcome::TooManySkipped
+        } else {
+            Outcome::Success
+        },
+    )
+}
 
-    Ok(())
+/// Returns whether more of the records read into `data` were skipped as malformed than
+/// `--max-skip-ratio` allows, logging an error with the ratio if so.
+fn too_many_skipped(options: &Options, data: &AggregatedData) -> bool {
+    let (Some(max), read) = (options.max_skip_ratio, data.records_read()) else {
+        return false;
+    };
+    if read == 0 {
+        return false;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let ratio = data.parse_errors() as f64 / read as f64;
+    if ratio <= max {
+        return false;
+    }
+    log::error(format_args!(
+        "{} of {read} records ({:.2}%) were skipped as malformed, more than --max-skip-ratio {max}",
+        data.parse_errors(),
+        ratio * 100.0
+    ));
+    true
 }
@@ -0,0 +1,313 @@
+//! Signed checksum manifest of the output files.
+//!
+//! With `--sign-key <file>`, every run writes `manifest.json` next to the JSON outputs.
+//! It lists the SHA-256 checksum of each output file and an HMAC-SHA256 signature over
+//! that list, keyed with the contents of the key file, e.g.:
+//!
+//! ```json
+//! {
+//!   "algorithm": "HMAC-SHA256",
+//!   "files": [{ "path": "events.json", "sha256": "9f86d0..." }],
+//!   "signature": "2c26b4..."
+//! }
+//! ```
+//!
+//! The `verify` subcommand checks the signature and recomputes the checksums, for
+//! chain-of-custody requirements. Paths are relative to the manifest's directory unless
+//! the file was written elsewhere.
+
+use crate::cli::{Options, SplitOutput};
+use crate::output;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Name of the manifest file in the output directory.
+const MANIFEST: &str = "manifest.json";
+
+/// Signature algorithm recorded in the manifest.
+const ALGORITHM: &str = "HMAC-SHA256";
+
+/// The manifest document.
+///
+/// # Fields
+/// - `algorithm`: The signature algorithm, always `HMAC-SHA256`.
+/// - `files`: The output files and their checksums.
+/// - `signature`: The hex HMAC of the `files` entries, formatted by `signed_message`.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    algorithm: String,
+    files: Vec<Entry>,
+    signature: String,
+}
+
+/// An output file listed in the manifest.
+///
+/// # Fields
+/// - `path`: The path of the file, relative to the manifest's directory.
+/// - `sha256`: The hex SHA-256 checksum of the file contents.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    path: String,
+    sha256: String,
+}
+
+/// Reads a secret key from `path`, ignoring a trailing newline.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or is empty.
+pub fn read_key(path: &Path) -> io::Result<Vec<u8>> {
+    let mut key = fs::read(path)?;
+    while key.last().is_some_and(|byte| matches!(byte, b'\n' | b'\r')) {
+        key.pop();
+    }
+    if key.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Key file {} is empty", path.display()),
+        ));
+    }
+    Ok(key)
+}
+
+/// Returns the SHA-256 checksum of the file at `path`, in hex.
+///
+/// # Errors
+/// Returns an error if the file cannot be read.
+fn checksum(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hash = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hex::encode(hash.finalize()));
+        }
+        hash.update(&buffer[..read]);
+    }
+}
+
+/// Formats the entries as the signed message, one `<sha256>  <path>` line per file as
+/// printed by `sha256sum`.
+fn signed_message(files: &[Entry]) -> String {
+    let mut message = String::new();
+    for entry in files {
+        let _ = writeln!(message, "{}  {}", entry.sha256, entry.path);
+    }
+    message
+}
+
+/// Returns the output files written for `options`, with the `section_files` written by
+/// `--split-output`.
+fn output_files(options: &Options, section_files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if options.split_output != Some(SplitOutput::Only) {
+        files.push(options.output_path("events.json"));
+    }
+    files.push(options.output_path("threat_sources.json"));
+    files.extend_from_slice(section_files);
+    files.extend(options.xlsx.clone());
+    files.extend(options.stix.clone());
+    if options.template.is_some() {
+        files.extend(options.template_output.clone());
+    }
+    files
+}
+
+/// Checksums the output files written for `options`.
+///
+/// # Arguments
+/// - `options`: The parsed command line, which determines the output files.
+/// - `section_files`: The section files written by `--split-output`.
+/// - `dir`: The directory the entry paths are made relative to, where possible.
+///
+/// # Errors
+/// Returns an error if an output file cannot be read.
+pub fn checksums(options: &Options, section_files: &[PathBuf], dir: &Path) -> io::Result<Vec<Entry>> {
+    output_files(options, section_files)
+        .iter()
+        .map(|path| {
+            Ok(Entry {
+                path: path.strip_prefix(dir).unwrap_or(path).display().to_string(),
+                sha256: checksum(path)?,
+            })
+        })
+        .collect()
+}
+
+/// Writes the signed manifest of the output files for `options`, including the
+/// `section_files` written by `--split-output`, if `--sign-key` is set.
+///
+/// # Returns
+/// The path of the manifest, or `None` if no signing key is configured.
+///
+/// # Errors
+/// Returns an error if the key or an output file cannot be read, or the manifest cannot
+/// be written.
+pub fn write(options: &Options, section_files: &[PathBuf]) -> io::Result<Option<PathBuf>> {
+    let Some(key_path) = &options.sign_key else {
+        return Ok(None);
+    };
+    let key = read_key(key_path)?;
+    let manifest_path = options.output_dir_path(&options.stamped(MANIFEST));
+    let dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let files = checksums(options, section_files, dir)?;
+    let manifest = Manifest {
+        algorithm: ALGORITHM.to_string(),
+        signature: signature(&key, &files)?,
+        files,
+    };
+    output::write_file(&manifest_path, serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    Ok(Some(manifest_path))
+}
+
+/// Verifies the manifest at `path` with the key at `key_path`, printing the result for
+/// each listed file.
+///
+/// # Errors
+/// Returns an error if the manifest or key cannot be read, the signature does not match,
+/// or a file is missing or has changed.
+pub fn verify(path: &Path, key_path: &Path) -> io::Result<()> {
+    let key = read_key(key_path)?;
+    let manifest: Manifest = serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid manifest: {e}"))
+    })?;
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    if manifest.algorithm != ALGORITHM {
+        return Err(invalid(format!(
+            "Unsupported manifest algorithm {}",
+            manifest.algorithm
+        )));
+    }
+    let expected = signature(&key, &manifest.files)?;
+    if !constant_time_eq(expected.as_bytes(), manifest.signature.as_bytes()) {
+        return Err(invalid(format!(
+            "Signature of {} does not match: the manifest was modified or the key is wrong",
+            path.display()
+        )));
+    }
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut failed = 0;
+    for entry in &manifest.files {
+        let status = match checksum(&dir.join(&entry.path)) {
+            Ok(sha256) if sha256 == entry.sha256 => "OK".to_string(),
+            Ok(_) => "FAILED (checksum mismatch)".to_string(),
+            Err(e) => format!("FAILED ({e})"),
+        };
+        if status != "OK" {
+            failed += 1;
+        }
+        println!("{}: {status}", entry.path);
+    }
+    if failed > 0 {
+        return Err(invalid(format!(
+            "{failed} of {} files failed verification",
+            manifest.files.len()
+        )));
+    }
+    println!("Signature OK, {} files verified", manifest.files.len());
+    Ok(())
+}
+
+/// Returns the HMAC-SHA256 signature of `files` keyed with `key`, in hex.
+///
+/// # Errors
+/// Returns an error if the key is rejected, which HMAC does for no key length.
+fn signature(key: &[u8], files: &[Entry]) -> io::Result<String> {
+    let mac = Hmac::<Sha256>::new_from_slice(key).map_err(io::Error::other)?;
+    Ok(hex::encode(mac.chain_update(signed_message(files)).finalize().into_bytes()))
+}
+
+/// Compares `a` and `b` in time independent of where they differ, so signatures cannot be
+/// guessed byte by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `events.json` and a manifest of it signed with `key` to a new directory
+    /// named after `test`, returning the directory and the manifest path.
+    fn signed_output(test: &str, key: &[u8]) -> io::Result<(PathBuf, PathBuf)> {
+        let dir = std::env::temp_dir().join(format!("da-manifest-{test}-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("events.json"), "{}")?;
+        let files = vec![Entry {
+            path: "events.json".to_string(),
+            sha256: checksum(&dir.join("events.json"))?,
+        }];
+        let manifest = Manifest {
+            algorithm: ALGORITHM.to_string(),
+            signature: signature(key, &files)?,
+            files,
+        };
+        let path = dir.join(MANIFEST);
+        fs::write(&path, serde_json::to_string(&manifest)?)?;
+        fs::write(dir.join("key"), [key, b"\n"].concat())?;
+        Ok((dir, path))
+    }
+
+    #[test]
+    fn signed_message_lists_checksums_like_sha256sum() {
+        let files = [
+            Entry {
+                path: "events.json".to_string(),
+                sha256: "ab".to_string(),
+            },
+            Entry {
+                path: "sections/priorities.json".to_string(),
+                sha256: "cd".to_string(),
+            },
+        ];
+        assert_eq!(
+            signed_message(&files),
+            "ab  events.json\ncd  sections/priorities.json\n"
+        );
+    }
+
+    #[test]
+    fn checksum_matches_sha256_of_the_file() -> io::Result<()> {
+        let (dir, _) = signed_output("checksum", b"key")?;
+        let sum = checksum(&dir.join("events.json"));
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(sum?, hex::encode(Sha256::digest(b"{}")));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_accepts_unchanged_outputs() -> io::Result<()> {
+        let (dir, path) = signed_output("unchanged", b"secret")?;
+        let result = verify(&path, &dir.join("key"));
+        fs::remove_dir_all(&dir)?;
+        result
+    }
+
+    #[test]
+    fn verify_rejects_changed_outputs_and_wrong_keys() -> io::Result<()> {
+        let (dir, path) = signed_output("changed", b"secret")?;
+        fs::write(dir.join("other"), "secret2")?;
+        let wrong_key = verify(&path, &dir.join("other"));
+        fs::write(dir.join("events.json"), "{ }")?;
+        let changed = verify(&path, &dir.join("key"));
+        fs::remove_dir_all(&dir)?;
+        assert!(wrong_key.is_err());
+        assert!(changed.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
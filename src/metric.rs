@@ -0,0 +1,3021 @@
+//! Pluggable aggregations.
+//!
+//! Every record that passes the filters is turned into an `Event` and handed to each
+//! enabled `Metric`, which keeps whatever state it needs and renders its own
+//! `events.json` section when processing finishes. New aggregations are added by
+//! implementing `Metric` and registering it in `build`; `process_csv_file` does not
+//! need to change. Which metrics run is controlled by the `metrics` configuration
+//! setting.
+
+use crate::baseline::{Baseline, Stats};
+use crate::config::{
+    BusinessHoursConfig, Config, PriorityConfig, SeriesVariant, WebTargetsConfig,
+};
+use crate::derived::{self, DerivedConfig};
+use crate::domain::{self, SuffixList};
+use crate::filter::Sample;
+use crate::intern::Interner;
+use crate::oui::{self, VendorTable};
+#[cfg(feature = "wasm")]
+use crate::plugin::PluginMetric;
+use crate::schema::{
+    array, columns, count, integer, map, nullable_string, number, object, string,
+};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::any::Any;
+use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Names of the built-in metrics, as used in the `metrics` configuration setting.
+pub const BUILTIN: [&str; 17] = [
+    "priorities",
+    "threat_sources",
+    "threat_destinations",
+    "aware_threats",
+    "threat_score",
+    "signatures",
+    "bucket_rates",
+    "priority_trend",
+    "sessions",
+    "domains",
+    "web_targets",
+    "users",
+    "zones",
+    "source_macs",
+    "icmp",
+    "scan_patterns",
+    "business_hours",
+];
+
+/// Names of the metrics computed when the configuration does not list any.
+pub const DEFAULT: [&str; 5] = [
+    "priorities",
+    "threat_sources",
+    "threat_destinations",
+    "aware_threats",
+    "threat_score",
+];
+
+/// Number of entries in the source and destination sections of `events.json`.
+const TOP_ADDRESSES: usize = 10;
+
+/// Number of entries in the signature section of `events.json`.
+const TOP_SIGNATURES: usize = 10;
+
+/// Number of entries in the domain section of `events.json`.
+const TOP_DOMAINS: usize = 10;
+
+/// Number of entries in the user section of `events.json`.
+const TOP_USERS: usize = 10;
+
+/// Number of entries in the source MAC section of `events.json`.
+const TOP_MACS: usize = 10;
+
+/// Number of type and code combinations in the ICMP section of `events.json`.
+const TOP_ICMP: usize = 20;
+
+/// Number of top sources of each zone in the zone section of `events.json`.
+const TOP_ZONE_SOURCES: usize = 5;
+
+/// Number of top sources of each pattern in the scan pattern section of `events.json`.
+const TOP_PATTERN_SOURCES: usize = 5;
+
+/// Zone of events without a zone, interface or VLAN.
+const UNKNOWN_ZONE: &str = "(unknown)";
+
+/// Number of hosts and of paths in the web target section of `events.json`.
+const TOP_WEB_TARGETS: usize = 10;
+
+/// Number of entries in the longest sessions of the session section of `events.json`.
+const TOP_SESSIONS: usize = 10;
+
+/// Number of hours in a time bucket, a morning or afternoon.
+const BUCKET_HOURS: usize = 12;
+
+/// Number of minutes in a time bucket.
+const BUCKET_MINUTES: usize = BUCKET_HOURS * 60;
+
+/// A single record that passed the filters, reduced to the fields metrics work with.
+///
+/// # Fields
+/// - `datetime`: When the event happened.
+/// - `priority`: The raw priority value.
+/// - `signature`: The name of the IDS signature that matched.
+/// - `category`: The event category, e.g. containing `AWARE`.
+/// - `source`: The source address.
+/// - `destination`: The destination address.
+/// - `mapped`: The optional fields read from the columns mapped in the configuration.
+pub struct Event<'a> {
+    pub datetime: NaiveDateTime,
+    pub priority: &'a str,
+    pub signature: &'a str,
+    pub category: &'a str,
+    pub source: &'a str,
+    pub destination: &'a str,
+    pub mapped: MappedFields<'a>,
+    interner: &'a RefCell<Interner>,
+}
+
+/// The optional fields of an event, read from the columns mapped in the `columns`
+/// configuration setting.
+///
+/// # Fields
+/// - `hostname`: The hostname, URL or TLS SNI; `None` if unmapped or empty.
+/// - `url`: The requested URL or URI path; `None` if unmapped or empty.
+/// - `sni`: The TLS server name; `None` if unmapped or empty.
+/// - `user`: The authenticated username; `None` if unmapped or empty.
+/// - `zone`: The security zone; `None` if unmapped or empty.
+/// - `interface`: The network interface; `None` if unmapped or empty.
+/// - `vlan`: The VLAN ID; `None` if unmapped or empty.
+/// - `mac`: The source MAC address; `None` if unmapped or empty.
+/// - `protocol`: The IP protocol; `None` if unmapped or empty.
+/// - `icmp_type`: The ICMP type; `None` if unmapped or empty.
+/// - `icmp_code`: The ICMP code; `None` if unmapped or empty.
+/// - `tcp_flags`: The TCP flags; `None` if unmapped or empty.
+#[derive(Clone, Copy, Default)]
+pub struct MappedFields<'a> {
+    pub hostname: Option<&'a str>,
+    pub url: Option<&'a str>,
+    pub sni: Option<&'a str>,
+    pub user: Option<&'a str>,
+    pub zone: Option<&'a str>,
+    pub interface: Option<&'a str>,
+    pub vlan: Option<&'a str>,
+    pub mac: Option<&'a str>,
+    pub protocol: Option<&'a str>,
+    pub icmp_type: Option<&'a str>,
+    pub icmp_code: Option<&'a str>,
+    pub tcp_flags: Option<&'a str>,
+}
+
+impl<'a> Event<'a> {
+    /// Creates an event whose metrics share the string storage of `interner`.
+    pub const fn new(
+        datetime: NaiveDateTime,
+        priority: &'a str,
+        signature: &'a str,
+        category: &'a str,
+        source: &'a str,
+        destination: &'a str,
+        interner: &'a RefCell<Interner>,
+    ) -> Self {
+        Self {
+            datetime,
+            priority,
+            signature,
+            category,
+            source,
+            destination,
+            mapped: MappedFields {
+                hostname: None,
+                url: None,
+                sni: None,
+                user: None,
+                zone: None,
+                interface: None,
+                vlan: None,
+                mac: None,
+                protocol: None,
+                icmp_type: None,
+                icmp_code: None,
+                tcp_flags: None,
+            },
+            interner,
+        }
+    }
+
+    /// Sets the optional fields of the event.
+    #[must_use]
+    pub const fn with_mapped(mut self, mapped: MappedFields<'a>) -> Self {
+        self.mapped = mapped;
+        self
+    }
+
+    /// Returns the shared copy of `value`, so keys stored by different metrics (such as
+    /// an address seen as both source and destination) are only allocated once.
+    #[must_use]
+    pub fn intern(&self, value: &str) -> Arc<str> {
+        self.interner.borrow_mut().intern(value)
+    }
+}
+
+/// An aggregation over events.
+///
+/// Each file is aggregated into its own set of metrics, which are then merged, so
+/// implementations must be able to combine with another instance of themselves.
+pub trait Metric: Send {
+    /// Returns the name of the metric's section in `events.json`.
+    fn name(&self) -> &'static str;
+
+    /// Updates the metric with a single event.
+    fn observe(&mut self, event: &Event);
+
+    /// Adds the state of `other`, which is always a metric of the same type and name.
+    fn merge(&mut self, other: &dyn Metric);
+
+    /// Returns the complete state of the metric, for a checkpoint. Unlike `finish`,
+    /// nothing is left out or relabeled, so `merge_state` can restore it.
+    fn state(&self) -> Value;
+
+    /// Adds a `state` saved by a metric of the same type and name, like `merge`.
+    ///
+    /// # Errors
+    /// Returns a message if `state` is not a state of this metric.
+    fn merge_state(&mut self, state: &Value) -> Result<(), String>;
+
+    /// Returns the metric's `events.json` section.
+    fn finish(&self) -> Value;
+
+    /// Returns the JSON Schema of the section `finish` returns (see the `schema` module).
+    fn schema(&self) -> Value;
+
+    /// Returns `self` as `Any`, for downcasting in `merge` and by typed consumers.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Scales the metric from a sample up to an estimate for the full input. Metrics
+    /// whose values are counts or sums should override this.
+    fn scale(&mut self, _sample: &Sample) {}
+
+    /// Returns the approximate heap usage of the metric, in bytes.
+    fn memory(&self) -> usize {
+        0
+    }
+
+    /// Drops low-value entries with a count of at most `threshold` to save memory,
+    /// returning how many were dropped. Only metrics with unbounded keys need this.
+    fn prune(&mut self, _threshold: u32) -> usize {
+        0
+    }
+
+    /// Replaces every address in the metric with `anonymize(address)`, adding up the
+    /// counts of addresses that become the same. Only metrics keyed by address need this.
+    fn anonymize(&mut self, _anonymize: &dyn Fn(&str) -> String) {}
+
+    /// Returns the time series of the metric, for the baseline: the values of each
+    /// series by date-sorted time bucket, keyed by the name of the metric, and for the
+    /// series of a labelled time series by `<name>/<label>`. Only time series metrics
+    /// have any.
+    fn series(&self) -> Vec<(String, Vec<(String, u64)>)> {
+        Vec::new()
+    }
+}
+
+/// Builds the metrics enabled in `config`, in the order they appear there.
+///
+/// The threat score is only computed when priority weights are configured. Unknown
+/// names are rejected when the configuration is loaded. The derived metrics come after
+/// them, if the configuration has a `derived` section, and then the plugins.
+#[must_use]
+pub fn build(config: &Config) -> Vec<Box<dyn Metric>> {
+    build_named(config)
+        .into_iter()
+        .map(|(_, metric)| metric)
+        .collect()
+}
+
+/// Builds the metrics enabled in `config` like `build`, each with the name it is enabled
+/// by, such as `threat_sources`, `derived` for the derived metrics, or the name of a
+/// plugin.
+#[must_use]
+pub fn build_named(config: &Config) -> Vec<(&str, Box<dyn Metric>)> {
+    let series = Series::new(config);
+    config
+        .metrics
+        .iter()
+        .filter_map(|name| {
+            let metric: Option<Box<dyn Metric>> = match name.as_str() {
+                "priorities" => Some(Box::new(Priorities::new(&config.priorities))),
+                "threat_sources" => {
+                    Some(Box::new(Addresses::new(Direction::Source, config.cap_per_source)))
+                }
+                "threat_destinations" => {
+                    Some(Box::new(Addresses::new(Direction::Destination, None)))
+                }
+                "aware_threats" => Some(Box::new(AwareThreats::new(series.clone()))),
+                "signatures" => Some(Box::new(Signatures::default())),
+                "bucket_rates" => Some(Box::new(BucketRates::default())),
+                "priority_trend" => {
+                    Some(Box::new(PriorityTrend::new(&config.priorities, series.clone())))
+                }
+                "sessions" => Some(Box::new(Sessions::new(config.session_gap_minutes))),
+                "domains" => Some(Box::new(Domains::new(Arc::clone(&config.suffixes)))),
+                "web_targets" => Some(Box::new(WebTargets::new(&config.web_targets))),
+                "users" => Some(Box::new(Users::default())),
+                "zones" => Some(Box::new(Zones::default())),
+                "source_macs" => Some(Box::new(SourceMacs::new(Arc::clone(&config.vendors)))),
+                "icmp" => Some(Box::new(Icmp::default())),
+                "scan_patterns" => Some(Box::new(ScanPatterns::default())),
+                "business_hours" => {
+                    Some(Box::new(BusinessHours::new(&config.business_hours, series.clone())))
+                }
+                "threat_score" if !config.priorities.weights.is_empty() => {
+                    Some(Box::new(ThreatScore::new(&config.priorities, series.clone())))
+                }
+                _ => None,
+            };
+            metric.map(|metric| (name.as_str(), metric))
+        })
+        .chain(config.derived.as_ref().map(|derived| -> (&str, Box<dyn Metric>) {
+            ("derived", Box::new(Derived::new(derived)))
+        }))
+        .chain(plugins(config))
+        .collect()
+}
+
+/// Builds the metrics of the plugins loaded in `config`, with their names.
+#[cfg(feature = "wasm")]
+fn plugins(config: &Config) -> impl Iterator<Item = (&str, Box<dyn Metric>)> {
+    config.loaded_plugins.iter().map(|plugin| -> (&str, Box<dyn Metric>) {
+        (plugin.name(), Box::new(PluginMetric::new(plugin)))
+    })
+}
+
+/// Builds no metrics, as plugins need the `wasm` feature.
+#[cfg(not(feature = "wasm"))]
+fn plugins(_config: &Config) -> impl Iterator<Item = (&str, Box<dyn Metric>)> {
+    std::iter::empty()
+}
+
+/// Returns the time bucket an event belongs to: its date and whether it happened in
+/// the morning or afternoon, e.g. `2024-04-01 PM`.
+#[must_use]
+pub fn time_bucket(date_time: &NaiveDateTime) -> String {
+    let period = if date_time.hour() < 12 {
+        "AM" //"00-11"
+    } else {
+        "PM" //"12-23"
+    };
+    format!("{} {}", date_time.date(), period)
+}
+
+/// Returns the approximate heap usage of a map with string keys. Interned keys shared
+/// between maps are counted once per map, which errs on the side of overestimating.
+fn map_memory<K: AsRef<str>, V>(map: &HashMap<K, V>) -> usize {
+    // Each bucket holds the key, the value, and one control byte
+    map.capacity() * (mem::size_of::<(K, V)>() + 1)
+        + map.keys().map(|key| key.as_ref().len()).sum::<usize>()
+}
+
+/// Scales a sampled count, saturating at `u32::MAX`.
+fn scale_count(sample: &Sample, count: &mut u32) {
+    *count = u32::try_from(sample.scale(u64::from(*count))).unwrap_or(u32::MAX);
+}
+
+/// Adds every entry of `other` into `counts`.
+fn merge_counts<K: Clone + Eq + std::hash::Hash, V: Copy + std::ops::AddAssign + Default>(
+    counts: &mut HashMap<K, V>,
+    other: &HashMap<K, V>,
+) {
+    for (key, &value) in other {
+        *counts.entry(key.clone()).or_default() += value;
+    }
+}
+
+/// Returns the state of a map of counts, as a JSON object.
+fn counts_state<K: AsRef<str>, V: Copy + Into<Value>>(counts: &HashMap<K, V>) -> Value {
+    Value::Object(
+        counts
+            .iter()
+            .map(|(key, &count)| (key.as_ref().to_string(), count.into()))
+            .collect(),
+    )
+}
+
+/// Adds the counts of a `state` returned by `counts_state` into `counts`.
+fn merge_counts_state<K, V>(counts: &mut HashMap<K, V>, state: &Value) -> Result<(), String>
+where
+    K: for<'a> From<&'a str> + Eq + std::hash::Hash,
+    V: DeserializeOwned + std::ops::AddAssign + Default,
+{
+    let Value::Object(entries) = state else {
+        return Err("expected an object of counts".to_string());
+    };
+    for (key, count) in entries {
+        let count = V::deserialize(count).map_err(|e| format!("invalid count for `{key}`: {e}"))?;
+        *counts.entry(K::from(key.as_str())).or_default() += count;
+    }
+    Ok(())
+}
+
+/// Returns the entries of a date-keyed map sorted by date.
+fn by_date<V: Copy>(map: &HashMap<String, V>) -> Vec<(String, V)> {
+    let mut entries: Vec<_> = map
+        .iter()
+        .map(|(date, &value)| (date.clone(), value))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+/// Returns the position of a time bucket such as `2024-04-01 PM`: the half-days since
+/// the common era, so consecutive buckets are consecutive numbers.
+fn bucket_position(bucket: &str) -> i64 {
+    let days = bucket
+        .get(..10)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .map_or(0, |date| i64::from(date.num_days_from_ce()));
+    days * 2 + i64::from(bucket.ends_with("PM"))
+}
+
+/// Returns the trailing moving average over `window` time buckets of each of the
+/// date-sorted `entries`. Buckets missing from `entries` count as zero, and the first
+/// buckets of the series are averaged over the buckets since its start.
+#[allow(clippy::cast_precision_loss)]
+fn moving_average<V: Copy + Into<u64>>(entries: &[(String, V)], window: usize) -> Vec<f64> {
+    let positions: Vec<i64> = entries
+        .iter()
+        .map(|(bucket, _)| bucket_position(bucket))
+        .collect();
+    let window = i64::try_from(window).unwrap_or(i64::MAX);
+    let mut start = 0;
+    let mut sum = 0;
+    entries
+        .iter()
+        .zip(&positions)
+        .map(|((_, value), &position)| {
+            sum += (*value).into();
+            while positions[start] <= position.saturating_sub(window) {
+                sum -= entries[start].1.into();
+                start += 1;
+            }
+            let buckets = window.min(position - positions[0] + 1);
+            sum as f64 / buckets as f64
+        })
+        .collect()
+}
+
+/// Returns the value per hour of each of the `entries`.
+#[allow(clippy::cast_precision_loss)]
+fn rates<V: Copy + Into<u64>>(entries: &[(String, V)]) -> Vec<f64> {
+    entries
+        .iter()
+        .map(|(_, value)| (*value).into() as f64 / BUCKET_HOURS as f64)
+        .collect()
+}
+
+/// Returns the z-score of each of the date-sorted `entries` of the series `key`, against
+/// its learned values in `baseline` if it has enough of them, or else against the
+/// series itself: the mean and standard deviation of every bucket from its first to its
+/// last, where buckets missing from `entries` count as zero.
+#[allow(clippy::cast_precision_loss)]
+fn z_scores<V: Copy + Into<u64>>(
+    key: &str,
+    entries: &[(String, V)],
+    baseline: Option<&Baseline>,
+) -> Vec<f64> {
+    let (Some((first, _)), Some((last, _))) = (entries.first(), entries.last()) else {
+        return Vec::new();
+    };
+    let buckets = usize::try_from(bucket_position(last) - bucket_position(first) + 1);
+    let values: Vec<f64> = entries.iter().map(|(_, value)| (*value).into() as f64).collect();
+    let own = Stats::new(&values, buckets.unwrap_or(0));
+    entries
+        .iter()
+        .zip(values)
+        .map(|((bucket, _), value)| {
+            let stats = baseline.and_then(|baseline| baseline.stats(key, bucket));
+            stats.unwrap_or(own).z_score(value)
+        })
+        .collect()
+}
+
+/// Returns the labelled `series` of the metric `name` as the series of `Metric::series`.
+fn labelled_series<L: AsRef<str>>(
+    name: &str,
+    series: &[(L, Vec<(String, u32)>)],
+) -> Vec<(String, Vec<(String, u64)>)> {
+    series
+        .iter()
+        .map(|(label, counts)| {
+            let counts = counts.iter().map(|(bucket, count)| (bucket.clone(), (*count).into()));
+            (format!("{name}/{}", label.as_ref()), counts.collect())
+        })
+        .collect()
+}
+
+/// The series derived from the counts of the time series sections, and added next to
+/// them: the `Smoothed` moving average and the normalized variants, in that order.
+///
+/// # Fields
+/// - `window`: The number of buckets averaged into the `Smoothed` series; not smoothed
+///   if `None`.
+/// - `variants`: The normalized variants, `Rate` and `Z-Score`, that are added.
+/// - `baseline`: The learned baseline of the `Z-Score` series, if any.
+#[derive(Clone, Default)]
+pub struct Series {
+    window: Option<usize>,
+    variants: Vec<SeriesVariant>,
+    baseline: Option<Arc<RwLock<Baseline>>>,
+}
+
+impl Series {
+    /// Creates the derived series configured in `config`.
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        Self {
+            window: config.smoothing_window,
+            variants: config.series_variants.clone(),
+            baseline: config.baseline.clone(),
+        }
+    }
+
+    /// Returns each derived series of the date-sorted `entries` of the series `key` (see
+    /// `Metric::series`), with its name.
+    fn derive<V: Copy + Into<u64>>(
+        &self,
+        key: &str,
+        entries: &[(String, V)],
+    ) -> Vec<(&'static str, Vec<f64>)> {
+        let mut series = Vec::new();
+        if let Some(window) = self.window {
+            series.push(("Smoothed", moving_average(entries, window)));
+        }
+        if self.variants.contains(&SeriesVariant::Rate) {
+            series.push(("Rate", rates(entries)));
+        }
+        if self.variants.contains(&SeriesVariant::ZScore) {
+            let baseline = self
+                .baseline
+                .as_ref()
+                .map(|baseline| baseline.read().unwrap_or_else(PoisonError::into_inner));
+            series.push(("Z-Score", z_scores(key, entries, baseline.as_deref())));
+        }
+        series
+    }
+
+    /// Returns the names of the derived series.
+    fn names(&self) -> Vec<&'static str> {
+        self.derive::<u32>("", &[])
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Adds the derived series of the date-sorted `entries` of the series `key` to a time
+    /// series `section`.
+    fn add<V: Copy + Into<u64>>(&self, section: &mut Value, key: &str, entries: &[(String, V)]) {
+        for (name, values) in self.derive(key, entries) {
+            section[name] = json!(values);
+        }
+    }
+
+    /// Adds the derived series of each of the labelled, date-sorted `series` of the
+    /// metric `key` to a time series `section`, as a map keyed by label per derived
+    /// series.
+    fn add_labelled<L: AsRef<str>>(
+        &self,
+        section: &mut Value,
+        key: &str,
+        series: &[(L, Vec<(String, u32)>)],
+    ) {
+        for name in self.names() {
+            section[name] = json!({});
+        }
+        for (label, counts) in series {
+            let key = format!("{key}/{}", label.as_ref());
+            for (name, values) in self.derive(&key, counts) {
+                section[name][label.as_ref()] = json!(values);
+            }
+        }
+    }
+
+    /// Adds the schema of each derived series to the `properties` of a section schema,
+    /// with `values` as the schema of its values.
+    fn add_schema(&self, properties: &mut Vec<(&str, Value)>, values: impl Fn() -> Value) {
+        properties.extend(self.names().into_iter().map(|name| (name, values())));
+    }
+}
+
+/// Returns the schema of a time series section with a `column` of counts, and the
+/// derived `series`.
+fn series_schema(column: &str, series: &Series) -> Value {
+    let mut properties = vec![("Date", array(string())), (column, array(count()))];
+    series.add_schema(&mut properties, || array(number()));
+    object(properties)
+}
+
+/// Downcasts `other` to `M`, panicking if `merge` was called with a different metric.
+fn same<M: 'static>(other: &dyn Metric) -> &M {
+    other
+        .as_any()
+        .downcast_ref()
+        .expect("metrics are merged with metrics of the same type")
+}
+
+/// Compares two raw priority values, numerically if both are integers, so `"10"` sorts
+/// after `"2"`. Values that are not integers sort before those that are, in lexical
+/// order.
+fn compare_priorities(a: &str, b: &str) -> Ordering {
+    let number = |priority: &str| priority.trim().parse::<i64>().ok();
+    number(a).cmp(&number(b)).then_with(|| a.cmp(b))
+}
+
+/// Counts events per priority, always including the configured priority values.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by raw priority value.
+/// - `labels`: Display labels keyed by raw priority value.
+pub struct Priorities {
+    pub counts: HashMap<Arc<str>, u32>,
+    labels: HashMap<String, String>,
+}
+
+impl Priorities {
+    /// Creates the metric with the expected priorities prepopulated at zero, so the
+    /// dashboard always receives the full priority range.
+    fn new(config: &PriorityConfig) -> Self {
+        Self {
+            counts: config
+                .values
+                .iter()
+                .map(|priority| (Arc::from(priority.as_str()), 0))
+                .collect(),
+            labels: config.labels.clone(),
+        }
+    }
+
+    /// Returns the labelled counts, sorted by raw priority value in descending order,
+    /// numerically for integer priorities.
+    #[must_use]
+    pub fn sorted(&self) -> Vec<(String, u32)> {
+        let mut priorities: Vec<_> = self.counts.iter().collect();
+        priorities.sort_by(|a, b| compare_priorities(b.0, a.0));
+        priorities
+            .into_iter()
+            .map(|(priority, &count)| {
+                let label = self
+                    .labels
+                    .get(&**priority)
+                    .map_or(&**priority, String::as_str);
+                (label.to_string(), count)
+            })
+            .collect()
+    }
+}
+
+impl Metric for Priorities {
+    fn name(&self) -> &'static str {
+        "Priorities"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        if let Some(count) = self.counts.get_mut(event.priority) {
+            *count += 1;
+        } else {
+            self.counts.insert(event.intern(event.priority), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let sorted = self.sorted();
+        json!({
+            "Priority": sorted.iter().map(|(priority, _)| priority).collect::<Vec<_>>(),
+            "Count": sorted.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        })
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![("Priority", string()), ("Count", count())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+}
+/// Counts events per priority and time bucket, for a trend line of each priority.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by date and morning or afternoon period, and then by
+///   raw priority value.
+/// - `priorities`: The configured priority values, which get a series even if they
+///   never occur.
+/// - `labels`: Display labels keyed by raw priority value.
+/// - `series`: The series derived from the counts.
+pub struct PriorityTrend {
+    counts: HashMap<String, HashMap<Arc<str>, u32>>,
+    priorities: Vec<String>,
+    labels: HashMap<String, String>,
+    series: Series,
+}
+
+impl PriorityTrend {
+    /// Creates an empty metric for the configured priorities, with the derived `series`.
+    fn new(config: &PriorityConfig, series: Series) -> Self {
+        Self {
+            counts: HashMap::new(),
+            priorities: config.values.clone(),
+            labels: config.labels.clone(),
+            series,
+        }
+    }
+
+    /// Returns the count of every priority in every bucket, with the series labelled
+    /// and the buckets sorted by date.
+    #[must_use]
+    pub fn series(&self) -> Vec<(String, Vec<(String, u32)>)> {
+        let mut buckets: Vec<_> = self.counts.keys().cloned().collect();
+        buckets.sort();
+        let mut priorities: Vec<&str> = self
+            .priorities
+            .iter()
+            .map(String::as_str)
+            .chain(self.counts.values().flat_map(|counts| counts.keys().map(|p| &**p)))
+            .collect();
+        priorities.sort_unstable_by(|a, b| compare_priorities(b, a));
+        priorities.dedup();
+        priorities
+            .into_iter()
+            .map(|priority| {
+                let label = self.labels.get(priority).map_or(priority, String::as_str);
+                let counts = buckets
+                    .iter()
+                    .map(|bucket| {
+                        let count = self.counts[bucket].get(priority).copied().unwrap_or(0);
+                        (bucket.clone(), count)
+                    })
+                    .collect();
+                (label.to_string(), counts)
+            })
+            .collect()
+    }
+}
+
+impl Metric for PriorityTrend {
+    fn name(&self) -> &'static str {
+        "Priority Trend"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let counts = self
+            .counts
+            .entry(time_bucket(&event.datetime))
+            .or_default();
+        if let Some(count) = counts.get_mut(event.priority) {
+            *count += 1;
+        } else {
+            counts.insert(event.intern(event.priority), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        for (bucket, counts) in &same::<Self>(other).counts {
+            merge_counts(self.counts.entry(bucket.clone()).or_default(), counts);
+        }
+    }
+
+    fn state(&self) -> Value {
+        Value::Object(
+            self.counts
+                .iter()
+                .map(|(bucket, counts)| (bucket.clone(), counts_state(counts)))
+                .collect(),
+        )
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        let Value::Object(buckets) = state else {
+            return Err("expected an object of buckets".to_string());
+        };
+        for (bucket, counts) in buckets {
+            merge_counts_state(self.counts.entry(bucket.clone()).or_default(), counts)
+                .map_err(|e| format!("{e} for `{bucket}`"))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Value {
+        let series = self.series();
+        let dates: Vec<_> = series
+            .first()
+            .map(|(_, counts)| counts.iter().map(|(date, _)| date.clone()).collect())
+            .unwrap_or_default();
+        let mut section = json!({
+            "Date": dates,
+            "Count": series
+                .iter()
+                .map(|(label, counts)| {
+                    let counts: Vec<_> = counts.iter().map(|(_, count)| count).collect();
+                    (label.clone(), json!(counts))
+                })
+                .collect::<serde_json::Map<_, _>>(),
+        });
+        self.series.add_labelled(&mut section, self.name(), &series);
+        section
+    }
+
+    fn schema(&self) -> Value {
+        // The counts and derived series are keyed by priority label
+        let mut properties = vec![("Date", array(string())), ("Count", map(array(count())))];
+        self.series.add_schema(&mut properties, || map(array(number())));
+        object(properties)
+    }
+
+    fn series(&self) -> Vec<(String, Vec<(String, u64)>)> {
+        labelled_series(self.name(), &self.series())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .flat_map(HashMap::values_mut)
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts) + self.counts.values().map(map_memory).sum::<usize>()
+    }
+}
+
+/// Counts events within and outside business hours, in total and per time bucket.
+///
+/// # Fields
+/// - `hours`: The configured business hours.
+/// - `business`: Event counts within business hours, keyed by date and morning or
+///   afternoon period.
+/// - `off`: Event counts outside business hours, keyed like `business`.
+/// - `series`: The series derived from the counts.
+pub struct BusinessHours {
+    hours: BusinessHoursConfig,
+    business: HashMap<String, u32>,
+    off: HashMap<String, u32>,
+    series: Series,
+}
+
+impl BusinessHours {
+    /// Creates an empty metric for the configured business hours, with the derived
+    /// `series`.
+    fn new(hours: &BusinessHoursConfig, series: Series) -> Self {
+        Self {
+            hours: hours.clone(),
+            business: HashMap::new(),
+            off: HashMap::new(),
+            series,
+        }
+    }
+
+    /// Returns the business and off-hours series, with a count for every bucket that
+    /// has events in either, sorted by date.
+    #[must_use]
+    pub fn series(&self) -> [(&'static str, Vec<(String, u32)>); 2] {
+        let mut buckets: Vec<_> = self.business.keys().chain(self.off.keys()).collect();
+        buckets.sort();
+        buckets.dedup();
+        let series = |counts: &HashMap<String, u32>| {
+            buckets
+                .iter()
+                .map(|&bucket| (bucket.clone(), counts.get(bucket).copied().unwrap_or(0)))
+                .collect()
+        };
+        [
+            ("Business Hours", series(&self.business)),
+            ("Off Hours", series(&self.off)),
+        ]
+    }
+}
+
+impl Metric for BusinessHours {
+    fn name(&self) -> &'static str {
+        "Business Hours"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let counts = if self.hours.contains(event.datetime) {
+            &mut self.business
+        } else {
+            &mut self.off
+        };
+        *counts.entry(time_bucket(&event.datetime)).or_insert(0) += 1;
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        let other = same::<Self>(other);
+        merge_counts(&mut self.business, &other.business);
+        merge_counts(&mut self.off, &other.off);
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "business": counts_state(&self.business),
+            "off": counts_state(&self.off)
+        })
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.business, &state["business"])
+            .map_err(|e| format!("{e} in business"))?;
+        merge_counts_state(&mut self.off, &state["off"]).map_err(|e| format!("{e} in off"))
+    }
+
+    fn finish(&self) -> Value {
+        let series = self.series();
+        let dates: Vec<_> = series[0].1.iter().map(|(date, _)| date).collect();
+        let total = |counts: &HashMap<String, u32>| {
+            counts.values().map(|&count| u64::from(count)).sum::<u64>()
+        };
+        let mut section = json!({
+            "Business Hours": total(&self.business),
+            "Off Hours": total(&self.off),
+            "Date": dates,
+            "Count": series
+                .iter()
+                .map(|(label, counts)| {
+                    let counts: Vec<_> = counts.iter().map(|(_, count)| count).collect();
+                    ((*label).to_string(), json!(counts))
+                })
+                .collect::<serde_json::Map<_, _>>(),
+        });
+        self.series.add_labelled(&mut section, self.name(), &series);
+        section
+    }
+
+    fn schema(&self) -> Value {
+        let series = |values: fn() -> Value| {
+            columns(vec![("Business Hours", values()), ("Off Hours", values())])
+        };
+        let mut properties = vec![
+            ("Business Hours", count()),
+            ("Off Hours", count()),
+            ("Date", array(string())),
+            ("Count", series(count)),
+        ];
+        self.series.add_schema(&mut properties, || series(number));
+        object(properties)
+    }
+
+    fn series(&self) -> Vec<(String, Vec<(String, u64)>)> {
+        labelled_series(self.name(), &self.series())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.business
+            .values_mut()
+            .chain(self.off.values_mut())
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.business) + map_memory(&self.off)
+    }
+}
+
+/// Which address of an event an `Addresses` metric counts.
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Source,
+    Destination,
+}
+
+/// Counts events per source or destination address.
+///
+/// # Fields
+/// - `direction`: Which address is counted.
+/// - `cap`: The most events counted for a single address, if capped; an address that
+///   reaches it is listed as capped, as it may have had more.
+/// - `counts`: Event counts keyed by address.
+pub struct Addresses {
+    direction: Direction,
+    cap: Option<u32>,
+    pub counts: HashMap<Arc<str>, u32>,
+}
+
+impl Addresses {
+    /// Creates an empty metric counting the given address of each event, up to `cap`
+    /// events per address if given.
+    fn new(direction: Direction, cap: Option<u32>) -> Self {
+        Self {
+            direction,
+            cap,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Lowers the counts above the cap, if any, to the cap, e.g. after adding up the
+    /// counts of several files.
+    fn apply_cap(&mut self) {
+        if let Some(cap) = self.cap {
+            for count in self.counts.values_mut() {
+                *count = (*count).min(cap);
+            }
+        }
+    }
+
+    /// Returns the `n` addresses with the highest counts, in descending order, with equal
+    /// counts in address order so the output is the same from run to run.
+    #[must_use]
+    pub fn top(&self, n: usize) -> Vec<(String, u32)> {
+        let mut entries: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(address, &count)| (address.to_string(), count))
+            .collect();
+        entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        entries.truncate(n);
+        entries
+    }
+
+    /// Returns the label used for addresses in `events.json`.
+    const fn label(&self) -> &'static str {
+        match self.direction {
+            Direction::Source => "Source",
+            Direction::Destination => "Destination",
+        }
+    }
+}
+
+impl Metric for Addresses {
+    fn name(&self) -> &'static str {
+        match self.direction {
+            Direction::Source => "Threat Sources",
+            Direction::Destination => "Threat Destinations",
+        }
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let address = match self.direction {
+            Direction::Source => event.source,
+            Direction::Destination => event.destination,
+        };
+        if let Some(count) = self.counts.get_mut(address) {
+            if self.cap.map_or(true, |cap| *count < cap) {
+                *count += 1;
+            }
+        } else {
+            self.counts.insert(event.intern(address), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+        self.apply_cap();
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)?;
+        self.apply_cap();
+        Ok(())
+    }
+
+    fn finish(&self) -> Value {
+        // Only the top addresses are included; the full list goes to threat_sources.json
+        let top = self.top(TOP_ADDRESSES);
+        let mut section = json!({
+            self.label(): top.iter().map(|(address, _)| address).collect::<Vec<_>>(),
+            "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        });
+        if let Some(cap) = self.cap {
+            section["Capped"] = top.iter().map(|&(_, count)| count >= cap).collect();
+        }
+        section
+    }
+
+    fn schema(&self) -> Value {
+        let mut columns_schema = vec![(self.label(), string()), ("Count", count())];
+        if self.cap.is_some() {
+            columns_schema.push(("Capped", json!({ "type": "boolean" })));
+        }
+        columns(columns_schema)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+        self.apply_cap();
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+
+    fn anonymize(&mut self, anonymize: &dyn Fn(&str) -> String) {
+        let mut counts: HashMap<Arc<str>, u32> = HashMap::with_capacity(self.counts.len());
+        for (address, count) in self.counts.drain() {
+            *counts.entry(Arc::from(anonymize(&address))).or_default() += count;
+        }
+        self.counts = counts;
+        self.apply_cap();
+    }
+}
+
+/// Counts events per IDS signature.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by signature name.
+#[derive(Default)]
+pub struct Signatures {
+    pub counts: HashMap<Arc<str>, u32>,
+}
+
+impl Signatures {
+    /// Returns the `n` signatures with the highest counts, in descending order, with equal
+    /// counts in signature order.
+    #[must_use]
+    pub fn top(&self, n: usize) -> Vec<(String, u32)> {
+        let mut entries: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(signature, &count)| (signature.to_string(), count))
+            .collect();
+        entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        entries.truncate(n);
+        entries
+    }
+}
+
+impl Metric for Signatures {
+    fn name(&self) -> &'static str {
+        "Top Signatures"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        if let Some(count) = self.counts.get_mut(event.signature) {
+            *count += 1;
+        } else {
+            self.counts.insert(event.intern(event.signature), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let top = self.top(TOP_SIGNATURES);
+        json!({
+            "Signature": top.iter().map(|(signature, _)| signature).collect::<Vec<_>>(),
+            "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        })
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![("Signature", string()), ("Count", count())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+}
+
+/// Counts events per registrable domain of their hostname, such as `example.co.uk` for
+/// `cdn.example.co.uk`. Events without a hostname, or with an IP address instead, are
+/// not counted.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by registrable domain.
+/// - `suffixes`: The public suffixes the hostnames are rolled up with.
+pub struct Domains {
+    pub counts: HashMap<Arc<str>, u32>,
+    suffixes: Arc<SuffixList>,
+}
+
+impl Domains {
+    /// Creates an empty domain count using the public suffix rules in `suffixes`.
+    #[must_use]
+    pub fn new(suffixes: Arc<SuffixList>) -> Self {
+        Self {
+            counts: HashMap::new(),
+            suffixes,
+        }
+    }
+}
+
+impl Metric for Domains {
+    fn name(&self) -> &'static str {
+        "Top Domains"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let Some(hostname) = event.mapped.hostname.and_then(domain::hostname) else {
+            return;
+        };
+        // Hostnames that are public suffixes themselves are counted as they are
+        let domain = self.suffixes.registrable(&hostname).unwrap_or(&hostname);
+        if let Some(count) = self.counts.get_mut(domain) {
+            *count += 1;
+        } else {
+            self.counts.insert(event.intern(domain), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let mut top: Vec<_> = self.counts.iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        top.truncate(TOP_DOMAINS);
+        json!({
+            "Domain": top.iter().map(|(domain, _)| domain.as_ref()).collect::<Vec<_>>(),
+            "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        })
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![("Domain", string()), ("Count", count())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+}
+
+/// Counts events per authenticated user, for insider-risk reviews. Events without a
+/// username, or with a `-` placeholder, are not counted.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by username.
+#[derive(Default)]
+pub struct Users {
+    pub counts: HashMap<Arc<str>, u32>,
+}
+
+impl Metric for Users {
+    fn name(&self) -> &'static str {
+        "Top Users in Threat Events"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let Some(user) = event
+            .mapped
+            .user
+            .map(str::trim)
+            .filter(|user| !user.is_empty() && *user != "-")
+        else {
+            return;
+        };
+        if let Some(count) = self.counts.get_mut(user) {
+            *count += 1;
+        } else {
+            self.counts.insert(event.intern(user), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let mut top: Vec<_> = self.counts.iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        top.truncate(TOP_USERS);
+        json!({
+            "User": top.iter().map(|(user, _)| user.as_ref()).collect::<Vec<_>>(),
+            "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        })
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![("User", string()), ("Count", count())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+}
+
+/// Counts events per source MAC address, with the vendor of each address, to track
+/// down misbehaving devices on flat networks. Fields that are not MAC addresses are not
+/// counted.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by MAC address, in lowercase colon-separated form.
+/// - `vendors`: The vendors the addresses are looked up in.
+pub struct SourceMacs {
+    pub counts: HashMap<Arc<str>, u32>,
+    vendors: Arc<VendorTable>,
+}
+
+impl SourceMacs {
+    /// Creates an empty MAC count looking vendors up in `vendors`.
+    #[must_use]
+    pub fn new(vendors: Arc<VendorTable>) -> Self {
+        Self {
+            counts: HashMap::new(),
+            vendors,
+        }
+    }
+}
+
+impl Metric for SourceMacs {
+    fn name(&self) -> &'static str {
+        "Top Source MACs"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let Some(mac) = event.mapped.mac.and_then(oui::normalize) else {
+            return;
+        };
+        if let Some(count) = self.counts.get_mut(mac.as_str()) {
+            *count += 1;
+        } else {
+            self.counts.insert(event.intern(&mac), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let mut top: Vec<_> = self.counts.iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        top.truncate(TOP_MACS);
+        json!({
+            "MAC": top.iter().map(|(mac, _)| mac.as_ref()).collect::<Vec<_>>(),
+            "Vendor": top.iter().map(|(mac, _)| self.vendors.vendor(mac)).collect::<Vec<_>>(),
+            "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        })
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![
+            ("MAC", string()),
+            ("Vendor", nullable_string()),
+            ("Count", count()),
+        ])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+}
+
+/// Returns the name of an ICMP (`v6` false) or `ICMPv6` (`v6` true) message type and
+/// code, such as `Destination Unreachable (Port)`, or `None` if it is not a common one.
+/// Types logged by name, like pfSense does, are translated if known and returned as
+/// they are otherwise.
+fn icmp_name(v6: bool, icmp_type: &str, code: &str) -> Option<String> {
+    let Ok(number) = icmp_type.parse::<u8>() else {
+        let name = match icmp_type {
+            "request" => "Echo Request",
+            "reply" => "Echo Reply",
+            "unreach" => "Destination Unreachable",
+            "unreachproto" => "Destination Unreachable (Protocol)",
+            "unreachport" => "Destination Unreachable (Port)",
+            "needfrag" => "Destination Unreachable (Fragmentation Needed)",
+            "toobig" => "Packet Too Big",
+            "redirect" => "Redirect",
+            "timexceed" => "Time Exceeded",
+            "paramprob" => "Parameter Problem",
+            "tstamp" => "Timestamp Request",
+            "tstampreply" => "Timestamp Reply",
+            other => other,
+        };
+        return Some(name.to_string());
+    };
+    let code = code.parse::<u8>().ok();
+    let (name, detail) = match (v6, number) {
+        (false, 0) | (true, 129) => ("Echo Reply", None),
+        (false, 8) | (true, 128) => ("Echo Request", None),
+        (false, 3) => (
+            "Destination Unreachable",
+            match code {
+                Some(0) => Some("Network"),
+                Some(1) => Some("Host"),
+                Some(2) => Some("Protocol"),
+                Some(3) => Some("Port"),
+                Some(4) => Some("Fragmentation Needed"),
+                Some(13) => Some("Administratively Prohibited"),
+                _ => None,
+            },
+        ),
+        (true, 1) => (
+            "Destination Unreachable",
+            match code {
+                Some(0) => Some("No Route"),
+                Some(1) => Some("Administratively Prohibited"),
+                Some(3) => Some("Address"),
+                Some(4) => Some("Port"),
+                _ => None,
+            },
+        ),
+        (true, 2) => ("Packet Too Big", None),
+        (false, 5) | (true, 137) => ("Redirect", None),
+        (false, 11) | (true, 3) => ("Time Exceeded", None),
+        (false, 12) | (true, 4) => ("Parameter Problem", None),
+        (false, 13) => ("Timestamp Request", None),
+        (false, 14) => ("Timestamp Reply", None),
+        (true, 133) => ("Router Solicitation", None),
+        (true, 134) => ("Router Advertisement", None),
+        (true, 135) => ("Neighbor Solicitation", None),
+        (true, 136) => ("Neighbor Advertisement", None),
+        _ => return None,
+    };
+    Some(detail.map_or_else(|| name.to_string(), |detail| format!("{name} ({detail})")))
+}
+
+/// Counts ICMP events per protocol, type and code, so ping floods can be told apart
+/// from path MTU discovery and other routine messages.
+///
+/// Events are counted if they have an ICMP type and, when the protocol is mapped, their
+/// protocol is ICMP or `ICMPv6`.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by protocol, type and code, e.g. `icmp/3/4`.
+#[derive(Default)]
+pub struct Icmp {
+    pub counts: HashMap<Arc<str>, u32>,
+}
+
+impl Metric for Icmp {
+    fn name(&self) -> &'static str {
+        "ICMP Types"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let mapped = &event.mapped;
+        let Some(icmp_type) = mapped.icmp_type.map(str::trim) else {
+            return;
+        };
+        let protocol = match mapped.protocol.map(|p| p.trim().to_ascii_lowercase()) {
+            None => "icmp",
+            Some(protocol) => match protocol.as_str() {
+                "icmp" | "1" => "icmp",
+                "icmpv6" | "ipv6-icmp" | "icmp6" | "58" => "icmpv6",
+                _ => return,
+            },
+        };
+        let code = mapped.icmp_code.map_or("", str::trim);
+        let key = format!("{protocol}/{icmp_type}/{code}");
+        if let Some(count) = self.counts.get_mut(key.as_str()) {
+            *count += 1;
+        } else {
+            self.counts.insert(event.intern(&key), 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let mut top: Vec<_> = self.counts.iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        top.truncate(TOP_ICMP);
+        let mut section = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (key, count) in top {
+            let mut parts = key.splitn(3, '/');
+            let protocol = parts.next().unwrap_or_default();
+            let icmp_type = parts.next().unwrap_or_default();
+            let code = parts.next().unwrap_or_default();
+            section.0.push(if protocol == "icmpv6" { "ICMPv6" } else { "ICMP" });
+            section.1.push(icmp_type);
+            section.2.push(Some(code).filter(|code| !code.is_empty()));
+            section.3.push(icmp_name(protocol == "icmpv6", icmp_type, code));
+            section.4.push(count);
+        }
+        json!({
+            "Protocol": section.0,
+            "Type": section.1,
+            "Code": section.2,
+            "Name": section.3,
+            "Count": section.4
+        })
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![
+            ("Protocol", string()),
+            ("Type", string()),
+            ("Code", nullable_string()),
+            ("Name", nullable_string()),
+            ("Count", count()),
+        ])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+}
+
+/// The events of a single group, such as a zone.
+///
+/// # Fields
+/// - `count`: The number of events.
+/// - `sources`: Event counts keyed by source address.
+#[derive(Default)]
+struct SourceGroup {
+    count: u32,
+    sources: HashMap<Arc<str>, u32>,
+}
+
+/// Event counts and their sources per group, for the metrics that break events down by
+/// something other than their addresses, such as their zone.
+///
+/// # Fields
+/// - `groups`: The events keyed by group.
+#[derive(Default)]
+struct SourceGroups {
+    groups: HashMap<Arc<str>, SourceGroup>,
+}
+
+impl SourceGroups {
+    /// Counts `event` in the group named `name`.
+    fn observe(&mut self, event: &Event, name: &str) {
+        let group = if let Some(group) = self.groups.get_mut(name) {
+            group
+        } else {
+            self.groups.entry(event.intern(name)).or_default()
+        };
+        group.count += 1;
+        if let Some(count) = group.sources.get_mut(event.source) {
+            *count += 1;
+        } else {
+            group.sources.insert(event.intern(event.source), 1);
+        }
+    }
+
+    /// Adds the counts of `other`.
+    fn merge(&mut self, other: &Self) {
+        for (name, other) in &other.groups {
+            let group = self.groups.entry(Arc::clone(name)).or_default();
+            group.count += other.count;
+            merge_counts(&mut group.sources, &other.sources);
+        }
+    }
+
+    /// Returns the counts of every group, for a checkpoint.
+    fn state(&self) -> Value {
+        Value::Object(
+            self.groups
+                .iter()
+                .map(|(name, group)| {
+                    let state = json!({ "count": group.count, "sources": counts_state(&group.sources) });
+                    (name.to_string(), state)
+                })
+                .collect(),
+        )
+    }
+
+    /// Adds a `state` returned by `state`.
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        let Value::Object(groups) = state else {
+            return Err("expected an object of groups".to_string());
+        };
+        for (name, state) in groups {
+            let count = state["count"]
+                .as_u64()
+                .and_then(|count| u32::try_from(count).ok())
+                .ok_or_else(|| format!("invalid count for `{name}`"))?;
+            let group = self.groups.entry(Arc::from(name.as_str())).or_default();
+            group.count += count;
+            merge_counts_state(&mut group.sources, &state["sources"])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the section listing every group under `label`, by descending event
+    /// count, with its `Count`, its number of distinct `Sources` and its `top` sources.
+    fn finish(&self, label: &str, top: usize) -> Value {
+        let mut groups: Vec<_> = self.groups.iter().collect();
+        groups.sort_unstable_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        let top_sources: Vec<_> = groups
+            .iter()
+            .map(|(_, group)| {
+                let mut sources: Vec<_> = group.sources.iter().collect();
+                sources.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                sources.truncate(top);
+                json!({
+                    "Source": sources.iter().map(|(source, _)| source.as_ref()).collect::<Vec<_>>(),
+                    "Count": sources.iter().map(|(_, count)| count).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        json!({
+            label: groups.iter().map(|(name, _)| name.as_ref()).collect::<Vec<_>>(),
+            "Count": groups.iter().map(|(_, group)| group.count).collect::<Vec<_>>(),
+            "Sources": groups.iter().map(|(_, group)| group.sources.len()).collect::<Vec<_>>(),
+            "Top Sources": top_sources
+        })
+    }
+
+    /// Returns the schema of the section `finish` returns for `label`.
+    fn schema(label: &str) -> Value {
+        let top_sources = columns(vec![("Source", string()), ("Count", count())]);
+        object(vec![
+            (label, array(string())),
+            ("Count", array(count())),
+            ("Sources", array(count())),
+            ("Top Sources", array(top_sources)),
+        ])
+    }
+
+    /// Scales the counts from a sample up to an estimate for the full input.
+    fn scale(&mut self, sample: &Sample) {
+        for group in self.groups.values_mut() {
+            scale_count(sample, &mut group.count);
+            group
+                .sources
+                .values_mut()
+                .for_each(|count| scale_count(sample, count));
+        }
+    }
+
+    /// Returns the approximate heap usage, in bytes.
+    fn memory(&self) -> usize {
+        map_memory(&self.groups)
+            + self
+                .groups
+                .values()
+                .map(|group| map_memory(&group.sources))
+                .sum::<usize>()
+    }
+
+    /// Drops the sources with a count of at most `threshold`, returning how many were
+    /// dropped.
+    fn prune(&mut self, threshold: u32) -> usize {
+        // Group totals are kept, so only the per-group source counts become approximate
+        self.groups
+            .values_mut()
+            .map(|group| {
+                let before = group.sources.len();
+                group.sources.retain(|_, count| *count > threshold);
+                group.sources.shrink_to_fit();
+                before - group.sources.len()
+            })
+            .sum()
+    }
+
+    /// Replaces every source with `anonymize(source)`, adding up the counts of sources
+    /// that become the same.
+    fn anonymize(&mut self, anonymize: &dyn Fn(&str) -> String) {
+        for group in self.groups.values_mut() {
+            let mut sources: HashMap<Arc<str>, u32> = HashMap::with_capacity(group.sources.len());
+            for (source, count) in group.sources.drain() {
+                *sources.entry(Arc::from(anonymize(&source))).or_default() += count;
+            }
+            group.sources = sources;
+        }
+    }
+}
+
+/// Counts events and their sources per network segment, so the perimeter, DMZ and
+/// internal segments can be compared.
+///
+/// The segment of an event is its zone, or else its interface, or else its VLAN, and
+/// `(unknown)` if it has none of them.
+///
+/// # Fields
+/// - `zones`: The events keyed by segment.
+#[derive(Default)]
+pub struct Zones {
+    zones: SourceGroups,
+}
+
+impl Zones {
+    /// Returns the segment of `event`.
+    fn zone_of<'a>(event: &Event<'a>) -> Cow<'a, str> {
+        let mapped = &event.mapped;
+        let trimmed = |field: Option<&'a str>| field.map(str::trim).filter(|value| !value.is_empty());
+        trimmed(mapped.zone)
+            .or_else(|| trimmed(mapped.interface))
+            .map(Cow::Borrowed)
+            .or_else(|| trimmed(mapped.vlan).map(|vlan| Cow::Owned(format!("VLAN {vlan}"))))
+            .unwrap_or(Cow::Borrowed(UNKNOWN_ZONE))
+    }
+}
+
+impl Metric for Zones {
+    fn name(&self) -> &'static str {
+        "Zones"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        self.zones.observe(event, &Self::zone_of(event));
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        self.zones.merge(&same::<Self>(other).zones);
+    }
+
+    fn state(&self) -> Value {
+        self.zones.state()
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        self.zones.merge_state(state)
+    }
+
+    fn finish(&self) -> Value {
+        self.zones.finish("Zone", TOP_ZONE_SOURCES)
+    }
+
+    fn schema(&self) -> Value {
+        SourceGroups::schema("Zone")
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.zones.scale(sample);
+    }
+
+    fn memory(&self) -> usize {
+        self.zones.memory()
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        self.zones.prune(threshold)
+    }
+
+    fn anonymize(&mut self, anonymize: &dyn Fn(&str) -> String) {
+        self.zones.anonymize(anonymize);
+    }
+}
+
+/// Parses TCP flags written as letters (`S`, `SA`, `FPU`, with `W` or `C` for CWR),
+/// names (`SYN ACK`, `RST`), or a hexadecimal (`0x12`) or decimal (`18`) number.
+///
+/// # Returns
+/// The flags as the bits of the TCP header, or `None` if `field` is not TCP flags.
+fn tcp_flags(field: &str) -> Option<u8> {
+    const NAMES: [(&str, u8); 8] = [
+        ("FIN", 0x01),
+        ("SYN", 0x02),
+        ("RST", 0x04),
+        ("PSH", 0x08),
+        ("ACK", 0x10),
+        ("URG", 0x20),
+        ("ECE", 0x40),
+        ("CWR", 0x80),
+    ];
+    let field = field.trim();
+    if let Some(hex) = field.strip_prefix("0x") {
+        return u8::from_str_radix(hex, 16).ok();
+    }
+    if field.bytes().all(|byte| byte.is_ascii_digit()) {
+        return field.parse().ok();
+    }
+    let words: Vec<_> = field
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let names = words.iter().try_fold(0, |flags, word| {
+        let (_, bit) = NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(word))?;
+        Some(flags | bit)
+    });
+    if names.is_some() {
+        return names;
+    }
+    words.concat().chars().try_fold(0, |flags, letter| {
+        let bit = match letter.to_ascii_uppercase() {
+            'F' => 0x01,
+            'S' => 0x02,
+            'R' => 0x04,
+            'P' => 0x08,
+            'A' => 0x10,
+            'U' => 0x20,
+            'E' => 0x40,
+            'W' | 'C' => 0x80,
+            _ => return None,
+        };
+        Some(flags | bit)
+    })
+}
+
+/// Returns the scan pattern of a packet with the TCP `flags`: `SYN` for a SYN scan or
+/// connection attempt, `SYN/ACK`, `RST`, `Xmas` (FIN, PSH and URG), `Null` (no flags),
+/// `FIN`, or `Other`. The ECN flags are ignored.
+const fn scan_pattern(flags: u8) -> &'static str {
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const SYN_ACK: u8 = SYN | 0x10;
+    const XMAS: u8 = FIN | 0x08 | 0x20;
+    match flags & 0x3f {
+        SYN => "SYN",
+        SYN_ACK => "SYN/ACK",
+        flags if flags & RST != 0 => "RST",
+        XMAS => "Xmas",
+        0 => "Null",
+        FIN => "FIN",
+        _ => "Other",
+    }
+}
+
+/// Counts TCP events and their sources per flag pattern, such as SYN-only or Xmas
+/// packets, to support scan detection.
+///
+/// Events are counted if they have TCP flags and, when the protocol is mapped, their
+/// protocol is TCP.
+///
+/// # Fields
+/// - `patterns`: The events keyed by pattern.
+#[derive(Default)]
+pub struct ScanPatterns {
+    patterns: SourceGroups,
+}
+
+impl Metric for ScanPatterns {
+    fn name(&self) -> &'static str {
+        "Scan Patterns"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let mapped = &event.mapped;
+        if mapped.protocol.map(str::trim).is_some_and(|protocol| {
+            protocol != "6" && !protocol.eq_ignore_ascii_case("tcp")
+        }) {
+            return;
+        }
+        if let Some(flags) = mapped.tcp_flags.and_then(tcp_flags) {
+            self.patterns.observe(event, scan_pattern(flags));
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        self.patterns.merge(&same::<Self>(other).patterns);
+    }
+
+    fn state(&self) -> Value {
+        self.patterns.state()
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        self.patterns.merge_state(state)
+    }
+
+    fn finish(&self) -> Value {
+        self.patterns.finish("Pattern", TOP_PATTERN_SOURCES)
+    }
+
+    fn schema(&self) -> Value {
+        SourceGroups::schema("Pattern")
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.patterns.scale(sample);
+    }
+
+    fn memory(&self) -> usize {
+        self.patterns.memory()
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        self.patterns.prune(threshold)
+    }
+
+    fn anonymize(&mut self, anonymize: &dyn Fn(&str) -> String) {
+        self.patterns.anonymize(anonymize);
+    }
+}
+
+/// Event counts keyed by strings of unbounded variety, such as URL paths, of which only
+/// the first `cap` keys are counted individually.
+///
+/// # Fields
+/// - `counts`: Event counts keyed by string.
+/// - `other`: The number of events with keys beyond the cap.
+/// - `cap`: The most keys counted individually.
+struct CappedCounts {
+    counts: HashMap<Arc<str>, u32>,
+    other: u32,
+    cap: usize,
+}
+
+impl CappedCounts {
+    /// Creates empty counts of at most `cap` keys.
+    fn new(cap: usize) -> Self {
+        Self {
+            counts: HashMap::new(),
+            other: 0,
+            cap,
+        }
+    }
+
+    /// Counts an event of `event` with `key`.
+    fn observe(&mut self, event: &Event, key: &str) {
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+        } else if self.counts.len() < self.cap {
+            self.counts.insert(event.intern(key), 1);
+        } else {
+            self.other += 1;
+        }
+    }
+
+    /// Moves the lowest counts into `other` until at most `cap` keys are left, after a
+    /// merge of counts that each kept to the cap.
+    fn enforce_cap(&mut self) {
+        let Some(excess) = self.counts.len().checked_sub(self.cap) else {
+            return;
+        };
+        let mut entries: Vec<_> = self.counts.drain().collect();
+        entries.sort_unstable_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+        self.other += entries[..excess].iter().map(|(_, count)| count).sum::<u32>();
+        self.counts.extend(entries.drain(excess..));
+    }
+
+    /// Adds the counts of `other`.
+    fn merge(&mut self, other: &Self) {
+        merge_counts(&mut self.counts, &other.counts);
+        self.other += other.other;
+        self.enforce_cap();
+    }
+
+    /// Returns the counts and the total of the others, for a checkpoint.
+    fn state(&self) -> Value {
+        json!({ "counts": counts_state(&self.counts), "other": self.other })
+    }
+
+    /// Adds a `state` returned by `state`.
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, &state["counts"])?;
+        self.other += state["other"]
+            .as_u64()
+            .and_then(|other| u32::try_from(other).ok())
+            .ok_or("expected a count of the other keys")?;
+        self.enforce_cap();
+        Ok(())
+    }
+
+    /// Returns the `key` and `Count` arrays of the keys with the highest counts, and the
+    /// total of the others.
+    fn finish(&self, key: &str) -> Value {
+        let mut top: Vec<_> = self.counts.iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        top.truncate(TOP_WEB_TARGETS);
+        json!({
+            key: top.iter().map(|(key, _)| key.as_ref()).collect::<Vec<_>>(),
+            "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>(),
+            "Other": self.other
+        })
+    }
+
+    /// Returns the schema of the section `finish` returns for `key`.
+    fn schema(key: &str) -> Value {
+        object(vec![
+            (key, array(string())),
+            ("Count", array(count())),
+            ("Other", count()),
+        ])
+    }
+
+    /// Scales the counts from a sample up to an estimate for the full input.
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .chain([&mut self.other])
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    /// Drops the keys with a count of at most `threshold`, returning how many were
+    /// dropped. Their events are no longer counted, as with the other metrics.
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.counts.len();
+        self.counts.retain(|_, count| *count > threshold);
+        self.counts.shrink_to_fit();
+        before - self.counts.len()
+    }
+}
+
+/// Counts events per targeted web host and URL path, to surface which web resources
+/// attacks go after.
+///
+/// The host is the TLS SNI, or else the host of the URL or the mapped hostname; the
+/// path is that of the URL, without its query string.
+///
+/// # Fields
+/// - `hosts`: Event counts keyed by host.
+/// - `paths`: Event counts keyed by URL path.
+pub struct WebTargets {
+    hosts: CappedCounts,
+    paths: CappedCounts,
+}
+
+impl WebTargets {
+    /// Creates empty counts within the limits of `config`.
+    #[must_use]
+    pub fn new(config: &WebTargetsConfig) -> Self {
+        Self {
+            hosts: CappedCounts::new(config.max_hosts),
+            paths: CappedCounts::new(config.max_paths),
+        }
+    }
+}
+
+impl Metric for WebTargets {
+    fn name(&self) -> &'static str {
+        "Web Targets"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let mapped = &event.mapped;
+        let host = mapped
+            .sni
+            .and_then(domain::hostname)
+            .or_else(|| mapped.url.and_then(domain::hostname))
+            .or_else(|| mapped.hostname.and_then(domain::hostname));
+        if let Some(host) = host {
+            self.hosts.observe(event, &host);
+        }
+        if let Some(path) = mapped.url.and_then(domain::url_path) {
+            self.paths.observe(event, path);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        let other = same::<Self>(other);
+        self.hosts.merge(&other.hosts);
+        self.paths.merge(&other.paths);
+    }
+
+    fn state(&self) -> Value {
+        json!({ "hosts": self.hosts.state(), "paths": self.paths.state() })
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        self.hosts.merge_state(&state["hosts"])?;
+        self.paths.merge_state(&state["paths"])
+    }
+
+    fn finish(&self) -> Value {
+        json!({
+            "Hosts": self.hosts.finish("Host"),
+            "Paths": self.paths.finish("Path")
+        })
+    }
+
+    fn schema(&self) -> Value {
+        object(vec![
+            ("Hosts", CappedCounts::schema("Host")),
+            ("Paths", CappedCounts::schema("Path")),
+        ])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.hosts.scale(sample);
+        self.paths.scale(sample);
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.hosts.counts) + map_memory(&self.paths.counts)
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        self.hosts.prune(threshold) + self.paths.prune(threshold)
+    }
+}
+
+/// Counts AWARE events per time bucket.
+///
+/// # Fields
+/// - `counts`: AWARE event counts keyed by date and morning or afternoon period.
+/// - `series`: The series derived from the counts.
+#[derive(Default)]
+pub struct AwareThreats {
+    counts: HashMap<String, u32>,
+    series: Series,
+}
+
+impl AwareThreats {
+    /// Creates an empty metric with the derived `series`.
+    #[must_use]
+    pub fn new(series: Series) -> Self {
+        Self {
+            counts: HashMap::new(),
+            series,
+        }
+    }
+
+    /// Returns the counts sorted by date.
+    #[must_use]
+    pub fn sorted(&self) -> Vec<(String, u32)> {
+        by_date(&self.counts)
+    }
+}
+
+impl Metric for AwareThreats {
+    fn name(&self) -> &'static str {
+        "AWARE Threats"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        if event.category.contains("AWARE") {
+            // Bucket by date and morning or afternoon period
+            *self.counts.entry(time_bucket(&event.datetime)).or_insert(0) += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.counts, &same::<Self>(other).counts);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.counts)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.counts, state)
+    }
+
+    fn finish(&self) -> Value {
+        let sorted = self.sorted();
+        let mut section = json!({
+            "Date": sorted.iter().map(|(date, _)| date).collect::<Vec<_>>(),
+            "Count": sorted.iter().map(|(_, count)| count).collect::<Vec<_>>()
+        });
+        self.series.add(&mut section, self.name(), &sorted);
+        section
+    }
+
+    fn schema(&self) -> Value {
+        series_schema("Count", &self.series)
+    }
+
+    fn series(&self) -> Vec<(String, Vec<(String, u64)>)> {
+        let counts = self.sorted();
+        let counts = counts.into_iter().map(|(bucket, count)| (bucket, count.into()));
+        vec![(self.name().to_string(), counts.collect())]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.counts
+            .values_mut()
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.counts)
+    }
+}
+
+/// Counts events per minute of each time bucket, for the event rate and the spread of
+/// the per-minute counts within the bucket.
+///
+/// # Fields
+/// - `minutes`: Event counts keyed by date and morning or afternoon period, and then by
+///   minute since the start of the bucket.
+#[derive(Default)]
+pub struct BucketRates {
+    minutes: HashMap<String, HashMap<u16, u32>>,
+}
+
+impl Metric for BucketRates {
+    fn name(&self) -> &'static str {
+        "Bucket Rates"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let minute = (event.datetime.hour() % 12) * 60 + event.datetime.minute();
+        let minute = u16::try_from(minute).unwrap_or_default();
+        *self
+            .minutes
+            .entry(time_bucket(&event.datetime))
+            .or_default()
+            .entry(minute)
+            .or_insert(0) += 1;
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        for (bucket, minutes) in &same::<Self>(other).minutes {
+            merge_counts(self.minutes.entry(bucket.clone()).or_default(), minutes);
+        }
+    }
+
+    fn state(&self) -> Value {
+        Value::Object(
+            self.minutes
+                .iter()
+                .map(|(bucket, minutes)| {
+                    let minutes = minutes
+                        .iter()
+                        .map(|(minute, &count)| (minute.to_string(), count.into()))
+                        .collect();
+                    (bucket.clone(), Value::Object(minutes))
+                })
+                .collect(),
+        )
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        let Value::Object(buckets) = state else {
+            return Err("expected an object of buckets".to_string());
+        };
+        for (bucket, minutes) in buckets {
+            let Value::Object(minutes) = minutes else {
+                return Err(format!("expected an object of minutes for `{bucket}`"));
+            };
+            let counts = self.minutes.entry(bucket.clone()).or_default();
+            for (minute, count) in minutes {
+                let minute = minute
+                    .parse()
+                    .map_err(|_| format!("invalid minute `{minute}` in `{bucket}`"))?;
+                let count = count
+                    .as_u64()
+                    .and_then(|count| u32::try_from(count).ok())
+                    .ok_or_else(|| format!("invalid count for `{bucket}` minute {minute}"))?;
+                *counts.entry(minute).or_default() += count;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Value {
+        let mut buckets: Vec<_> = self.minutes.iter().collect();
+        buckets.sort_by(|a, b| a.0.cmp(b.0));
+        let mut section = json!({
+            "Date": buckets.iter().map(|(bucket, _)| bucket).collect::<Vec<_>>(),
+        });
+        let (mut events, mut rates, mut p50, mut p95, mut max) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (_, minutes) in buckets {
+            // Minutes without events count as zero
+            let mut counts = vec![0; BUCKET_MINUTES];
+            for (&minute, &count) in minutes {
+                if let Some(slot) = counts.get_mut(usize::from(minute)) {
+                    *slot = count;
+                }
+            }
+            counts.sort_unstable();
+            let total: u64 = counts.iter().copied().map(u64::from).sum();
+            events.push(total);
+            #[allow(clippy::cast_precision_loss)]
+            rates.push(total as f64 / BUCKET_MINUTES as f64);
+            p50.push(percentile(&counts, 50));
+            p95.push(percentile(&counts, 95));
+            max.push(counts.last().copied().unwrap_or(0));
+        }
+        section["Events"] = json!(events);
+        section["Per Minute"] = json!(rates);
+        section["P50"] = json!(p50);
+        section["P95"] = json!(p95);
+        section["Max"] = json!(max);
+        section
+    }
+
+    fn schema(&self) -> Value {
+        columns(vec![
+            ("Date", string()),
+            ("Events", count()),
+            ("Per Minute", number()),
+            ("P50", count()),
+            ("P95", count()),
+            ("Max", count()),
+        ])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.minutes
+            .values_mut()
+            .flat_map(HashMap::values_mut)
+            .for_each(|count| scale_count(sample, count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.minutes)
+            + self
+                .minutes
+                .values()
+                .map(|minutes| minutes.capacity() * (mem::size_of::<(u16, u32)>() + 1))
+                .sum::<usize>()
+    }
+}
+
+/// A run of events from one source without a pause longer than the session gap.
+///
+/// # Fields
+/// - `start`: When the first event happened.
+/// - `end`: When the last event happened.
+/// - `count`: The number of events.
+#[derive(Clone, Copy)]
+struct Session {
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    count: u32,
+}
+
+/// Groups the events of each source into sessions, separated by pauses longer than
+/// the configured gap, to tell sustained attacks from one-off events.
+///
+/// Events may be observed in any order; each source keeps its sessions sorted by start,
+/// and a new event either extends a session or starts one, joining the sessions it
+/// bridges. Merging the sessions of two files the same way gives the same result as
+/// reading them as one.
+///
+/// # Fields
+/// - `gap`: The longest pause between two events of the same session.
+/// - `sessions`: The sessions of each source address, sorted by start.
+pub struct Sessions {
+    gap: TimeDelta,
+    sessions: HashMap<Arc<str>, Vec<Session>>,
+}
+
+impl Sessions {
+    /// Creates an empty metric that ends a session after `gap_minutes` without events.
+    fn new(gap_minutes: u32) -> Self {
+        Self {
+            gap: TimeDelta::minutes(i64::from(gap_minutes)),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Adds `session` to the sorted `sessions`, joining it with the sessions that are
+    /// no more than `gap` apart from it.
+    fn insert(sessions: &mut Vec<Session>, session: Session, gap: TimeDelta) {
+        let mut index = sessions.partition_point(|existing| existing.start <= session.start);
+        sessions.insert(index, session);
+        if index > 0 && sessions[index].start - sessions[index - 1].end <= gap {
+            index -= 1;
+            let joined = sessions.remove(index + 1);
+            Self::join(&mut sessions[index], &joined);
+        }
+        while index + 1 < sessions.len() && sessions[index + 1].start - sessions[index].end <= gap
+        {
+            let joined = sessions.remove(index + 1);
+            Self::join(&mut sessions[index], &joined);
+        }
+    }
+
+    /// Extends `session` by the events of `other`.
+    fn join(session: &mut Session, other: &Session) {
+        session.start = session.start.min(other.start);
+        session.end = session.end.max(other.end);
+        session.count = session.count.saturating_add(other.count);
+    }
+
+    /// Returns the `n` longest sessions with their sources, longest first, ties broken
+    /// by event count and then by source.
+    #[must_use]
+    fn longest(&self, n: usize) -> Vec<(&str, Session)> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .iter()
+            .flat_map(|(source, sessions)| sessions.iter().map(|session| (&**source, *session)))
+            .collect();
+        sessions.sort_by(|a, b| {
+            (b.1.end - b.1.start)
+                .cmp(&(a.1.end - a.1.start))
+                .then(b.1.count.cmp(&a.1.count))
+                .then(a.0.cmp(b.0))
+        });
+        sessions.truncate(n);
+        sessions
+    }
+}
+
+impl Metric for Sessions {
+    fn name(&self) -> &'static str {
+        "Sessions"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let session = Session {
+            start: event.datetime,
+            end: event.datetime,
+            count: 1,
+        };
+        if let Some(sessions) = self.sessions.get_mut(event.source) {
+            Self::insert(sessions, session, self.gap);
+        } else {
+            self.sessions
+                .insert(event.intern(event.source), vec![session]);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        for (source, sessions) in &same::<Self>(other).sessions {
+            let existing = self.sessions.entry(Arc::clone(source)).or_default();
+            for session in sessions {
+                Self::insert(existing, *session, self.gap);
+            }
+        }
+    }
+
+    fn state(&self) -> Value {
+        Value::Object(
+            self.sessions
+                .iter()
+                .map(|(source, sessions)| {
+                    let sessions = sessions
+                        .iter()
+                        .map(|session| {
+                            json!([
+                                session.start.and_utc().timestamp(),
+                                session.end.and_utc().timestamp(),
+                                session.count
+                            ])
+                        })
+                        .collect();
+                    (source.to_string(), Value::Array(sessions))
+                })
+                .collect(),
+        )
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        let Value::Object(sources) = state else {
+            return Err("expected an object of sessions".to_string());
+        };
+        for (source, sessions) in sources {
+            let sessions: Vec<(i64, i64, u32)> = serde_json::from_value(sessions.clone())
+                .map_err(|e| format!("invalid sessions for `{source}`: {e}"))?;
+            let existing = self.sessions.entry(Arc::from(source.as_str())).or_default();
+            for (start, end, count) in sessions {
+                let time = |seconds| {
+                    DateTime::from_timestamp(seconds, 0)
+                        .map(|time| time.naive_utc())
+                        .ok_or_else(|| format!("invalid session time for `{source}`"))
+                };
+                let session = Session {
+                    start: time(start)?,
+                    end: time(end)?,
+                    count,
+                };
+                Self::insert(existing, session, self.gap);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Value {
+        let durations: Vec<i64> = self
+            .sessions
+            .values()
+            .flatten()
+            .map(|session| (session.end - session.start).num_seconds())
+            .collect();
+        let single = self
+            .sessions
+            .values()
+            .flatten()
+            .filter(|session| session.count == 1)
+            .count();
+        let average = if durations.is_empty() {
+            0
+        } else {
+            durations.iter().sum::<i64>() / i64::try_from(durations.len()).unwrap_or(i64::MAX)
+        };
+        let longest = self.longest(TOP_SESSIONS);
+        let time = |time: NaiveDateTime| time.format("%Y-%m-%d %H:%M:%S").to_string();
+        json!({
+            "Sessions": durations.len(),
+            "Sources": self.sessions.len(),
+            "Single Event": single,
+            "Average Duration": average,
+            "Max Duration": durations.iter().max().copied().unwrap_or(0),
+            "Longest": {
+                "Source": longest.iter().map(|(source, _)| source).collect::<Vec<_>>(),
+                "Start": longest.iter().map(|(_, session)| time(session.start)).collect::<Vec<_>>(),
+                "End": longest.iter().map(|(_, session)| time(session.end)).collect::<Vec<_>>(),
+                "Duration": longest
+                    .iter()
+                    .map(|(_, session)| (session.end - session.start).num_seconds())
+                    .collect::<Vec<_>>(),
+                "Count": longest.iter().map(|(_, session)| session.count).collect::<Vec<_>>()
+            }
+        })
+    }
+
+    fn schema(&self) -> Value {
+        object(vec![
+            ("Sessions", count()),
+            ("Sources", count()),
+            ("Single Event", count()),
+            ("Average Duration", integer()),
+            ("Max Duration", integer()),
+            (
+                "Longest",
+                columns(vec![
+                    ("Source", string()),
+                    ("Start", string()),
+                    ("End", string()),
+                    ("Duration", integer()),
+                    ("Count", count()),
+                ]),
+            ),
+        ])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.sessions
+            .values_mut()
+            .flatten()
+            .for_each(|session| scale_count(sample, &mut session.count));
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.sessions)
+            + self
+                .sessions
+                .values()
+                .map(|sessions| sessions.capacity() * mem::size_of::<Session>())
+                .sum::<usize>()
+    }
+
+    fn prune(&mut self, threshold: u32) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, sessions| {
+            sessions.iter().map(|session| session.count).sum::<u32>() > threshold
+        });
+        self.sessions.shrink_to_fit();
+        before - self.sessions.len()
+    }
+
+    fn anonymize(&mut self, anonymize: &dyn Fn(&str) -> String) {
+        let mut anonymized: HashMap<Arc<str>, Vec<Session>> =
+            HashMap::with_capacity(self.sessions.len());
+        for (source, sessions) in self.sessions.drain() {
+            let existing = anonymized
+                .entry(Arc::from(anonymize(&source)))
+                .or_default();
+            for session in sessions {
+                Self::insert(existing, session, self.gap);
+            }
+        }
+        self.sessions = anonymized;
+    }
+}
+
+/// Returns the nearest-rank `p`th percentile of the ascending `sorted` values.
+fn percentile(sorted: &[u32], p: usize) -> u32 {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied().unwrap_or(0)
+}
+
+/// Sums configured priority weights per time bucket.
+///
+/// # Fields
+/// - `weights`: Weights keyed by raw priority value; unlisted priorities weigh 0.
+/// - `scores`: Summed weights keyed by date and morning or afternoon period.
+/// - `series`: The series derived from the counts.
+pub struct ThreatScore {
+    weights: HashMap<String, u32>,
+    scores: HashMap<String, u64>,
+    series: Series,
+}
+
+impl ThreatScore {
+    /// Creates an empty metric using the configured priority weights, with the derived
+    /// `series`.
+    fn new(config: &PriorityConfig, series: Series) -> Self {
+        Self {
+            weights: config.weights.clone(),
+            scores: HashMap::new(),
+            series,
+        }
+    }
+
+    /// Returns the scores sorted by date.
+    #[must_use]
+    pub fn sorted(&self) -> Vec<(String, u64)> {
+        by_date(&self.scores)
+    }
+}
+
+impl Metric for ThreatScore {
+    fn name(&self) -> &'static str {
+        "Threat Score"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let weight = self.weights.get(event.priority).copied().unwrap_or(0);
+        *self.scores.entry(time_bucket(&event.datetime)).or_insert(0) += u64::from(weight);
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        merge_counts(&mut self.scores, &same::<Self>(other).scores);
+    }
+
+    fn state(&self) -> Value {
+        counts_state(&self.scores)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        merge_counts_state(&mut self.scores, state)
+    }
+
+    fn finish(&self) -> Value {
+        let sorted = self.sorted();
+        let mut section = json!({
+            "Date": sorted.iter().map(|(date, _)| date).collect::<Vec<_>>(),
+            "Score": sorted.iter().map(|(_, score)| score).collect::<Vec<_>>()
+        });
+        self.series.add(&mut section, self.name(), &sorted);
+        section
+    }
+
+    fn schema(&self) -> Value {
+        series_schema("Score", &self.series)
+    }
+
+    fn series(&self) -> Vec<(String, Vec<(String, u64)>)> {
+        vec![(self.name().to_string(), self.sorted())]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        for score in self.scores.values_mut() {
+            *score = sample.scale(*score);
+        }
+    }
+
+    fn memory(&self) -> usize {
+        map_memory(&self.scores)
+    }
+}
+
+/// Counts the events matching the conditions of the `derived` configuration section,
+/// and evaluates its formulas over them.
+///
+/// # Fields
+/// - `definitions`: The counts and formulas, shared with the metrics of other files.
+/// - `total`: The number of events observed.
+/// - `counts`: The number of events matching each count's condition, in the order of
+///   `definitions.counts`.
+pub struct Derived {
+    definitions: DerivedConfig,
+    total: u64,
+    counts: Vec<u64>,
+}
+
+impl Derived {
+    /// Creates the metric for the counts and formulas of `definitions`.
+    #[must_use]
+    pub fn new(definitions: &DerivedConfig) -> Self {
+        Self {
+            definitions: DerivedConfig {
+                counts: Arc::clone(&definitions.counts),
+                metrics: Arc::clone(&definitions.metrics),
+            },
+            total: 0,
+            counts: vec![0; definitions.counts.len()],
+        }
+    }
+}
+
+impl Metric for Derived {
+    fn name(&self) -> &'static str {
+        "Derived Metrics"
+    }
+
+    fn observe(&mut self, event: &Event) {
+        self.total += 1;
+        if self.counts.is_empty() {
+            return;
+        }
+        let record = DerivedConfig::record([
+            event.priority,
+            event.signature,
+            event.category,
+            event.source,
+            event.destination,
+        ]);
+        for (count, definition) in self.counts.iter_mut().zip(self.definitions.counts.iter()) {
+            if definition.matches(&record) {
+                *count += 1;
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        let other = same::<Self>(other);
+        self.total += other.total;
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    fn state(&self) -> Value {
+        let counts: serde_json::Map<String, Value> = self
+            .definitions
+            .counts
+            .iter()
+            .zip(&self.counts)
+            .map(|(definition, &count)| (definition.name.clone(), count.into()))
+            .collect();
+        json!({ "total": self.total, "counts": counts })
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        let number = |value: Option<&Value>| {
+            value
+                .and_then(Value::as_u64)
+                .ok_or_else(|| "expected counts of derived metrics".to_string())
+        };
+        self.total += number(state.get("total"))?;
+        for (count, definition) in self.counts.iter_mut().zip(self.definitions.counts.iter()) {
+            *count += number(state.pointer(&format!("/counts/{}", definition.name)))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Value {
+        let count = |name: &str| {
+            if name == derived::TOTAL {
+                return self.total;
+            }
+            self.definitions
+                .counts
+                .iter()
+                .position(|definition| definition.name == name)
+                .map_or(0, |index| self.counts[index])
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let variable = |name: &str| count(name) as f64;
+        let mut names = Vec::new();
+        let mut values = Vec::new();
+        for (definition, &value) in self.definitions.counts.iter().zip(&self.counts) {
+            names.push(definition.name.as_str());
+            values.push(json!(value));
+        }
+        for (name, formula) in self.definitions.metrics.iter() {
+            names.push(name);
+            values.push(json!(formula.evaluate(&variable)));
+        }
+        json!({ "Metric": names, "Value": values })
+    }
+
+    fn schema(&self) -> Value {
+        // Formulas that divide by zero have no value
+        columns(vec![
+            ("Metric", string()),
+            ("Value", json!({ "type": ["number", "null"] })),
+        ])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn scale(&mut self, sample: &Sample) {
+        self.total = sample.scale(self.total);
+        for count in &mut self.counts {
+            *count = sample.scale(*count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Observes an event with each of `priorities` in `metric`, at `hour` on 1 May 2024.
+    fn observe(metric: &mut dyn Metric, priorities: &[&str], hour: u32) {
+        let interner = RefCell::new(Interner::default());
+        let datetime = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .and_then(|date| date.and_hms_opt(hour, 0, 0))
+            .unwrap_or_default();
+        for priority in priorities {
+            let event = Event::new(datetime, priority, "", "", "10.0.0.1", "10.0.0.2", &interner);
+            metric.observe(&event);
+        }
+    }
+
+    #[test]
+    fn integer_priorities_compare_numerically() {
+        assert_eq!(compare_priorities("2", "10"), Ordering::Less);
+        assert_eq!(compare_priorities("10", "2"), Ordering::Greater);
+        assert_eq!(compare_priorities("-1", "0"), Ordering::Less);
+        assert_eq!(compare_priorities("3", "3"), Ordering::Equal);
+    }
+
+    #[test]
+    fn other_priorities_compare_below_integers_and_lexically() {
+        assert_eq!(compare_priorities("high", "0"), Ordering::Less);
+        assert_eq!(compare_priorities("-5", "high"), Ordering::Greater);
+        assert_eq!(compare_priorities("high", "low"), Ordering::Less);
+        assert_eq!(compare_priorities("2.5", "2"), Ordering::Less);
+    }
+
+    #[test]
+    fn priorities_are_listed_from_highest_to_lowest() {
+        let config = PriorityConfig {
+            labels: HashMap::from([("10".to_string(), "Critical".to_string())]),
+            ..PriorityConfig::default()
+        };
+        let mut priorities = Priorities::new(&config);
+        observe(&mut priorities, &["10", "2", "high", "2", "low"], 6);
+
+        let sorted = priorities.sorted();
+        let order: Vec<&str> = sorted.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(order, ["Critical", "5", "4", "3", "2", "1", "0", "low", "high"]);
+        assert_eq!(sorted[4], ("2".to_string(), 2));
+    }
+
+    #[test]
+    fn priority_trend_lists_priorities_from_highest_to_lowest() {
+        let config = PriorityConfig {
+            values: vec!["1".to_string(), "2".to_string()],
+            ..PriorityConfig::default()
+        };
+        let mut trend = PriorityTrend::new(&config, Series::default());
+        observe(&mut trend, &["10", "high", "2"], 18);
+        observe(&mut trend, &["2", "2"], 6);
+
+        let series = trend.series();
+        let order: Vec<&str> = series.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(order, ["10", "2", "1", "high"]);
+        assert_eq!(
+            series[1].1,
+            [
+                ("2024-05-01 AM".to_string(), 2),
+                ("2024-05-01 PM".to_string(), 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Export of the top threat sources to MISP.
+//!
+//! With `--misp-url <url> --misp-key <file>`, every run creates a MISP event through the
+//! REST API (`POST <url>/events/add`) with one `ip-src` attribute per threat source that
+//! has at least `--misp-min-count` events. The event is tagged with the aggregation
+//! window, so the CTI team can tell the exports apart. The key file holds the MISP
+//! automation key.
+
+use crate::cli::Options;
+use crate::cutoff;
+use crate::http;
+use crate::manifest::read_key;
+use chrono::Local;
+use dashboard_aggregator::metric::Addresses;
+use dashboard_aggregator::AggregatedData;
+use serde_json::{json, Value};
+use std::io;
+
+/// MISP distribution level of the created events: your organisation only.
+const DISTRIBUTION: u8 = 0;
+
+/// MISP threat level of the created events: low.
+const THREAT_LEVEL: u8 = 3;
+
+/// MISP analysis state of the created events: completed.
+const ANALYSIS: u8 = 2;
+
+/// Creates a MISP event with the threat sources in `data`, if `--misp-url` is set.
+///
+/// # Returns
+/// The ID of the created event and the number of attributes, or `None` if MISP export is
+/// not configured or no source reaches the threshold.
+///
+/// # Errors
+/// Returns an error if the key cannot be read or the event cannot be created.
+pub fn push(options: &Options, data: &AggregatedData) -> io::Result<Option<(String, usize)>> {
+    let (Some(url), Some(key_path)) = (&options.misp_url, &options.misp_key) else {
+        return Ok(None);
+    };
+    let mut sources: Vec<(&str, u32)> = data
+        .metric::<Addresses>("Threat Sources")
+        .map(|metric| &metric.counts)
+        .into_iter()
+        .flatten()
+        .filter(|(_, &count)| count >= options.misp_min_count)
+        .map(|(source, &count)| (source.as_ref(), count))
+        .collect();
+    if sources.is_empty() {
+        return Ok(None);
+    }
+    sources.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let key = String::from_utf8(read_key(key_path)?).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("MISP key {} is not valid UTF-8", key_path.display()),
+        )
+    })?;
+    let event = event(options, &sources);
+    let response = http::post_json(
+        &format!("{}/events/add", url.trim_end_matches('/')),
+        &[format!("Authorization: {}", key.trim())],
+        &event,
+    )?;
+    let id = match &response["Event"]["id"] {
+        Value::String(id) => id.clone(),
+        id => id.to_string(),
+    };
+    Ok(Some((id, sources.len())))
+}
+
+/// Builds the MISP event document for `sources`, sorted by descending count.
+fn event(options: &Options, sources: &[(&str, u32)]) -> Value {
+    let now = Local::now();
+    let window = format!(
+        "{} to {}",
+        cutoff(options.days_back).format("%Y-%m-%d %H:%M"),
+        now.format("%Y-%m-%d %H:%M")
+    );
+    let attributes: Vec<Value> = sources
+        .iter()
+        .map(|(source, count)| {
+            json!({
+                "type": "ip-src",
+                "category": "Network activity",
+                "value": source,
+                "to_ids": false,
+                "comment": format!("{count} events from {window}")
+            })
+        })
+        .collect();
+    json!({
+        "Event": {
+            "info": format!("Dashboard aggregator threat sources, {window}"),
+            "date": now.format("%Y-%m-%d").to_string(),
+            "distribution": DISTRIBUTION,
+            "threat_level_id": THREAT_LEVEL,
+            "analysis": ANALYSIS,
+            "Attribute": attributes,
+            "Tag": [
+                { "name": format!("dashboard-aggregator:window=\"{window}\"") },
+                { "name": format!("dashboard-aggregator:days-back=\"{}\"", options.days_back) }
+            ]
+        }
+    })
+}
@@ -0,0 +1,45 @@
+//! Memory-mapped log files for `--mmap`.
+//!
+//! The crate denies unsafe code, and this module is its one exception: mapping a file is
+//! unsafe because the mapped bytes change, or become unreadable, if another process
+//! modifies or truncates the file while it is mapped. Log files are appended to and
+//! rotated by renaming, which keeps the mapped bytes intact, but a file truncated in
+//! place while it is read can crash the program with `SIGBUS`.
+#![allow(unsafe_code)]
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Maps the file at `path` into memory, read-only.
+///
+/// # Errors
+/// Returns an error if the file cannot be opened or mapped.
+pub fn map(path: &Path) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: The map is read-only and only read through shared slices. Changes to the
+    // file while it is mapped are the caller's concern; see the module documentation
+    unsafe { Mmap::map(&file) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn maps_the_file_contents() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("da-mmap-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("events.csv");
+        fs::write(&path, "Date/Time,Priority\n2024-01-01 00:00:00,1\n")?;
+        let empty = dir.join("empty.csv");
+        fs::write(&empty, "")?;
+
+        assert_eq!(&map(&path)?[..], b"Date/Time,Priority\n2024-01-01 00:00:00,1\n");
+        assert!(map(&empty)?.is_empty());
+        assert!(map(&dir.join("missing.csv")).is_err());
+        fs::remove_dir_all(&dir)
+    }
+}
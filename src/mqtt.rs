@@ -0,0 +1,255 @@
+//! MQTT publishing of the aggregates.
+//!
+//! With `--mqtt [user:password@]host:port`, the `events.json` document is published to
+//! `--topic` (default `dashboard/aggregates`) every time the outputs are written. With
+//! `--mqtt-per-section`, each section is published to its own subtopic instead, named
+//! after the section in lower case with underscores, e.g. `soc/aggregates/aware_threats`.
+//!
+//! Messages are published with `QoS` 1 and the retain flag, so the broker confirms every
+//! message and subscribers that connect later receive the latest aggregates at once.
+//! The client speaks MQTT 3.1.1 directly over TCP, without TLS, opening a new clean
+//! session for every publication.
+
+use crate::cli::Options;
+use dashboard_aggregator::AggregatedData;
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::time::Duration;
+
+/// Topic published to when `--topic` is not given.
+const DEFAULT_TOPIC: &str = "dashboard/aggregates";
+
+/// Time after which connecting to or waiting for the broker is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keep alive interval announced to the broker, in seconds.
+const KEEP_ALIVE_SECS: u16 = 60;
+
+/// MQTT 3.1.1 protocol level.
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// The largest remaining length of an MQTT packet.
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// First byte of a CONNECT packet.
+const CONNECT: u8 = 0x10;
+
+/// First byte of a CONNACK packet.
+const CONNACK: u8 = 0x20;
+
+/// First byte of a PUBLISH packet with `QoS` 1 and the retain flag.
+const PUBLISH_QOS1_RETAIN: u8 = 0x33;
+
+/// First byte of a PUBACK packet.
+const PUBACK: u8 = 0x40;
+
+/// First byte of a DISCONNECT packet.
+const DISCONNECT: u8 = 0xE0;
+
+/// The broker given to `--mqtt`.
+///
+/// # Fields
+/// - `address`: The host and port of the broker.
+/// - `credentials`: The user name and password to connect with, if any.
+struct Broker {
+    address: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Broker {
+    /// Parses `[user:password@]host:port`.
+    ///
+    /// # Errors
+    /// Returns a message describing the expected form if `broker` is not one.
+    fn parse(broker: &str) -> Result<Self, String> {
+        let invalid =
+            || format!("Invalid MQTT broker `{broker}`, expected [user:password@]host:port");
+        let (credentials, address) = match broker.rsplit_once('@') {
+            Some((credentials, address)) => {
+                let (user, password) = credentials.split_once(':').ok_or_else(invalid)?;
+                (Some((user.to_string(), password.to_string())), address)
+            }
+            None => (None, broker),
+        };
+        let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+        if host.is_empty() || port.parse::<u16>().is_err() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            address: address.to_string(),
+            credentials,
+        })
+    }
+}
+
+/// Parses the broker given to `--mqtt`, to reject a malformed one before processing.
+///
+/// # Errors
+/// Returns a message describing the expected form if `broker` is malformed.
+pub fn validate_broker(broker: &str) -> Result<(), String> {
+    Broker::parse(broker).map(drop)
+}
+
+/// Appends `text` as an MQTT string, prefixed with its length.
+fn string(packet: &mut Vec<u8>, text: &str) -> io::Result<()> {
+    let length = u16::try_from(text.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{text}` is too long for MQTT"),
+        )
+    })?;
+    packet.extend_from_slice(&length.to_be_bytes());
+    packet.extend_from_slice(text.as_bytes());
+    Ok(())
+}
+
+/// Returns the packet of type `first` with the variable header and payload `body`.
+///
+/// # Errors
+/// Returns an error if `body` is too large for an MQTT packet.
+fn packet(first: u8, body: &[u8]) -> io::Result<Vec<u8>> {
+    if body.len() > MAX_REMAINING_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes are too large for an MQTT message", body.len()),
+        ));
+    }
+    let mut packet = vec![first];
+    // The remaining length is encoded in 7-bit groups, least significant first
+    let mut length = body.len();
+    loop {
+        let mut byte = u8::try_from(length % 128).unwrap_or_default();
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    Ok(packet)
+}
+
+/// Reads a packet from `stream`, checking that it is of type `first` and has a body of
+/// `length` bytes.
+///
+/// # Errors
+/// Returns an error if the packet cannot be read or is not the expected one.
+fn expect(stream: &mut TcpStream, first: u8, length: usize) -> io::Result<Vec<u8>> {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header)?;
+    if header != [first, u8::try_from(length).unwrap_or(u8::MAX)] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected MQTT packet {:#04x} from the broker", header[0]),
+        ));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Returns the messages to publish for `data`: the whole `events.json` document, or
+/// each section on its own subtopic.
+fn messages(options: &Options, data: &AggregatedData) -> Vec<(String, Value)> {
+    let topic = options.mqtt_topic.as_deref().unwrap_or(DEFAULT_TOPIC);
+    let events = data.events_json();
+    match events {
+        Value::Object(sections) if options.mqtt_per_section => sections
+            .into_iter()
+            .map(|(name, section)| {
+                let name = name.to_lowercase().replace(' ', "_");
+                (format!("{topic}/{name}"), section)
+            })
+            .collect(),
+        events => vec![(topic.to_string(), events)],
+    }
+}
+
+/// Publishes the aggregates in `data` to the MQTT broker, if `--mqtt` is set.
+///
+/// # Errors
+/// Returns an error if the broker cannot be reached, refuses the connection, or does not
+/// acknowledge a message.
+pub fn publish(options: &Options, data: &AggregatedData) -> io::Result<()> {
+    let Some(broker) = &options.mqtt else {
+        return Ok(());
+    };
+    let broker = Broker::parse(broker)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    send(&broker, &messages(options, data)).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to publish to MQTT broker {}: {e}", broker.address),
+        )
+    })
+}
+
+/// Connects to `broker`, publishes `messages` and disconnects.
+///
+/// # Errors
+/// Returns an error if a packet cannot be sent or is not acknowledged.
+fn send(broker: &Broker, messages: &[(String, Value)]) -> io::Result<()> {
+    let address = broker
+        .address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut connect = Vec::new();
+    string(&mut connect, "MQTT")?;
+    connect.push(PROTOCOL_LEVEL);
+    // Clean session, with the user name and password flags if given
+    connect.push(if broker.credentials.is_some() {
+        0xC2
+    } else {
+        0x02
+    });
+    connect.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+    string(
+        &mut connect,
+        &format!("dashboard_aggregator-{}", process::id()),
+    )?;
+    if let Some((user, password)) = &broker.credentials {
+        string(&mut connect, user)?;
+        string(&mut connect, password)?;
+    }
+    stream.write_all(&packet(CONNECT, &connect)?)?;
+    let connack = expect(&mut stream, CONNACK, 2)?;
+    if connack[1] != 0 {
+        let reason = match connack[1] {
+            1 => "unacceptable protocol version",
+            2 => "client identifier rejected",
+            3 => "server unavailable",
+            4 => "bad user name or password",
+            5 => "not authorized",
+            _ => "unknown reason",
+        };
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("connection refused: {reason}"),
+        ));
+    }
+
+    for (id, (topic, payload)) in (1..=u16::MAX).zip(messages) {
+        let mut publish = Vec::new();
+        string(&mut publish, topic)?;
+        publish.extend_from_slice(&id.to_be_bytes());
+        publish.extend_from_slice(&serde_json::to_vec(payload)?);
+        stream.write_all(&packet(PUBLISH_QOS1_RETAIN, &publish)?)?;
+        if expect(&mut stream, PUBACK, 2)? != id.to_be_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the broker did not acknowledge {topic}"),
+            ));
+        }
+    }
+    stream.write_all(&packet(DISCONNECT, &[])?)
+}
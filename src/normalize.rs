@@ -0,0 +1,217 @@
+//! Normalization of the key fields before counting.
+//!
+//! Exports from different appliances spell the same value differently, e.g. with
+//! trailing whitespace, in another case, or with the port appended to an address, which
+//! splits its count across several rows. The `normalize` configuration section cleans
+//! the priority, signature, category, source and destination of every record before the
+//! filters and metrics see them.
+
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// A key field of the records.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Priority,
+    Signature,
+    Category,
+    Source,
+    Destination,
+}
+
+/// Normalization settings.
+///
+/// # Fields
+/// - `fields`: The fields that are normalized. Defaults to all of them.
+/// - `trim`: Whether leading and trailing whitespace is removed. Defaults to `true`.
+/// - `case_fold`: Whether values are lowercased. Defaults to `false`.
+/// - `strip_port`: Whether a port suffix is removed from addresses, as in `1.2.3.4:443`
+///   or `[2001:db8::1]:443`. Defaults to `false`.
+/// - `unknown`: Values that mean the field is unknown, such as `""`, `unknown` or `n/a`,
+///   compared case-insensitively after trimming. Defaults to none.
+/// - `sentinel`: The value that unknown values are replaced with. Defaults to
+///   `(unknown)`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NormalizeConfig {
+    pub fields: Vec<Field>,
+    pub trim: bool,
+    pub case_fold: bool,
+    pub strip_port: bool,
+    pub unknown: Vec<String>,
+    pub sentinel: String,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        Self {
+            fields: vec![
+                Field::Priority,
+                Field::Signature,
+                Field::Category,
+                Field::Source,
+                Field::Destination,
+            ],
+            trim: true,
+            case_fold: false,
+            strip_port: false,
+            unknown: Vec::new(),
+            sentinel: "(unknown)".to_string(),
+        }
+    }
+}
+
+impl NormalizeConfig {
+    /// Returns the normalized form of `value`, a value of `field`. Values that need no
+    /// change are borrowed rather than copied.
+    #[must_use]
+    pub fn apply<'a>(&'a self, field: Field, value: &'a str) -> Cow<'a, str> {
+        if !self.fields.contains(&field) {
+            return Cow::Borrowed(value);
+        }
+        let mut value = if self.trim { value.trim() } else { value };
+        if self.strip_port && matches!(field, Field::Source | Field::Destination) {
+            value = strip_port(value);
+        }
+        if self
+            .unknown
+            .iter()
+            .any(|unknown| unknown.trim().eq_ignore_ascii_case(value.trim()))
+        {
+            return Cow::Borrowed(&self.sentinel);
+        }
+        if self.case_fold && value.chars().any(char::is_uppercase) {
+            Cow::Owned(value.to_lowercase())
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+}
+
+/// Removes the port from an address like `1.2.3.4:443` or `[2001:db8::1]:443`. Bare
+/// IPv6 addresses, whose colons are not followed by a port, are returned unchanged.
+fn strip_port(address: &str) -> &str {
+    let is_port = |port: &str| !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit());
+    if let Some(rest) = address.strip_prefix('[') {
+        if let Some((address, port)) = rest.split_once("]:") {
+            if is_port(port) {
+                return address;
+            }
+        }
+        return rest.strip_suffix(']').unwrap_or(address);
+    }
+    match address.split_once(':') {
+        // A second colon makes it an IPv6 address
+        Some((host, port)) if is_port(port) => host,
+        _ => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> serde_json::Result<NormalizeConfig> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn defaults_only_trim() -> serde_json::Result<()> {
+        let config = parse("{}")?;
+        assert_eq!(
+            config.apply(Field::Source, " 10.0.0.1:443\t"),
+            "10.0.0.1:443"
+        );
+        assert_eq!(config.apply(Field::Signature, " ET SCAN "), "ET SCAN");
+        assert_eq!(config.apply(Field::Priority, "n/a"), "n/a");
+        assert!(matches!(
+            config.apply(Field::Category, "web"),
+            Cow::Borrowed("web")
+        ));
+        assert!(parse(r#"{"trim": true, "other": 1}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn only_the_listed_fields_are_normalized() -> serde_json::Result<()> {
+        let config = parse(r#"{"fields": ["signature", "destination"], "case_fold": true}"#)?;
+        assert_eq!(config.apply(Field::Signature, " ET SCAN "), "et scan");
+        assert_eq!(
+            config.apply(Field::Destination, "Host.Example"),
+            "host.example"
+        );
+        assert_eq!(config.apply(Field::Category, " Web "), " Web ");
+        assert_eq!(config.apply(Field::Source, " A "), " A ");
+        assert!(parse(r#"{"fields": ["zone"]}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn values_are_case_folded_and_left_untrimmed_as_configured() -> serde_json::Result<()> {
+        let config = parse(r#"{"trim": false, "case_fold": true}"#)?;
+        assert_eq!(config.apply(Field::Category, " Web "), " web ");
+        assert_eq!(config.apply(Field::Signature, "ÉTÉ"), "été");
+        // Lowercase values are not copied
+        assert!(matches!(
+            config.apply(Field::Category, "web"),
+            Cow::Borrowed("web")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ports_are_stripped_from_addresses_only() -> serde_json::Result<()> {
+        let config = parse(r#"{"strip_port": true}"#)?;
+        let cases = [
+            ("10.0.0.1:443", "10.0.0.1"),
+            (" 10.0.0.1:443 ", "10.0.0.1"),
+            ("[2001:db8::1]:443", "2001:db8::1"),
+            ("[2001:db8::1]", "2001:db8::1"),
+            ("2001:db8::1", "2001:db8::1"),
+            ("::1", "::1"),
+            ("10.0.0.1:", "10.0.0.1:"),
+            ("10.0.0.1:https", "10.0.0.1:https"),
+            ("[2001:db8::1]:https", "[2001:db8::1]:https"),
+            ("10.0.0.1", "10.0.0.1"),
+        ];
+        for (address, expected) in cases {
+            assert_eq!(config.apply(Field::Source, address), expected, "{address}");
+            assert_eq!(
+                config.apply(Field::Destination, address),
+                expected,
+                "{address}"
+            );
+        }
+        assert_eq!(config.apply(Field::Signature, "rule:42"), "rule:42");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_values_are_replaced_with_the_sentinel() -> serde_json::Result<()> {
+        let config = parse(r#"{"unknown": ["N/A", " unknown ", "-"], "case_fold": true}"#)?;
+        for value in ["n/a", " N/A ", "UNKNOWN", "-"] {
+            assert_eq!(config.apply(Field::Source, value), "(unknown)", "{value}");
+        }
+        assert_eq!(config.apply(Field::Source, "n/a/b"), "n/a/b");
+        let config = parse(r#"{"unknown": ["none"], "sentinel": "?", "strip_port": true}"#)?;
+        // Compared after the port is stripped
+        assert_eq!(config.apply(Field::Destination, "none:80"), "?");
+        Ok(())
+    }
+
+    #[test]
+    fn blank_values_are_emptied_unless_listed_as_unknown() -> serde_json::Result<()> {
+        // Left empty for the `empty_fields` policy to handle
+        let config = parse("{}")?;
+        assert_eq!(config.apply(Field::Priority, " \t "), "");
+        assert_eq!(config.apply(Field::Source, ""), "");
+        let config = parse(r#"{"trim": false}"#)?;
+        assert_eq!(config.apply(Field::Priority, " \t "), " \t ");
+        // An empty unknown value matches blank values, trimmed or not
+        let config = parse(r#"{"trim": false, "unknown": [""]}"#)?;
+        assert_eq!(config.apply(Field::Priority, " \t "), "(unknown)");
+        assert_eq!(config.apply(Field::Source, ""), "(unknown)");
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! Vendors of MAC addresses, from the organizationally unique identifier (OUI) the IEEE
+//! assigns to each manufacturer as the first bytes of its addresses.
+//!
+//! A small built-in table covers the virtualization platforms and single-board computers
+//! that most often turn up on internal networks. Deployments that need every vendor can
+//! point the `oui_file` setting at the IEEE registry (`oui.txt`) or at Wireshark's
+//! `manuf` file, whose longer MA-M and MA-S prefixes are also used.
+
+use std::collections::HashMap;
+
+/// Vendors known without an OUI file, by 24-bit prefix.
+const BUILTIN: &[(&str, &str)] = &[
+    ("00:05:69", "VMware"),
+    ("00:0c:29", "VMware"),
+    ("00:50:56", "VMware"),
+    ("00:15:5d", "Microsoft Hyper-V"),
+    ("00:16:3e", "Xen"),
+    ("00:1c:42", "Parallels"),
+    ("08:00:27", "VirtualBox"),
+    ("52:54:00", "QEMU/KVM"),
+    ("b8:27:eb", "Raspberry Pi"),
+    ("dc:a6:32", "Raspberry Pi"),
+    ("e4:5f:01", "Raspberry Pi"),
+];
+
+/// Vendor reported for addresses with the locally administered bit set, such as the
+/// randomized addresses of phones and the addresses of containers.
+const LOCALLY_ADMINISTERED: &str = "(locally administered)";
+
+/// A table of MAC address prefixes and their vendors.
+///
+/// # Fields
+/// - `prefixes`: Vendors keyed by prefix length in bits and the prefix, as the leading
+///   bits of the 48-bit address.
+#[derive(Debug, Default)]
+pub struct VendorTable {
+    prefixes: HashMap<(u32, u64), String>,
+}
+
+impl VendorTable {
+    /// Returns the built-in table of common vendors.
+    #[must_use]
+    pub fn builtin() -> Self {
+        let mut table = Self::default();
+        for (prefix, vendor) in BUILTIN {
+            if let Some(prefix) = parse_prefix(prefix) {
+                table.prefixes.insert(prefix, (*vendor).to_string());
+            }
+        }
+        table
+    }
+
+    /// Parses the IEEE `oui.txt` registry, whose entries are lines like
+    /// `00-50-56   (hex)  VMware, Inc.`, or Wireshark's `manuf` file, whose entries are
+    /// lines like `00:50:56  VMware  VMware, Inc.` with optional `/28` and `/36` prefix
+    /// lengths. Other lines are ignored.
+    #[must_use]
+    pub fn parse(contents: &str) -> Self {
+        let mut table = Self::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some(first) = line.split_whitespace().next() else {
+                continue;
+            };
+            let vendor = if let Some((_, vendor)) = line.split_once("(hex)") {
+                vendor.trim()
+            } else if first.contains(':') {
+                // The manuf file has a short name, then the full name if known, which
+                // older versions put in a comment
+                let names: Vec<_> = line.split('\t').skip(1).filter(|f| !f.is_empty()).collect();
+                names
+                    .last()
+                    .map_or("", |name| name.trim().trim_start_matches('#').trim())
+            } else {
+                continue;
+            };
+            let Some(prefix) = parse_prefix(first) else {
+                continue;
+            };
+            if !vendor.is_empty() {
+                table.prefixes.insert(prefix, vendor.to_string());
+            }
+        }
+        table
+    }
+
+    /// Returns the number of prefixes in the table.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    /// Returns whether the table has no prefixes.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// Returns the vendor of `mac`, an address as returned by `normalize`, preferring
+    /// the longest matching prefix.
+    #[must_use]
+    pub fn vendor(&self, mac: &str) -> Option<&str> {
+        let address = u64::from_str_radix(&mac.replace(':', ""), 16).ok()?;
+        [36, 28, 24]
+            .into_iter()
+            .find_map(|bits| self.prefixes.get(&(bits, address >> (48 - bits))))
+            .map(String::as_str)
+            .or_else(|| ((address >> 40) & 0x02 != 0).then_some(LOCALLY_ADMINISTERED))
+    }
+}
+
+/// Parses an OUI prefix such as `00-50-56`, `00:50:56` or `00:1B:C5:00:00:00/36` into
+/// its length in bits and value.
+fn parse_prefix(field: &str) -> Option<(u32, u64)> {
+    let (hex, bits) = field.split_once('/').unwrap_or((field, ""));
+    let digits: String = hex.split([':', '-']).collect();
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u64::from_str_radix(&digits, 16).ok()?;
+    let length = u32::try_from(digits.len()).ok()? * 4;
+    if bits.is_empty() {
+        return (length == 24).then_some((24, value));
+    }
+    let bits: u32 = bits.parse().ok()?;
+    (matches!(bits, 24 | 28 | 36) && length <= 48 && bits <= length)
+        .then(|| (bits, value >> (length - bits)))
+}
+
+/// Normalizes a MAC address written as `AA:BB:CC:DD:EE:FF`, `aa-bb-cc-dd-ee-ff`,
+/// `aabb.ccdd.eeff` or `aabbccddeeff` to lowercase colon-separated form.
+///
+/// # Returns
+/// The normalized address, or `None` if `field` is not a MAC address.
+#[must_use]
+pub fn normalize(field: &str) -> Option<String> {
+    let digits: String = field
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    if digits.len() != 12 || !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits = digits.to_lowercase();
+    let pairs: Vec<_> = (0..12).step_by(2).map(|i| &digits[i..i + 2]).collect();
+    Some(pairs.join(":"))
+}
@@ -0,0 +1,386 @@
+//! Writers for the JSON output files.
+//!
+//! The outputs are serialized straight into a buffered file, optionally through a gzip
+//! or zstd stream (see `--compress-output`), so that the serialized JSON never has to be held in
+//! memory as a whole.
+//!
+//! With `--encrypt-to`, the (compressed) bytes are encrypted with age to the given
+//! recipients as they are written, so the plaintext never reaches the disk.
+//!
+//! Either way, the file is written under a temporary name next to it and only renamed
+//! into place once complete, so a run that is killed while writing leaves the outputs
+//! of the previous run rather than truncated JSON. The signed manifest is written the
+//! same way with `write_file`.
+
+use crate::cli::{Compression, Options};
+use age::stream::StreamWriter;
+use age::x25519::Recipient;
+use age::Encryptor;
+use flate2::write::GzEncoder;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Directory of the output directory that `--split-output` writes the sections to.
+pub const SECTIONS_DIR: &str = "sections";
+
+/// Where the bytes of an output file go after compression.
+///
+/// # Fields
+/// - `writer`: The file the bytes are written to, encrypted or not.
+/// - `path`: Where the file is moved once complete.
+pub struct Sink {
+    writer: Writer,
+    path: PathBuf,
+}
+
+/// What the bytes of an output file are written to.
+enum Writer {
+    /// Written to the file as-is.
+    File(BufWriter<File>),
+    /// Encrypted with age into the file.
+    Age(StreamWriter<BufWriter<File>>),
+}
+
+/// Returns the temporary path the output file at `path` is written to until complete.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or(path.as_os_str()));
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+impl Sink {
+    /// Creates the file at `path`, encrypted to `recipients` unless there are none.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created.
+    fn create(path: &Path, recipients: &[Recipient]) -> io::Result<Self> {
+        Ok(Self {
+            writer: Writer::create(&temporary_path(path), recipients)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Completes the file and moves it into place.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written or moved.
+    fn finish(self) -> io::Result<()> {
+        let temporary = temporary_path(&self.path);
+        if let Err(e) = self.writer.finish() {
+            // Ignore the error of removing it, the error of writing it is more useful
+            let _ = fs::remove_file(&temporary);
+            return Err(e);
+        }
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.writer.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Writer {
+    /// Creates the file at `path`, encrypted to `recipients` unless there are none.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created.
+    fn create(path: &Path, recipients: &[Recipient]) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        if recipients.is_empty() {
+            return Ok(Self::File(file));
+        }
+        let encryptor =
+            Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as _))
+                .map_err(io::Error::other)?;
+        Ok(Self::Age(encryptor.wrap_output(file)?))
+    }
+
+    /// Completes the file, writing the final encrypted chunk if encrypting.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::File(mut file) => file.flush(),
+            Self::Age(age) => age.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(data),
+            Self::Age(age) => age.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Age(age) => age.flush(),
+        }
+    }
+}
+
+/// An output file being written, compressed or not.
+pub enum Output {
+    Plain(Sink),
+    Gzip(GzEncoder<Sink>),
+    Zstd(zstd::Encoder<'static, Sink>),
+}
+
+impl Output {
+    /// Creates the output file at `path`, compressed with `compression` if given, and
+    /// encrypted to `recipients` if there are any.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created.
+    pub fn create(
+        path: &Path,
+        compression: Option<Compression>,
+        recipients: &[Recipient],
+    ) -> io::Result<Self> {
+        let sink = Sink::create(path, recipients)?;
+        Ok(match compression {
+            None => Self::Plain(sink),
+            Some(Compression::Gzip) => {
+                Self::Gzip(GzEncoder::new(sink, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => Self::Zstd(zstd::Encoder::new(sink, 0)?),
+        })
+    }
+
+    /// Completes the file, writing out anything that is still buffered.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Plain(sink) => sink.finish(),
+            Self::Gzip(gzip) => gzip.finish()?.finish(),
+            Self::Zstd(zstd) => zstd.finish()?.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(sink) => sink.write(data),
+            Self::Gzip(gzip) => gzip.write(data),
+            Self::Zstd(zstd) => zstd.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(sink) => sink.flush(),
+            Self::Gzip(gzip) => gzip.flush(),
+            Self::Zstd(zstd) => zstd.flush(),
+        }
+    }
+}
+
+/// Writes `contents` to the file at `path` under a temporary name, which is renamed into
+/// place once complete, like the JSON outputs.
+///
+/// # Errors
+/// Returns an error if the file cannot be written or moved.
+pub fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut sink = Sink::create(path, &[])?;
+    sink.write_all(contents)?;
+    sink.finish()
+}
+
+/// Writes `value` as pretty-printed JSON to the output file called `name`, compressed and
+/// encrypted as configured in `options`.
+///
+/// # Errors
+/// Returns an error if the file cannot be written.
+pub fn write_json<T: Serialize>(options: &Options, name: &str, value: &T) -> io::Result<()> {
+    let mut output = Output::create(
+        &options.output_path(name),
+        options.compress,
+        &options.encrypt_to,
+    )?;
+    serde_json::to_writer_pretty(&mut output, value)?;
+    output.finish()
+}
+
+/// Writes each of `sections` to its own file in the sections directory of the output
+/// directory, named after the section in lowercase with other characters than letters
+/// and digits replaced by `_`, such as `sections/aware_threats.json` for `AWARE Threats`.
+///
+/// # Returns
+/// The paths of the files written.
+///
+/// # Errors
+/// Returns an error if the directory cannot be created or a file cannot be written.
+pub fn write_sections(options: &Options, sections: &Map<String, Value>) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(options.output_dir_path(SECTIONS_DIR))?;
+    sections
+        .iter()
+        .map(|(name, section)| {
+            let file: String = name
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let name = format!("{SECTIONS_DIR}/{file}.json");
+            write_json(options, &name, section)?;
+            Ok(options.output_path(&name))
+        })
+        .collect()
+}
+
+/// A JSON object written with the keys of `first` first, in that order, followed by its
+/// other keys in sorted order.
+///
+/// # Fields
+/// - `object`: The object to write.
+/// - `first`: The keys written first; keys that are not in `object` are skipped.
+pub struct Ordered<'a> {
+    pub object: &'a Map<String, Value>,
+    pub first: &'a [String],
+}
+
+impl Serialize for Ordered<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.object.len()))?;
+        for key in self.first {
+            if let Some(value) = self.object.get(key) {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        for (key, value) in self.object {
+            if !self.first.contains(key) {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// Checks that the output directory and the directories of the report files exist and
+/// are writable, by creating and removing a file in each. A read-only or missing volume
+/// then fails the program at startup rather than after the logs were read.
+///
+/// # Errors
+/// Returns an error naming the first directory that cannot be written to.
+pub fn check_writable(options: &Options) -> io::Result<()> {
+    let files = [
+        &options.template_output,
+        &options.xlsx,
+        &options.stix,
+        &options.geojson,
+        &options.audit_log,
+        &options.history,
+        &options.baseline,
+        &options.error_report,
+    ];
+    let directories = iter::once(options.output_dir.clone().unwrap_or_default()).chain(
+        files
+            .into_iter()
+            .flatten()
+            .map(|path| path.parent().map(Path::to_path_buf).unwrap_or_default()),
+    );
+    let mut checked = Vec::new();
+    for directory in directories {
+        let directory = if directory.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            directory
+        };
+        if checked.contains(&directory) {
+            continue;
+        }
+        let probe = directory.join(format!(".dashboard_aggregator-{}.tmp", process::id()));
+        File::create(&probe)
+            .and_then(|_| fs::remove_file(&probe))
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{} is not writable: {e}", directory.display()),
+                )
+            })?;
+        checked.push(directory);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn round_trip(compression: Compression) -> io::Result<Vec<u8>> {
+        let dir = std::env::temp_dir().join(format!("da-output-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("events.json{}", compression.extension()));
+        let mut output = Output::create(&path, Some(compression), &[])?;
+        output.write_all(b"{\"events\":[]}")?;
+        output.finish()?;
+        let compressed = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        Ok(compressed)
+    }
+
+    #[test]
+    fn gzip_output_round_trips() -> io::Result<()> {
+        let compressed = round_trip(Compression::Gzip)?;
+        let mut contents = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut contents)?;
+        assert_eq!(contents, "{\"events\":[]}");
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_output_decrypts_with_the_identity() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("da-output-age-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("events.json.age");
+        let identity = age::x25519::Identity::generate();
+        let mut output = Output::create(&path, None, &[identity.to_public()])?;
+        output.write_all(b"{\"events\":[]}")?;
+        output.finish()?;
+
+        let encrypted = fs::read(&path)?;
+        assert!(encrypted.starts_with(b"age-encryption.org/v1"));
+        let decryptor = age::Decryptor::new(encrypted.as_slice())?;
+        let mut contents = String::new();
+        decryptor
+            .decrypt(iter::once(&identity as &dyn age::Identity))?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "{\"events\":[]}");
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn zstd_output_round_trips() -> io::Result<()> {
+        let compressed = round_trip(Compression::Zstd)?;
+        assert_eq!(zstd::decode_all(compressed.as_slice())?, b"{\"events\":[]}");
+        Ok(())
+    }
+}
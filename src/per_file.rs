@@ -0,0 +1,68 @@
+//! Per-file breakdown of the aggregates.
+//!
+//! With `--per-file`, `events.json` gains a `Per File` section that maps the name of
+//! every log file read to its own sections, so a spike can be traced to the appliance or
+//! rotation that produced it. Next to the sections of the enabled metrics, each file
+//! lists the records it contributed (`Events`), the records that could not be parsed
+//! (`Parse Errors`) and the records with an empty key (`Empty Records`). Files that
+//! could not be read list the `Error` instead.
+
+use crate::anonymize::Anonymizer;
+use dashboard_aggregator::AggregatedData;
+use serde_json::{json, Map, Value};
+use std::io;
+use std::path::Path;
+
+/// The per-file sections collected so far.
+///
+/// # Fields
+/// - `anonymizer`: Applied to the addresses of each file, like to the totals.
+/// - `files`: The sections of each file, by file name.
+pub struct Breakdown<'a> {
+    anonymizer: Option<&'a Anonymizer>,
+    files: Map<String, Value>,
+}
+
+impl<'a> Breakdown<'a> {
+    /// Creates an empty breakdown, anonymizing addresses with `anonymizer` if given.
+    pub fn new(anonymizer: Option<&'a Anonymizer>) -> Self {
+        Self {
+            anonymizer,
+            files: Map::new(),
+        }
+    }
+
+    /// Adds the aggregates of the file at `path`.
+    pub fn add(&mut self, path: &Path, mut data: AggregatedData) {
+        if let Some(anonymizer) = self.anonymizer {
+            anonymizer.apply(&mut data);
+        }
+        let mut sections = data.events_json();
+        if let Value::Object(sections) = &mut sections {
+            sections.insert("Events".to_string(), json!(data.records()));
+            sections.insert("Parse Errors".to_string(), json!(data.parse_errors()));
+            sections.insert("Empty Records".to_string(), json!(data.empty_records()));
+        }
+        self.files.insert(name(path), sections);
+    }
+
+    /// Adds the file at `path`, which could not be read because of `error`.
+    pub fn add_error(&mut self, path: &Path, error: &io::Error) {
+        self.files
+            .insert(name(path), json!({ "Error": error.to_string() }));
+    }
+
+    /// Returns the `Per File` section.
+    pub fn into_json(self) -> Value {
+        Value::Object(self.files)
+    }
+}
+
+/// Returns the name `path` is listed under: its file name, as the log files are all in
+/// the same directory.
+fn name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
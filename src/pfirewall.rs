@@ -0,0 +1,169 @@
+//! Parser for the `pfirewall.log` files of Windows Defender Firewall.
+//!
+//! The files are in a W3C-style format: comment lines starting with `#`, of which
+//! `#Fields:` names the space-separated columns, followed by one line per packet:
+//!
+//! ```text
+//! #Fields: date time action protocol src-ip dst-ip src-port dst-port size tcpflags tcpsyn tcpack tcpwin icmptype icmpcode info path
+//! 2026-10-16 09:30:01 DROP TCP 203.0.113.5 192.168.1.10 51234 3389 52 S 12345 0 64240 - - - RECEIVE
+//! ```
+//!
+//! Missing values are written as `-`. Times are local, as the files are written by
+//! default.
+
+use crate::input::{LineParser, Normalized};
+use chrono::NaiveDateTime;
+
+/// The columns written by Windows, used until a `#Fields:` line names them.
+const DEFAULT_FIELDS: [&str; 17] = [
+    "date", "time", "action", "protocol", "src-ip", "dst-ip", "src-port", "dst-port", "size",
+    "tcpflags", "tcpsyn", "tcpack", "tcpwin", "icmptype", "icmpcode", "info", "path",
+];
+
+/// Priority of dropped packets.
+const DROPPED_PRIORITY: &str = "1";
+
+/// Priority of allowed packets.
+const ALLOWED_PRIORITY: &str = "5";
+
+/// Parses `pfirewall.log` lines, keeping track of the column names.
+///
+/// # Fields
+/// - `fields`: The column names from the last `#Fields:` line, or `DEFAULT_FIELDS`
+///   before one.
+pub struct Parser {
+    fields: Vec<String>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            fields: DEFAULT_FIELDS.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl LineParser for Parser {
+    /// Parses a `pfirewall.log` line. Dropped packets get priority 1 and allowed ones
+    /// priority 5, and the signature is the action, protocol and path, e.g.
+    /// `DROP TCP RECEIVE`.
+    fn parse(&mut self, line: &str) -> Option<Normalized> {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(fields) = comment.strip_prefix("Fields:") {
+                self.fields = fields.split_whitespace().map(str::to_string).collect();
+            }
+            return None;
+        }
+
+        let values: Vec<&str> = line.split_whitespace().collect();
+        let field = |name: &str| {
+            let position = self.fields.iter().position(|field| field == name)?;
+            values.get(position).copied().filter(|value| *value != "-")
+        };
+        let value = |name: &str| field(name).unwrap_or_default().to_string();
+
+        let datetime = NaiveDateTime::parse_from_str(
+            &format!("{} {}", field("date")?, field("time")?),
+            "%Y-%m-%d %H:%M:%S",
+        )
+        .ok()?;
+        let (action, protocol, path) = (value("action"), value("protocol"), value("path"));
+        Some(Normalized {
+            id: String::new(),
+            priority: if action == "ALLOW" {
+                ALLOWED_PRIORITY
+            } else {
+                DROPPED_PRIORITY
+            }
+            .to_string(),
+            signature: format!("{action} {protocol} {path}"),
+            category: "pfirewall",
+            datetime,
+            proto: protocol.to_ascii_lowercase(),
+            source: field("src-ip")?.to_string(),
+            src_port: value("src-port"),
+            interface: String::new(),
+            action: action.to_ascii_lowercase(),
+            direction: path.to_ascii_lowercase(),
+            reason: value("info"),
+            destination: field("dst-ip")?.to_string(),
+            dst_port: value("dst-port"),
+            bytes: value("size"),
+            icmp_type: value("icmptype"),
+            icmp_code: value("icmpcode"),
+            tcp_flags: value("tcpflags"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DROP: &str = "2026-10-16 09:30:01 DROP TCP 203.0.113.5 192.168.1.10 51234 3389 52 \
+                        S 12345 0 64240 - - - RECEIVE";
+
+    #[test]
+    fn drop_and_allow_rows_are_parsed() -> Result<(), &'static str> {
+        let mut parser = Parser::default();
+        let event = parser.parse(DROP).ok_or("DROP row not parsed")?;
+        assert_eq!(event.priority, DROPPED_PRIORITY);
+        assert_eq!(event.signature, "DROP TCP RECEIVE");
+        assert_eq!(event.datetime.to_string(), "2026-10-16 09:30:01");
+        assert_eq!(
+            [&event.proto, &event.source, &event.src_port, &event.destination, &event.dst_port],
+            ["tcp", "203.0.113.5", "51234", "192.168.1.10", "3389"]
+        );
+        assert_eq!([&event.action, &event.direction], ["drop", "receive"]);
+        assert_eq!([&event.bytes, &event.tcp_flags], ["52", "S"]);
+        // `-` is a missing value
+        assert_eq!([&event.icmp_type, &event.reason], ["", ""]);
+
+        let event = parser
+            .parse("2026-10-16 09:31:00 ALLOW ICMP 192.168.1.10 8.8.8.8 - - 60 - - - - 8 0 - SEND")
+            .ok_or("ALLOW row not parsed")?;
+        assert_eq!(event.priority, ALLOWED_PRIORITY);
+        assert_eq!(event.signature, "ALLOW ICMP SEND");
+        assert_eq!([&event.icmp_type, &event.icmp_code], ["8", "0"]);
+        assert_eq!([&event.src_port, &event.dst_port], ["", ""]);
+        Ok(())
+    }
+
+    #[test]
+    fn fields_headers_set_the_columns() -> Result<(), &'static str> {
+        let mut parser = Parser::default();
+        for comment in ["#Version: 1.5", "#Software: Microsoft Windows Firewall"] {
+            assert!(parser.parse(comment).is_none());
+        }
+        // Columns in another order, some of them missing
+        assert!(parser
+            .parse("#Fields: date time src-ip dst-ip action protocol path")
+            .is_none());
+        let event = parser
+            .parse("2026-10-16 09:30:01 203.0.113.5 192.168.1.10 DROP UDP RECEIVE")
+            .ok_or("row not parsed")?;
+        assert_eq!([&event.source, &event.destination], ["203.0.113.5", "192.168.1.10"]);
+        assert_eq!(event.signature, "DROP UDP RECEIVE");
+        assert_eq!([&event.src_port, &event.bytes], ["", ""]);
+        // The last header applies
+        parser.parse("#Fields: date time action protocol src-ip dst-ip");
+        let event = parser.parse(DROP).ok_or("row not parsed")?;
+        assert_eq!([&event.source, &event.src_port], ["203.0.113.5", ""]);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_rows_are_skipped() {
+        let mut parser = Parser::default();
+        for line in [
+            "",
+            "2026-10-16 DROP TCP",
+            "16/10/2026 09:30:01 DROP TCP 203.0.113.5 192.168.1.10 51234 3389",
+            // No addresses
+            "2026-10-16 09:30:01 DROP TCP - -",
+        ] {
+            assert!(parser.parse(line).is_none(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Parser for the pfSense `filterlog` syslog format.
+//!
+//! Every packet matched by a logging firewall rule produces a line like
+//!
+//! ```text
+//! Oct 16 09:30:01 fw filterlog[4242]: 5,,,1000000103,igb1,match,block,in,4,0x0,,64,0,0,DF,6,tcp,60,203.0.113.5,192.168.1.10,51234,22,0,S,123456,,64240,,mss
+//! ```
+//!
+//! (or with an RFC 5424 header on pfSense 2.5 and later). The comma-separated message
+//! starts with the rule and packet fields, followed by IP fields whose layout depends on
+//! the IP version, and ports for TCP and UDP.
+
+use crate::input::{syslog_header, Normalized};
+use chrono::NaiveDateTime;
+
+/// Name of the program logging the firewall events.
+const PROGRAM: &str = "filterlog";
+
+/// Priority of blocked and rejected packets.
+const BLOCKED_PRIORITY: &str = "1";
+
+/// Priority of passed packets.
+const PASSED_PRIORITY: &str = "5";
+
+/// Returns the `filterlog` message of the syslog line `rest` (after the timestamp), or
+/// `None` if the line was logged by another program.
+fn message(rest: &str) -> Option<&str> {
+    let (_, after) = rest.split_once(PROGRAM)?;
+    if after.starts_with(['[', ':']) {
+        // RFC 3164: `filterlog[pid]: message`
+        after.split_once(": ").map(|(_, message)| message)
+    } else {
+        // RFC 5424: `filterlog pid msgid structured-data message`
+        after.trim_start().splitn(4, ' ').nth(3)
+    }
+}
+
+/// Parses a `filterlog` line, returning `None` if it is not one or is malformed.
+///
+/// Blocked and rejected packets get priority 1 and passed ones priority 5. The signature
+/// names the action, direction, interface and rule, e.g. `block in on igb1 (rule 5)`.
+pub fn parse(line: &str, now: NaiveDateTime) -> Option<Normalized> {
+    let (datetime, rest) = syslog_header(line, now)?;
+    let fields: Vec<&str> = message(rest)?.trim_end().split(',').collect();
+    let field = |index: usize| fields.get(index).copied().unwrap_or_default();
+
+    let (rule, tracker, interface, reason, action, direction) =
+        (field(0), field(3), field(4), field(5), field(6), field(7));
+    // The position of the protocol name and addresses depends on the IP version
+    let (proto, source, destination, ports) = match field(8) {
+        "4" => (field(16), field(18), field(19), 20),
+        "6" => (field(12), field(15), field(16), 17),
+        _ => return None,
+    };
+    if source.is_empty() || destination.is_empty() {
+        return None;
+    }
+    let (src_port, dst_port) = if matches!(proto, "tcp" | "udp") {
+        (field(ports), field(ports + 1))
+    } else {
+        ("", "")
+    };
+    // TCP flags follow the ports and data length, e.g. `S` or `FPA`
+    let tcp_flags = if proto == "tcp" { field(ports + 3) } else { "" };
+    // ICMP messages are logged with the name of their type, e.g. `request`, instead
+    let icmp_type = if matches!(proto, "icmp" | "ipv6-icmp") {
+        field(ports)
+    } else {
+        ""
+    };
+
+    Some(Normalized {
+        id: tracker.to_string(),
+        priority: if action == "pass" {
+            PASSED_PRIORITY
+        } else {
+            BLOCKED_PRIORITY
+        }
+        .to_string(),
+        signature: format!("{action} {direction} on {interface} (rule {rule})"),
+        category: PROGRAM,
+        datetime,
+        proto: proto.to_string(),
+        source: source.to_string(),
+        src_port: src_port.to_string(),
+        interface: interface.to_string(),
+        action: action.to_string(),
+        direction: direction.to_string(),
+        reason: reason.to_string(),
+        destination: destination.to_string(),
+        dst_port: dst_port.to_string(),
+        bytes: String::new(),
+        icmp_type: icmp_type.to_string(),
+        icmp_code: String::new(),
+        tcp_flags: tcp_flags.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// Parses `line` at noon on the day of the example lines.
+    fn parse_line(line: &str) -> Result<Normalized, String> {
+        let now = NaiveDate::from_ymd_opt(2026, 10, 16)
+            .and_then(|date| date.and_hms_opt(12, 0, 0))
+            .unwrap_or_default();
+        parse(line, now).ok_or_else(|| format!("not parsed: {line}"))
+    }
+
+    #[test]
+    fn ipv4_lines_are_parsed() -> Result<(), String> {
+        let event = parse_line(
+            "Oct 16 09:30:01 fw filterlog[4242]: 5,,,1000000103,igb1,match,block,in,4,0x0,,64,\
+             0,0,DF,6,tcp,60,203.0.113.5,192.168.1.10,51234,22,0,S,123456,,64240,,mss",
+        )?;
+        assert_eq!(event.id, "1000000103");
+        assert_eq!(event.priority, BLOCKED_PRIORITY);
+        assert_eq!(event.signature, "block in on igb1 (rule 5)");
+        assert_eq!(event.category, "filterlog");
+        assert_eq!(event.datetime.to_string(), "2026-10-16 09:30:01");
+        assert_eq!(
+            [&event.proto, &event.source, &event.src_port, &event.destination, &event.dst_port],
+            ["tcp", "203.0.113.5", "51234", "192.168.1.10", "22"]
+        );
+        assert_eq!(
+            [&event.interface, &event.action, &event.direction, &event.reason],
+            ["igb1", "block", "in", "match"]
+        );
+        assert_eq!(event.tcp_flags, "S");
+        assert_eq!(event.icmp_type, "");
+
+        // ICMP has the type instead of ports
+        let event = parse_line(
+            "Oct 16 09:31:00 fw filterlog[4242]: 7,,,1000000105,igb0,match,pass,in,4,0x0,,64,\
+             0,0,none,1,icmp,84,10.0.0.1,10.0.0.2,request,1234,1",
+        )?;
+        assert_eq!(event.priority, PASSED_PRIORITY);
+        assert_eq!([&event.src_port, &event.dst_port], ["", ""]);
+        assert_eq!(event.icmp_type, "request");
+        Ok(())
+    }
+
+    #[test]
+    fn ipv6_lines_are_parsed() -> Result<(), String> {
+        // An RFC 5424 header, as logged by pfSense 2.5 and later
+        let event = parse_line(
+            "<134>1 2026-10-16T09:30:01+00:00 fw filterlog 4242 - - 9,,,1000000104,igb0,match,\
+             pass,out,6,0x00,0x00000,64,udp,17,40,2001:db8::1,2001:db8::2,5353,53,40",
+        )?;
+        assert_eq!(event.id, "1000000104");
+        assert_eq!(event.priority, PASSED_PRIORITY);
+        assert_eq!(event.signature, "pass out on igb0 (rule 9)");
+        assert_eq!(
+            [&event.proto, &event.source, &event.src_port, &event.destination, &event.dst_port],
+            ["udp", "2001:db8::1", "5353", "2001:db8::2", "53"]
+        );
+        assert_eq!(event.tcp_flags, "");
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_and_other_lines_are_skipped() {
+        for line in [
+            // Cut off before the addresses, and before the IP version
+            "Oct 16 09:30:01 fw filterlog[4242]: 5,,,1000000103,igb1,match,block,in,4,0x0,,64",
+            "Oct 16 09:30:01 fw filterlog[4242]: 5,,,1000000103,igb1,match,block,in",
+            "Oct 16 09:30:01 fw filterlog[4242]:",
+            // Other programs and lines that are not syslog
+            "Oct 16 09:30:01 fw sshd[123]: Accepted publickey for admin from 10.0.0.5",
+            "5,,,1000000103,igb1,match,block,in,4,0x0,,64,0,0,DF,6,tcp,60,10.0.0.1,10.0.0.2",
+            "",
+        ] {
+            assert!(parse_line(line).is_err(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Metric plugins compiled to WebAssembly.
+//!
+//! With the `wasm` feature, the `plugins` configuration setting loads metrics from
+//! WebAssembly modules, so proprietary aggregations such as an in-house score can be
+//! added without forking the crate:
+//!
+//! ```json
+//! {
+//!   "plugins": [{ "name": "Risk Score", "path": "/etc/dashboard_aggregator/risk.wasm" }]
+//! }
+//! ```
+//!
+//! Each plugin becomes a metric whose section in `events.json` is named after it. A
+//! module implements the `Metric` interface with these exports, where the host passes
+//! and receives JSON as UTF-8 bytes in the module's memory:
+//!
+//! - `memory`: The module's memory.
+//! - `alloc(len: i32) -> i32`: Returns the address of `len` bytes the host writes its
+//!   input to, for the next call.
+//! - `observe(ptr: i32, len: i32)`: Counts an event, an object with the `datetime`,
+//!   `priority`, `signature`, `category`, `source` and `destination` of the event.
+//! - `merge(ptr: i32, len: i32)`: Adds a state returned by `state`, from the module's
+//!   metric of another file or from a checkpoint.
+//! - `state() -> i64` and `finish() -> i64`: Return the complete state of the metric
+//!   and its section, as the address of the JSON in the high 32 bits and its length in
+//!   the low 32 bits.
+//!
+//! The modules are sandboxed: they cannot import anything, so they have no access to
+//! the filesystem, network or clock, and every call is limited in memory and in fuel,
+//! i.e. the number of instructions it runs. A plugin that fails, e.g. by running out
+//! of fuel, is left out of `events.json` with a warning instead of stopping the run.
+
+use crate::log;
+use crate::metric::{Event, Metric};
+use serde_json::{json, Value};
+use std::any::Any;
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::Arc;
+use std::{fs, io};
+use wasmtime::{
+    Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+/// Format of the `datetime` of the events passed to `observe`.
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The most instructions a single call to a plugin may run.
+const FUEL: u64 = 1_000_000_000;
+
+/// The most memory a plugin may use, in bytes.
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// A compiled plugin, shared by its metrics of every file.
+///
+/// # Fields
+/// - `name`: The name of the plugin's section in `events.json`.
+/// - `engine`: The engine the module is compiled for.
+/// - `module`: The compiled module.
+pub struct Plugin {
+    name: &'static str,
+    engine: Engine,
+    module: Module,
+}
+
+impl Plugin {
+    /// Loads and compiles the plugin at `path`, named `name`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, or is not a valid plugin.
+    pub fn load(name: &str, path: &Path) -> io::Result<Self> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid plugin {}: {e}", path.display()),
+            )
+        };
+        Self::new(name, &fs::read(path)?).map_err(invalid)
+    }
+
+    /// Compiles the plugin module `bytes`, in the binary or text format, named `name`,
+    /// and checks that it can be instantiated.
+    ///
+    /// # Errors
+    /// Returns a message if the module is not valid, imports anything, or lacks an
+    /// export of the plugin interface.
+    pub fn new(name: &str, bytes: &[u8]) -> Result<Self, String> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        let module = Module::new(&engine, bytes).map_err(|e| e.to_string())?;
+        if let Some(import) = module.imports().next() {
+            return Err(format!(
+                "plugins cannot import anything, but it imports `{}::{}`",
+                import.module(),
+                import.name()
+            ));
+        }
+        let plugin = Self {
+            // Metric names live as long as the program, and plugins are only loaded
+            // with the configuration
+            name: Box::leak(name.to_string().into_boxed_str()),
+            engine,
+            module,
+        };
+        Guest::new(&plugin).map_err(|e| e.to_string())?;
+        Ok(plugin)
+    }
+
+    /// Returns the name of the plugin's section in `events.json`.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// An instance of a plugin module, with its exports.
+struct Guest {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    observe: TypedFunc<(i32, i32), ()>,
+    merge: TypedFunc<(i32, i32), ()>,
+    state: TypedFunc<(), i64>,
+    finish: TypedFunc<(), i64>,
+}
+
+impl Guest {
+    /// Instantiates the module of `plugin`.
+    ///
+    /// # Errors
+    /// Returns an error if the module cannot be instantiated or lacks an export.
+    fn new(plugin: &Plugin) -> wasmtime::Result<Self> {
+        let limits = StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build();
+        let mut store = Store::new(&plugin.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL)?;
+        let instance = Linker::new(&plugin.engine).instantiate(&mut store, &plugin.module)?;
+        Ok(Self {
+            memory: instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| wasmtime::Error::msg("missing export `memory`"))?,
+            alloc: instance.get_typed_func(&mut store, "alloc")?,
+            observe: instance.get_typed_func(&mut store, "observe")?,
+            merge: instance.get_typed_func(&mut store, "merge")?,
+            state: instance.get_typed_func(&mut store, "state")?,
+            finish: instance.get_typed_func(&mut store, "finish")?,
+            store,
+        })
+    }
+
+    /// Passes an `event` to the plugin's `observe`.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin traps or runs out of fuel or memory.
+    fn observe(&mut self, event: &Value) -> wasmtime::Result<()> {
+        let input = self.input(event)?;
+        self.observe.call(&mut self.store, input)
+    }
+
+    /// Passes a `state` to the plugin's `merge`.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin traps or runs out of fuel or memory.
+    fn merge(&mut self, state: &Value) -> wasmtime::Result<()> {
+        let input = self.input(state)?;
+        self.merge.call(&mut self.store, input)
+    }
+
+    /// Returns the JSON returned by the plugin's `state`.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin traps or runs out of fuel or memory, or does not
+    /// return valid JSON.
+    fn state(&mut self) -> wasmtime::Result<Value> {
+        self.store.set_fuel(FUEL)?;
+        let output = self.state.call(&mut self.store, ())?;
+        self.output(output)
+    }
+
+    /// Returns the JSON returned by the plugin's `finish`.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin traps or runs out of fuel or memory, or does not
+    /// return valid JSON.
+    fn finish(&mut self) -> wasmtime::Result<Value> {
+        self.store.set_fuel(FUEL)?;
+        let output = self.finish.call(&mut self.store, ())?;
+        self.output(output)
+    }
+
+    /// Writes `value` as JSON to the memory the plugin allocates for it, returning its
+    /// address and length, and refuels the plugin for the call it is passed to.
+    ///
+    /// # Errors
+    /// Returns an error if the plugin traps or runs out of fuel, or the memory it
+    /// returns is out of bounds.
+    fn input(&mut self, value: &Value) -> wasmtime::Result<(i32, i32)> {
+        let bytes = serde_json::to_vec(value)?;
+        let len = i32::try_from(bytes.len())?;
+        self.store.set_fuel(FUEL)?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, usize::try_from(ptr)?, &bytes)?;
+        self.store.set_fuel(FUEL)?;
+        Ok((ptr, len))
+    }
+
+    /// Reads the JSON at the address and length packed into the `output` of a call.
+    ///
+    /// # Errors
+    /// Returns an error if the memory is out of bounds or does not hold valid JSON.
+    fn output(&self, output: i64) -> wasmtime::Result<Value> {
+        let ptr = usize::try_from(output >> 32)?;
+        let len = usize::try_from(output & 0xffff_ffff)?;
+        let mut bytes = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut bytes)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// The metric of a plugin.
+///
+/// # Fields
+/// - `name`: The name of the plugin.
+/// - `guest`: The plugin's instance, which calls need mutable access to even when
+///   the metric is only read, e.g. by `finish`; `None` once the plugin has failed.
+pub struct PluginMetric {
+    name: &'static str,
+    guest: RefCell<Option<Guest>>,
+}
+
+impl PluginMetric {
+    /// Creates the metric of `plugin`.
+    #[must_use]
+    pub fn new(plugin: &Arc<Plugin>) -> Self {
+        let guest = Guest::new(plugin).inspect_err(|e| fail(plugin.name, e)).ok();
+        Self {
+            name: plugin.name,
+            guest: RefCell::new(guest),
+        }
+    }
+
+    /// Runs `call` on the plugin's guest, unless it has failed. If `call` fails, the
+    /// plugin is dropped with a warning.
+    fn with<T>(&self, call: impl FnOnce(&mut Guest) -> wasmtime::Result<T>) -> Option<T> {
+        let mut guest = self.guest.borrow_mut();
+        let result = call(guest.as_mut()?);
+        result
+            .inspect_err(|e| {
+                fail(self.name, e);
+                *guest = None;
+            })
+            .ok()
+    }
+}
+
+/// Warns that the plugin `name` failed with `error`.
+fn fail(name: &str, error: &wasmtime::Error) {
+    log::warn(format_args!(
+        "Plugin `{name}` failed and is left out of the outputs: {error:#}"
+    ));
+}
+
+impl Metric for PluginMetric {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn observe(&mut self, event: &Event) {
+        let event = json!({
+            "datetime": event.datetime.format(DATETIME_FORMAT).to_string(),
+            "priority": event.priority,
+            "signature": event.signature,
+            "category": event.category,
+            "source": event.source,
+            "destination": event.destination,
+        });
+        self.with(|guest| guest.observe(&event));
+    }
+
+    fn merge(&mut self, other: &dyn Metric) {
+        if let Some(other) = other.as_any().downcast_ref::<Self>() {
+            if other.guest.borrow().is_none() {
+                // Counting on without the other file's events would give wrong results
+                self.guest.replace(None);
+                return;
+            }
+        }
+        let state = other.state();
+        self.with(|guest| guest.merge(&state));
+    }
+
+    fn state(&self) -> Value {
+        self.with(Guest::state).unwrap_or(Value::Null)
+    }
+
+    fn merge_state(&mut self, state: &Value) -> Result<(), String> {
+        let mut guest = self.guest.borrow_mut();
+        let Some(guest) = guest.as_mut() else {
+            return Ok(());
+        };
+        guest.merge(state).map_err(|e| format!("{e:#}"))
+    }
+
+    fn finish(&self) -> Value {
+        self.with(Guest::finish).unwrap_or(Value::Null)
+    }
+
+    fn schema(&self) -> Value {
+        // The sections of plugins can hold anything
+        json!({})
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::Interner;
+    use chrono::NaiveDateTime;
+
+    /// A plugin counting the events, whose state and section are the count.
+    const COUNTER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $count (mut i64) (i64.const 0))
+          (func (export "alloc") (param $len i32) (result i32) (i32.const 1024))
+          (func (export "observe") (param $ptr i32) (param $len i32)
+            (global.set $count (i64.add (global.get $count) (i64.const 1))))
+          (func (export "merge") (param $ptr i32) (param $len i32)
+            (local $end i32)
+            (local $n i64)
+            (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+            (block $done
+              (loop $digit
+                (br_if $done (i32.ge_u (local.get $ptr) (local.get $end)))
+                (local.set $n
+                  (i64.add
+                    (i64.mul (local.get $n) (i64.const 10))
+                    (i64.extend_i32_u
+                      (i32.sub (i32.load8_u (local.get $ptr)) (i32.const 48)))))
+                (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                (br $digit)))
+            (global.set $count (i64.add (global.get $count) (local.get $n))))
+          (func $number (result i64)
+            (local $ptr i32)
+            (local $n i64)
+            (local.set $ptr (i32.const 512))
+            (local.set $n (global.get $count))
+            (loop $digit
+              (local.set $ptr (i32.sub (local.get $ptr) (i32.const 1)))
+              (i32.store8
+                (local.get $ptr)
+                (i32.add
+                  (i32.const 48)
+                  (i32.wrap_i64 (i64.rem_u (local.get $n) (i64.const 10)))))
+              (local.set $n (i64.div_u (local.get $n) (i64.const 10)))
+              (br_if $digit (i64.ne (local.get $n) (i64.const 0))))
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (i32.sub (i32.const 512) (local.get $ptr)))))
+          (func (export "state") (result i64) (call $number))
+          (func (export "finish") (result i64) (call $number)))
+    "#;
+
+    fn observe(metric: &mut PluginMetric, events: usize) {
+        let interner = RefCell::new(Interner::default());
+        let datetime = NaiveDateTime::parse_from_str("2024-04-01 10:00:00", DATETIME_FORMAT)
+            .expect("valid date/time");
+        for _ in 0..events {
+            let event =
+                Event::new(datetime, "1", "SSH", "AWARE", "10.0.0.1", "10.0.0.2", &interner);
+            metric.observe(&event);
+        }
+    }
+
+    #[test]
+    fn counts_and_merges_events() -> Result<(), String> {
+        let plugin = Arc::new(Plugin::new("Event Count", COUNTER.as_bytes())?);
+        let mut first = PluginMetric::new(&plugin);
+        let mut second = PluginMetric::new(&plugin);
+        observe(&mut first, 3);
+        observe(&mut second, 2);
+        first.merge(&second);
+        assert_eq!(first.finish(), json!(5));
+
+        first.merge_state(&json!(10))?;
+        assert_eq!(first.state(), json!(15));
+        assert_eq!(first.name(), "Event Count");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_imports_and_missing_exports() {
+        let imports = r#"(module (import "env" "clock" (func)) (memory (export "memory") 1))"#;
+        assert!(Plugin::new("Clock", imports.as_bytes())
+            .is_err_and(|e| e.contains("cannot import anything")));
+        let exports = r#"(module (memory (export "memory") 1))"#;
+        assert!(Plugin::new("Empty", exports.as_bytes()).is_err());
+        assert!(Plugin::new("Invalid", b"not a module").is_err());
+    }
+
+    #[test]
+    fn drops_a_plugin_that_runs_out_of_fuel() -> Result<(), String> {
+        let looping = COUNTER.replace(
+            "(global.set $count (i64.add (global.get $count) (i64.const 1))))",
+            "(loop $forever (br $forever)))",
+        );
+        let plugin = Arc::new(Plugin::new("Loop", looping.as_bytes())?);
+        let mut metric = PluginMetric::new(&plugin);
+        observe(&mut metric, 1);
+        assert_eq!(metric.finish(), Value::Null);
+        assert_eq!(metric.merge_state(&json!(1)), Ok(()));
+        Ok(())
+    }
+}
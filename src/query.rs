@@ -0,0 +1,130 @@
+//! Ad-hoc SQL over the parsed logs.
+//!
+//! `query <sql> <path_to_log_files> <days_back>` reads the log files like a run, with the
+//! same formats, normalization, filters and anonymization, and runs `<sql>` with `DuckDB`
+//! over a table `events` of the counted events, printing the result. The table has the
+//! columns `timestamp`, `priority`, `src`, `dst`, `signature`, `category` and `device`
+//! (see `device_name`), e.g.:
+//!
+//! ```sh
+//! dashboard_aggregator query "SELECT src, count(*) FROM events WHERE priority = 1 GROUP BY 1 ORDER BY 2 DESC LIMIT 20" /var/log/fwd/db 7
+//! ```
+//!
+//! This is an integration with the external `duckdb` command line tool, which must be on
+//! the `PATH`: `DuckDB` is not linked into this program. The events are written to a
+//! temporary CSV file, created exclusively and readable only by the user, which `duckdb`
+//! reads with type detection and which is removed afterwards.
+
+use crate::anonymize::Anonymizer;
+use crate::cli::Options;
+use crate::{device_name, guard_files, list_log_files, read_csv_file};
+use dashboard_aggregator::config::Config;
+use std::io::{self, BufWriter, ErrorKind};
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+/// Program run to execute the query.
+const DUCKDB: &str = "duckdb";
+
+/// Format of the `timestamp` column, which `DuckDB` detects as a `TIMESTAMP`.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Runs `sql` over the events of the log files selected by `options`.
+///
+/// # Errors
+/// Returns an error if a log file cannot be read, the events cannot be written, or
+/// `DuckDB` is not installed or fails to execute the query.
+pub fn run(options: &Options, config: &Config, sql: &str) -> io::Result<()> {
+    // The events file is removed when dropped, whether or not the query succeeded
+    let file = tempfile::Builder::new()
+        .prefix("dashboard_aggregator-query-")
+        .suffix(".csv")
+        .tempfile()?;
+    write_events(options, config, &file)?;
+    execute(file.path(), sql)
+}
+
+/// Writes the events of the log files selected by `options` to the CSV file `file`.
+///
+/// # Errors
+/// Returns an error if a log file cannot be read or the CSV file cannot be written.
+fn write_events(options: &Options, config: &Config, file: &NamedTempFile) -> io::Result<()> {
+    let anonymizer = Anonymizer::load(options)?;
+    let anonymize = |address: &str| {
+        anonymizer.as_ref().map_or_else(
+            || address.to_string(),
+            |anonymizer| anonymizer.address(address),
+        )
+    };
+    let files = guard_files(list_log_files(options)?, options, false);
+
+    let mut writer = csv::Writer::from_writer(BufWriter::new(file.as_file()));
+    writer.write_record([
+        "timestamp",
+        "priority",
+        "src",
+        "dst",
+        "signature",
+        "category",
+        "device",
+    ])?;
+    for file in &files {
+        let device = device_name(config, file);
+        let mut error = None;
+        read_csv_file(file, options, config, None, |event| {
+            if error.is_some() {
+                return;
+            }
+            let timestamp = event.datetime.format(TIMESTAMP_FORMAT).to_string();
+            let record: [&str; 7] = [
+                timestamp.as_str(),
+                event.priority,
+                &anonymize(event.source),
+                &anonymize(event.destination),
+                event.signature,
+                event.category,
+                &device,
+            ];
+            error = writer.write_record(record).err();
+        })?;
+        if let Some(e) = error {
+            return Err(e.into());
+        }
+    }
+    writer.flush()
+}
+
+/// Runs `sql` with `DuckDB` over a table `events` read from the CSV file at `path`,
+/// printing the result.
+///
+/// # Errors
+/// Returns an error if `DuckDB` is not on the `PATH`, cannot be run or exits with an error.
+fn execute(path: &Path, sql: &str) -> io::Result<()> {
+    let path = path.display().to_string().replace('\'', "''");
+    let script = format!("CREATE VIEW events AS SELECT * FROM read_csv_auto('{path}');\n{sql}");
+    let status = Command::new(DUCKDB)
+        .args([":memory:", "-c"])
+        .arg(script)
+        .status()
+        .map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "The {DUCKDB} command line tool was not found on the PATH; \
+                         install DuckDB (https://duckdb.org/) to run queries"
+                    ),
+                )
+            } else {
+                io::Error::new(e.kind(), format!("Failed to run {DUCKDB}: {e}"))
+            }
+        })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{DUCKDB} failed to run the query"
+        )))
+    }
+}
@@ -0,0 +1,323 @@
+//! Redis output sink.
+//!
+//! With `--redis-url redis://[[user]:password@]host[:port][/db]`, the aggregates are also
+//! written to Redis every time the outputs are, for dashboard backends that read from
+//! Redis rather than from the JSON files. The keys, prefixed with `--redis-prefix`
+//! (default `dashboard`), are:
+//!
+//! - `<prefix>:priorities`, `<prefix>:aware_threats` and `<prefix>:threat_score`: hashes
+//!   of counts by priority label or time bucket.
+//! - `<prefix>:threat_sources`, `<prefix>:threat_destinations` and
+//!   `<prefix>:top_signatures`: sorted sets scored by count, so `ZREVRANGE` gives the top
+//!   N. Only the `--max-output-entries` highest counts are kept if set.
+//! - `<prefix>:run`: a hash describing the run, with the time it was updated, days back,
+//!   and the event, parse error and empty record counts.
+//!
+//! Sections whose metric is disabled are left out. The keys are replaced in a single
+//! `MULTI`/`EXEC` transaction, so readers never see a half-written update, and expire
+//! after `--redis-ttl` seconds if set. The connection speaks the Redis protocol (RESP)
+//! directly over TCP, without TLS.
+
+use crate::cli::Options;
+use chrono::Local;
+use dashboard_aggregator::metric::{Addresses, AwareThreats, Priorities, Signatures, ThreatScore};
+use dashboard_aggregator::AggregatedData;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Port used when the URL does not give one.
+const DEFAULT_PORT: u16 = 6379;
+
+/// Time after which connecting to or waiting for Redis is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of members added by each `ZADD` command.
+const BATCH: usize = 1000;
+
+/// The parts of a `redis://` URL.
+///
+/// # Fields
+/// - `host`: The host name or address, with the port.
+/// - `user`: The ACL user to authenticate as, if any.
+/// - `password`: The password to authenticate with, if any.
+/// - `database`: The database number to select.
+struct Url {
+    host: String,
+    user: Option<String>,
+    password: Option<String>,
+    database: u32,
+}
+
+impl Url {
+    /// Parses a `redis://[[user]:password@]host[:port][/db]` URL.
+    ///
+    /// # Errors
+    /// Returns a message describing the expected form if `url` is not one.
+    fn parse(url: &str) -> Result<Self, String> {
+        let invalid =
+            || format!("Invalid Redis URL `{url}`, expected redis://[:password@]host[:port][/db]");
+        let rest = url.strip_prefix("redis://").ok_or_else(invalid)?;
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (host, database) = match rest.split_once('/') {
+            Some((host, "")) => (host, 0),
+            Some((host, database)) => (host, database.parse().map_err(|_| invalid())?),
+            None => (rest, 0),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let host = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+        {
+            host.to_string()
+        } else {
+            format!("{host}:{DEFAULT_PORT}")
+        };
+        let (user, password) = match credentials.map(|credentials| credentials.split_once(':')) {
+            Some(Some((user, password))) => {
+                (Some(user).filter(|user| !user.is_empty()), Some(password))
+            }
+            Some(None) => (None, credentials),
+            None => (None, None),
+        };
+        Ok(Self {
+            host,
+            user: user.map(str::to_string),
+            password: password.map(str::to_string),
+            database,
+        })
+    }
+}
+
+/// Parses the URL given to `--redis-url`, to reject a malformed one before processing.
+///
+/// # Errors
+/// Returns a message describing the expected form if `url` is not a `redis://` URL.
+pub fn validate_url(url: &str) -> Result<(), String> {
+    Url::parse(url).map(drop)
+}
+
+/// Commands to send to Redis in one go.
+///
+/// # Fields
+/// - `buffer`: The encoded commands.
+/// - `commands`: How many commands are in `buffer`, each of which gets one reply.
+#[derive(Default)]
+struct Pipeline {
+    buffer: Vec<u8>,
+    commands: usize,
+}
+
+impl Pipeline {
+    /// Appends the command `args`.
+    fn command<S: AsRef<[u8]>>(&mut self, args: &[S]) {
+        self.buffer
+            .extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+        for arg in args {
+            let arg = arg.as_ref();
+            self.buffer
+                .extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            self.buffer.extend_from_slice(arg);
+            self.buffer.extend_from_slice(b"\r\n");
+        }
+        self.commands += 1;
+    }
+
+    /// Appends the commands replacing the hash `key` with the fields of `entries`.
+    fn hash<V: ToString>(&mut self, key: &str, entries: &[(String, V)]) {
+        self.command(&["DEL", key]);
+        if entries.is_empty() {
+            return;
+        }
+        let mut args = vec!["HSET".to_string(), key.to_string()];
+        for (field, value) in entries {
+            args.push(field.clone());
+            args.push(value.to_string());
+        }
+        self.command(&args);
+    }
+
+    /// Appends the commands replacing the sorted set `key` with the members of `counts`,
+    /// keeping the `max` highest counts if given.
+    fn sorted_set(&mut self, key: &str, counts: &HashMap<Arc<str>, u32>, max: Option<usize>) {
+        self.command(&["DEL", key]);
+        let mut entries: Vec<(&str, u32)> = counts
+            .iter()
+            .map(|(member, &count)| (member.as_ref(), count))
+            .collect();
+        if let Some(max) = max {
+            entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            entries.truncate(max);
+        }
+        for batch in entries.chunks(BATCH) {
+            let mut args = vec!["ZADD".to_string(), key.to_string()];
+            for (member, count) in batch {
+                args.push(count.to_string());
+                args.push((*member).to_string());
+            }
+            self.command(&args);
+        }
+    }
+}
+
+/// Reads one reply from `reader`, including the elements of an array reply.
+///
+/// # Errors
+/// Returns an error if the reply cannot be read, or is or contains an error reply.
+fn read_reply(reader: &mut impl BufRead) -> io::Result<()> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Redis closed the connection",
+        ));
+    }
+    let line = line.trim_end();
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid Redis reply `{line}`"),
+        )
+    };
+    let (kind, value) = line.split_at_checked(1).ok_or_else(invalid)?;
+    match kind {
+        "+" | ":" => Ok(()),
+        "-" => Err(io::Error::other(format!("Redis error: {value}"))),
+        "$" => {
+            let length: i64 = value.parse().map_err(|_| invalid())?;
+            // A length of -1 is a null value, without a line to skip
+            if let Ok(length) = u64::try_from(length) {
+                // Skip the value and its line break
+                io::copy(&mut Read::take(&mut *reader, length + 2), &mut io::sink())?;
+            }
+            Ok(())
+        }
+        "*" => {
+            let count: i64 = value.parse().map_err(|_| invalid())?;
+            // Read every element before reporting an error, to stay in step
+            let mut result = Ok(());
+            for _ in 0..count.max(0) {
+                let element = read_reply(reader);
+                result = result.and(element);
+            }
+            result
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Writes the aggregates in `data` to Redis, if `--redis-url` is set.
+///
+/// # Errors
+/// Returns an error if Redis cannot be reached, authentication fails, or a command is
+/// rejected; the keys are then left as they were.
+pub fn write(options: &Options, data: &AggregatedData) -> io::Result<()> {
+    let Some(url) = &options.redis_url else {
+        return Ok(());
+    };
+    let url =
+        Url::parse(url).map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    let key = |name: &str| format!("{}:{name}", options.redis_prefix);
+    let mut keys = Vec::new();
+
+    let mut pipeline = Pipeline::default();
+    if let Some(password) = &url.password {
+        match &url.user {
+            Some(user) => pipeline.command(&["AUTH", user, password]),
+            None => pipeline.command(&["AUTH", password]),
+        }
+    }
+    pipeline.command(&["SELECT", &url.database.to_string()]);
+    pipeline.command(&["MULTI"]);
+
+    if let Some(priorities) = data.metric::<Priorities>("Priorities") {
+        keys.push(key("priorities"));
+        pipeline.hash(&key("priorities"), &priorities.sorted());
+    }
+    if let Some(aware) = data.metric::<AwareThreats>("AWARE Threats") {
+        keys.push(key("aware_threats"));
+        pipeline.hash(&key("aware_threats"), &aware.sorted());
+    }
+    if let Some(score) = data.metric::<ThreatScore>("Threat Score") {
+        keys.push(key("threat_score"));
+        pipeline.hash(&key("threat_score"), &score.sorted());
+    }
+    let addresses = [
+        ("Threat Sources", "threat_sources"),
+        ("Threat Destinations", "threat_destinations"),
+    ];
+    for (metric, name) in addresses {
+        if let Some(addresses) = data.metric::<Addresses>(metric) {
+            keys.push(key(name));
+            pipeline.sorted_set(&key(name), &addresses.counts, options.max_output_entries);
+        }
+    }
+    if let Some(signatures) = data.metric::<Signatures>("Top Signatures") {
+        keys.push(key("top_signatures"));
+        pipeline.sorted_set(
+            &key("top_signatures"),
+            &signatures.counts,
+            options.max_output_entries,
+        );
+    }
+    keys.push(key("run"));
+    pipeline.hash(
+        &key("run"),
+        &[
+            ("updated".to_string(), Local::now().to_rfc3339()),
+            ("days_back".to_string(), options.days_back.to_string()),
+            ("events".to_string(), data.records().to_string()),
+            ("parse_errors".to_string(), data.parse_errors().to_string()),
+            (
+                "empty_records".to_string(),
+                data.empty_records().to_string(),
+            ),
+            ("approximate".to_string(), data.is_approximate().to_string()),
+        ],
+    );
+    if let Some(ttl) = options.redis_ttl {
+        for key in &keys {
+            pipeline.command(&["EXPIRE", key, &ttl.to_string()]);
+        }
+    }
+    pipeline.command(&["EXEC"]);
+
+    send(&url, &pipeline).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to write to Redis at {}: {e}", url.host),
+        )
+    })
+}
+
+/// Sends the commands of `pipeline` to the Redis server at `url` and reads their replies.
+///
+/// # Errors
+/// Returns an error if the server cannot be reached or any reply is an error.
+fn send(url: &Url, pipeline: &Pipeline) -> io::Result<()> {
+    let address = url
+        .host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(&pipeline.buffer)?;
+    let mut reader = BufReader::new(stream);
+    // Read every reply, so an error reply to a queued command is reported rather than
+    // only the EXECABORT that follows it
+    let mut result = Ok(());
+    for _ in 0..pipeline.commands {
+        let reply = read_reply(&mut reader);
+        result = result.and(reply);
+    }
+    result
+}
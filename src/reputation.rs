@@ -0,0 +1,216 @@
+//! Reputation lookups for the top threat sources.
+//!
+//! With a `reputation` section in the configuration file, the top threat sources are
+//! looked up with AbuseIPDB.com or VirusTotal.com after aggregating, and `events.json` gets a
+//! `Reputation` section listing each source with its score and a link to its report on
+//! the service. Private and other non-public addresses are not looked up.
+//!
+//! Results are cached in a JSON file between runs, so scheduled runs only look up new
+//! sources and those whose result has expired. Lookups are spaced out to stay within the
+//! configured rate, and a failed lookup only leaves its source out of the section.
+
+use crate::cli::Options;
+use crate::http;
+use chrono::Utc;
+use dashboard_aggregator::config::{ReputationConfig, ReputationProvider};
+use dashboard_aggregator::metric::Addresses;
+use dashboard_aggregator::{log, AggregatedData};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Name of the cache file in the output directory when none is configured.
+const DEFAULT_CACHE: &str = "reputation_cache.json";
+
+/// A cached lookup result.
+///
+/// # Fields
+/// - `score`: The reputation score reported by the service.
+/// - `checked`: When the address was looked up, as a Unix timestamp.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    score: u64,
+    checked: i64,
+}
+
+/// Returns whether `ip` is a public address that a reputation service can know about.
+pub const fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast())
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+/// Looks up the score of `ip` with the API of `provider`.
+///
+/// # Errors
+/// Returns an error if the request fails or the response has no score.
+fn fetch_score(provider: ReputationProvider, ip: &str, api_key: &str) -> io::Result<u64> {
+    let (url, header, pointer) = match provider {
+        ReputationProvider::AbuseIpDb => (
+            format!("https://api.abuseipdb.com/api/v2/check?ipAddress={ip}&maxAgeInDays=90"),
+            format!("Key: {api_key}"),
+            "/data/abuseConfidenceScore",
+        ),
+        ReputationProvider::VirusTotal => (
+            format!("https://www.virustotal.com/api/v3/ip_addresses/{ip}"),
+            format!("x-apikey: {api_key}"),
+            "/data/attributes/last_analysis_stats/malicious",
+        ),
+    };
+    let response = http::get_json(&url, &[header])?;
+    response
+        .pointer(pointer)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No score for {ip} in the {} response", provider.name()),
+            )
+        })
+}
+
+/// Loads the cache file at `path`. A missing file is an empty cache, and an invalid one
+/// is ignored with a warning, as it is rebuilt by the next lookups.
+///
+/// # Errors
+/// Returns an error if the file exists but cannot be read.
+fn load_cache(path: &Path) -> io::Result<HashMap<String, Entry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(serde_json::from_str(&contents).unwrap_or_else(|e| {
+        log::warn(format_args!(
+            "ignoring invalid reputation cache {}: {e}",
+            path.display()
+        ));
+        HashMap::new()
+    }))
+}
+
+/// Looks up the reputation of the top threat sources in `data`, if configured.
+///
+/// # Arguments
+/// - `options`: The parsed command line, providing the output directory.
+/// - `reputation`: The reputation settings from the configuration file.
+/// - `data`: The aggregates whose top sources are looked up.
+///
+/// # Returns
+/// The `Reputation` section of `events.json`, with parallel `Source`, `Score` and
+/// `Report` arrays, or `None` if lookups are not configured or addresses are anonymized.
+///
+/// # Errors
+/// Returns an error if the cache file cannot be read or written. Failed lookups are
+/// reported as warnings instead.
+pub fn lookup(
+    options: &Options,
+    reputation: Option<&ReputationConfig>,
+    data: &AggregatedData,
+) -> io::Result<Option<Value>> {
+    let Some(reputation) = reputation else {
+        return Ok(None);
+    };
+    if options.anonymize.is_some() {
+        log::warn("reputation lookups are skipped for anonymized addresses");
+        return Ok(None);
+    }
+    let mut sources: Vec<(&str, u32)> = data
+        .metric::<Addresses>("Threat Sources")
+        .map(|metric| &metric.counts)
+        .into_iter()
+        .flatten()
+        .filter(|(source, _)| source.trim().parse().is_ok_and(|ip| is_public(&ip)))
+        .map(|(source, &count)| (source.as_ref(), count))
+        .collect();
+    sources.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    sources.truncate(reputation.top);
+
+    let cache_path = reputation
+        .cache
+        .clone()
+        .unwrap_or_else(|| options.output_dir_path(DEFAULT_CACHE));
+    let mut cache = load_cache(&cache_path)?;
+    let provider = reputation.provider;
+    let now = Utc::now().timestamp();
+    let max_age = i64::from(reputation.cache_hours) * 3600;
+    let interval = Duration::from_secs(60) / reputation.requests_per_minute;
+    let mut last_request: Option<Instant> = None;
+    let mut looked_up = 0;
+
+    let mut section = (Vec::new(), Vec::new(), Vec::new());
+    for (source, _) in sources {
+        let key = format!("{}:{source}", provider.name());
+        let cached = cache
+            .get(&key)
+            .filter(|entry| now - entry.checked < max_age)
+            .map(|entry| entry.score);
+        let score = if let Some(score) = cached {
+            score
+        } else {
+            if let Some(elapsed) = last_request.map(|last| last.elapsed()) {
+                thread::sleep(interval.saturating_sub(elapsed));
+            }
+            last_request = Some(Instant::now());
+            match fetch_score(provider, source, &reputation.api_key) {
+                Ok(score) => {
+                    cache.insert(
+                        key,
+                        Entry {
+                            score,
+                            checked: now,
+                        },
+                    );
+                    looked_up += 1;
+                    score
+                }
+                Err(e) => {
+                    log::warn(format_args!("reputation lookup of {source} failed: {e}"));
+                    continue;
+                }
+            }
+        };
+        section.0.push(source);
+        section.1.push(score);
+        section.2.push(provider.report_url(source));
+    }
+
+    if looked_up > 0 {
+        // Expired entries of sources that left the top are dropped
+        cache.retain(|_, entry| now - entry.checked < max_age);
+        fs::write(&cache_path, serde_json::to_string(&cache)?)?;
+    }
+    log::info(format_args!(
+        "Reputation of {} sources from {} ({looked_up} looked up)",
+        section.0.len(),
+        provider.name()
+    ));
+    Ok(Some(json!({
+        "Source": section.0,
+        "Score": section.1,
+        "Report": section.2
+    })))
+}
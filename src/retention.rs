@@ -0,0 +1,74 @@
+//! Timestamped output files and their retention.
+//!
+//! With `--timestamp-outputs`, the JSON outputs and the manifest of every run are named
+//! after the time the run started, such as `events-20240501T060000Z.json`, so earlier
+//! reports are kept next to the latest. `--keep-outputs <n>` then removes the files of
+//! all but the `n` most recent runs from the output directory once a run has written its
+//! own, so the directory does not fill up.
+
+use crate::cli::Options;
+use chrono::{NaiveDateTime, Utc};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Format of the time in timestamped output file names.
+const STAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Names of the output files that are timestamped, without their extensions.
+const STAMPED: [&str; 3] = ["events", "threat_sources", "manifest"];
+
+/// Returns the current time as it appears in timestamped output file names.
+pub fn stamp() -> String {
+    Utc::now().format(STAMP_FORMAT).to_string()
+}
+
+/// Removes the timestamped output files of all but the newest `options.keep_outputs`
+/// runs from the output directory, if set.
+///
+/// # Returns
+/// The number of files removed.
+///
+/// # Errors
+/// Returns an error if the output directory cannot be listed or a file cannot be removed.
+pub fn prune(options: &Options) -> io::Result<usize> {
+    let Some(keep) = options.keep_outputs else {
+        return Ok(0);
+    };
+    let dir = options
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let stamp = path
+            .file_name()
+            .and_then(|name| file_stamp(&name.to_string_lossy()));
+        if let Some(stamp) = stamp {
+            files.push((stamp, path));
+        }
+    }
+    let runs: BTreeSet<&String> = files.iter().map(|(stamp, _)| stamp).collect();
+    let kept: BTreeSet<&String> = runs.into_iter().rev().take(keep).collect();
+    let old: Vec<&PathBuf> = files
+        .iter()
+        .filter(|(stamp, _)| !kept.contains(stamp))
+        .map(|(_, path)| path)
+        .collect();
+    for path in &old {
+        fs::remove_file(path)?;
+    }
+    Ok(old.len())
+}
+
+/// Returns the time stamp in `name` if it is the name of a timestamped output file.
+fn file_stamp(name: &str) -> Option<String> {
+    STAMPED.iter().find_map(|base| {
+        let rest = name.strip_prefix(base)?.strip_prefix('-')?;
+        let (stamp, extensions) = rest.split_at(rest.find('.')?);
+        NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).ok()?;
+        extensions.starts_with(".json").then(|| stamp.to_string())
+    })
+}
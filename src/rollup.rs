@@ -0,0 +1,308 @@
+//! Rolling aggregates of the run history.
+//!
+//! The `rollup` subcommand reads the runs appended to the `--history` file and writes
+//! `rollup.json` to the output directory, without re-reading the logs. Its `Rollup`
+//! section holds a 7-day and a 30-day window up to now, for the dashboard's long-range
+//! charts:
+//!
+//! - `Date`, `Events`, `AWARE Threats` and `Threat Score`: The totals of the last run
+//!   of each day, in the columnar form of the other sections. A total that the run did
+//!   not record is `null`.
+//! - `Trend`: The least-squares line through the totals of every run in the window, as
+//!   its value at the `Start` and `End` of the window and its `Slope` per day, or `null`
+//!   with fewer than two runs.
+//! - `Priorities`: The mean count of each priority per run.
+//! - `Threat Sources` and `Threat Destinations`: The 10 addresses with the most events
+//!   in the top lists of the runs, summed over the runs.
+//! - `meta`: The number of runs and the bounds of the window.
+//!
+//! As consecutive runs usually cover overlapping days, sums over the runs rank the
+//! addresses rather than count their events.
+
+use crate::cli::Options;
+use crate::output;
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta};
+use dashboard_aggregator::log;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The windows rolled up, with their lengths in days.
+const WINDOWS: [(&str, i64); 2] = [("7d", 7), ("30d", 30)];
+
+/// Number of addresses listed per window.
+const TOP: usize = 10;
+
+/// Returns a total of a run, if the run recorded it.
+type Total = fn(&Run) -> Option<u64>;
+
+/// Returns a list of top addresses of a run.
+type Addresses = fn(&Run) -> &[(String, u32)];
+
+/// The totals of a run that are rolled up, with the name of their column.
+const TOTALS: [(&str, Total); 3] = [
+    ("Events", |run| Some(run.events)),
+    ("AWARE Threats", |run| run.aware_threats),
+    ("Threat Score", |run| run.threat_score),
+];
+
+/// The top address lists of a run that are rolled up, with the names of their section
+/// and column.
+const ADDRESSES: [(&str, &str, Addresses); 2] = [
+    ("Threat Sources", "Source", |run| &run.top_sources),
+    ("Threat Destinations", "Destination", |run| &run.top_destinations),
+];
+
+/// A run of the history, as far as it is rolled up.
+#[derive(Deserialize)]
+struct Run {
+    timestamp: String,
+    events: u64,
+    #[serde(default)]
+    priorities: Vec<(String, u32)>,
+    #[serde(default)]
+    top_sources: Vec<(String, u32)>,
+    #[serde(default)]
+    top_destinations: Vec<(String, u32)>,
+    aware_threats: Option<u64>,
+    threat_score: Option<u64>,
+}
+
+/// Rolls up the history of `--history` and writes it to `rollup.json`.
+///
+/// # Errors
+/// Returns an error if the history cannot be read or the output cannot be written.
+pub fn run(options: &Options) -> io::Result<()> {
+    let path = options.history.as_deref().unwrap_or_else(|| Path::new(""));
+    let history = fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to read history {}: {e}", path.display()),
+        )
+    })?;
+    let (runs, invalid) = parse(&history);
+    if invalid > 0 {
+        log::warn(format_args!("skipped {invalid} lines of the history that are not runs"));
+    }
+
+    let now = Local::now();
+    let document = json!({
+        "Rollup": rollup(&runs, now),
+        "meta": {
+            "generated": now.to_rfc3339_opts(SecondsFormat::Secs, false),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    });
+    output::write_json(options, "rollup.json", &document)?;
+    log::info(format_args!(
+        "Rolled up {} runs of {} into {}",
+        runs.len(),
+        path.display(),
+        options.output_path("rollup.json").display()
+    ));
+    Ok(())
+}
+
+/// Parses the lines of `history` into runs, sorted by time.
+///
+/// # Returns
+/// The runs, and the number of non-empty lines that are not runs.
+fn parse(history: &str) -> (Vec<(DateTime<Local>, Run)>, usize) {
+    let mut invalid = 0;
+    let mut runs: Vec<_> = history
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let run = serde_json::from_str::<Run>(line).ok().and_then(|run| {
+                let time = DateTime::parse_from_rfc3339(&run.timestamp).ok()?;
+                Some((time.with_timezone(&Local), run))
+            });
+            invalid += usize::from(run.is_none());
+            run
+        })
+        .collect();
+    runs.sort_by_key(|(time, _)| *time);
+    (runs, invalid)
+}
+
+/// Returns the `Rollup` section of `runs`, sorted by time, for the windows up to `now`.
+fn rollup(runs: &[(DateTime<Local>, Run)], now: DateTime<Local>) -> Value {
+    let windows = WINDOWS.iter().map(|&(name, days)| {
+        let start = now - TimeDelta::days(days);
+        let from = runs.partition_point(|(time, _)| *time < start);
+        let window = &runs[from..runs.partition_point(|(time, _)| *time <= now)];
+        (name.to_string(), roll_window(window, start, now))
+    });
+    Value::Object(windows.collect())
+}
+
+/// Returns the rollup of the runs of `window`, sorted by time, from `start` to `end`.
+#[allow(clippy::cast_precision_loss)]
+fn roll_window(
+    window: &[(DateTime<Local>, Run)],
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+) -> Value {
+    // The last run of each day stands for that day
+    let mut daily: Vec<&(DateTime<Local>, Run)> = Vec::new();
+    for entry in window {
+        match daily.last_mut() {
+            Some(last) if last.0.date_naive() == entry.0.date_naive() => *last = entry,
+            _ => daily.push(entry),
+        }
+    }
+
+    let mut section = Map::new();
+    let dates: Vec<String> = daily
+        .iter()
+        .map(|(time, _)| time.format("%Y-%m-%d").to_string())
+        .collect();
+    section.insert("Date".to_string(), json!(dates));
+    let mut trends = Map::new();
+    for (name, total) in TOTALS {
+        let column: Vec<Option<u64>> = daily.iter().map(|(_, run)| total(run)).collect();
+        section.insert(name.to_string(), json!(column));
+        let points: Vec<(f64, f64)> = window
+            .iter()
+            .filter_map(|(time, run)| Some((days_between(start, *time), total(run)? as f64)))
+            .collect();
+        trends.insert(name.to_string(), trend(&points, days_between(start, end)));
+    }
+    section.insert("Trend".to_string(), Value::Object(trends));
+
+    let runs: Vec<&Run> = window.iter().map(|(_, run)| run).collect();
+    let priorities = mean_priorities(&runs);
+    section.insert(
+        "Priorities".to_string(),
+        json!({
+            "Priority": priorities.iter().map(|(label, _)| label).collect::<Vec<_>>(),
+            "Count": priorities.iter().map(|(_, mean)| mean).collect::<Vec<_>>(),
+        }),
+    );
+    for (name, column, list) in ADDRESSES {
+        let top = top_addresses(runs.iter().map(|run| list(run)));
+        section.insert(
+            name.to_string(),
+            json!({
+                column: top.iter().map(|(address, _)| address).collect::<Vec<_>>(),
+                "Count": top.iter().map(|(_, count)| count).collect::<Vec<_>>(),
+            }),
+        );
+    }
+    let time = |time: DateTime<Local>| time.to_rfc3339_opts(SecondsFormat::Secs, false);
+    section.insert(
+        "meta".to_string(),
+        json!({ "runs": window.len(), "start": time(start), "end": time(end) }),
+    );
+    Value::Object(section)
+}
+
+/// Returns the number of days from `start` to `time`.
+#[allow(clippy::cast_precision_loss)]
+fn days_between(start: DateTime<Local>, time: DateTime<Local>) -> f64 {
+    (time - start).num_seconds() as f64 / TimeDelta::days(1).num_seconds() as f64
+}
+
+/// Returns the least-squares line through `points` of days and values, as its values
+/// at day 0 and day `end`, and its slope per day, or `null` without two distinct days.
+#[allow(clippy::cast_precision_loss)]
+fn trend(points: &[(f64, f64)], end: f64) -> Value {
+    if points.len() < 2 {
+        return Value::Null;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(cov, var), (x, y)| {
+        let dx = x - mean_x;
+        (dx.mul_add(y - mean_y, cov), dx.mul_add(dx, var))
+    });
+    if variance == 0.0 {
+        return Value::Null;
+    }
+    let slope = covariance / variance;
+    let at = |x: f64| slope.mul_add(x - mean_x, mean_y);
+    json!({ "Start": at(0.0), "End": at(end), "Slope": slope })
+}
+
+/// Returns the mean count of each priority per run of `runs`, in the order the
+/// priorities are first listed. A run that does not list a priority counts it as 0.
+#[allow(clippy::cast_precision_loss)]
+fn mean_priorities(runs: &[&Run]) -> Vec<(String, f64)> {
+    let mut totals: Vec<(String, u64)> = Vec::new();
+    for (label, count) in runs.iter().flat_map(|run| &run.priorities) {
+        match totals.iter_mut().find(|(known, _)| known == label) {
+            Some((_, total)) => *total += u64::from(*count),
+            None => totals.push((label.clone(), u64::from(*count))),
+        }
+    }
+    totals
+        .into_iter()
+        .map(|(label, total)| (label, total as f64 / runs.len() as f64))
+        .collect()
+}
+
+/// Returns the `TOP` addresses with the most events summed over the top lists
+/// `lists`, most events first and then by address.
+fn top_addresses<'a>(lists: impl Iterator<Item = &'a [(String, u32)]>) -> Vec<(String, u64)> {
+    let mut totals: HashMap<&str, u64> = HashMap::new();
+    for (address, count) in lists.flatten() {
+        *totals.entry(address).or_default() += u64::from(*count);
+    }
+    let mut top: Vec<(String, u64)> = totals
+        .into_iter()
+        .map(|(address, total)| (address.to_string(), total))
+        .collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(TOP);
+    top
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a history line of a run `days` before `now` with `events` events.
+    fn line(now: DateTime<Local>, days: i64, events: u64) -> String {
+        let time = (now - TimeDelta::days(days)).to_rfc3339();
+        format!(
+            "{{\"timestamp\":\"{time}\",\"events\":{events},\"priorities\":[[\"1\",{events}]],\
+             \"top_sources\":[[\"10.0.0.1\",{events}],[\"10.0.1.{days}\",1]],\
+             \"top_destinations\":[],\"aware_threats\":null,\"threat_score\":null}}\n"
+        )
+    }
+
+    #[test]
+    fn windows_roll_up_the_runs_within_them() {
+        let now = Local::now();
+        let history = [(40, 1000), (20, 500), (6, 100), (3, 460), (1, 700)]
+            .iter()
+            .map(|&(days, events)| line(now, days, events))
+            .collect::<String>()
+            + "not a run\n";
+        let (runs, invalid) = parse(&history);
+        assert_eq!((runs.len(), invalid), (5, 1));
+
+        let rollup = rollup(&runs, now);
+        let week = &rollup["7d"];
+        assert_eq!(week["meta"]["runs"], 3);
+        assert_eq!(week["Events"], json!([100, 460, 700]));
+        assert_eq!(week["AWARE Threats"], json!([null, null, null]));
+        assert_eq!(week["Priorities"]["Count"], json!([420.0]));
+        assert_eq!(
+            week["Threat Sources"],
+            json!({
+                "Source": ["10.0.0.1", "10.0.1.1", "10.0.1.3", "10.0.1.6"],
+                "Count": [1260, 1, 1, 1],
+            })
+        );
+        // The events grow by 120 a day, from 100 six days ago
+        let slope = week["Trend"]["Events"]["Slope"].as_f64().unwrap_or_default();
+        assert!((slope - 120.0).abs() < 1e-6, "{slope}");
+        assert_eq!(week["Trend"]["AWARE Threats"], Value::Null);
+        assert_eq!(rollup["30d"]["meta"]["runs"], 4);
+    }
+}
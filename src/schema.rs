@@ -0,0 +1,192 @@
+//! JSON Schemas of the output documents.
+//!
+//! The schemas are built from the same metrics as the documents themselves (see
+//! `Metric::schema`), so they describe exactly the sections a configuration produces,
+//! under their dashboard titles. They use JSON Schema draft 2020-12. `tests/schema.rs`
+//! validates the outputs of a run with every metric against them.
+
+use crate::config::Config;
+use crate::metric;
+use serde_json::{json, Map, Value};
+
+/// The JSON Schema dialect of the schemas.
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Returns the schema of a non-negative integer, such as a count.
+#[must_use]
+pub fn count() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+/// Returns the schema of an integer.
+#[must_use]
+pub fn integer() -> Value {
+    json!({ "type": "integer" })
+}
+
+/// Returns the schema of a number, such as a rate or an average.
+#[must_use]
+pub fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+/// Returns the schema of a string.
+#[must_use]
+pub fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+/// Returns the schema of a string that may be `null`.
+#[must_use]
+pub fn nullable_string() -> Value {
+    json!({ "type": ["string", "null"] })
+}
+
+/// Returns the schema of an array of `items`.
+#[must_use]
+pub fn array(items: Value) -> Value {
+    let mut schema = json!({ "type": "array" });
+    schema["items"] = items;
+    schema
+}
+
+/// Returns the schema of an object with exactly the given `properties`.
+#[must_use]
+pub fn object(properties: Vec<(&str, Value)>) -> Value {
+    let required: Vec<_> = properties.iter().map(|(name, _)| *name).collect();
+    let mut schema = json!({
+        "type": "object",
+        "required": required,
+        "additionalProperties": false
+    });
+    schema["properties"] = properties
+        .into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect::<Map<_, _>>()
+        .into();
+    schema
+}
+
+/// Returns the schema of an object of parallel columns, each an array of its `items`.
+#[must_use]
+pub fn columns(columns: Vec<(&str, Value)>) -> Value {
+    object(
+        columns
+            .into_iter()
+            .map(|(name, items)| (name, array(items)))
+            .collect(),
+    )
+}
+
+/// Returns the schema of an object whose keys are not known in advance, such as labels,
+/// each holding `values`.
+#[must_use]
+pub fn map(values: Value) -> Value {
+    let mut schema = json!({ "type": "object" });
+    schema["additionalProperties"] = values;
+    schema
+}
+
+/// Returns the schema of `events.json` for `config`.
+///
+/// Every enabled metric has a required section, as does the `meta` block. The `Windows` and `Dimensions`
+/// sections are optional, as they depend on the command line and the log file names,
+/// and the sections the binary adds, such as `Reputation`, are allowed but not described.
+#[must_use]
+pub fn events(config: &Config) -> Value {
+    let sections: Vec<_> = metric::build_named(config)
+        .into_iter()
+        .map(|(name, metric)| {
+            let title = config.title(name).unwrap_or_else(|| metric.name());
+            (title.to_string(), metric.schema())
+        })
+        .collect();
+    let mut required: Vec<_> = sections.iter().map(|(title, _)| title.clone()).collect();
+    let mut window = sections.clone();
+    window.push(("Records".to_string(), count()));
+    let window = object(
+        window
+            .iter()
+            .map(|(title, schema)| (title.as_str(), schema.clone()))
+            .collect(),
+    );
+    let mut properties: Map<String, Value> = sections.into_iter().collect();
+    properties.insert("meta".to_string(), meta());
+    required.push("meta".to_string());
+    properties.insert(
+        "Windows".to_string(),
+        json!({
+            "description": "The sections of each recent window of --windows, keyed by span",
+            "type": "object",
+            "additionalProperties": window
+        }),
+    );
+    // Each dimension section has a column named after the dimension, besides Count
+    properties.insert(
+        "Dimensions".to_string(),
+        json!({
+            "description": "The event counts by each dimension of the file name pattern",
+            "type": "object",
+            "additionalProperties": {
+                "type": "object",
+                "properties": { "Count": array(count()) },
+                "required": ["Count"],
+                "additionalProperties": array(string())
+            }
+        }),
+    );
+    let description = format!(
+        "Aggregated events written by dashboard_aggregator {}",
+        env!("CARGO_PKG_VERSION")
+    );
+    json!({
+        "$schema": DIALECT,
+        "title": "events.json",
+        "description": description,
+        "type": "object",
+        "properties": properties,
+        "required": required
+    })
+}
+
+/// Returns the schema of the `meta` block the binary adds to `events.json`, describing
+/// when and from what the document was generated. Times are RFC 3339 with the local
+/// UTC offset.
+fn meta() -> Value {
+    let time = json!({ "type": "string", "format": "date-time" });
+    object(vec![
+        ("generated", time.clone()),
+        ("version", string()),
+        (
+            "window",
+            object(vec![("start", time.clone()), ("end", time)]),
+        ),
+        ("files_processed", count()),
+        ("files_failed", count()),
+        ("records", count()),
+        (
+            "newest_record",
+            json!({ "type": ["string", "null"], "format": "date-time" }),
+        ),
+        ("parse_errors", count()),
+        ("empty_records", count()),
+        ("approximate", json!({ "type": "boolean" })),
+    ])
+}
+
+/// Returns the schema of `threat_sources.json`, which lists every threat source with its
+/// count.
+#[must_use]
+pub fn threat_sources() -> Value {
+    let mut schema = object(vec![(
+        "Threat Sources",
+        columns(vec![("Source", string()), ("Count", count())]),
+    )]);
+    schema["$schema"] = json!(DIALECT);
+    schema["title"] = json!("threat_sources.json");
+    schema["description"] = json!(format!(
+        "Threat sources written by dashboard_aggregator {}",
+        env!("CARGO_PKG_VERSION")
+    ));
+    schema
+}
@@ -0,0 +1,144 @@
+//! Record transformation scripts.
+//!
+//! With the `scripting` feature, the `script` configuration setting names a
+//! [Rhai](https://rhai.rs/) script that is run on every record that passes the filters,
+//! before it is counted. The script sees the fields of the record in a `record` map,
+//! with the keys `priority`, `signature`, `category`, `source` and `destination`, after
+//! normalization. It can rewrite them, e.g. to derive a category label, and drops the
+//! record by evaluating to `false`:
+//!
+//! ```rhai
+//! if record.signature.contains("SSH") {
+//!     record.category = "AWARE ssh";
+//! }
+//! record.source != "10.0.0.1"
+//! ```
+//!
+//! Every run of the script is limited in the number of operations, so a script that
+//! loops forever fails instead of hanging the run.
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::borrow::Cow;
+use std::path::Path;
+use std::{fs, io};
+
+/// The fields of a record that the script sees, in the order they are passed to `run`.
+pub const FIELDS: [&str; 5] = ["priority", "signature", "category", "source", "destination"];
+
+/// The most operations a single run of the script may perform.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A compiled script.
+///
+/// # Fields
+/// - `engine`: The engine the script runs in.
+/// - `ast`: The compiled script.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    /// Loads and compiles the script at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, or is not a valid script.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::compile(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid script {}: {e}", path.display()),
+            )
+        })
+    }
+
+    /// Compiles the script `source`.
+    ///
+    /// # Errors
+    /// Returns a message if `source` is not a valid script.
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script on the `fields` of a record, in the order of `FIELDS`.
+    ///
+    /// # Returns
+    /// The fields as the script left them, or `None` if it dropped the record.
+    ///
+    /// # Errors
+    /// Returns a message if the script fails, or replaces `record` with something that
+    /// is not a map.
+    pub fn run<'a>(&self, fields: [Cow<'a, str>; 5]) -> Result<Option<[Cow<'a, str>; 5]>, String> {
+        let record: Map = FIELDS
+            .iter()
+            .zip(&fields)
+            .map(|(name, value)| ((*name).into(), Dynamic::from(value.to_string())))
+            .collect();
+        let mut scope = Scope::new();
+        scope.push("record", record);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+        if result.as_bool() == Ok(false) {
+            return Ok(None);
+        }
+        let record = scope
+            .get_value::<Map>("record")
+            .ok_or_else(|| "`record` is no longer a map".to_string())?;
+        let mut fields = fields;
+        for (name, field) in FIELDS.iter().zip(&mut fields) {
+            // A field the script removed is empty, and other values are written as text
+            let value = record
+                .get(*name)
+                .filter(|value| !value.is_unit())
+                .map_or_else(String::new, ToString::to_string);
+            if value != *field {
+                *field = Cow::Owned(value);
+            }
+        }
+        Ok(Some(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> [Cow<'static, str>; 5] {
+        ["1", "ET SCAN SSH", "ALERT", "10.0.0.1", "10.0.0.2"].map(Cow::Borrowed)
+    }
+
+    #[test]
+    fn rewrites_fields() -> Result<(), String> {
+        let script = Script::compile(
+            r#"
+            if record.signature.contains("SSH") { record.category = "AWARE ssh"; }
+            record.priority = 2;
+            "#,
+        )?;
+        let fields = script.run(fields())?.ok_or("dropped")?;
+        assert_eq!(fields, ["2", "ET SCAN SSH", "AWARE ssh", "10.0.0.1", "10.0.0.2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn drops_records() -> Result<(), String> {
+        let script = Script::compile(r#"record.source != "10.0.0.1""#)?;
+        assert_eq!(script.run(fields())?, None);
+        let script = Script::compile(r#"record.source != "10.0.0.9""#)?;
+        assert!(script.run(fields())?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_and_failing_scripts() -> Result<(), String> {
+        assert!(Script::compile("record.priority = ").is_err());
+        assert!(Script::compile("loop {}")?.run(fields()).is_err());
+        assert!(Script::compile("record = 1;")?.run(fields()).is_err());
+        Ok(())
+    }
+}
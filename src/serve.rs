@@ -0,0 +1,477 @@
+//! Grafana JSON datasource server.
+//!
+//! `serve` re-runs the aggregation over the log directory every `refresh_secs` seconds,
+//! like the `tui` subcommand, and answers the HTTP requests of the Grafana simple JSON
+//! datasource (also accepted by the Infinity datasource's JSON backend), so panels can
+//! query the aggregates directly:
+//!
+//! - `GET /` answers `200 OK`, for the datasource's connection test.
+//! - `POST /search` lists the queryable targets (`TARGETS`).
+//! - `POST /query` returns the requested targets. `AWARE Threats` and `Threat Score` are
+//!   time series with a data point per half-day bucket within the requested `range`.
+//!   `Priorities`, `Threat Sources` and `Threat Destinations` have no time dimension and
+//!   are returned as a table for table queries, or otherwise as one series per label
+//!   with a single data point at the end of the range, which suits stat and bar gauge
+//!   panels.
+//! - `POST /annotations` returns no annotations.
+//! - `GET /healthz` reports when the logs were last aggregated successfully, the last
+//!   aggregation error, and the input lag between the newest record and now, for
+//!   liveness probes. With `--max-staleness`, it answers `503 Service Unavailable`
+//!   once the last successful aggregation or the newest record is older than that.
+//!
+//! With `--grpc <address>` (in builds with the `grpc` feature), the same aggregates are
+//! also served over gRPC on that address, as defined in `proto/dashboard.proto` (see the
+//! `grpc` module).
+//!
+//! Requests are served one at a time over plain HTTP; put a reverse proxy in front for
+//! TLS or authentication. So that a slow or misbehaving client cannot hold up the probes
+//! for long, each request must arrive within `REQUEST_TIMEOUT`, and its request line and
+//! headers within `MAX_HEAD` bytes and `MAX_HEADERS` headers.
+
+use crate::anonymize::Anonymizer;
+use crate::cli::Options;
+use crate::systemd;
+use crate::{aggregate, guard_files, list_log_files, rfc3339, FileSinks, Summary};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone};
+use dashboard_aggregator::config::Config;
+use dashboard_aggregator::log;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The targets that can be queried.
+const TARGETS: [&str; 5] = [
+    "Priorities",
+    "Threat Sources",
+    "Threat Destinations",
+    "AWARE Threats",
+    "Threat Score",
+];
+
+/// Time after which a client that has not sent its whole request is disconnected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest request line and headers accepted, in bytes.
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// The most headers accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// The largest request body accepted, in bytes.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// What is served: the latest aggregates, and how fresh they are.
+///
+/// # Fields
+/// - `summary`: The aggregates of the last successful aggregation.
+/// - `newest`: The date and time of the newest record in `summary`, if any.
+/// - `loaded`: When the last successful aggregation finished.
+/// - `last_error`: When the last failed aggregation finished, and why it failed.
+pub struct State {
+    pub summary: Summary,
+    pub newest: Option<NaiveDateTime>,
+    pub loaded: DateTime<Local>,
+    pub last_error: Option<(DateTime<Local>, String)>,
+}
+
+/// Serves the aggregates on `address` until the process is interrupted.
+///
+/// # Arguments
+/// - `options`: The parsed command line, providing the log directory, cutoff, and filters.
+/// - `config`: The configuration, e.g. for priority labels.
+/// - `address`: The address to listen on.
+/// - `refresh_secs`: The number of seconds to wait between aggregations.
+///
+/// # Errors
+/// Returns an error if the first aggregation fails or the address cannot be bound. Later
+/// aggregations that fail are reported as warnings, and the previous aggregates served.
+pub fn run(
+    options: &Options,
+    config: &Config,
+    address: SocketAddr,
+    refresh_secs: u64,
+) -> io::Result<()> {
+    let anonymizer = Anonymizer::load(options)?;
+    let (summary, newest) = load(options, config, anonymizer.as_ref())?;
+    let state = Arc::new(Mutex::new(State {
+        summary,
+        newest,
+        loaded: Local::now(),
+        last_error: None,
+    }));
+    let listener = TcpListener::bind(address)?;
+    let serving = format!(
+        "Serving the Grafana JSON datasource on http://{} (refresh every {refresh_secs}s)",
+        listener.local_addr()?
+    );
+    log::info(&serving);
+    systemd::ready(&serving);
+
+    thread::scope(|scope| {
+        #[cfg(feature = "grpc")]
+        if let Some(address) = options.grpc {
+            let state = Arc::clone(&state);
+            scope.spawn(move || {
+                if let Err(e) = crate::grpc::serve(address, state) {
+                    log::error(format_args!("the gRPC service stopped: {e}"));
+                }
+            });
+        }
+        scope.spawn(|| loop {
+            systemd::sleep(Duration::from_secs(refresh_secs));
+            let loaded = load(options, config, anonymizer.as_ref());
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            match loaded {
+                Ok((summary, newest)) => {
+                    (state.summary, state.newest) = (summary, newest);
+                    state.loaded = Local::now();
+                }
+                Err(e) => {
+                    log::warn(format_args!("serving the previous aggregates: {e}"));
+                    state.last_error = Some((Local::now(), e.to_string()));
+                }
+            }
+        });
+        for stream in listener.incoming() {
+            if let Err(e) = stream.and_then(|stream| handle(stream, &state, options)) {
+                log::warn(format_args!("failed to answer a request: {e}"));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Aggregates the log files selected by `options` and summarizes them.
+///
+/// # Returns
+/// The summary, and the date and time of the newest record, if any.
+///
+/// # Errors
+/// Returns an error if the log directory or a log file cannot be read.
+fn load(
+    options: &Options,
+    config: &Config,
+    anonymizer: Option<&Anonymizer>,
+) -> io::Result<(Summary, Option<NaiveDateTime>)> {
+    let files = guard_files(list_log_files(options)?, options, false);
+    let (mut data, errors) = aggregate(&files, options, config, false, FileSinks::default());
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e);
+    }
+    if let Some(anonymizer) = anonymizer {
+        anonymizer.apply(&mut data);
+    }
+    Ok((Summary::new(&data), data.newest()))
+}
+
+/// Reads one request from `stream` and writes the response from the aggregates in
+/// `state`, which is only locked once the request has been read.
+///
+/// # Errors
+/// Returns an error if the request cannot be read or the response cannot be written.
+fn handle(mut stream: TcpStream, state: &Mutex<State>, options: &Options) -> io::Result<()> {
+    let mut reader = BufReader::new(Deadline {
+        stream: &stream,
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+    });
+    let (status, body) = match read_head(&mut reader)? {
+        None => (
+            "431 Request Header Fields Too Large",
+            json!({ "error": "request headers too large" }),
+        ),
+        Some((_, content_length)) if content_length > MAX_BODY => (
+            "413 Payload Too Large",
+            json!({ "error": "request too large" }),
+        ),
+        Some((request_line, content_length)) => {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            let mut parts = request_line.split_whitespace();
+            let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            route(method, path, &body, &state, options)
+        }
+    };
+
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Reads from a stream until `deadline`, after which reads fail with
+/// `io::ErrorKind::TimedOut`, however slowly the data keeps arriving.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request not received in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Reads the request line and headers of a request from `reader`.
+///
+/// # Returns
+/// The request line and the `Content-Length`, or `None` if the request line and headers
+/// are longer than `MAX_HEAD` bytes or there are more than `MAX_HEADERS` headers.
+///
+/// # Errors
+/// Returns an error if the request cannot be read.
+fn read_head(reader: impl BufRead) -> io::Result<Option<(String, usize)>> {
+    let mut head = reader.take(MAX_HEAD);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    for _ in 0..=MAX_HEADERS {
+        let mut header = String::new();
+        let read = head.read_line(&mut header)?;
+        if header.trim().is_empty() {
+            // The blank line ending the headers, or the end of a head cut off at MAX_HEAD
+            let complete = read > 0 || head.limit() > 0;
+            return Ok(complete.then_some((request_line, content_length)));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the status and body of the response to a `method` request for `path`.
+fn route(
+    method: &str,
+    path: &str,
+    body: &Value,
+    state: &State,
+    options: &Options,
+) -> (&'static str, Value) {
+    let path = path.split('?').next().unwrap_or(path);
+    let summary = &state.summary;
+    match (method, path.trim_end_matches('/')) {
+        ("GET", "") => ("200 OK", json!({ "status": "ok" })),
+        ("GET", "/healthz") => health(state, options.max_staleness),
+        ("POST", "/search") => ("200 OK", json!(TARGETS)),
+        ("POST", "/query") => ("200 OK", query(body, summary)),
+        ("POST", "/annotations") => ("200 OK", json!([])),
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    }
+}
+
+/// Answers a `/healthz` request, which fails once the last successful aggregation or
+/// the newest record is older than `max_staleness`, if set.
+fn health(state: &State, max_staleness: Option<Duration>) -> (&'static str, Value) {
+    let now = Local::now();
+    let age = (now - state.loaded).to_std().unwrap_or_default();
+    let lag = state
+        .newest
+        .map(|newest| (now.naive_local() - newest).to_std().unwrap_or_default());
+    let unhealthy = max_staleness.is_some_and(|max| age > max || lag.is_some_and(|lag| lag > max));
+    let time = |time: DateTime<Local>| time.to_rfc3339_opts(SecondsFormat::Secs, false);
+    let body = json!({
+        "status": if unhealthy { "stale" } else { "ok" },
+        "last_success": time(state.loaded),
+        "last_error": state.last_error.as_ref().map(|(at, message)| {
+            json!({ "time": time(*at), "message": message })
+        }),
+        "newest_record": state.newest.map(rfc3339),
+        "input_lag_secs": lag.map(|lag| lag.as_secs()),
+        "max_staleness_secs": max_staleness.map(|max| max.as_secs()),
+    });
+    let status = if unhealthy {
+        "503 Service Unavailable"
+    } else {
+        "200 OK"
+    };
+    (status, body)
+}
+
+/// Answers a `/query` request.
+fn query(body: &Value, summary: &Summary) -> Value {
+    let range = |bound: &str| {
+        body.pointer(&format!("/range/{bound}"))
+            .and_then(Value::as_str)
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.timestamp_millis())
+    };
+    let from = range("from").unwrap_or(i64::MIN);
+    let now = Local::now().timestamp_millis();
+    let to = range("to").map_or(now, |to| to.min(now));
+
+    let mut results = Vec::new();
+    let targets = body.get("targets").and_then(Value::as_array);
+    for target in targets.into_iter().flatten() {
+        let name = target.get("target").and_then(Value::as_str).unwrap_or("");
+        let table = target.get("type").and_then(Value::as_str) == Some("table");
+        match name {
+            "AWARE Threats" => {
+                let counts = summary.aware_threats.iter();
+                let points = counts.map(|(bucket, count)| (bucket, u64::from(*count)));
+                results.push(series(name, points, from, to));
+            }
+            "Threat Score" => {
+                let points = summary
+                    .threat_score
+                    .iter()
+                    .map(|(bucket, score)| (bucket, *score));
+                results.push(series(name, points, from, to));
+            }
+            "Priorities" => counts(&mut results, "Priority", &summary.priorities, table, to),
+            "Threat Sources" => counts(&mut results, "Source", &summary.top_sources, table, to),
+            "Threat Destinations" => {
+                counts(
+                    &mut results,
+                    "Destination",
+                    &summary.top_destinations,
+                    table,
+                    to,
+                );
+            }
+            _ => {}
+        }
+    }
+    Value::Array(results)
+}
+
+/// Returns the time series `target` of `points`, keeping those from `from` to `to`
+/// milliseconds since the epoch.
+fn series<'a>(
+    target: &str,
+    points: impl Iterator<Item = (&'a String, u64)>,
+    from: i64,
+    to: i64,
+) -> Value {
+    let datapoints: Vec<Value> = points
+        .filter_map(|(bucket, value)| {
+            let time = bucket_millis(bucket)?;
+            (from..=to).contains(&time).then(|| json!([value, time]))
+        })
+        .collect();
+    json!({ "target": target, "datapoints": datapoints })
+}
+
+/// Adds the labelled `counts` to `results`, as a table with a `label` column if `table`
+/// is set, and otherwise as one series per label with a single data point at `time`.
+fn counts(results: &mut Vec<Value>, label: &str, counts: &[(String, u32)], table: bool, time: i64) {
+    if table {
+        results.push(json!({
+            "type": "table",
+            "columns": [
+                { "text": label, "type": "string" },
+                { "text": "Count", "type": "number" },
+            ],
+            "rows": counts
+                .iter()
+                .map(|(label, count)| json!([label, count]))
+                .collect::<Vec<_>>(),
+        }));
+    } else {
+        results.extend(
+            counts
+                .iter()
+                .map(|(label, count)| json!({ "target": label, "datapoints": [[count, time]] })),
+        );
+    }
+}
+
+/// Returns the start of a time bucket such as `2024-04-01 PM`, in milliseconds since
+/// the epoch. Log times are local times, like the `days_back` cutoff.
+fn bucket_millis(bucket: &str) -> Option<i64> {
+    let (date, period) = bucket.split_once(' ')?;
+    let hour = if period == "PM" { 12 } else { 0 };
+    let start = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(hour, 0, 0)?;
+    Some(
+        Local
+            .from_local_datetime(&start)
+            .earliest()?
+            .timestamp_millis(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(request: &str) -> io::Result<Option<(String, usize)>> {
+        read_head(request.as_bytes())
+    }
+
+    #[test]
+    fn heads_within_the_limits_are_read() -> io::Result<()> {
+        let request = "POST /query HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(
+            head(request)?,
+            Some(("POST /query HTTP/1.1\r\n".to_string(), 2))
+        );
+        // A client that closes the connection after the headers
+        assert_eq!(
+            head("GET /healthz HTTP/1.1\r\n")?,
+            Some(("GET /healthz HTTP/1.1\r\n".to_string(), 0))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn heads_beyond_the_limits_are_rejected() -> io::Result<()> {
+        let long_header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(20_000));
+        assert_eq!(head(&long_header)?, None);
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(20_000));
+        assert_eq!(head(&long_line)?, None);
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(101));
+        assert_eq!(head(&many_headers)?, None);
+        let enough_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(100));
+        assert!(head(&enough_headers)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn slow_requests_time_out() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        // The client keeps sending its request line a byte at a time
+        let trickle = thread::spawn(move || {
+            for byte in b"GET /healthz HTTP/1.1" {
+                thread::sleep(Duration::from_millis(50));
+                if (&client).write_all(&[*byte]).is_err() {
+                    break;
+                }
+            }
+        });
+        let start = Instant::now();
+        let mut reader = BufReader::new(Deadline {
+            stream: &server,
+            deadline: start + Duration::from_millis(200),
+        });
+        let error = read_head(&mut reader).err();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(error.is_some_and(|e| matches!(
+            e.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        )));
+        drop(server);
+        let _ = trickle.join();
+        Ok(())
+    }
+}
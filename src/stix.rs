@@ -0,0 +1,214 @@
+//! STIX 2.1 bundle export.
+//!
+//! With `--stix <file>`, the aggregates are also written as a STIX 2.1 bundle for import
+//! into TAXII-speaking threat intelligence platforms:
+//!
+//! - Every top source and destination becomes an `ipv4-addr` or `ipv6-addr` observable
+//!   with an `observed-data` object counting its events over the aggregation window,
+//!   labelled `threat-source` or `threat-destination`.
+//! - Every top source also becomes an `indicator` with a STIX pattern matching it, unless
+//!   the addresses are anonymized.
+//! - Every top signature becomes an `attack-pattern` with a `sighting` counting its
+//!   events, as STIX has no observable for IDS signature names.
+//!
+//! Observables get name-based version 5 UUIDs as recommended by the specification, so
+//! the same address has the same ID in every bundle; all other objects get unique IDs.
+
+use crate::cli::Options;
+use chrono::{DateTime, Duration, Utc};
+use dashboard_aggregator::metric::{Addresses, Signatures};
+use dashboard_aggregator::AggregatedData;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of sources, destinations and signatures included in the bundle.
+const TOP_ENTRIES: usize = 100;
+
+/// Namespace of the version 5 UUIDs of STIX observables.
+const STIX_NAMESPACE: [u8; 16] = [
+    0x00, 0xab, 0xed, 0xb4, 0xaa, 0x42, 0x4a, 0xc8, 0xad, 0x10, 0x2a, 0x2f, 0x1d, 0xe8, 0x8b, 0x97,
+];
+
+/// Largest count allowed by `number_observed` and `count`.
+const MAX_COUNT: u32 = 999_999_999;
+
+/// Number of unique identifiers generated so far, mixed into each one.
+static GENERATED: AtomicU64 = AtomicU64::new(0);
+
+/// Formats 16 bytes as a UUID with the given version, setting the RFC 4122 variant.
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let mut uuid = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            uuid.push('-');
+        }
+        let _ = write!(uuid, "{byte:02x}");
+    }
+    uuid
+}
+
+/// Returns the STIX ID of an observable of type `kind` with the given ID contributing
+/// properties, as a version 5 UUID of their canonical JSON.
+fn observable_id(kind: &str, properties: &Value) -> String {
+    let mut name = STIX_NAMESPACE.to_vec();
+    name.extend_from_slice(properties.to_string().as_bytes());
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&Sha1::digest(&name)[..16]);
+    format!("{kind}--{}", format_uuid(bytes, 5))
+}
+
+/// Returns a new unique STIX ID for an object of type `kind`, as a version 4 UUID.
+///
+/// The crate has no random number generator, so the bits are a hash of the time, the
+/// process ID and a counter, which is unique without being unpredictable.
+fn unique_id(kind: &str) -> String {
+    let mut hash = Sha256::new();
+    hash.update(Utc::now().timestamp_nanos_opt().unwrap_or(0).to_le_bytes());
+    hash.update(process::id().to_le_bytes());
+    hash.update(GENERATED.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hash.update(kind.as_bytes());
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&hash.finalize()[..16]);
+    format!("{kind}--{}", format_uuid(bytes, 4))
+}
+
+/// Formats a STIX timestamp, in UTC with millisecond precision.
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// Builds the objects describing the addresses in `top`.
+///
+/// # Arguments
+/// - `objects`: The bundle objects to add to.
+/// - `top`: The addresses and their counts.
+/// - `label`: The label of the `observed-data` objects.
+/// - `indicators`: Whether to also add an indicator per address.
+/// - `window`: The first and last timestamp of the aggregation window.
+fn addresses(
+    objects: &mut Vec<Value>,
+    top: &[(String, u32)],
+    label: &str,
+    indicators: bool,
+    window: (&str, &str),
+) {
+    let (start, now) = window;
+    for (address, count) in top {
+        // Anonymized pseudonyms and malformed values have no observable type
+        let Ok(ip) = address.parse::<IpAddr>() else {
+            continue;
+        };
+        let kind = if ip.is_ipv4() {
+            "ipv4-addr"
+        } else {
+            "ipv6-addr"
+        };
+        let id = observable_id(kind, &json!({ "value": address }));
+        // An address seen as both source and destination is only listed once
+        if !objects.iter().any(|object| object["id"] == id) {
+            objects.push(json!({
+                "type": kind,
+                "spec_version": "2.1",
+                "id": id,
+                "value": address
+            }));
+        }
+        objects.push(json!({
+            "type": "observed-data",
+            "spec_version": "2.1",
+            "id": unique_id("observed-data"),
+            "created": now,
+            "modified": now,
+            "first_observed": start,
+            "last_observed": now,
+            "number_observed": (*count).clamp(1, MAX_COUNT),
+            "object_refs": [id],
+            "labels": [label]
+        }));
+        if indicators {
+            objects.push(json!({
+                "type": "indicator",
+                "spec_version": "2.1",
+                "id": unique_id("indicator"),
+                "created": now,
+                "modified": now,
+                "name": format!("Threat source {address}"),
+                "description": format!("{count} firewall events from {start} to {now}"),
+                "indicator_types": ["malicious-activity"],
+                "pattern": format!("[{kind}:value = '{address}']"),
+                "pattern_type": "stix",
+                "valid_from": now
+            }));
+        }
+    }
+}
+
+/// Builds the STIX bundle for `data`.
+fn bundle(options: &Options, data: &AggregatedData) -> Value {
+    let now = Utc::now();
+    let start = timestamp(&(now - Duration::days(options.days_back)));
+    let now = timestamp(&now);
+    let window = (start.as_str(), now.as_str());
+
+    let mut objects = Vec::new();
+    if let Some(sources) = data.metric::<Addresses>("Threat Sources") {
+        let indicators = options.anonymize.is_none();
+        let top = sources.top(TOP_ENTRIES);
+        addresses(&mut objects, &top, "threat-source", indicators, window);
+    }
+    if let Some(destinations) = data.metric::<Addresses>("Threat Destinations") {
+        let top = destinations.top(TOP_ENTRIES);
+        addresses(&mut objects, &top, "threat-destination", false, window);
+    }
+    if let Some(signatures) = data.metric::<Signatures>("Top Signatures") {
+        for (signature, count) in signatures.top(TOP_ENTRIES) {
+            if signature.is_empty() {
+                continue;
+            }
+            let id = unique_id("attack-pattern");
+            objects.push(json!({
+                "type": "attack-pattern",
+                "spec_version": "2.1",
+                "id": id,
+                "created": now,
+                "modified": now,
+                "name": signature
+            }));
+            objects.push(json!({
+                "type": "sighting",
+                "spec_version": "2.1",
+                "id": unique_id("sighting"),
+                "created": now,
+                "modified": now,
+                "first_seen": start,
+                "last_seen": now,
+                "count": count.min(MAX_COUNT),
+                "sighting_of_ref": id
+            }));
+        }
+    }
+
+    json!({
+        "type": "bundle",
+        "id": unique_id("bundle"),
+        "objects": objects
+    })
+}
+
+/// Writes the STIX bundle for `data` to `path`.
+///
+/// # Errors
+/// Returns an error if the file cannot be written.
+pub fn write(path: &Path, options: &Options, data: &AggregatedData) -> io::Result<()> {
+    fs::write(path, serde_json::to_string_pretty(&bundle(options, data))?)
+}
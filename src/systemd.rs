@@ -0,0 +1,130 @@
+//! systemd service notifications.
+//!
+//! When run as a `Type=notify` unit, the long-running modes (`--loop`, `--follow`,
+//! `serve` and `netflow-listen`) report to systemd over `$NOTIFY_SOCKET` once they are
+//! ready (`READY=1`), what they last did (`STATUS=`), and, if the unit sets
+//! `WatchdogSec=`, that they are still making progress (`WATCHDOG=1`).
+//!
+//! Watchdog pings are only sent from the code that makes progress: between files,
+//! between runs and while waiting for the next one, never from a timer of their own. A
+//! run that hangs, e.g. on a dead NFS mount, stops pinging, and systemd restarts the
+//! service once `WatchdogSec` has passed. It must therefore be longer than the slowest
+//! file takes to read.
+//!
+//! Socket activation is not supported, as taking over a listening socket passed by
+//! systemd requires unsafe code, which the crate forbids. Outside systemd,
+//! `NOTIFY_SOCKET` is not set and nothing is sent.
+
+use crate::interrupt;
+use dashboard_aggregator::log;
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::process;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest time `sleep` goes without checking whether the process was interrupted.
+const INTERRUPT_POLL: Duration = Duration::from_secs(1);
+
+/// When the watchdog was last pinged.
+static LAST_PING: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Tells systemd that startup is complete, with `status` as the status of the unit.
+pub fn ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={status}"));
+}
+
+/// Sets the status of the unit shown by `systemctl status`.
+pub fn status(status: &str) {
+    notify(&format!("STATUS={status}"));
+}
+
+/// Pings the watchdog, if it is enabled for this process. Pings are sent at most four
+/// times per watchdog interval, so this can be called after every unit of progress.
+pub fn watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    let due = {
+        let mut last = LAST_PING.lock().unwrap_or_else(PoisonError::into_inner);
+        let due = last.map_or(true, |last| last.elapsed() >= interval / 4);
+        if due {
+            *last = Some(Instant::now());
+        }
+        due
+    };
+    if due {
+        notify("WATCHDOG=1");
+    }
+}
+
+/// Sleeps for `duration`, or until the process is interrupted, pinging the watchdog at
+/// least twice per watchdog interval meanwhile, as waiting for the next run is progress
+/// too.
+pub fn sleep(duration: Duration) {
+    let step = watchdog_interval()
+        .map_or(duration, |interval| interval / 2)
+        .min(INTERRUPT_POLL);
+    let deadline = Instant::now() + duration;
+    loop {
+        watchdog();
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || interrupt::requested() {
+            break;
+        }
+        thread::sleep(left.min(step));
+    }
+}
+
+/// Returns the interval within which systemd expects watchdog pings, or `None` if the
+/// watchdog is not enabled for this process.
+fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // A watchdog set for another process, such as a wrapping shell, is not ours to ping
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
+
+/// Sends `message` to the notification socket of systemd, if there is one. A failure
+/// is logged as a warning, as the service itself is unaffected.
+fn notify(message: &str) {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(socket, message) {
+        log::warn(format_args!("failed to notify systemd: {e}"));
+    }
+}
+
+/// Sends `message` as a datagram to the Unix socket at `path`, which is in the abstract
+/// namespace if it starts with `@`.
+///
+/// # Errors
+/// Returns an error if the socket cannot be created or the message cannot be sent.
+#[cfg(target_os = "linux")]
+fn send(path: OsString, message: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => {
+            socket.send_to_addr(message.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+        }
+        None => socket.send_to(message.as_bytes(), path),
+    }
+    .map(|_| ())
+}
+
+/// systemd only runs on Linux, so there is nothing to notify elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn send(_path: OsString, _message: &str) -> io::Result<()> {
+    Ok(())
+}
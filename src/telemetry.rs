@@ -0,0 +1,312 @@
+//! OpenTelemetry traces and metrics of a run.
+//!
+//! With `--otel-endpoint <url>`, a run records a `run` span, with a child `file` span for
+//! every log file it reads, and exports them to `<url>/v1/traces` once the outputs are
+//! written, followed by these metrics to `<url>/v1/metrics`:
+//!
+//! - `dashboard_aggregator.records.processed`: The records aggregated.
+//! - `dashboard_aggregator.records.skipped`: The records that could not be parsed
+//!   (`reason` `parse_error`) or had an empty priority, source or destination (`empty`).
+//! - `dashboard_aggregator.files`: The log files read (`outcome` `read`) or that could
+//!   not be read (`failed`).
+//! - `dashboard_aggregator.file.duration`: A histogram of the time taken per file.
+//! - `dashboard_aggregator.run.duration`: The time taken by the run.
+//!
+//! Both are sent with the OTLP/HTTP JSON encoding through `curl` (see the `http` module),
+//! so any OpenTelemetry collector listening on port 4318 can receive them. A failed
+//! export is reported as a warning and does not fail the run.
+
+use crate::cli::Options;
+use crate::http;
+use dashboard_aggregator::AggregatedData;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Value of the `service.name` resource attribute.
+const SERVICE_NAME: &str = "dashboard_aggregator";
+
+/// Prefix of the names of the exported metrics.
+const METRIC_PREFIX: &str = "dashboard_aggregator";
+
+/// OTLP span kind of all spans: internal.
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// OTLP status code of spans that succeeded.
+const STATUS_OK: u8 = 1;
+
+/// OTLP status code of spans that failed.
+const STATUS_ERROR: u8 = 2;
+
+/// OTLP aggregation temporality of the sums and histograms: cumulative over the run.
+const CUMULATIVE: u8 = 2;
+
+/// The telemetry recorded so far in a run.
+///
+/// # Fields
+/// - `endpoint`: The base URL of the OTLP/HTTP receiver.
+/// - `seed`: Makes the IDs of this run's trace and spans unique.
+/// - `ids`: How many IDs have been generated.
+/// - `trace_id`: The ID of the run's trace.
+/// - `run_span_id`: The ID of the `run` span, the parent of the `file` spans.
+/// - `start`: When the run started.
+/// - `spans`: The `file` spans recorded so far.
+/// - `durations`: The time taken by each file, in seconds.
+pub struct Telemetry {
+    endpoint: String,
+    seed: Vec<u8>,
+    ids: u64,
+    trace_id: String,
+    run_span_id: String,
+    start: SystemTime,
+    spans: Vec<Value>,
+    durations: Vec<f64>,
+}
+
+impl Telemetry {
+    /// Starts recording the run, if `--otel-endpoint` is set.
+    pub fn start(options: &Options) -> Option<Self> {
+        let endpoint = options.otel_endpoint.as_ref()?;
+        let start = SystemTime::now();
+        let mut seed = nanos(start).into_bytes();
+        seed.extend_from_slice(&process::id().to_le_bytes());
+        let mut telemetry = Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            seed,
+            ids: 0,
+            trace_id: String::new(),
+            run_span_id: String::new(),
+            start,
+            spans: Vec::new(),
+            durations: Vec::new(),
+        };
+        telemetry.trace_id = telemetry.id(16);
+        telemetry.run_span_id = telemetry.id(8);
+        Some(telemetry)
+    }
+
+    /// Returns a new hexadecimal ID of `bytes` bytes.
+    fn id(&mut self, bytes: usize) -> String {
+        self.ids += 1;
+        let mut hash = Sha256::new();
+        hash.update(&self.seed);
+        hash.update(self.ids.to_le_bytes());
+        hex::encode(&hash.finalize()[..bytes])
+    }
+
+    /// Records a `file` span for the log file at `path`, which took `elapsed` from
+    /// `started` to read into `result`.
+    pub fn file(
+        &mut self,
+        path: &Path,
+        started: SystemTime,
+        elapsed: Duration,
+        result: &io::Result<AggregatedData>,
+    ) {
+        let mut attributes = vec![attribute("file.path", json!(path.display().to_string()))];
+        let status = match result {
+            Ok(data) => {
+                attributes.push(attribute("records", json!(data.records())));
+                attributes.push(attribute("parse_errors", json!(data.parse_errors())));
+                json!({ "code": STATUS_OK })
+            }
+            Err(e) => json!({ "code": STATUS_ERROR, "message": e.to_string() }),
+        };
+        let span_id = self.id(8);
+        self.spans.push(json!({
+            "traceId": self.trace_id,
+            "spanId": span_id,
+            "parentSpanId": self.run_span_id,
+            "name": "file",
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": nanos(started),
+            "endTimeUnixNano": nanos(started + elapsed),
+            "attributes": attributes,
+            "status": status,
+        }));
+        self.durations.push(elapsed.as_secs_f64());
+    }
+
+    /// Ends the `run` span and exports the spans and metrics of the run.
+    ///
+    /// # Arguments
+    /// - `options`: The parsed command line.
+    /// - `data`: The merged aggregates of the run.
+    /// - `files`: How many log files matched.
+    /// - `failed_files`: How many of the matched files could not be read.
+    ///
+    /// # Errors
+    /// Returns an error if the traces or metrics cannot be sent.
+    pub fn export(
+        mut self,
+        options: &Options,
+        data: &AggregatedData,
+        files: usize,
+        failed_files: usize,
+    ) -> io::Result<()> {
+        let end = SystemTime::now();
+        let status = if failed_files == 0 {
+            json!({ "code": STATUS_OK })
+        } else {
+            json!({ "code": STATUS_ERROR, "message": format!("{failed_files} files failed") })
+        };
+        self.spans.push(json!({
+            "traceId": self.trace_id,
+            "spanId": self.run_span_id,
+            "name": "run",
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(end),
+            "attributes": [
+                attribute("log.path", json!(options.log_path.display().to_string())),
+                attribute("days_back", json!(options.days_back)),
+                attribute("files", json!(files)),
+            ],
+            "status": status,
+        }));
+        let traces = json!({
+            "resourceSpans": [{
+                "resource": resource(),
+                "scopeSpans": [{ "scope": scope(), "spans": self.spans }],
+            }],
+        });
+        self.post("traces", &traces)?;
+
+        let metrics = json!({
+            "resourceMetrics": [{
+                "resource": resource(),
+                "scopeMetrics": [{
+                    "scope": scope(),
+                    "metrics": self.metrics(data, files, failed_files, end),
+                }],
+            }],
+        });
+        self.post("metrics", &metrics)
+    }
+
+    /// Returns the metrics of a run that ended at `end`.
+    fn metrics(
+        &self,
+        data: &AggregatedData,
+        files: usize,
+        failed_files: usize,
+        end: SystemTime,
+    ) -> Value {
+        let run_duration = end.duration_since(self.start).unwrap_or_default();
+        let (start, end) = (nanos(self.start), nanos(end));
+        let sum = |name: &str, unit: &str, points: Vec<(Option<(&str, &str)>, u64)>| {
+            let points: Vec<Value> = points
+                .into_iter()
+                .map(|(attr, value)| {
+                    json!({
+                        "attributes": attr
+                            .map(|(key, value)| attribute(key, json!(value)))
+                            .into_iter()
+                            .collect::<Vec<_>>(),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": end,
+                        "asInt": value.to_string(),
+                    })
+                })
+                .collect();
+            json!({
+                "name": format!("{METRIC_PREFIX}.{name}"),
+                "unit": unit,
+                "sum": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "isMonotonic": true,
+                    "dataPoints": points,
+                },
+            })
+        };
+        let read = files.saturating_sub(failed_files) as u64;
+        let durations = &self.durations;
+        json!([
+            sum("records.processed", "{record}", vec![(None, data.records())]),
+            sum(
+                "records.skipped",
+                "{record}",
+                vec![
+                    (Some(("reason", "parse_error")), data.parse_errors()),
+                    (Some(("reason", "empty")), data.empty_records()),
+                ],
+            ),
+            sum(
+                "files",
+                "{file}",
+                vec![
+                    (Some(("outcome", "read")), read),
+                    (Some(("outcome", "failed")), failed_files as u64),
+                ],
+            ),
+            {
+                "name": format!("{METRIC_PREFIX}.file.duration"),
+                "unit": "s",
+                "histogram": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "dataPoints": [{
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": end,
+                        "count": durations.len().to_string(),
+                        "sum": durations.iter().sum::<f64>(),
+                        "min": durations.iter().copied().reduce(f64::min),
+                        "max": durations.iter().copied().reduce(f64::max),
+                        "bucketCounts": [durations.len().to_string()],
+                        "explicitBounds": [],
+                    }],
+                },
+            },
+            {
+                "name": format!("{METRIC_PREFIX}.run.duration"),
+                "unit": "s",
+                "gauge": {
+                    "dataPoints": [{ "timeUnixNano": end, "asDouble": run_duration.as_secs_f64() }],
+                },
+            },
+        ])
+    }
+
+    /// POSTs `body` to the OTLP/HTTP receiver of `signal`, `traces` or `metrics`.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    fn post(&self, signal: &str, body: &Value) -> io::Result<()> {
+        let url = format!("{}/v1/{signal}", self.endpoint);
+        let headers = ["Content-Type: application/json".to_string()];
+        http::request("POST", &url, &headers, Some(&body.to_string())).map(drop)
+    }
+}
+
+/// Returns `time` in nanoseconds since the epoch, as OTLP/JSON encodes 64-bit integers.
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Returns an OTLP attribute, typed after `value`.
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::String(text) => json!({ "stringValue": text }),
+        Value::Number(number) if number.is_f64() => json!({ "doubleValue": number }),
+        // 64-bit integers are encoded as strings
+        Value::Number(number) => json!({ "intValue": number.to_string() }),
+        Value::Bool(flag) => json!({ "boolValue": flag }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// Returns the OTLP resource describing this program.
+fn resource() -> Value {
+    json!({ "attributes": [attribute("service.name", json!(SERVICE_NAME))] })
+}
+
+/// Returns the OTLP instrumentation scope of this program.
+fn scope() -> Value {
+    json!({ "name": SERVICE_NAME, "version": env!("CARGO_PKG_VERSION") })
+}
@@ -0,0 +1,185 @@
+//! Template rendering for custom report formats.
+//!
+//! Templates are rendered by [Tera](https://keats.github.io/tera/) against a JSON
+//! context, with Tera's built-in filters, tests and tags besides the `number` filter,
+//! which groups the digits of an integer with the `thousands_separator` of the context.
+//! Tera's optional filters that need the `builtins` feature, such as `date` or
+//! `filesizeformat`, are not included.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use tera::{Context, Tera};
+
+/// Name the template is registered under. It has no extension, so its output is not
+/// HTML-escaped.
+const NAME: &str = "report";
+
+/// A parsed template that can be rendered against a JSON context.
+pub struct Template {
+    tera: Tera,
+}
+
+impl Template {
+    /// Parses template source.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error describing the syntax error.
+    pub fn parse(source: &str) -> io::Result<Self> {
+        let mut tera = Tera::default();
+        tera.add_raw_template(NAME, source)
+            .map_err(|e| invalid(&e))?;
+        Ok(Self { tera })
+    }
+
+    /// Renders the template with `context` as the root object.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if `context` is not an object, or rendering fails,
+    /// e.g. on an undefined variable or filter.
+    pub fn render(&self, context: &Value) -> io::Result<String> {
+        let separator = context
+            .get("thousands_separator")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let mut tera = self.tera.clone();
+        tera.register_filter(
+            "number",
+            move |value: &Value, _: &HashMap<String, Value>| {
+                Ok(match value {
+                    Value::Number(number) => {
+                        Value::String(group_digits(&number.to_string(), &separator))
+                    }
+                    _ => value.clone(),
+                })
+            },
+        );
+        let context = Context::from_value(context.clone()).map_err(|e| invalid(&e))?;
+        tera.render(NAME, &context).map_err(|e| invalid(&e))
+    }
+}
+
+/// Returns an `InvalidData` error with the message of `error` and of its causes, which
+/// hold the details of Tera errors.
+fn invalid(error: &tera::Error) -> io::Error {
+    let mut messages = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        messages.push(cause.to_string());
+        source = cause.source();
+    }
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Template error: {}", messages.join(": ")),
+    )
+}
+
+/// Inserts `separator` between each group of three digits of the integer `number`,
+/// leaving numbers with a fraction or exponent as they are.
+pub fn group_digits(number: &str, separator: &str) -> String {
+    let (sign, digits) = number.split_at(usize::from(number.starts_with('-')));
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return number.to_string();
+    }
+    let mut grouped = sign.to_string();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(source: &str, context: &Value) -> io::Result<String> {
+        Template::parse(source)?.render(context)
+    }
+
+    /// Returns the message of the parse or render error of `source`.
+    fn error(source: &str, context: &Value) -> String {
+        render(source, context)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default()
+    }
+
+    fn context() -> Value {
+        json!({
+            "top_sources": [{"ip": "10.0.0.1", "count": 1_234_567}, {"ip": "10.0.0.2", "count": 3}],
+            "events": {"Threat Sources": {"Count": [6, 3]}},
+            "meta": {"version": "0.1.0", "empty": []},
+            "thousands_separator": ",",
+        })
+    }
+
+    #[test]
+    fn expressions_render_paths_and_filters() -> io::Result<()> {
+        let context = context();
+        let cases = [
+            ("{{ top_sources.0.ip }}", "10.0.0.1"),
+            ("{{ top_sources[1].count }}", "3"),
+            ("{{ events[\"Threat Sources\"].Count[1] }}", "3"),
+            ("{{ meta.missing | default(value=\"none\") }}", "none"),
+            ("{{ top_sources | length }}", "2"),
+            ("{{ top_sources.0.ip | upper | lower }}", "10.0.0.1"),
+            ("{{ meta.empty | json_encode() }}", "[]"),
+            ("{{ top_sources.0.count | number }}", "1,234,567"),
+            ("{{ top_sources.1.ip | number }}", "10.0.0.2"),
+            (
+                "{{ top_sources | map(attribute=\"ip\") | join(sep=\" \") }}",
+                "10.0.0.1 10.0.0.2",
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(render(source, &context)?, expected, "{source}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn loops_and_conditionals_nest() -> io::Result<()> {
+        let source = "{% for source in top_sources %}\
+                      {{ loop.index }}:{{ source.ip }}{% if not loop.last %}, {% endif %}\
+                      {% endfor %}";
+        assert_eq!(render(source, &context())?, "1:10.0.0.1, 2:10.0.0.2");
+        let source = "{% for key, value in meta %}[{{ key }}={{ value | length }}]{% endfor %}";
+        assert_eq!(render(source, &context())?, "[empty=0][version=5]");
+        let source = "{% if meta.empty %}some{% else %}none{% endif %}";
+        assert_eq!(render(source, &context())?, "none");
+        Ok(())
+    }
+
+    #[test]
+    fn comments_and_whitespace_control_are_applied() -> io::Result<()> {
+        let source = "a  {#- a comment -#}  b\n{%- if meta -%}\n  c\n{%- endif %} \
+                      {{- meta.version -}} \n";
+        assert_eq!(render(source, &context())?, "abc0.1.0");
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let context = context();
+        assert!(error("{{ meta.version", &context).starts_with("Template error: "));
+        assert!(error("{% for x in meta %}", &context).starts_with("Template error: "));
+        let undefined = error("{{ meta.missing }}", &context);
+        assert!(undefined.contains("meta.missing"), "{undefined}");
+        let filter = error("{{ meta | frobnicate }}", &context);
+        assert!(filter.contains("frobnicate"), "{filter}");
+    }
+
+    #[test]
+    fn digits_are_grouped_in_threes() {
+        assert_eq!(group_digits("1234567", ","), "1,234,567");
+        assert_eq!(group_digits("-100000", " "), "-100 000");
+        assert_eq!(group_digits("999", ","), "999");
+        assert_eq!(group_digits("1234.5", ","), "1234.5");
+    }
+}
@@ -0,0 +1,366 @@
+//! Live-updating terminal dashboard.
+//!
+//! Periodically re-runs the aggregation over the log directory and redraws the
+//! top sources/destinations tables, a priority bar chart, and an AWARE threat
+//! sparkline with ratatui, so it works on any terminal (including jump hosts without a
+//! web dashboard). The dashboard takes over the alternate screen in raw mode until `q`,
+//! Esc or Ctrl-C is pressed, or SIGTERM is received, and then restores the terminal.
+//!
+//! A log file that cannot be read is listed with its error below the charts, and the
+//! dashboard shows the aggregates of the other files, as the file may be readable again
+//! by the next refresh, e.g. once it has been rotated.
+
+use crate::anonymize::Anonymizer;
+use crate::cli::Options;
+use crate::{aggregate, guard_files, interrupt, list_log_files, FileSinks, Summary};
+use chrono::Local;
+use dashboard_aggregator::config::{Config, ReportFormatConfig};
+use dashboard_aggregator::AggregatedData;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Cell, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Longest wait for a key before checking for SIGTERM again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Most file errors listed at once; the others are counted in the title.
+const MAX_ERRORS_SHOWN: usize = 5;
+
+/// Width of the count columns of the top tables.
+const COUNT_WIDTH: u16 = 10;
+
+/// Runs the dashboard until it is quit.
+///
+/// # Arguments
+/// - `options`: The parsed command line, providing the log directory, cutoff, and filters.
+/// - `config`: The configuration, e.g. for priority labels and the number and date formats.
+/// - `refresh_secs`: The number of seconds to wait between refreshes.
+///
+/// # Errors
+/// Returns an error if the anonymization key cannot be read, or the terminal cannot be
+/// set up or written to. The terminal is restored in either case.
+pub fn run(options: &Options, config: &Config, refresh_secs: u64) -> io::Result<()> {
+    let anonymizer = Anonymizer::load(options)?;
+    interrupt::install()?;
+    let mut terminal = ratatui::try_init()?;
+    let result = watch(
+        &mut terminal,
+        options,
+        config,
+        refresh_secs,
+        anonymizer.as_ref(),
+    );
+    let restored = ratatui::try_restore();
+    result.and(restored)
+}
+
+/// Refreshes and draws the dashboard on `terminal` every `refresh_secs` seconds, and
+/// redraws it when the terminal is resized, until it is quit.
+///
+/// # Errors
+/// Returns an error if the terminal cannot be written to or its events read.
+fn watch(
+    terminal: &mut DefaultTerminal,
+    options: &Options,
+    config: &Config,
+    refresh_secs: u64,
+    anonymizer: Option<&Anonymizer>,
+) -> io::Result<()> {
+    loop {
+        let view = View::load(options, config, refresh_secs, anonymizer);
+        terminal.draw(|frame| view.render(frame, &config.report_format))?;
+        let refresh = Instant::now() + Duration::from_secs(refresh_secs);
+        loop {
+            if interrupt::requested() {
+                return Ok(());
+            }
+            let timeout = refresh.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            if !event::poll(timeout.min(POLL_INTERVAL))? {
+                continue;
+            }
+            match event::read()? {
+                Event::Key(key) if quits(key) => return Ok(()),
+                Event::Resize(..) => {
+                    terminal.draw(|frame| view.render(frame, &config.report_format))?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns whether `key` quits the dashboard: `q`, Esc or Ctrl-C, as raw mode turns
+/// Ctrl-C into a key rather than SIGINT.
+fn quits(key: KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press
+        && match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        }
+}
+
+/// One refresh of the dashboard.
+///
+/// # Fields
+/// - `title`: The log directory, cutoff and number of files.
+/// - `status`: When the aggregates were taken, and how to quit.
+/// - `summary`: The aggregates of the files that could be read.
+/// - `errors`: The path and error of each file that could not be read, or of the log
+///   directory if it could not be listed.
+struct View {
+    title: String,
+    status: String,
+    summary: Summary,
+    errors: Vec<(PathBuf, String)>,
+}
+
+impl View {
+    /// Aggregates the log files selected by `options`, keeping the errors of those that
+    /// cannot be read.
+    fn load(
+        options: &Options,
+        config: &Config,
+        refresh_secs: u64,
+        anonymizer: Option<&Anonymizer>,
+    ) -> Self {
+        let mut errors = Vec::new();
+        let (files, mut data) = match list_log_files(options) {
+            Ok(files) => {
+                let files = guard_files(files, options, false);
+                let sinks = FileSinks {
+                    failed: Some(&mut errors),
+                    ..FileSinks::default()
+                };
+                let (data, _) = aggregate(&files, options, config, false, sinks);
+                (files.len(), data)
+            }
+            Err(e) => {
+                errors.push((options.log_path.clone(), e.to_string()));
+                (0, AggregatedData::new(config))
+            }
+        };
+        if let Some(anonymizer) = anonymizer {
+            anonymizer.apply(&mut data);
+        }
+        Self {
+            title: format!(
+                "Dashboard Aggregator - {} - last {} days - {files} files",
+                options.log_path.display(),
+                options.days_back,
+            ),
+            status: format!(
+                "Updated {} (every {refresh_secs}s, q to quit)",
+                config.report_format.datetime(Local::now().naive_local()),
+            ),
+            summary: Summary::new(&data),
+            errors,
+        }
+    }
+
+    /// Draws the dashboard on `frame`, with numbers and dates as set by `format`.
+    fn render(&self, frame: &mut Frame, format: &ReportFormatConfig) {
+        let priorities = u16::try_from(self.summary.priorities.len()).unwrap_or(u16::MAX);
+        let errors = if self.errors.is_empty() {
+            0
+        } else {
+            // At least 1, and at most MAX_ERRORS_SHOWN
+            u16::try_from(self.errors.len().min(MAX_ERRORS_SHOWN)).unwrap_or(1) + 2
+        };
+        let [header, chart, tables, aware, failed] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Length(priorities.max(1).saturating_add(2)),
+            Constraint::Min(5),
+            Constraint::Length(4),
+            Constraint::Length(errors),
+        ])
+        .areas(frame.area());
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(self.title.as_str()),
+                Line::from(self.status.as_str()),
+            ]),
+            header,
+        );
+        frame.render_widget(self.priorities(format), chart);
+        let [sources, destinations] =
+            Layout::horizontal([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)]).areas(tables);
+        frame.render_widget(
+            top("Top Sources", &self.summary.top_sources, format),
+            sources,
+        );
+        frame.render_widget(
+            top("Top Destinations", &self.summary.top_destinations, format),
+            destinations,
+        );
+        self.render_aware(frame, aware, format);
+        if !self.errors.is_empty() {
+            frame.render_widget(self.failed(), failed);
+        }
+    }
+
+    /// Returns the priority bar chart, with bars scaled to the largest count.
+    fn priorities(&self, format: &ReportFormatConfig) -> BarChart<'_> {
+        let bars: Vec<Bar> = self
+            .summary
+            .priorities
+            .iter()
+            .map(|(priority, count)| {
+                Bar::default()
+                    .label(Line::from(priority.as_str()))
+                    .value(u64::from(*count))
+                    .text_value(format.number(u64::from(*count)))
+            })
+            .collect();
+        BarChart::default()
+            .block(Block::bordered().title("Priorities"))
+            .direction(Direction::Horizontal)
+            .bar_width(1)
+            .bar_gap(0)
+            .data(BarGroup::default().bars(&bars))
+    }
+
+    /// Draws the AWARE sparkline in `area`, keeping only the most recent periods that
+    /// fit.
+    fn render_aware(&self, frame: &mut Frame, area: Rect, format: &ReportFormatConfig) {
+        let width = usize::from(area.width.saturating_sub(2)).max(1);
+        let skip = self.summary.aware_threats.len().saturating_sub(width);
+        let series = &self.summary.aware_threats[skip..];
+        let (Some((first, _)), Some((last, _))) = (series.first(), series.last()) else {
+            frame.render_widget(
+                Paragraph::new("(none)").block(Block::bordered().title("AWARE Threats")),
+                area,
+            );
+            return;
+        };
+        let counts: Vec<u64> = series.iter().map(|(_, count)| u64::from(*count)).collect();
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let title = format!(
+            "AWARE Threats ({} .. {}) - max {}, total {}",
+            format.bucket(first),
+            format.bucket(last),
+            format.number(max),
+            format.number(counts.iter().sum()),
+        );
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&counts),
+            area,
+        );
+    }
+
+    /// Returns the list of the files that could not be read, by file name as they are
+    /// all in the log directory, with their errors.
+    fn failed(&self) -> Paragraph<'_> {
+        let lines: Vec<Line> = self
+            .errors
+            .iter()
+            .take(MAX_ERRORS_SHOWN)
+            .map(|(path, e)| {
+                let name = path.file_name().unwrap_or(path.as_os_str());
+                Line::from(format!("{}: {e}", name.to_string_lossy()))
+            })
+            .collect();
+        let title = format!("Failed files: {}", self.errors.len());
+        Paragraph::new(lines)
+            .style(Style::default().fg(Color::Red))
+            .block(Block::bordered().title(title))
+    }
+}
+
+/// Returns the table of the addresses in `entries` and their counts, titled `title`.
+fn top<'a>(title: &'a str, entries: &'a [(String, u32)], format: &ReportFormatConfig) -> Table<'a> {
+    let rows = entries.iter().map(|(ip, count)| {
+        Row::new([
+            Cell::from(ip.as_str()),
+            Cell::from(Line::from(format.number(u64::from(*count))).alignment(Alignment::Right)),
+        ])
+    });
+    Table::new(rows, [Constraint::Min(0), Constraint::Length(COUNT_WIDTH)])
+        .header(Row::new([
+            Cell::from("IP"),
+            Cell::from(Line::from("Count").alignment(Alignment::Right)),
+        ]))
+        .block(Block::bordered().title(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    /// Draws `view` on a 80x30 terminal and returns its lines.
+    fn draw(view: &View) -> io::Result<Vec<String>> {
+        let format = ReportFormatConfig::default();
+        let mut terminal = Terminal::new(TestBackend::new(80, 30))?;
+        terminal.draw(|frame| view.render(frame, &format))?;
+        let buffer = terminal.backend().buffer();
+        Ok(buffer
+            .content()
+            .chunks(usize::from(buffer.area.width))
+            .map(|row| row.iter().map(ratatui::buffer::Cell::symbol).collect())
+            .collect())
+    }
+
+    fn view(errors: Vec<(PathBuf, String)>) -> View {
+        View {
+            title: "Dashboard Aggregator".to_string(),
+            status: "Updated".to_string(),
+            summary: Summary {
+                priorities: vec![("1".to_string(), 7), ("2".to_string(), 3)],
+                top_sources: vec![("10.0.0.1".to_string(), 9)],
+                top_destinations: vec![("10.0.0.2".to_string(), 4)],
+                aware_threats: vec![("2024-01-01 AM".to_string(), 2)],
+                threat_score: Vec::new(),
+            },
+            errors,
+        }
+    }
+
+    #[test]
+    fn draws_the_aggregates() -> io::Result<()> {
+        let lines = draw(&view(Vec::new()))?;
+        let screen = lines.join("\n");
+        assert!(screen.contains("Top Sources"));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("10.0.0.1") && line.contains('9')));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("10.0.0.2") && line.contains('4')));
+        assert!(screen.contains("AWARE Threats (2024-01-01 AM .. 2024-01-01 AM)"));
+        assert!(!screen.contains("Failed files"));
+        Ok(())
+    }
+
+    #[test]
+    fn lists_the_files_that_failed() -> io::Result<()> {
+        let errors = (0..7)
+            .map(|i| {
+                (
+                    PathBuf::from(format!("/var/log/fwd/db/fwddmp.log.{i}")),
+                    "Permission denied".to_string(),
+                )
+            })
+            .collect();
+        let screen = draw(&view(errors))?.join("\n");
+        assert!(screen.contains("Failed files: 7"));
+        assert!(screen.contains("fwddmp.log.0: Permission denied"));
+        assert!(screen.contains("fwddmp.log.4: Permission denied"));
+        assert!(!screen.contains("fwddmp.log.5"));
+        // The aggregates of the other files are still shown
+        assert!(screen.contains("10.0.0.1"));
+        Ok(())
+    }
+}
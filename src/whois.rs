@@ -0,0 +1,214 @@
+//! WHOIS lookups of the owners of the top threat sources.
+//!
+//! With a `whois` section in the configuration file, the top threat sources are looked
+//! up over RDAP, the JSON successor of WHOIS, after aggregating, and `events.json` gets
+//! a `Whois` section listing each source with the network it belongs to, the
+//! organization owning it and its abuse contact. Private and other non-public addresses
+//! are not looked up.
+//!
+//! Results are cached in a JSON file between runs, by default for a week, so daily runs
+//! only look up new sources. Lookups are spaced out to stay within the configured rate,
+//! and a failed lookup only leaves its source out of the section.
+
+use crate::cli::Options;
+use crate::http;
+use crate::reputation::is_public;
+use chrono::Utc;
+use dashboard_aggregator::config::WhoisConfig;
+use dashboard_aggregator::metric::Addresses;
+use dashboard_aggregator::{log, AggregatedData};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Name of the cache file in the output directory when none is configured.
+const DEFAULT_CACHE: &str = "whois_cache.json";
+
+/// A cached lookup result.
+///
+/// # Fields
+/// - `network`: The name of the network the address belongs to, if known.
+/// - `organization`: The name of the registrant of the network, if known.
+/// - `abuse_email`: The email address of the network's abuse contact, if known.
+/// - `checked`: When the address was looked up, as a Unix timestamp.
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    network: Option<String>,
+    organization: Option<String>,
+    abuse_email: Option<String>,
+    checked: i64,
+}
+
+/// Returns the first value of the vCard `property`, such as `fn` or `email`, of an RDAP
+/// `entity`.
+fn vcard(entity: &Value, property: &str) -> Option<String> {
+    entity
+        .pointer("/vcardArray/1")?
+        .as_array()?
+        .iter()
+        .find(|field| field.get(0).and_then(Value::as_str) == Some(property))?
+        .get(3)?
+        .as_str()
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// Returns the first entity with `role` among the entities of an RDAP object, searching
+/// the nested entities, which is where registries often put the abuse contact.
+fn entity_with_role<'a>(object: &'a Value, role: &str) -> Option<&'a Value> {
+    let entities = object.get("entities")?.as_array()?;
+    entities
+        .iter()
+        .find(|entity| {
+            entity
+                .get("roles")
+                .and_then(Value::as_array)
+                .is_some_and(|roles| roles.iter().any(|r| r.as_str() == Some(role)))
+        })
+        .or_else(|| {
+            entities
+                .iter()
+                .find_map(|entity| entity_with_role(entity, role))
+        })
+}
+
+/// Looks up the network of `ip` with the RDAP service at `server`.
+///
+/// # Errors
+/// Returns an error if the request fails or the response is not JSON.
+fn fetch(server: &str, ip: &str, checked: i64) -> io::Result<Entry> {
+    let url = format!("{}/ip/{ip}", server.trim_end_matches('/'));
+    let response = http::get_json_redirected(&url)?;
+    Ok(Entry {
+        network: response
+            .get("name")
+            .and_then(Value::as_str)
+            .map(String::from),
+        organization: entity_with_role(&response, "registrant")
+            .and_then(|entity| vcard(entity, "fn")),
+        abuse_email: entity_with_role(&response, "abuse").and_then(|entity| vcard(entity, "email")),
+        checked,
+    })
+}
+
+/// Loads the cache file at `path`. A missing file is an empty cache, and an invalid one
+/// is ignored with a warning, as it is rebuilt by the next lookups.
+///
+/// # Errors
+/// Returns an error if the file exists but cannot be read.
+fn load_cache(path: &Path) -> io::Result<HashMap<String, Entry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(serde_json::from_str(&contents).unwrap_or_else(|e| {
+        log::warn(format_args!(
+            "ignoring invalid WHOIS cache {}: {e}",
+            path.display()
+        ));
+        HashMap::new()
+    }))
+}
+
+/// Looks up the owners of the top threat sources in `data`, if configured.
+///
+/// # Arguments
+/// - `options`: The parsed command line, providing the output directory.
+/// - `whois`: The WHOIS settings from the configuration file.
+/// - `data`: The aggregates whose top sources are looked up.
+///
+/// # Returns
+/// The `Whois` section of `events.json`, with parallel `Source`, `Network`,
+/// `Organization` and `Abuse Email` arrays, or `None` if lookups are not configured or
+/// addresses are anonymized.
+///
+/// # Errors
+/// Returns an error if the cache file cannot be read or written. Failed lookups are
+/// reported as warnings instead.
+pub fn lookup(
+    options: &Options,
+    whois: Option<&WhoisConfig>,
+    data: &AggregatedData,
+) -> io::Result<Option<Value>> {
+    let Some(whois) = whois else {
+        return Ok(None);
+    };
+    if options.anonymize.is_some() {
+        log::warn("WHOIS lookups are skipped for anonymized addresses");
+        return Ok(None);
+    }
+    let mut sources: Vec<(&str, u32)> = data
+        .metric::<Addresses>("Threat Sources")
+        .map(|metric| &metric.counts)
+        .into_iter()
+        .flatten()
+        .filter(|(source, _)| source.trim().parse().is_ok_and(|ip| is_public(&ip)))
+        .map(|(source, &count)| (source.as_ref(), count))
+        .collect();
+    sources.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    sources.truncate(whois.top);
+
+    let cache_path = whois
+        .cache
+        .clone()
+        .unwrap_or_else(|| options.output_dir_path(DEFAULT_CACHE));
+    let mut cache = load_cache(&cache_path)?;
+    let now = Utc::now().timestamp();
+    let max_age = i64::from(whois.cache_hours) * 3600;
+    let interval = Duration::from_secs(60) / whois.requests_per_minute;
+    let mut last_request: Option<Instant> = None;
+    let mut looked_up = 0;
+
+    let mut section = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (source, _) in sources {
+        let cached = cache
+            .get(source)
+            .filter(|entry| now - entry.checked < max_age)
+            .cloned();
+        let entry = if let Some(entry) = cached {
+            entry
+        } else {
+            if let Some(elapsed) = last_request.map(|last| last.elapsed()) {
+                thread::sleep(interval.saturating_sub(elapsed));
+            }
+            last_request = Some(Instant::now());
+            match fetch(&whois.server, source, now) {
+                Ok(entry) => {
+                    cache.insert(source.to_string(), entry.clone());
+                    looked_up += 1;
+                    entry
+                }
+                Err(e) => {
+                    log::warn(format_args!("WHOIS lookup of {source} failed: {e}"));
+                    continue;
+                }
+            }
+        };
+        section.0.push(source);
+        section.1.push(entry.network);
+        section.2.push(entry.organization);
+        section.3.push(entry.abuse_email);
+    }
+
+    if looked_up > 0 {
+        // Expired entries of sources that left the top are dropped
+        cache.retain(|_, entry| now - entry.checked < max_age);
+        fs::write(&cache_path, serde_json::to_string(&cache)?)?;
+    }
+    log::info(format_args!(
+        "WHOIS of {} sources over RDAP ({looked_up} looked up)",
+        section.0.len()
+    ));
+    Ok(Some(json!({
+        "Source": section.0,
+        "Network": section.1,
+        "Organization": section.2,
+        "Abuse Email": section.3
+    })))
+}
@@ -0,0 +1,279 @@
+//! Parser for the Windows Filtering Platform events of the Windows `Security` log.
+//!
+//! With auditing of the Filtering Platform enabled, Windows logs an event for every
+//! blocked packet and every blocked or allowed connection. The events are read as the
+//! XML that `wevtutil qe Security /f:xml` writes, one `<Event>` element after another:
+//!
+//! ```text
+//! <Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'><System>...<EventID>5157</EventID>...<TimeCreated SystemTime='2026-10-16T07:30:01.1234567Z'/><EventRecordID>4242</EventRecordID>...</System><EventData><Data Name='Direction'>%%14592</Data><Data Name='SourceAddress'>192.168.1.10</Data>...</EventData></Event>
+//! ```
+//!
+//! The events in `EVENT_IDS` are mapped into the normalized layout; all other events
+//! are skipped.
+
+use crate::input::{protocol_name, LineParser, Normalized};
+use chrono::{DateTime, Local};
+
+/// The IDs of the events that are parsed: blocked packets (5152, and 5153 for a more
+/// restrictive filter), allowed connections (5156) and blocked connections (5157).
+pub const EVENT_IDS: [u32; 4] = [5152, 5153, 5156, 5157];
+
+/// Priority of blocked packets and connections.
+const BLOCKED_PRIORITY: &str = "1";
+
+/// Priority of allowed connections.
+const ALLOWED_PRIORITY: &str = "5";
+
+/// Returns the text of the first `<tag>` element in `xml`.
+fn text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{tag}");
+    let mut rest = xml;
+    // Skip elements whose name only starts with `tag`, e.g. `EventIDs` for `EventID`
+    loop {
+        let start = rest.find(&open)? + open.len();
+        rest = &rest[start..];
+        if rest.starts_with(['>', ' ']) {
+            break;
+        }
+    }
+    let start = rest.find('>')? + 1;
+    let end = rest.find(&format!("</{tag}>"))?;
+    rest.get(start..end)
+}
+
+/// Returns the value of the attribute `name` of the first `<tag>` element in `xml`.
+fn attribute<'a>(xml: &'a str, tag: &str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag} "))?;
+    let element = &xml[start..start + xml[start..].find('>')?];
+    let value = &element[element.find(&format!("{name}="))? + name.len() + 1..];
+    let quote = value.chars().next().filter(|c| matches!(c, '\'' | '"'))?;
+    let value = &value[1..];
+    value.find(quote).map(|end| &value[..end])
+}
+
+/// Returns the value of the event data field `name`, empty if it is missing.
+fn data<'a>(xml: &'a str, name: &str) -> &'a str {
+    ["'", "\""]
+        .iter()
+        .find_map(|quote| {
+            let start = xml.find(&format!("<Data Name={quote}{name}{quote}"))?;
+            let element = &xml[start..];
+            let open_end = element.find('>')?;
+            // `<Data Name='...'/>` has no value
+            if element[..open_end].ends_with('/') {
+                return Some("");
+            }
+            let value = &element[open_end + 1..];
+            value.find("</Data>").map(|end| &value[..end])
+        })
+        .unwrap_or_default()
+}
+
+/// Parses one `<Event>` element, returning `None` if it is not one of `EVENT_IDS` or is
+/// malformed.
+///
+/// The Filtering Platform logs the local address as the source of every event. For
+/// inbound traffic, the remote address is made the source instead, so the threat
+/// sources are the peers that the traffic came from.
+fn parse_event(event: &str) -> Option<Normalized> {
+    let id: u32 = text(event, "EventID")?.trim().parse().ok()?;
+    let (action, description) = match id {
+        5152 => ("block", "packet blocked"),
+        5153 => ("block", "packet blocked by a more restrictive filter"),
+        5156 => ("allow", "connection allowed"),
+        5157 => ("block", "connection blocked"),
+        _ => return None,
+    };
+    let datetime = DateTime::parse_from_rfc3339(attribute(event, "TimeCreated", "SystemTime")?)
+        .ok()?
+        .with_timezone(&Local)
+        .naive_local();
+    let direction = match data(event, "Direction") {
+        "%%14592" => "inbound",
+        "%%14593" => "outbound",
+        other => other,
+    };
+    let local = (data(event, "SourceAddress"), data(event, "SourcePort"));
+    let remote = (data(event, "DestAddress"), data(event, "DestPort"));
+    let ((source, src_port), (destination, dst_port)) = if direction == "inbound" {
+        (remote, local)
+    } else {
+        (local, remote)
+    };
+    if source.is_empty() || destination.is_empty() {
+        return None;
+    }
+    let proto = data(event, "Protocol")
+        .parse()
+        .map_or_else(|_| data(event, "Protocol").to_string(), protocol_name);
+
+    Some(Normalized {
+        id: text(event, "EventRecordID").unwrap_or_default().to_string(),
+        priority: if action == "allow" {
+            ALLOWED_PRIORITY
+        } else {
+            BLOCKED_PRIORITY
+        }
+        .to_string(),
+        signature: format!("{id} {description}"),
+        category: "winevent",
+        datetime,
+        proto,
+        source: source.to_string(),
+        src_port: src_port.to_string(),
+        action: action.to_string(),
+        direction: direction.to_string(),
+        destination: destination.to_string(),
+        dst_port: dst_port.to_string(),
+        ..Normalized::default()
+    })
+}
+
+/// Parses `<Event>` elements, which may span several lines.
+///
+/// # Fields
+/// - `event`: The lines of the event being read, from its start tag on.
+#[derive(Default)]
+pub(crate) struct Parser {
+    event: String,
+}
+
+impl LineParser for Parser {
+    fn parse(&mut self, line: &str) -> Option<Normalized> {
+        if let Some(start) = line.find("<Event ").or_else(|| line.find("<Event>")) {
+            self.event.clear();
+            self.event.push_str(&line[start..]);
+        } else if self.event.is_empty() {
+            return None;
+        } else {
+            self.event.push_str(line);
+        }
+        let end = self.event.find("</Event>")?;
+        let event = std::mem::take(&mut self.event);
+        parse_event(&event[..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    /// Returns a Filtering Platform event as `wevtutil` writes it, with the event data
+    /// fields `data`.
+    fn event(id: u32, data: &[(&str, &str)]) -> String {
+        let mut fields = String::new();
+        for (name, value) in data {
+            let _ = write!(fields, "<Data Name='{name}'>{value}</Data>");
+        }
+        format!(
+            "<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'><System>\
+             <Provider Name='Microsoft-Windows-Security-Auditing'/><EventID>{id}</EventID>\
+             <TimeCreated SystemTime='2026-10-16T07:30:01.1234567Z'/>\
+             <EventRecordID>4242</EventRecordID><Channel>Security</Channel></System>\
+             <EventData>{fields}</EventData></Event>"
+        )
+    }
+
+    fn connection(id: u32, direction: &str) -> String {
+        event(
+            id,
+            &[
+                ("ProcessID", "4"),
+                ("Direction", direction),
+                ("SourceAddress", "192.168.1.10"),
+                ("SourcePort", "3389"),
+                ("DestAddress", "203.0.113.5"),
+                ("DestPort", "51234"),
+                ("Protocol", "6"),
+                ("FilterRTID", "0"),
+            ],
+        )
+    }
+
+    /// Feeds `lines` to a parser, returning the events parsed.
+    fn parse_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Normalized> {
+        let mut parser = Parser::default();
+        lines
+            .into_iter()
+            .filter_map(|line| parser.parse(line))
+            .collect()
+    }
+
+    #[test]
+    fn inbound_events_have_the_remote_address_as_source() -> Result<(), &'static str> {
+        let events = parse_lines([connection(5157, "%%14592").as_str()]);
+        let event = events.first().ok_or("5157 not parsed")?;
+        assert_eq!(event.id, "4242");
+        assert_eq!(event.priority, BLOCKED_PRIORITY);
+        assert_eq!(event.signature, "5157 connection blocked");
+        assert_eq!(event.category, "winevent");
+        assert_eq!([&event.action, &event.direction], ["block", "inbound"]);
+        assert_eq!(
+            [
+                &event.proto,
+                &event.source,
+                &event.src_port,
+                &event.destination,
+                &event.dst_port
+            ],
+            ["tcp", "203.0.113.5", "51234", "192.168.1.10", "3389"]
+        );
+        let expected = DateTime::parse_from_rfc3339("2026-10-16T07:30:01.1234567Z")
+            .map(|time| time.with_timezone(&Local).naive_local())
+            .ok();
+        assert_eq!(Some(event.datetime), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn outbound_and_allowed_events_keep_the_local_source() -> Result<(), &'static str> {
+        let events = parse_lines([connection(5156, "%%14593").as_str()]);
+        let event = events.first().ok_or("5156 not parsed")?;
+        assert_eq!(event.priority, ALLOWED_PRIORITY);
+        assert_eq!(event.signature, "5156 connection allowed");
+        assert_eq!([&event.action, &event.direction], ["allow", "outbound"]);
+        assert_eq!(
+            [&event.source, &event.destination],
+            ["192.168.1.10", "203.0.113.5"]
+        );
+
+        // Double quotes, an empty port and a protocol number without a name
+        let xml = connection(5152, "%%14593")
+            .replace('\'', "\"")
+            .replace(
+                "<Data Name=\"SourcePort\">3389</Data>",
+                "<Data Name=\"SourcePort\"/>",
+            )
+            .replace(">6<", ">47<");
+        let events = parse_lines([xml.as_str()]);
+        let event = events.first().ok_or("5152 not parsed")?;
+        assert_eq!(event.signature, "5152 packet blocked");
+        assert_eq!([&event.src_port, &event.proto], ["", "47"]);
+        Ok(())
+    }
+
+    #[test]
+    fn events_may_span_lines() {
+        // As exported with line breaks between the elements, after other output
+        let xml = connection(5153, "%%14592").replace("><", ">\n<");
+        let lines = std::iter::once("Some preamble").chain(xml.lines());
+        let events = parse_lines(lines);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events.first().map(|event| event.signature.as_str()),
+            Some("5153 packet blocked by a more restrictive filter")
+        );
+    }
+
+    #[test]
+    fn other_and_malformed_events_are_skipped() {
+        let logon = event(4624, &[("IpAddress", "203.0.113.5")]);
+        let no_addresses = event(5157, &[("Direction", "%%14592"), ("Protocol", "6")]);
+        let bad_time = connection(5157, "%%14592").replace("2026-10-16T07", "yesterday");
+        let truncated = connection(5157, "%%14592").replace("</Event>", "");
+        for xml in [logon, no_addresses, bad_time, truncated] {
+            assert!(parse_lines([xml.as_str()]).is_empty(), "{xml}");
+        }
+    }
+}
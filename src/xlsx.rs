@@ -0,0 +1,207 @@
+//! Excel (.xlsx) report writer.
+//!
+//! Workbooks are written with [`rust_xlsxwriter`], one sheet per section, with a bold,
+//! shaded and frozen header row and columns sized to their values.
+
+use rust_xlsxwriter::{Color, Format, Workbook, Worksheet, XlsxError};
+use std::io;
+use std::path::Path;
+
+/// A single cell value.
+pub enum Cell {
+    Text(String),
+    Number(u64),
+}
+
+/// A worksheet made of a header row followed by data rows, whose numbers are shown
+/// with thousands separators if `grouped` is set.
+pub struct Sheet {
+    pub name: String,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+    pub grouped: bool,
+}
+
+/// Writes `sheets` as a workbook to `path`.
+///
+/// # Errors
+/// Returns an error if the file cannot be created or written, or a sheet has more rows
+/// or columns than Excel allows.
+pub fn write(path: &Path, sheets: &[Sheet]) -> io::Result<()> {
+    workbook(sheets)
+        .and_then(|mut workbook| workbook.save(path))
+        .map_err(|e| match e {
+            XlsxError::IoError(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidInput, e),
+        })
+}
+
+/// Builds the workbook of `sheets`.
+fn workbook(sheets: &[Sheet]) -> Result<Workbook, XlsxError> {
+    let header = Format::new()
+        .set_bold()
+        .set_background_color(Color::RGB(0x00D9_E1F2));
+    // Number format 3, shown with the thousands separator of the reader's locale
+    let grouped = Format::new().set_num_format("#,##0");
+    let plain = Format::new();
+
+    let mut workbook = Workbook::new();
+    for sheet in sheets {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(sheet_name(&sheet.name))?;
+        worksheet.set_freeze_panes(1, 0)?;
+        for (col, name) in sheet.headers.iter().enumerate() {
+            worksheet.write_string_with_format(0, column(col)?, name, &header)?;
+            worksheet.set_column_width(column(col)?, column_width(sheet, col))?;
+        }
+        let number_format = if sheet.grouped { &grouped } else { &plain };
+        for (i, cells) in sheet.rows.iter().enumerate() {
+            let row = u32::try_from(i + 1).map_err(|_| XlsxError::RowColumnLimitError)?;
+            write_row(worksheet, row, cells, number_format)?;
+        }
+    }
+    Ok(workbook)
+}
+
+fn write_row(
+    worksheet: &mut Worksheet,
+    row: u32,
+    cells: &[Cell],
+    number_format: &Format,
+) -> Result<(), XlsxError> {
+    for (col, cell) in cells.iter().enumerate() {
+        match cell {
+            Cell::Text(text) => {
+                worksheet.write_string(row, column(col)?, text)?;
+            }
+            Cell::Number(number) => {
+                // Exact for counts below 2^53
+                #[allow(clippy::cast_precision_loss)]
+                let number = *number as f64;
+                worksheet.write_number_with_format(row, column(col)?, number, number_format)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts a zero-based column index into a worksheet column.
+fn column(col: usize) -> Result<u16, XlsxError> {
+    u16::try_from(col).map_err(|_| XlsxError::RowColumnLimitError)
+}
+
+/// Returns the width of column `col` of `sheet`: that of its widest value, within
+/// reason.
+fn column_width(sheet: &Sheet, col: usize) -> u16 {
+    let widest = sheet
+        .rows
+        .iter()
+        .filter_map(|row| row.get(col))
+        .map(|cell| match cell {
+            Cell::Text(text) => text.chars().count(),
+            Cell::Number(number) => {
+                let digits = number.to_string().len();
+                // Room for a separator between each group of three digits
+                digits + if sheet.grouped { (digits - 1) / 3 } else { 0 }
+            }
+        })
+        .chain(sheet.headers.get(col).map(|header| header.chars().count()))
+        .max()
+        .unwrap_or(0);
+    u16::try_from((widest + 2).clamp(8, 60)).unwrap_or(60)
+}
+
+/// Excel limits sheet names to 31 characters and forbids `[]:*?/\`.
+fn sheet_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '[' | ']' | ':' | '*' | '?' | '/' | '\\'))
+        .take(31)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Cursor, Read};
+
+    /// Returns the contents of the entry `name` of the zip archive `zip`.
+    fn entry(zip: &[u8], name: &str) -> io::Result<String> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip))?;
+        let mut contents = String::new();
+        archive.by_name(name)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn sheets() -> [Sheet; 2] {
+        [
+            Sheet {
+                name: "Threat Sources: top [10] of the last day".to_string(),
+                headers: vec!["Source".to_string(), "Count".to_string()],
+                rows: vec![
+                    vec![Cell::Text("10.0.0.1".to_string()), Cell::Number(1_234_567)],
+                    vec![
+                        Cell::Text("<a & \"b\"> and more".to_string()),
+                        Cell::Number(3),
+                    ],
+                ],
+                grouped: true,
+            },
+            Sheet {
+                name: "Priorities".to_string(),
+                headers: vec!["Priority".to_string(), "Count".to_string()],
+                rows: vec![vec![Cell::Text("1".to_string()), Cell::Number(1_234_567)]],
+                grouped: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn workbooks_have_one_worksheet_per_sheet() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("da-xlsx-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("events.xlsx");
+        write(&path, &sheets())?;
+        let zip = fs::read(&path)?;
+        fs::remove_dir_all(&dir)?;
+
+        let workbook = entry(&zip, "xl/workbook.xml")?;
+        assert!(workbook.contains(r#"<sheet name="Threat Sources top 10 of the la" sheetId="1""#));
+        assert!(workbook.contains(r#"<sheet name="Priorities" sheetId="2""#));
+        let strings = entry(&zip, "xl/sharedStrings.xml")?;
+        assert!(
+            strings.contains("<t>&lt;a &amp; \"b\"&gt; and more</t>"),
+            "{strings}"
+        );
+
+        let first = entry(&zip, "xl/worksheets/sheet1.xml")?;
+        assert!(
+            first.contains(r#"<pane ySplit="1" topLeftCell="A2""#),
+            "{first}"
+        );
+        assert!(first.contains("<v>1234567</v>"));
+        // 1,234,567 is 9 characters wide, plus a margin of 2
+        assert!(first.contains(r#"<col min="2" max="2" width="11.7109375" customWidth="1"/>"#));
+        let second = entry(&zip, "xl/worksheets/sheet2.xml")?;
+        assert!(second.contains(r#"<col min="2" max="2" width="9.7109375" customWidth="1"/>"#));
+        Ok(())
+    }
+
+    #[test]
+    fn only_grouped_sheets_format_numbers() -> io::Result<()> {
+        let zip = workbook(&sheets())
+            .and_then(|mut workbook| workbook.save_to_buffer())
+            .map_err(io::Error::other)?;
+        let styles = entry(&zip, "xl/styles.xml")?;
+        assert!(styles.contains("<b/>"));
+        assert!(styles.contains(r#"<fgColor rgb="FFD9E1F2"/>"#));
+        let grouped = entry(&zip, "xl/worksheets/sheet1.xml")?;
+        let plain = entry(&zip, "xl/worksheets/sheet2.xml")?;
+        assert!(
+            grouped.contains(r#"<c r="B2" s="2"><v>1234567</v></c>"#),
+            "{grouped}"
+        );
+        assert!(plain.contains(r#"<c r="B2"><v>1234567</v></c>"#), "{plain}");
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::{Duration, Local};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Writes a log with many sources of equal counts, so that an unordered output would
+/// differ between runs.
+fn write_log(dir: &Path) -> io::Result<()> {
+    let time = (Local::now() - Duration::hours(1)).format("%Y/%m/%d %H:%M:%S");
+    let mut log = String::from("id,prio,sig,cat,dt,x,src,a,b,c,d,e,dst\n");
+    for i in 0..600 {
+        let source = format!("10.0.{}.{}", i % 7, i % 50);
+        let _ = writeln!(
+            log,
+            "{i},{},sig{},AWARE,{time},x,{source},a,b,c,d,e,10.1.0.{}",
+            i % 4,
+            i % 9,
+            i % 11
+        );
+    }
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("fwddmp.log.tmp.1"), log)
+}
+
+/// Runs the aggregator over `logs` and returns its `events.json`, without the time of the
+/// run, and `threat_sources.json`.
+fn run(logs: &Path, output: &Path) -> io::Result<(String, Vec<u8>)> {
+    fs::create_dir_all(output)?;
+    let run = Command::new(env!("CARGO_BIN_EXE_dashboard_aggregator"))
+        .arg(logs)
+        .arg("1")
+        .arg("--output-dir")
+        .arg(output)
+        .output()?;
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let events = fs::read_to_string(output.join("events.json"))?
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("\"generated\""))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((events, fs::read(output.join("threat_sources.json"))?))
+}
+
+#[test]
+fn same_logs_give_identical_outputs() -> io::Result<()> {
+    let dir = std::env::temp_dir().join(format!("da-determinism-{}", std::process::id()));
+    let logs = dir.join("logs");
+    write_log(&logs)?;
+
+    let first = run(&logs, &dir.join("first"))?;
+    let second = run(&logs, &dir.join("second"))?;
+    fs::remove_dir_all(&dir)?;
+
+    assert_eq!(first.0, second.0);
+    assert_eq!(first.1, second.1);
+    Ok(())
+}
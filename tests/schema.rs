@@ -0,0 +1,113 @@
+use chrono::{Duration, Local};
+use serde_json::Value;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Enables every metric, with the columns they need, and derived metrics.
+const CONFIG: &str = r#"{
+  "metrics": ["priorities", "threat_sources", "threat_destinations", "aware_threats",
+              "threat_score", "signatures", "bucket_rates", "priority_trend", "sessions",
+              "domains", "web_targets", "users", "zones", "source_macs", "icmp",
+              "scan_patterns", "business_hours"],
+  "priorities": { "weights": { "1": 100, "2": 10, "3": 1 } },
+  "columns": {
+    "hostname": "Host", "url": "URL", "user": "User", "zone": "Zone", "mac": "MAC",
+    "protocol": "Proto", "icmp_type": "IcmpType", "icmp_code": "IcmpCode",
+    "tcp_flags": "Flags"
+  },
+  "derived": {
+    "counts": { "deny_count": "col:category=~\"DENY\"" },
+    "metrics": { "blocked_ratio": "deny_count / total" }
+  }
+}"#;
+
+/// Writes a log with the columns of every metric.
+fn write_log(dir: &Path) -> io::Result<()> {
+    let time = (Local::now() - Duration::hours(1)).format("%Y/%m/%d %H:%M:%S");
+    let mut log = String::from(
+        "id,prio,sig,cat,dt,x,src,a,b,c,d,e,dst,\
+         Host,URL,User,Zone,MAC,Proto,IcmpType,IcmpCode,Flags\n",
+    );
+    for i in 0..40 {
+        let category = if i % 2 == 0 { "DENY" } else { "AWARE" };
+        let (protocol, icmp, flags) = if i % 3 == 0 {
+            ("icmp", "8,0", "")
+        } else {
+            ("tcp", ",", "S")
+        };
+        let _ = writeln!(
+            log,
+            "{i},{},sig{},{category},{time},x,10.0.{}.{},a,b,c,d,e,10.1.0.{},www{}.example.co.uk,\
+             https://shop.example.com/p{}?q=1,user{},zone{},00:50:56:aa:bb:{:02x},{protocol},\
+             {icmp},{flags}",
+            i % 4,
+            i % 5,
+            i % 3,
+            i % 7,
+            i % 5,
+            i % 3,
+            i % 4,
+            i % 3,
+            i % 2,
+            i % 10,
+        );
+    }
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("fwddmp.log.tmp.1"), log)
+}
+
+/// Runs the binary with `args` and returns its standard output.
+fn run(args: &[&str]) -> io::Result<Vec<u8>> {
+    let run = Command::new(env!("CARGO_BIN_EXE_dashboard_aggregator"))
+        .args(args)
+        .output()?;
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    Ok(run.stdout)
+}
+
+/// Asserts that `document` is valid against `schema`, listing the errors if not.
+fn assert_valid(schema: &[u8], document: &[u8]) -> io::Result<()> {
+    let schema: Value = serde_json::from_slice(schema)?;
+    let document: Value = serde_json::from_slice(document)?;
+    let validator = jsonschema::options()
+        .should_validate_formats(true)
+        .build(&schema)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let errors: Vec<_> = validator
+        .iter_errors(&document)
+        .map(|e| format!("{}: {e}", e.instance_path))
+        .collect();
+    assert!(errors.is_empty(), "{errors:#?}");
+    Ok(())
+}
+
+#[test]
+fn outputs_are_valid_against_their_schemas() -> io::Result<()> {
+    let dir = std::env::temp_dir().join(format!("da-schema-{}", std::process::id()));
+    let logs = dir.join("logs");
+    write_log(&logs)?;
+    let config = dir.join("config.json");
+    fs::write(&config, CONFIG)?;
+    let (logs, config, output) = (
+        logs.to_string_lossy().into_owned(),
+        config.to_string_lossy().into_owned(),
+        dir.to_string_lossy().into_owned(),
+    );
+
+    run(&[&logs, "1", "--output-dir", &output, "--config", &config, "--windows", "1h"])?;
+    let events = fs::read(dir.join("events.json"))?;
+    let threat_sources = fs::read(dir.join("threat_sources.json"))?;
+    let events_schema = run(&["schema", "--config", &config])?;
+    let threat_sources_schema = run(&["schema", "threat_sources"])?;
+    fs::remove_dir_all(&dir)?;
+
+    // Every metric has a section, so the whole schema is exercised
+    let sections: Value = serde_json::from_slice(&events)?;
+    assert!(sections.get("Web Targets").is_some() && sections.get("Windows").is_some());
+    assert_valid(&events_schema, &events)?;
+    assert_valid(&threat_sources_schema, &threat_sources)?;
+    Ok(())
+}